@@ -2,14 +2,22 @@
 //!
 //! The types in this module are typically returned by methods on [`Tag`](crate::Tag).
 
-use crate::{Error, Result};
+use crate::{Error, ErrorKind, Result};
+#[cfg(feature = "id3")]
 use id3::frame::Picture as Id3Picture;
+#[cfg(feature = "id3")]
 use id3::frame::Timestamp as Id3Timestamp;
+#[cfg(feature = "metaflac")]
 use metaflac::block::Picture as FlacPicture;
+#[cfg(feature = "mp4ameta")]
 use mp4ameta::Img as Mp4Picture;
+#[cfg(feature = "mp4ameta")]
 use mp4ameta::ImgFmt as Mp4ImageFmt;
+#[cfg(feature = "oggmeta")]
 use oggmeta::Picture as OggPicture;
+#[cfg(feature = "opusmeta")]
 use opusmeta::picture::Picture as OpusPicture;
+use std::path::Path;
 use std::str::FromStr;
 
 /// Represents the album that a song is part of.
@@ -20,13 +28,190 @@ pub struct Album {
     pub cover: Option<Picture>,
 }
 
+/// A metadata field of a [`Tag`](crate::Tag), as reported by [`Tag::diff`](crate::Tag::diff).
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Field {
+    Title,
+    Artist,
+    AlbumTitle,
+    AlbumArtist,
+    AlbumCover,
+    Date,
+    Genres,
+    Lyrics,
+    Encoder,
+    Copyright,
+    ArtistUrl,
+    SourceUrl,
+    TotalTracks,
+    Podcast,
+    FeedUrl,
+    EpisodeId,
+    Category,
+    /// A free-form comment with the given key.
+    Comment(String),
+    /// A format-specific frame or atom which is not covered by any of the other fields.
+    Other,
+}
+
+impl std::fmt::Display for Field {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Title => f.write_str("title"),
+            Self::Artist => f.write_str("artist"),
+            Self::AlbumTitle => f.write_str("album title"),
+            Self::AlbumArtist => f.write_str("album artist"),
+            Self::AlbumCover => f.write_str("album cover"),
+            Self::Date => f.write_str("date"),
+            Self::Genres => f.write_str("genres"),
+            Self::Lyrics => f.write_str("lyrics"),
+            Self::Encoder => f.write_str("encoder"),
+            Self::Copyright => f.write_str("copyright"),
+            Self::ArtistUrl => f.write_str("artist url"),
+            Self::SourceUrl => f.write_str("source url"),
+            Self::TotalTracks => f.write_str("total tracks"),
+            Self::Podcast => f.write_str("podcast"),
+            Self::FeedUrl => f.write_str("feed url"),
+            Self::EpisodeId => f.write_str("episode id"),
+            Self::Category => f.write_str("category"),
+            Self::Comment(key) => write!(f, "comment '{key}'"),
+            Self::Other => f.write_str("other"),
+        }
+    }
+}
+
+/// The codec of the first logical stream in an Ogg container, as reported by
+/// [`Tag::detect_ogg_codec`](crate::Tag::detect_ogg_codec).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum OggCodec {
+    Vorbis,
+    Opus,
+    Flac,
+    Theora,
+    /// A codec this crate does not recognize.
+    Unknown,
+}
+
+impl std::fmt::Display for OggCodec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Vorbis => f.write_str("vorbis"),
+            Self::Opus => f.write_str("opus"),
+            Self::Flac => f.write_str("flac"),
+            Self::Theora => f.write_str("theora"),
+            Self::Unknown => f.write_str("unknown"),
+        }
+    }
+}
+
+/// An item which had to be dropped while reading a malformed tag, as reported by
+/// [`Tag::read_from_lenient`](crate::Tag::read_from_lenient).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SkippedItem {
+    /// The frame id, atom or other description of what was dropped.
+    pub item: String,
+    /// Why the item could not be read.
+    pub reason: String,
+}
+
+impl SkippedItem {
+    pub(crate) fn new(item: &str, reason: &impl std::fmt::Display) -> Self {
+        Self {
+            item: item.to_owned(),
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for SkippedItem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.item, self.reason)
+    }
+}
+
 /// Stores picture data.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Picture {
     pub data: Vec<u8>,
     pub mime_type: String,
 }
 
+impl Picture {
+    /// Writes the raw picture data to `path`.
+    ///
+    /// # Errors
+    /// This function errors if the file cannot be written.
+    pub fn save_to<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        std::fs::write(path, &self.data).map_err(|e| Error::from(e).with_path(path))
+    }
+
+    /// Returns the width and height of the picture in pixels, reading only the image header.
+    ///
+    /// Returns `None` if the picture cannot be decoded.
+    #[cfg(feature = "image")]
+    #[must_use]
+    pub fn dimensions(&self) -> Option<(u32, u32)> {
+        image::ImageReader::new(std::io::Cursor::new(&self.data))
+            .with_guessed_format()
+            .ok()?
+            .into_dimensions()
+            .ok()
+    }
+
+    /// Returns a jpeg copy of the picture which fits into `max_size` x `max_size` pixels,
+    /// keeping the aspect ratio.
+    ///
+    /// Returns `None` if the picture already fits or cannot be decoded.
+    #[cfg(feature = "image")]
+    #[must_use]
+    pub fn downscaled(&self, max_size: u32) -> Option<Self> {
+        let img = image::load_from_memory(&self.data).ok()?;
+        if img.width() <= max_size && img.height() <= max_size {
+            return None;
+        }
+        Self::encode(&img.thumbnail(max_size, max_size), "image/jpeg")
+    }
+
+    /// Returns a copy of the picture encoded as `mime_type`, scaled down to fit into `max_size` x
+    /// `max_size` pixels if given, keeping the aspect ratio.
+    ///
+    /// Only `image/jpeg` and `image/png` can be encoded. Returns `None` if the picture cannot be
+    /// decoded or `mime_type` is not supported.
+    #[cfg(feature = "image")]
+    #[must_use]
+    pub fn reencoded(&self, max_size: Option<u32>, mime_type: &str) -> Option<Self> {
+        let img = image::load_from_memory(&self.data).ok()?;
+        let img = match max_size {
+            Some(max) if img.width() > max || img.height() > max => img.thumbnail(max, max),
+            _ => img,
+        };
+        Self::encode(&img, mime_type)
+    }
+
+    #[cfg(feature = "image")]
+    fn encode(img: &image::DynamicImage, mime_type: &str) -> Option<Self> {
+        let mut data = Vec::new();
+        let mut cursor = std::io::Cursor::new(&mut data);
+        match mime_type {
+            // jpeg has no alpha channel
+            "image/jpeg" => img
+                .to_rgb8()
+                .write_to(&mut cursor, image::ImageFormat::Jpeg)
+                .ok()?,
+            "image/png" => img.write_to(&mut cursor, image::ImageFormat::Png).ok()?,
+            _ => return None,
+        }
+        Some(Self {
+            data,
+            mime_type: mime_type.into(),
+        })
+    }
+}
+
+#[cfg(feature = "id3")]
 impl From<Id3Picture> for Picture {
     fn from(value: Id3Picture) -> Self {
         Self {
@@ -36,6 +221,7 @@ impl From<Id3Picture> for Picture {
     }
 }
 
+#[cfg(feature = "metaflac")]
 impl From<FlacPicture> for Picture {
     fn from(value: FlacPicture) -> Self {
         Self {
@@ -45,6 +231,7 @@ impl From<FlacPicture> for Picture {
     }
 }
 
+#[cfg(feature = "mp4ameta")]
 impl From<Mp4Picture<&[u8]>> for Picture {
     fn from(value: Mp4Picture<&[u8]>) -> Self {
         Self {
@@ -58,6 +245,7 @@ impl From<Mp4Picture<&[u8]>> for Picture {
     }
 }
 
+#[cfg(feature = "mp4ameta")]
 impl TryFrom<Picture> for Mp4Picture<Vec<u8>> {
     type Error = Error;
 
@@ -66,7 +254,7 @@ impl TryFrom<Picture> for Mp4Picture<Vec<u8>> {
             "image/bmp" => Ok(Mp4ImageFmt::Bmp),
             "image/jpeg" => Ok(Mp4ImageFmt::Jpeg),
             "image/png" => Ok(Mp4ImageFmt::Png),
-            _ => Err(Error::InvalidImageFormat),
+            _ => Err(Error::from(ErrorKind::InvalidImageFormat).with_field(Field::AlbumCover)),
         }?;
 
         Ok(Self {
@@ -76,6 +264,7 @@ impl TryFrom<Picture> for Mp4Picture<Vec<u8>> {
     }
 }
 
+#[cfg(feature = "opusmeta")]
 impl From<OpusPicture> for Picture {
     fn from(value: OpusPicture) -> Self {
         Self {
@@ -85,6 +274,7 @@ impl From<OpusPicture> for Picture {
     }
 }
 
+#[cfg(feature = "oggmeta")]
 impl From<OggPicture> for Picture {
     fn from(value: OggPicture) -> Self {
         Self {
@@ -94,6 +284,7 @@ impl From<OggPicture> for Picture {
     }
 }
 
+#[cfg(feature = "opusmeta")]
 impl From<Picture> for OpusPicture {
     fn from(value: Picture) -> Self {
         let mut picture = OpusPicture::new();
@@ -115,8 +306,11 @@ impl std::fmt::Display for Picture {
     }
 }
 
-/// Represents a date and time according to the ID3v2.4 spec.
-#[derive(Clone, Copy, Debug, Default)]
+/// Represents a date and time according to ISO 8601, as used by the ID3v2.4 spec.
+///
+/// Every field may only be set if the coarser ones are, the finest set field is the
+/// [`Precision`] of the timestamp.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub struct Timestamp {
     pub year: i32,
     pub month: Option<u8>,
@@ -124,8 +318,141 @@ pub struct Timestamp {
     pub hour: Option<u8>,
     pub minute: Option<u8>,
     pub second: Option<u8>,
+    /// Offset from UTC in minutes. Only meaningful if a time is set, `None` is local time.
+    pub utc_offset: Option<i16>,
+}
+
+/// The finest unit a [`Timestamp`] is specified in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Precision {
+    Year,
+    Month,
+    Day,
+    Hour,
+    Minute,
+    Second,
+}
+
+impl Timestamp {
+    /// Returns the finest unit set in this timestamp.
+    #[must_use]
+    pub fn precision(&self) -> Precision {
+        match (self.month, self.day, self.hour, self.minute, self.second) {
+            (None, ..) => Precision::Year,
+            (_, None, ..) => Precision::Month,
+            (_, _, None, ..) => Precision::Day,
+            (_, _, _, None, _) => Precision::Hour,
+            (_, _, _, _, None) => Precision::Minute,
+            _ => Precision::Second,
+        }
+    }
+
+    /// Drops all units finer than `precision`.
+    #[must_use]
+    pub fn truncated(mut self, precision: Precision) -> Self {
+        if precision < Precision::Second {
+            self.second = None;
+        }
+        if precision < Precision::Minute {
+            self.minute = None;
+        }
+        if precision < Precision::Hour {
+            self.hour = None;
+            self.utc_offset = None;
+        }
+        if precision < Precision::Day {
+            self.day = None;
+        }
+        if precision < Precision::Month {
+            self.month = None;
+        }
+        self
+    }
+
+    /// Converts the timestamp to UTC, carrying over into the date if needed.
+    ///
+    /// Timestamps without a time or offset are returned unchanged.
+    #[must_use]
+    pub fn to_utc(self) -> Self {
+        let (Some(offset), Some(hour), Some(mut day), Some(mut month)) =
+            (self.utc_offset, self.hour, self.day, self.month)
+        else {
+            return self;
+        };
+        if offset == 0 {
+            return self;
+        }
+
+        let minutes =
+            i32::from(hour) * 60 + i32::from(self.minute.unwrap_or(0)) - i32::from(offset);
+        let mut year = self.year;
+        match minutes.div_euclid(24 * 60) {
+            -1 if day > 1 => day -= 1,
+            -1 => {
+                (year, month) = if month == 1 {
+                    (year - 1, 12)
+                } else {
+                    (year, month - 1)
+                };
+                day = days_in_month(year, month);
+            }
+            1 if day < days_in_month(year, month) => day += 1,
+            1 => {
+                (year, month) = if month == 12 {
+                    (year + 1, 1)
+                } else {
+                    (year, month + 1)
+                };
+                day = 1;
+            }
+            _ => {}
+        }
+
+        let minutes = minutes.rem_euclid(24 * 60);
+        // An offset with minutes makes the minute of an hour precise timestamp known
+        let minute = match (self.minute, minutes % 60) {
+            (None, 0) => None,
+            (_, minute) => Some(minute),
+        };
+        Self {
+            year,
+            month: Some(month),
+            day: Some(day),
+            hour: u8::try_from(minutes / 60).ok(),
+            minute: minute.and_then(|m| u8::try_from(m).ok()),
+            second: self.second,
+            utc_offset: Some(0),
+        }
+    }
+
+    fn validate(self) -> Option<Self> {
+        let in_range = |value: Option<u8>, range: std::ops::RangeInclusive<u8>| {
+            value.is_none_or(|v| range.contains(&v))
+        };
+        let is_valid = in_range(self.month, 1..=12)
+            && in_range(
+                self.day,
+                1..=self.month.map_or(31, |m| days_in_month(self.year, m)),
+            )
+            && in_range(self.hour, 0..=23)
+            && in_range(self.minute, 0..=59)
+            && in_range(self.second, 0..=59)
+            && self.utc_offset.is_none_or(|o| o.abs() < 24 * 60);
+        is_valid.then_some(self)
+    }
+}
+
+fn days_in_month(year: i32, month: u8) -> u8 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
 }
 
+/// ID3 timestamps have no offset, they are stored as UTC.
+#[cfg(feature = "id3")]
 impl From<Id3Timestamp> for Timestamp {
     fn from(value: Id3Timestamp) -> Self {
         Self {
@@ -135,12 +462,16 @@ impl From<Id3Timestamp> for Timestamp {
             hour: value.hour,
             minute: value.minute,
             second: value.second,
+            utc_offset: None,
         }
     }
 }
 
+/// Timestamps with an offset are converted to UTC, as ID3 can not store the offset.
+#[cfg(feature = "id3")]
 impl From<Timestamp> for Id3Timestamp {
     fn from(value: Timestamp) -> Self {
+        let value = value.to_utc();
         Self {
             year: value.year,
             month: value.month,
@@ -155,15 +486,105 @@ impl From<Timestamp> for Id3Timestamp {
 impl FromStr for Timestamp {
     type Err = Error;
 
+    /// Parses an ISO 8601 timestamp of the form `YYYY[-MM[-DD[THH[:MM[:SS]][offset]]]]`, where
+    /// the offset is either `Z` or `±HH[:MM]`.
     fn from_str(s: &str) -> Result<Self> {
-        Ok(Id3Timestamp::from_str(s)
-            .map_err(|_| Error::TimestampParseError)?
-            .into())
+        fn parse_error() -> Error {
+            Error::from(ErrorKind::TimestampParseError).with_field(Field::Date)
+        }
+
+        fn part<T: FromStr>(part: Option<&str>) -> Result<Option<T>> {
+            part.map(|p| p.trim().parse().map_err(|_| parse_error()))
+                .transpose()
+        }
+
+        fn offset(offset: &str) -> Result<i16> {
+            if offset.eq_ignore_ascii_case("z") {
+                return Ok(0);
+            }
+            let (sign, offset) = offset.split_at(1);
+            let digits = offset.replace(':', "");
+            if !matches!(digits.len(), 2 | 4) || !digits.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(parse_error());
+            }
+            let hours: i16 = digits[..2].parse().map_err(|_| parse_error())?;
+            let minutes: i16 = digits[2..].parse().unwrap_or(0);
+            let offset = hours * 60 + minutes;
+            Ok(if sign == "-" { -offset } else { offset })
+        }
+
+        let s = s.trim();
+        let (date, time) = match s.split_once(['T', ' ']) {
+            Some((date, time)) => (date, Some(time)),
+            None => (s, None),
+        };
+        let (time, utc_offset) = match time.and_then(|t| t.find(['Z', 'z', '+', '-'])) {
+            Some(pos) => {
+                let time = time.unwrap_or_default();
+                (Some(&time[..pos]), Some(offset(time[pos..].trim())?))
+            }
+            None => (time, None),
+        };
+
+        let mut date = date.splitn(3, '-');
+        let year = part(date.next())?.ok_or_else(parse_error)?;
+        let month = part(date.next())?;
+        let day = part(date.next())?;
+
+        let mut time = time.into_iter().flat_map(|t| t.splitn(3, ':'));
+        let hour = part(time.next())?;
+        let minute = part(time.next())?;
+        let second = part(time.next())?;
+
+        if hour.is_some() && day.is_none() || utc_offset.is_some() && hour.is_none() {
+            return Err(parse_error());
+        }
+
+        Self {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+            utc_offset,
+        }
+        .validate()
+        .ok_or_else(parse_error)
     }
 }
 
 impl std::fmt::Display for Timestamp {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", Id3Timestamp::from(*self))
+        write!(f, "{:04}", self.year)?;
+        let Some(month) = self.month else {
+            return Ok(());
+        };
+        write!(f, "-{month:02}")?;
+        let Some(day) = self.day else {
+            return Ok(());
+        };
+        write!(f, "-{day:02}")?;
+        let Some(hour) = self.hour else {
+            return Ok(());
+        };
+        write!(f, "T{hour:02}")?;
+        if let Some(minute) = self.minute {
+            write!(f, ":{minute:02}")?;
+            if let Some(second) = self.second {
+                write!(f, ":{second:02}")?;
+            }
+        }
+        match self.utc_offset {
+            Some(0) => write!(f, "Z"),
+            Some(offset) => write!(
+                f,
+                "{}{:02}:{:02}",
+                if offset < 0 { '-' } else { '+' },
+                offset.abs() / 60,
+                offset.abs() % 60
+            ),
+            None => Ok(()),
+        }
     }
 }
@@ -0,0 +1,223 @@
+//! ID3 tags embedded in DSD containers.
+//!
+//! DSF files store the tag at the end of the file, referenced by a pointer in the `DSD ` header
+//! chunk. DSDIFF (dff) files have no official metadata chunk, but an `ID3 ` chunk inside the
+//! `FRM8` container is widely supported.
+
+use crate::Result;
+use id3::{StorageFile, Tag as Id3InternalTag, Version};
+use std::io::{Cursor, Read, Seek, SeekFrom};
+
+const DSF_MAGIC: &[u8; 4] = b"DSD ";
+const DFF_MAGIC: &[u8; 4] = b"FRM8";
+const DSF_HEADER_LEN: u64 = 28;
+const DFF_HEADER_LEN: u64 = 16;
+const DFF_ID3_CHUNK: &[u8; 4] = b"ID3 ";
+
+/// Writes `tag` into `file`, which may be any format the id3 backend supports.
+pub(crate) fn write_id3(tag: &Id3InternalTag, mut file: impl StorageFile) -> Result<()> {
+    let mut magic = [0u8; 4];
+    let is_long_enough = file.read_exact(&mut magic).is_ok();
+    file.rewind()?;
+
+    match &magic {
+        DSF_MAGIC if is_long_enough => write_dsf(tag, file),
+        DFF_MAGIC if is_long_enough => write_dff(tag, file),
+        _ => Ok(tag.write_to_file(file, Version::Id3v24)?),
+    }
+}
+
+pub(crate) fn read_dsf(mut f_in: impl Read + Seek) -> Result<Id3InternalTag> {
+    let header = read_dsf_header(&mut f_in)?;
+    if header.metadata_offset == 0 {
+        return Ok(Id3InternalTag::new());
+    }
+
+    f_in.seek(SeekFrom::Start(header.metadata_offset))?;
+    read_tag(f_in)
+}
+
+pub(crate) fn read_dff(mut f_in: impl Read + Seek) -> Result<Id3InternalTag> {
+    let Some(chunk) = read_dff_chunks(&mut f_in)?
+        .into_iter()
+        .find(|c| &c.id == DFF_ID3_CHUNK)
+    else {
+        return Ok(Id3InternalTag::new());
+    };
+
+    let mut data =
+        vec![0; usize::try_from(chunk.data_len).map_err(|_| parse_error("ID3 chunk too large"))?];
+    f_in.seek(SeekFrom::Start(chunk.offset + 12))?;
+    f_in.read_exact(&mut data)?;
+    read_tag(Cursor::new(data))
+}
+
+fn read_tag(f_in: impl Read + Seek) -> Result<Id3InternalTag> {
+    match Id3InternalTag::read_from2(f_in) {
+        Err(e) if matches!(e.kind, id3::ErrorKind::NoTag) => Ok(Id3InternalTag::new()),
+        res => Ok(res?),
+    }
+}
+
+fn write_dsf(tag: &Id3InternalTag, mut file: impl StorageFile) -> Result<()> {
+    read_dsf_header(&mut file)?;
+    let audio_end = dsf_audio_end(&mut file)?;
+
+    let mut data = Vec::new();
+    if tag.frames().next().is_some() {
+        tag.write_to(&mut data, Version::Id3v24)?;
+    }
+
+    file.set_len(audio_end)?;
+    file.seek(SeekFrom::Start(audio_end))?;
+    file.write_all(&data)?;
+
+    let metadata_offset = if data.is_empty() { 0 } else { audio_end };
+    file.seek(SeekFrom::Start(12))?;
+    file.write_all(&(audio_end + data.len() as u64).to_le_bytes())?;
+    file.write_all(&metadata_offset.to_le_bytes())?;
+    Ok(())
+}
+
+fn write_dff(tag: &Id3InternalTag, mut file: impl StorageFile) -> Result<()> {
+    // Remove existing tags back to front, so the offsets of the remaining ones stay valid.
+    let chunks = read_dff_chunks(&mut file)?;
+    for chunk in chunks.iter().rev().filter(|c| &c.id == DFF_ID3_CHUNK) {
+        remove_range(&mut file, chunk.offset, chunk.offset + chunk.total_len())?;
+    }
+
+    let mut end = file.seek(SeekFrom::End(0))?;
+    if tag.frames().next().is_some() {
+        let mut data = Vec::new();
+        tag.write_to(&mut data, Version::Id3v24)?;
+        file.write_all(DFF_ID3_CHUNK)?;
+        file.write_all(&(data.len() as u64).to_be_bytes())?;
+        file.write_all(&data)?;
+        if data.len() % 2 == 1 {
+            file.write_all(&[0])?;
+        }
+        end = file.stream_position()?;
+    }
+
+    file.seek(SeekFrom::Start(4))?;
+    file.write_all(&(end - 12).to_be_bytes())?;
+    Ok(())
+}
+
+struct DsfHeader {
+    metadata_offset: u64,
+}
+
+fn read_dsf_header(mut f_in: impl Read) -> Result<DsfHeader> {
+    let mut header = [0u8; 28];
+    f_in.read_exact(&mut header)?;
+    if &header[0..4] != DSF_MAGIC {
+        return Err(parse_error("Missing DSD chunk").into());
+    }
+
+    Ok(DsfHeader {
+        metadata_offset: read_u64_le(&header[20..28]),
+    })
+}
+
+/// Finds the end of the `data` chunk, which is where the tag starts.
+fn dsf_audio_end(mut f_in: impl Read + Seek) -> Result<u64> {
+    let mut offset = DSF_HEADER_LEN;
+    loop {
+        f_in.seek(SeekFrom::Start(offset))?;
+        let mut chunk_header = [0u8; 12];
+        f_in.read_exact(&mut chunk_header)?;
+
+        // DSF chunk sizes include the chunk header
+        let size = read_u64_le(&chunk_header[4..12]);
+        if size < 12 {
+            return Err(parse_error("Invalid DSF chunk size").into());
+        }
+        offset += size;
+        if &chunk_header[0..4] == b"data" {
+            return Ok(offset);
+        }
+    }
+}
+
+struct DffChunk {
+    id: [u8; 4],
+    offset: u64,
+    data_len: u64,
+}
+
+impl DffChunk {
+    /// Length of the chunk including its header and padding.
+    fn total_len(&self) -> u64 {
+        12 + self.data_len + self.data_len % 2
+    }
+}
+
+fn read_dff_chunks(mut f_in: impl Read + Seek) -> Result<Vec<DffChunk>> {
+    let mut header = [0u8; 16];
+    f_in.rewind()?;
+    f_in.read_exact(&mut header)?;
+    if &header[0..4] != DFF_MAGIC || &header[12..16] != b"DSD " {
+        return Err(parse_error("Missing FRM8 DSD container").into());
+    }
+    let file_len = f_in.seek(SeekFrom::End(0))?;
+    let end = (12 + read_u64_be(&header[4..12])).min(file_len);
+
+    let mut chunks = Vec::new();
+    let mut offset = DFF_HEADER_LEN;
+    while offset + 12 <= end {
+        let mut chunk_header = [0u8; 12];
+        f_in.seek(SeekFrom::Start(offset))?;
+        f_in.read_exact(&mut chunk_header)?;
+
+        let chunk = DffChunk {
+            id: [
+                chunk_header[0],
+                chunk_header[1],
+                chunk_header[2],
+                chunk_header[3],
+            ],
+            offset,
+            data_len: read_u64_be(&chunk_header[4..12]),
+        };
+        offset += chunk.total_len();
+        chunks.push(chunk);
+    }
+
+    Ok(chunks)
+}
+
+/// Cuts the bytes in `start..end` out of the file, moving everything after it forward.
+fn remove_range(mut file: impl StorageFile, start: u64, end: u64) -> Result<()> {
+    let mut buf = vec![0u8; 64 * 1024];
+    let (mut src, mut dst) = (end, start);
+    loop {
+        file.seek(SeekFrom::Start(src))?;
+        let read = file.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        file.seek(SeekFrom::Start(dst))?;
+        file.write_all(&buf[..read])?;
+        src += read as u64;
+        dst += read as u64;
+    }
+    file.set_len(dst)?;
+    Ok(())
+}
+
+fn parse_error(description: &str) -> id3::Error {
+    id3::Error::new(id3::ErrorKind::Parsing, description)
+}
+
+fn read_u64_le(bytes: &[u8]) -> u64 {
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(&bytes[..8]);
+    u64::from_le_bytes(buf)
+}
+
+fn read_u64_be(bytes: &[u8]) -> u64 {
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(&bytes[..8]);
+    u64::from_be_bytes(buf)
+}
@@ -0,0 +1,225 @@
+//! The error type of this crate.
+
+use crate::data::{Field, OggCodec};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// Error type.
+///
+/// Wraps an [`ErrorKind`] describing what went wrong together with the context it happened in:
+/// the file path (when known), the field being read or written and the backend in use.
+#[derive(Debug)]
+pub struct Error(Box<ErrorData>);
+
+#[derive(Debug)]
+struct ErrorData {
+    kind: ErrorKind,
+    path: Option<PathBuf>,
+    field: Option<Field>,
+    backend: Option<Backend>,
+}
+
+/// Describes the various errors that this crate could produce.
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// A file does not have a file extension.
+    #[error("Given file does not have a file extension")]
+    NoFileExtension,
+    /// The file *extension* does not contain valid unicode
+    #[error("File extension must be valid unicode")]
+    InvalidFileExtension,
+    /// The format of the specified audio file is not currently supported by this crate.
+    #[error("Unsupported audio format")]
+    UnsupportedAudioFormat,
+    /// The Ogg file contains a stream whose codec is not supported, or whose backend has been
+    /// disabled through its cargo feature.
+    #[error("Unsupported codec in ogg container: {0}")]
+    UnsupportedOggCodec(OggCodec),
+    /// Wrapper around an [`id3::Error`]. See there for more info.
+    #[cfg(feature = "id3")]
+    #[error("{0}")]
+    Id3Error(#[from] id3::Error),
+    /// Wrapper around a [`metaflac::Error`]. See there for more info.
+    #[cfg(feature = "metaflac")]
+    #[error("{0}")]
+    FlacError(#[from] metaflac::Error),
+    /// Wrapper around a [`mp4ameta::Error`]. See there for more info.
+    #[cfg(feature = "mp4ameta")]
+    #[error("{0}")]
+    Mp4Error(#[from] mp4ameta::Error),
+    /// Wrapper around a [`opusmeta::Error`]. See there for more info.
+    #[cfg(feature = "opusmeta")]
+    #[error("{0}")]
+    OpusError(#[from] opusmeta::Error),
+    /// Wrapper around a [`oggmeta::Error`]. See there for more info.
+    #[cfg(feature = "oggmeta")]
+    #[error("{0}")]
+    OggError(#[from] oggmeta::Error),
+    /// Unable to parse a [`Timestamp`](crate::data::Timestamp) from a string.
+    #[error("Unable to parse timestamp from string")]
+    TimestampParseError,
+    /// Specified cover image is not of a valid mime type.
+    /// Supported types are: bmp, jpg, png.
+    #[error("Given cover image data is not of valid type (bmp, jpeg, png)")]
+    InvalidImageFormat,
+    /// An unspecified I/O error occurred.
+    #[error("An I/O error occurred. Please see the contained io::Error for more info.")]
+    IoError(#[from] std::io::Error),
+}
+
+/// The library used to read and write a format.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Backend {
+    Id3,
+    Metaflac,
+    Mp4ameta,
+    Opusmeta,
+    Oggmeta,
+}
+
+impl Error {
+    /// What went wrong.
+    #[must_use]
+    pub fn kind(&self) -> &ErrorKind {
+        &self.0.kind
+    }
+
+    /// Consumes the error, returning the underlying [`ErrorKind`].
+    #[must_use]
+    pub fn into_kind(self) -> ErrorKind {
+        self.0.kind
+    }
+
+    /// The path of the file which was processed, if known.
+    #[must_use]
+    pub fn path(&self) -> Option<&Path> {
+        self.0.path.as_deref()
+    }
+
+    /// The field which was being read or written, if known.
+    #[must_use]
+    pub fn field(&self) -> Option<&Field> {
+        self.0.field.as_ref()
+    }
+
+    /// The backend which was in use, if known.
+    #[must_use]
+    pub fn backend(&self) -> Option<Backend> {
+        self.0.backend
+    }
+
+    /// Attaches the file path to the error, unless one is already set.
+    #[must_use]
+    pub fn with_path(mut self, path: impl AsRef<Path>) -> Self {
+        self.0
+            .path
+            .get_or_insert_with(|| path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Attaches the field to the error, unless one is already set.
+    #[must_use]
+    pub fn with_field(mut self, field: Field) -> Self {
+        self.0.field.get_or_insert(field);
+        self
+    }
+
+    /// Attaches the backend to the error, unless one is already set.
+    #[must_use]
+    pub fn with_backend(mut self, backend: Backend) -> Self {
+        self.0.backend.get_or_insert(backend);
+        self
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0.kind)?;
+
+        let mut context = Vec::new();
+        if let Some(path) = &self.0.path {
+            context.push(format!("path: {}", path.display()));
+        }
+        if let Some(field) = &self.0.field {
+            context.push(format!("field: {field}"));
+        }
+        if let Some(backend) = self.0.backend {
+            context.push(format!("backend: {backend}"));
+        }
+        if !context.is_empty() {
+            write!(f, " ({})", context.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        std::error::Error::source(&self.0.kind)
+    }
+}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Self {
+        let backend = match &kind {
+            #[cfg(feature = "id3")]
+            ErrorKind::Id3Error(_) => Some(Backend::Id3),
+            #[cfg(feature = "metaflac")]
+            ErrorKind::FlacError(_) => Some(Backend::Metaflac),
+            #[cfg(feature = "mp4ameta")]
+            ErrorKind::Mp4Error(_) => Some(Backend::Mp4ameta),
+            #[cfg(feature = "opusmeta")]
+            ErrorKind::OpusError(_) => Some(Backend::Opusmeta),
+            #[cfg(feature = "oggmeta")]
+            ErrorKind::OggError(_) => Some(Backend::Oggmeta),
+            _ => None,
+        };
+
+        Self(Box::new(ErrorData {
+            kind,
+            path: None,
+            field: None,
+            backend,
+        }))
+    }
+}
+
+macro_rules! impl_from_source {
+    ($($(#[$attr:meta])* $source:ty),* $(,)?) => {
+        $(
+            $(#[$attr])*
+            impl From<$source> for Error {
+                fn from(value: $source) -> Self {
+                    ErrorKind::from(value).into()
+                }
+            }
+        )*
+    };
+}
+
+impl_from_source!(
+    #[cfg(feature = "id3")]
+    id3::Error,
+    #[cfg(feature = "metaflac")]
+    metaflac::Error,
+    #[cfg(feature = "mp4ameta")]
+    mp4ameta::Error,
+    #[cfg(feature = "opusmeta")]
+    opusmeta::Error,
+    #[cfg(feature = "oggmeta")]
+    oggmeta::Error,
+    std::io::Error,
+);
+
+impl std::fmt::Display for Backend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Id3 => "id3",
+            Self::Metaflac => "metaflac",
+            Self::Mp4ameta => "mp4ameta",
+            Self::Opusmeta => "opusmeta",
+            Self::Oggmeta => "oggmeta",
+        })
+    }
+}
@@ -0,0 +1,320 @@
+//! Lenient reading, which recovers as much as possible from malformed tags.
+
+use crate::data::SkippedItem;
+use crate::{Backend, ErrorKind, Result, Tag};
+use std::fs::OpenOptions;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+impl Tag {
+    /// Like [`Tag::read_from_path`], but recovers from malformed tags instead of failing.
+    ///
+    /// See [`Tag::read_from_lenient`] for details.
+    ///
+    /// # Errors
+    /// This function errors if the path has no valid extension, the format is not supported or
+    /// the file cannot be opened.
+    pub fn read_from_path_lenient<P: AsRef<Path>>(path: P) -> Result<(Self, Vec<SkippedItem>)> {
+        let path = path.as_ref();
+        Self::read_from_path_lenient_impl(path).map_err(|e| e.with_path(path))
+    }
+
+    fn read_from_path_lenient_impl(path: &Path) -> Result<(Self, Vec<SkippedItem>)> {
+        let extension = path
+            .extension()
+            .ok_or(ErrorKind::NoFileExtension)?
+            .to_str()
+            .ok_or(ErrorKind::InvalidFileExtension)?;
+
+        let file = OpenOptions::new().read(true).open(path)?;
+        Tag::read_from_lenient(extension, file)
+    }
+
+    /// Like [`Tag::read_from`], but recovers as many items as possible from malformed tags
+    /// instead of failing the whole read. Every item which had to be dropped is reported in the
+    /// returned list, which is empty if the tag could be read normally.
+    ///
+    /// # Format-specific
+    /// For id3 each frame is decoded on its own, so a broken frame (bad frame size, truncated
+    /// picture, ...) only drops that frame. Text frames with invalid encodings are decoded lossily.
+    /// For mp4 a failing read is retried without the artwork. For all other formats a malformed
+    /// tag is replaced by an empty one.
+    ///
+    /// # Errors
+    /// This function errors if the extension is not supported or the reader fails.
+    pub fn read_from_lenient<R: Read + Seek>(
+        extension: &str,
+        mut f_in: R,
+    ) -> Result<(Self, Vec<SkippedItem>)> {
+        let start = f_in.stream_position()?;
+        let err = match Self::read_from(extension, &mut f_in) {
+            Ok(tag) => return Ok((tag, Vec::new())),
+            Err(err) => err,
+        };
+
+        let Some(backend) = err.backend() else {
+            return Err(err);
+        };
+        f_in.seek(SeekFrom::Start(start))?;
+
+        match backend {
+            #[cfg(feature = "id3")]
+            Backend::Id3 => id3_lenient::recover(f_in, err),
+            #[cfg(feature = "mp4ameta")]
+            Backend::Mp4ameta => {
+                let cfg = mp4ameta::ReadConfig {
+                    read_image_data: false,
+                    ..mp4ameta::ReadConfig::DEFAULT
+                };
+                let mut inner = mp4ameta::Tag::read_with(&mut f_in, &cfg).unwrap_or_default();
+                inner.remove_artworks();
+                Ok((
+                    Self::Mp4Tag { inner },
+                    vec![SkippedItem::new("artwork", &err)],
+                ))
+            }
+            #[cfg(feature = "metaflac")]
+            Backend::Metaflac => Ok((Self::new_empty_flac(), vec![SkippedItem::new("tag", &err)])),
+            #[cfg(feature = "opusmeta")]
+            Backend::Opusmeta => Ok((Self::new_empty_opus(), vec![SkippedItem::new("tag", &err)])),
+            #[cfg(feature = "oggmeta")]
+            Backend::Oggmeta => {
+                let inner = oggmeta::Tag::default();
+                Ok((Self::OggTag { inner }, vec![SkippedItem::new("tag", &err)]))
+            }
+            #[allow(unreachable_patterns)]
+            _ => Err(err),
+        }
+    }
+}
+
+#[cfg(feature = "id3")]
+mod id3_lenient {
+    use super::{Read, Result, SkippedItem, Tag};
+    use crate::Error;
+    use id3::frame::Frame;
+    use id3::{Tag as Id3InternalTag, TagLike, Version};
+    use std::io::Cursor;
+
+    const HEADER_LEN: usize = 10;
+    const FLAG_UNSYNCHRONISATION: u8 = 0x80;
+    const FLAG_EXTENDED_HEADER: u8 = 0x40;
+
+    /// Walks the frames of an ID3v2.3/2.4 tag by hand and decodes each one on its own.
+    pub(super) fn recover(mut f_in: impl Read, err: Error) -> Result<(Tag, Vec<SkippedItem>)> {
+        let partial = match err.into_kind() {
+            crate::ErrorKind::Id3Error(id3_err) => Some(id3_err),
+            _ => None,
+        };
+        let fallback = |reason: &str, skipped: &mut Vec<SkippedItem>| {
+            let partial_tag = partial.as_ref().and_then(|e| e.partial_tag.clone());
+            let item = if partial_tag.is_some() {
+                "remaining frames"
+            } else {
+                "tag"
+            };
+            skipped.push(SkippedItem::new(item, &reason));
+            partial_tag.unwrap_or_default()
+        };
+        let id3_reason = partial
+            .as_ref()
+            .map_or_else(String::new, ToString::to_string);
+
+        let mut skipped = Vec::new();
+        let mut header = [0u8; HEADER_LEN];
+        if f_in.read_exact(&mut header).is_err() || &header[0..3] != b"ID3" {
+            let inner = fallback(&id3_reason, &mut skipped);
+            return Ok((Tag::Id3Tag { inner }, skipped));
+        }
+
+        let version = header[3];
+        let flags = header[5];
+        if !matches!(version, 3 | 4) || flags & FLAG_UNSYNCHRONISATION != 0 {
+            let inner = fallback(&id3_reason, &mut skipped);
+            return Ok((Tag::Id3Tag { inner }, skipped));
+        }
+
+        let tag_size = decode_syncsafe(&header[6..10]);
+        let mut data = Vec::new();
+        f_in.take(u64::from(tag_size)).read_to_end(&mut data)?;
+        if data.len() < tag_size as usize {
+            skipped.push(SkippedItem {
+                item: "tag".into(),
+                reason: format!("tag truncated to {} of {tag_size} bytes", data.len()),
+            });
+        }
+
+        let mut pos = 0;
+        if flags & FLAG_EXTENDED_HEADER != 0 && data.len() >= 4 {
+            pos = if version == 4 {
+                decode_syncsafe(&data[0..4]) as usize
+            } else {
+                read_u32(&data[0..4]) as usize + 4
+            };
+        }
+
+        let mut inner = Id3InternalTag::with_version(if version == 4 {
+            Version::Id3v24
+        } else {
+            Version::Id3v23
+        });
+
+        while pos + HEADER_LEN <= data.len() {
+            let id = &data[pos..pos + 4];
+            if id[0] == 0 {
+                break; // padding
+            }
+            let id_str = String::from_utf8_lossy(id).into_owned();
+            if !is_frame_id(id) {
+                skipped.push(SkippedItem {
+                    item: format!("data at offset {pos}"),
+                    reason: "invalid frame id, stopped parsing".into(),
+                });
+                break;
+            }
+
+            let size = frame_size(version, &data, pos);
+            let end = pos + HEADER_LEN + size;
+            if end > data.len() {
+                skipped.push(SkippedItem {
+                    item: id_str,
+                    reason: format!("frame size of {size} bytes exceeds the tag"),
+                });
+                break;
+            }
+
+            let frame_flags = &data[pos + 8..pos + HEADER_LEN];
+            let body = &data[pos + HEADER_LEN..end];
+            match decode_frame(version, id, frame_flags, body) {
+                Ok(frames) => {
+                    for frame in frames {
+                        inner.add_frame(frame);
+                    }
+                }
+                Err(frame_err) => {
+                    if let Some(frame) = lossy_text_frame(&id_str, body) {
+                        inner.add_frame(frame);
+                    } else {
+                        skipped.push(SkippedItem {
+                            item: id_str,
+                            reason: frame_err.to_string(),
+                        });
+                    }
+                }
+            }
+            pos = end;
+        }
+
+        Ok((Tag::Id3Tag { inner }, skipped))
+    }
+
+    fn is_frame_id(id: &[u8]) -> bool {
+        id.iter()
+            .all(|b| b.is_ascii_uppercase() || b.is_ascii_digit())
+    }
+
+    /// Whether `pos` is a plausible place for the next frame to start.
+    fn is_boundary(data: &[u8], pos: usize) -> bool {
+        pos == data.len()
+            || data.get(pos) == Some(&0)
+            || data.get(pos..pos + 4).is_some_and(is_frame_id)
+    }
+
+    /// Determines the frame size, accounting for the common mistake of writers using plain
+    /// integers in v2.4 tags or syncsafe integers in v2.3 tags.
+    fn frame_size(version: u8, data: &[u8], pos: usize) -> usize {
+        let raw_bytes = &data[pos + 4..pos + 8];
+        let raw = read_u32(raw_bytes) as usize;
+        let is_syncsafe = raw_bytes.iter().all(|b| b & 0x80 == 0);
+        let syncsafe = decode_syncsafe(raw_bytes) as usize;
+
+        let (preferred, alternative) = if version == 4 && is_syncsafe {
+            (syncsafe, raw)
+        } else {
+            (raw, syncsafe)
+        };
+        let fits = |size: usize| is_boundary(data, pos + HEADER_LEN + size);
+        if !fits(preferred) && is_syncsafe && fits(alternative) {
+            alternative
+        } else {
+            preferred
+        }
+    }
+
+    /// Decodes a single frame by wrapping it into a tag of its own.
+    fn decode_frame(
+        version: u8,
+        id: &[u8],
+        frame_flags: &[u8],
+        body: &[u8],
+    ) -> id3::Result<Vec<Frame>> {
+        let body_len = u32::try_from(body.len()).unwrap_or(u32::MAX);
+        let mut buf = Vec::with_capacity(2 * HEADER_LEN + body.len());
+        buf.extend_from_slice(b"ID3");
+        buf.extend_from_slice(&[version, 0, 0]);
+        buf.extend_from_slice(&encode_syncsafe(body_len.saturating_add(10)));
+        buf.extend_from_slice(id);
+        if version == 4 {
+            buf.extend_from_slice(&encode_syncsafe(body_len));
+        } else {
+            buf.extend_from_slice(&body_len.to_be_bytes());
+        }
+        buf.extend_from_slice(frame_flags);
+        buf.extend_from_slice(body);
+
+        let tag = Id3InternalTag::read_from2(Cursor::new(buf))?;
+        Ok(tag.frames().cloned().collect())
+    }
+
+    /// Decodes a text frame, replacing invalid characters instead of failing.
+    fn lossy_text_frame(id: &str, body: &[u8]) -> Option<Frame> {
+        if !id.starts_with('T') || id == "TXXX" {
+            return None;
+        }
+        let (&encoding, text) = body.split_first()?;
+        let text = match encoding {
+            0 => text.iter().map(|&b| char::from(b)).collect(),
+            1 | 2 => {
+                let big_endian = encoding == 2 || text.starts_with(&[0xFE, 0xFF]);
+                let text = text
+                    .strip_prefix(&[0xFE, 0xFF])
+                    .or_else(|| text.strip_prefix(&[0xFF, 0xFE]))
+                    .unwrap_or(text);
+                let units: Vec<u16> = text
+                    .chunks_exact(2)
+                    .map(|c| {
+                        if big_endian {
+                            u16::from_be_bytes([c[0], c[1]])
+                        } else {
+                            u16::from_le_bytes([c[0], c[1]])
+                        }
+                    })
+                    .collect();
+                String::from_utf16_lossy(&units)
+            }
+            3 => String::from_utf8_lossy(text).into_owned(),
+            _ => return None,
+        };
+        Some(Frame::text(id, text.trim_end_matches('\0')))
+    }
+
+    fn read_u32(bytes: &[u8]) -> u32 {
+        u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+    }
+
+    fn decode_syncsafe(bytes: &[u8]) -> u32 {
+        bytes
+            .iter()
+            .take(4)
+            .fold(0, |acc, &b| (acc << 7) | u32::from(b & 0x7F))
+    }
+
+    fn encode_syncsafe(value: u32) -> [u8; 4] {
+        [
+            ((value >> 21) & 0x7F) as u8,
+            ((value >> 14) & 0x7F) as u8,
+            ((value >> 7) & 0x7F) as u8,
+            (value & 0x7F) as u8,
+        ]
+    }
+}
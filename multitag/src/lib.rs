@@ -1,80 +1,239 @@
 #![doc = include_str!("../README.md")]
 
 pub mod data;
-
-use data::{Album, Picture, Timestamp};
+#[cfg(feature = "id3")]
+mod dsd;
+mod error;
+mod lenient;
+#[cfg(any(feature = "opusmeta", feature = "oggmeta"))]
+mod ogg;
+#[cfg(all(
+    test,
+    any(
+        feature = "id3",
+        feature = "metaflac",
+        feature = "mp4ameta",
+        feature = "opusmeta"
+    )
+))]
+mod testdata;
+
+#[cfg(not(any(
+    feature = "id3",
+    feature = "metaflac",
+    feature = "mp4ameta",
+    feature = "opusmeta",
+    feature = "oggmeta"
+)))]
+compile_error!("multitag requires at least one format feature to be enabled");
+
+#[cfg(any(feature = "opusmeta", feature = "oggmeta"))]
+use data::OggCodec;
+use data::{Album, Field, Picture, Timestamp};
+pub use error::{Backend, Error, ErrorKind};
+#[cfg(feature = "id3")]
 use id3::Tag as Id3InternalTag;
+#[cfg(feature = "id3")]
 use id3::TagLike;
+#[cfg(feature = "metaflac")]
 use metaflac::Tag as FlacInternalTag;
+#[cfg(feature = "mp4ameta")]
 use mp4ameta::Data as Mp4Data;
+#[cfg(feature = "mp4ameta")]
+use mp4ameta::DataIdent as Mp4DataIdent;
+#[cfg(feature = "mp4ameta")]
 use mp4ameta::Fourcc as Mp4Fourcc;
+#[cfg(feature = "mp4ameta")]
 use mp4ameta::FreeformIdent;
+#[cfg(feature = "mp4ameta")]
 use mp4ameta::Ident as Mp4Ident;
+#[cfg(feature = "mp4ameta")]
 use mp4ameta::Tag as Mp4InternalTag;
+#[cfg(feature = "oggmeta")]
 use oggmeta::Tag as OggInternalTag;
+#[cfg(feature = "opusmeta")]
 use opusmeta::LowercaseString;
+#[cfg(feature = "opusmeta")]
 use opusmeta::Tag as OpusInternalTag;
+#[cfg(feature = "metaflac")]
 use std::collections::hash_map::Entry;
-use std::convert::Into;
 use std::fs::{File, OpenOptions};
 use std::io::Cursor;
-use std::io::{Read, Seek, Write};
+#[cfg(feature = "metaflac")]
+use std::io::Write;
+use std::io::{Read, Seek};
 use std::path::Path;
+#[cfg(any(
+    feature = "metaflac",
+    feature = "mp4ameta",
+    feature = "opusmeta",
+    feature = "oggmeta"
+))]
 use std::str::FromStr;
-use thiserror::Error;
 
+#[cfg(feature = "mp4ameta")]
 const DATE_FOURCC: Mp4Fourcc = Mp4Fourcc([169, 100, 97, 121]);
 
-/// Error type.
-///
-/// Describes various errors that this crate could produce.
-#[derive(Error, Debug)]
-#[non_exhaustive]
-pub enum Error {
-    /// A file does not have a file extension.
-    #[error("Given file does not have a file extension")]
-    NoFileExtension,
-    /// The file *extension* does not contain valid unicode
-    #[error("File extension must be valid unicode")]
-    InvalidFileExtension,
-    /// The format of the specified audio file is not currently supported by this crate.
-    #[error("Unsupported audio format")]
-    UnsupportedAudioFormat,
-    /// Wrapper around an [`id3::Error`]. See there for more info.
-    #[error("{0}")]
-    Id3Error(#[from] id3::Error),
-    /// Wrapper around a [`metaflac::Error`]. See there for more info.
-    #[error("{0}")]
-    FlacError(#[from] metaflac::Error),
-    /// Wrapper around a [`mp4ameta::Error`]. See there for more info.
-    #[error("{0}")]
-    Mp4Error(#[from] mp4ameta::Error),
-    /// Wrapper around a [`opusmeta::Error`]. See there for more info.
-    #[error("{0}")]
-    OpusError(#[from] opusmeta::Error),
-    /// Wrapper around a [`oggmeta::Error`]. See there for more info.
-    #[error("{0}")]
-    OggError(#[from] oggmeta::Error),
-    /// Unable to parse a [`Timestamp`] from a string.
-    #[error("Unable to parse timestamp from string")]
-    TimestampParseError,
-    /// Specified cover image is not of a valid mime type.
-    /// Supported types are: bmp, jpg, png.
-    #[error("Given cover image data is not of valid type (bmp, jpeg, png)")]
-    InvalidImageFormat,
-    /// An unspecified I/O error occurred.
-    #[error("An I/O error occurred. Please see the contained io::Error for more info.")]
-    IoError(#[from] std::io::Error),
+/// Vorbis comment keys which are exposed through dedicated accessors instead of as comments.
+#[cfg(any(feature = "metaflac", feature = "opusmeta", feature = "oggmeta"))]
+const VORBIS_FIELD_KEYS: &[&str] = &[
+    "TITLE",
+    "ARTIST",
+    "ALBUM",
+    "ALBUMARTIST",
+    "ALBUM ARTIST",
+    "ALBUM_ARTIST",
+    "DATE",
+    "GENRE",
+    "LYRICS",
+    "ENCODER",
+    "COPYRIGHT",
+    "WEBSITE",
+    "PURL",
+    "TRACKTOTAL",
+    "PODCAST",
+    "PODCASTURL",
+    "PODCASTID",
+    "CATEGORY",
+];
+
+/// id3 frames which are exposed through dedicated accessors or as comments (`TXXX`).
+/// `APIC` is only modeled for the front cover.
+#[cfg(feature = "id3")]
+const ID3_FIELD_FRAMES: &[&str] = &[
+    "TIT2", "TPE1", "TALB", "TPE2", "TDRL", "TCON", "USLT", "TSSE", "TCOP", "WOAR", "WOAS", "TRCK",
+    "PCST", "WFED", "TGID", "TCAT", "TXXX",
+];
+
+/// mp4 atoms which are exposed through dedicated accessors. Freeform atoms in the
+/// `com.apple.iTunes` mean are exposed as comments.
+#[cfg(feature = "mp4ameta")]
+const MP4_FIELD_FOURCCS: &[Mp4Fourcc] = &[
+    mp4ameta::ident::TITLE,
+    mp4ameta::ident::ARTIST,
+    mp4ameta::ident::ALBUM,
+    mp4ameta::ident::ALBUM_ARTIST,
+    mp4ameta::ident::ARTWORK,
+    DATE_FOURCC,
+    mp4ameta::ident::STANDARD_GENRE,
+    mp4ameta::ident::CUSTOM_GENRE,
+    mp4ameta::ident::LYRICS,
+    mp4ameta::ident::ENCODER,
+    mp4ameta::ident::COPYRIGHT,
+    mp4ameta::ident::PODCAST_URL,
+    mp4ameta::ident::TRACK_NUMBER,
+    mp4ameta::ident::PODCAST,
+    mp4ameta::ident::PODCAST_EPISODE_GLOBAL_UNIQUE_ID,
+    mp4ameta::ident::CATEGORY,
+];
+
+/// Freeform mp4 names (in the `com.apple.iTunes` mean) which are exposed through dedicated
+/// accessors instead of as comments.
+#[cfg(feature = "mp4ameta")]
+const MP4_FREEFORM_FIELD_NAMES: &[&str] = &["WEBSITE"];
+
+/// Where a plain text field is stored in each format.
+struct TextField {
+    /// id3 frame id. Ids starting with `W` are stored as url frames.
+    #[cfg(feature = "id3")]
+    id3: &'static str,
+    #[cfg(any(feature = "metaflac", feature = "opusmeta", feature = "oggmeta"))]
+    vorbis: &'static str,
+    #[cfg(feature = "mp4ameta")]
+    mp4: Mp4DataIdent,
 }
 
+const ENCODER_FIELD: TextField = TextField {
+    #[cfg(feature = "id3")]
+    id3: "TSSE",
+    #[cfg(any(feature = "metaflac", feature = "opusmeta", feature = "oggmeta"))]
+    vorbis: "ENCODER",
+    #[cfg(feature = "mp4ameta")]
+    mp4: Mp4DataIdent::Fourcc(mp4ameta::ident::ENCODER),
+};
+
+const COPYRIGHT_FIELD: TextField = TextField {
+    #[cfg(feature = "id3")]
+    id3: "TCOP",
+    #[cfg(any(feature = "metaflac", feature = "opusmeta", feature = "oggmeta"))]
+    vorbis: "COPYRIGHT",
+    #[cfg(feature = "mp4ameta")]
+    mp4: Mp4DataIdent::Fourcc(mp4ameta::ident::COPYRIGHT),
+};
+
+const ARTIST_URL_FIELD: TextField = TextField {
+    #[cfg(feature = "id3")]
+    id3: "WOAR",
+    #[cfg(any(feature = "metaflac", feature = "opusmeta", feature = "oggmeta"))]
+    vorbis: "WEBSITE",
+    #[cfg(feature = "mp4ameta")]
+    mp4: Mp4DataIdent::Freeform {
+        mean: std::borrow::Cow::Borrowed("com.apple.iTunes"),
+        name: std::borrow::Cow::Borrowed("WEBSITE"),
+    },
+};
+
+const SOURCE_URL_FIELD: TextField = TextField {
+    #[cfg(feature = "id3")]
+    id3: "WOAS",
+    #[cfg(any(feature = "metaflac", feature = "opusmeta", feature = "oggmeta"))]
+    vorbis: "PURL",
+    #[cfg(feature = "mp4ameta")]
+    mp4: Mp4DataIdent::Fourcc(mp4ameta::ident::PODCAST_URL),
+};
+
+/// Only the location of the podcast flag, it is not stored as text in id3 and mp4.
+const PODCAST_FIELD: TextField = TextField {
+    #[cfg(feature = "id3")]
+    id3: "PCST",
+    #[cfg(any(feature = "metaflac", feature = "opusmeta", feature = "oggmeta"))]
+    vorbis: "PODCAST",
+    #[cfg(feature = "mp4ameta")]
+    mp4: Mp4DataIdent::Fourcc(mp4ameta::ident::PODCAST),
+};
+
+const FEED_URL_FIELD: TextField = TextField {
+    #[cfg(feature = "id3")]
+    id3: "WFED",
+    #[cfg(any(feature = "metaflac", feature = "opusmeta", feature = "oggmeta"))]
+    vorbis: "PODCASTURL",
+    #[cfg(feature = "mp4ameta")]
+    mp4: Mp4DataIdent::Fourcc(mp4ameta::ident::PODCAST_URL),
+};
+
+const EPISODE_ID_FIELD: TextField = TextField {
+    #[cfg(feature = "id3")]
+    id3: "TGID",
+    #[cfg(any(feature = "metaflac", feature = "opusmeta", feature = "oggmeta"))]
+    vorbis: "PODCASTID",
+    #[cfg(feature = "mp4ameta")]
+    mp4: Mp4DataIdent::Fourcc(mp4ameta::ident::PODCAST_EPISODE_GLOBAL_UNIQUE_ID),
+};
+
+const CATEGORY_FIELD: TextField = TextField {
+    #[cfg(feature = "id3")]
+    id3: "TCAT",
+    #[cfg(any(feature = "metaflac", feature = "opusmeta", feature = "oggmeta"))]
+    vorbis: "CATEGORY",
+    #[cfg(feature = "mp4ameta")]
+    mp4: Mp4DataIdent::Fourcc(mp4ameta::ident::CATEGORY),
+};
+
 pub type Result<T> = std::result::Result<T, Error>;
 
 /// An object containing tags of one of the supported formats.
+///
+/// Each variant is only available when the cargo feature of its backend is enabled.
 pub enum Tag {
+    #[cfg(feature = "id3")]
     Id3Tag { inner: Id3InternalTag },
+    #[cfg(feature = "metaflac")]
     VorbisFlacTag { inner: FlacInternalTag },
+    #[cfg(feature = "mp4ameta")]
     Mp4Tag { inner: Mp4InternalTag },
+    #[cfg(feature = "opusmeta")]
     OpusTag { inner: OpusInternalTag },
+    #[cfg(feature = "oggmeta")]
     OggTag { inner: OggInternalTag },
 }
 
@@ -92,11 +251,15 @@ impl Tag {
     /// reason other than missing tags.
     pub fn read_from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
         let path = path.as_ref();
+        Self::read_from_path_impl(path).map_err(|e| e.with_path(path))
+    }
+
+    fn read_from_path_impl(path: &Path) -> Result<Self> {
         let extension = path
             .extension()
-            .ok_or(Error::NoFileExtension)?
+            .ok_or(ErrorKind::NoFileExtension)?
             .to_str()
-            .ok_or(Error::InvalidFileExtension)?;
+            .ok_or(ErrorKind::InvalidFileExtension)?;
 
         let file = OpenOptions::new().read(true).open(path)?;
         Tag::read_from(extension, file)
@@ -104,17 +267,23 @@ impl Tag {
 
     /// Attempts to read a set of tags from the given reader.
     /// The extension is necessary to determine which backend to use to decode the tags.
-    /// `extension` must be one of `[mp3, wav, aiff, flac, mp4, m4a, m4p, m4b, m4r, m4v, opus]`
+    /// `extension` must be one of `[mp3, wav, aiff, dsf, dff, flac, mp4, m4a, m4p, m4b, m4r, m4v, opus, ogg]`
+    ///
+    /// For `ogg` the backend is picked from the codec of the stream, see [`Tag::detect_ogg_codec`].
+    /// Ogg files containing opus are read as opus tags; flac streams in Ogg are not supported.
     ///
     /// # Errors
-    /// This function can error if the given extension is not supported by this crate.
+    /// This function can error if the given extension is not supported by this crate, or if the
+    /// backend handling it has been disabled through its cargo feature.
     ///
     /// Lastly, an error will be raised if the file type is supported but the reading the tags fails for some
     /// reason other than missing tags.
     /// This could be, for example, that the given reader ended too early or that the tags were
     /// encoded improperly. Please inspect the debug output of the error for more information.
+    #[allow(unused_mut)]
     pub fn read_from<R: Read + Seek>(extension: &str, mut f_in: R) -> Result<Self> {
         match extension {
+            #[cfg(feature = "id3")]
             "mp3" | "wav" | "aiff" => {
                 let res = Id3InternalTag::read_from2(f_in);
                 if res
@@ -127,10 +296,20 @@ impl Tag {
                 }
                 Ok(Self::Id3Tag { inner: res? })
             }
+            #[cfg(feature = "id3")]
+            "dsf" => Ok(Self::Id3Tag {
+                inner: dsd::read_dsf(f_in)?,
+            }),
+            #[cfg(feature = "id3")]
+            "dff" => Ok(Self::Id3Tag {
+                inner: dsd::read_dff(f_in)?,
+            }),
+            #[cfg(feature = "metaflac")]
             "flac" => {
                 let inner = FlacInternalTag::read_from(&mut f_in)?;
                 Ok(Self::VorbisFlacTag { inner })
             }
+            #[cfg(feature = "mp4ameta")]
             "mp4" | "m4a" | "m4p" | "m4b" | "m4r" | "m4v" => {
                 let res = Mp4InternalTag::read_from(&mut f_in);
                 if res
@@ -143,28 +322,114 @@ impl Tag {
                 }
                 Ok(Self::Mp4Tag { inner: res? })
             }
+            #[cfg(feature = "opusmeta")]
             "opus" => {
                 let inner = OpusInternalTag::read_from(f_in)?;
                 Ok(Self::OpusTag { inner })
             }
-            "ogg" => {
-                let inner = OggInternalTag::read_from(&mut f_in)?;
-                Ok(Self::OggTag { inner })
-            }
-            _ => Err(Error::UnsupportedAudioFormat),
+            #[cfg(any(feature = "opusmeta", feature = "oggmeta"))]
+            "ogg" => match Self::detect_ogg_codec(&mut f_in)? {
+                #[cfg(feature = "opusmeta")]
+                OggCodec::Opus => {
+                    let inner = OpusInternalTag::read_from(f_in)?;
+                    Ok(Self::OpusTag { inner })
+                }
+                #[cfg(feature = "oggmeta")]
+                OggCodec::Vorbis | OggCodec::Theora => {
+                    let inner = OggInternalTag::read_from(&mut f_in)?;
+                    Ok(Self::OggTag { inner })
+                }
+                codec => Err(ErrorKind::UnsupportedOggCodec(codec).into()),
+            },
+            _ => Err(ErrorKind::UnsupportedAudioFormat.into()),
+        }
+    }
+
+    /// Returns whether files with the given extension can be tagged by one of the enabled backends.
+    ///
+    /// For `ogg` this only says that some Ogg codecs are supported, use [`Tag::check_supported`]
+    /// to check the codec of an actual file.
+    #[must_use]
+    pub fn supports_extension(extension: &str) -> bool {
+        match extension {
+            #[cfg(feature = "id3")]
+            "mp3" | "wav" | "aiff" | "dsf" | "dff" => true,
+            #[cfg(feature = "metaflac")]
+            "flac" => true,
+            #[cfg(feature = "mp4ameta")]
+            "mp4" | "m4a" | "m4p" | "m4b" | "m4r" | "m4v" => true,
+            #[cfg(feature = "opusmeta")]
+            "opus" => true,
+            #[cfg(any(feature = "opusmeta", feature = "oggmeta"))]
+            "ogg" => true,
+            _ => false,
+        }
+    }
+
+    /// Checks whether the file at the given path can be tagged, without parsing its tags.
+    ///
+    /// This allows callers to find out about unsupported containers before doing any other work
+    /// on the file. Only the codec of `ogg` files is inspected, all other formats are decided by
+    /// their extension.
+    ///
+    /// # Errors
+    /// This function errors with the same kinds as [`Tag::read_from_path`] for missing or
+    /// unsupported extensions, and with [`ErrorKind::UnsupportedOggCodec`] for Ogg streams no
+    /// enabled backend can handle.
+    pub fn check_supported<P: AsRef<Path>>(path: P) -> Result<()> {
+        let path = path.as_ref();
+        Self::check_supported_impl(path).map_err(|e| e.with_path(path))
+    }
+
+    fn check_supported_impl(path: &Path) -> Result<()> {
+        let extension = path
+            .extension()
+            .ok_or(ErrorKind::NoFileExtension)?
+            .to_str()
+            .ok_or(ErrorKind::InvalidFileExtension)?;
+        if !Self::supports_extension(extension) {
+            return Err(ErrorKind::UnsupportedAudioFormat.into());
+        }
+
+        #[cfg(any(feature = "opusmeta", feature = "oggmeta"))]
+        if extension == "ogg" {
+            let file = OpenOptions::new().read(true).open(path)?;
+            return match Self::detect_ogg_codec(file)? {
+                #[cfg(feature = "opusmeta")]
+                OggCodec::Opus => Ok(()),
+                #[cfg(feature = "oggmeta")]
+                OggCodec::Vorbis | OggCodec::Theora => Ok(()),
+                codec => Err(ErrorKind::UnsupportedOggCodec(codec).into()),
+            };
         }
+        Ok(())
     }
 
     /// Attempts to write the tags to the indicated path.
     /// # Errors
     /// This function will error if writing the tags fails in any way.
     pub fn write_to_path<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let backend = self.backend();
+        self.write_to_path_impl(path)
+            .map_err(|e| e.with_path(path).with_backend(backend))
+    }
+
+    fn write_to_path_impl(&mut self, path: &Path) -> Result<()> {
         match self {
-            Self::Id3Tag { inner } => inner.write_to_path(path, id3::Version::Id3v24)?,
+            #[cfg(feature = "id3")]
+            Self::Id3Tag { inner } => {
+                let file = OpenOptions::new().read(true).write(true).open(path)?;
+                dsd::write_id3(inner, file)?;
+            }
+            #[cfg(feature = "metaflac")]
             Self::VorbisFlacTag { inner } => inner.write_to_path(path)?,
+            #[cfg(feature = "mp4ameta")]
             Self::Mp4Tag { inner } => inner.write_to_path(path)?,
+            #[cfg(feature = "opusmeta")]
             Self::OpusTag { inner } => inner.write_to_path(path)?,
-            Self::OggTag { inner } => inner.write_to_path(&path)?,
+            #[cfg(feature = "oggmeta")]
+            Self::OggTag { inner } => inner.write_to_path(path)?,
         }
         Ok(())
     }
@@ -179,8 +444,16 @@ impl Tag {
     /// This method can error if writing the tags fails, or if accessing the file fails (for
     /// example, if the modes are set wrong).
     pub fn write_to_file(&mut self, file: &mut File) -> Result<()> {
+        let backend = self.backend();
+        self.write_to_file_impl(file)
+            .map_err(|e| e.with_backend(backend))
+    }
+
+    fn write_to_file_impl(&mut self, file: &mut File) -> Result<()> {
         match self {
-            Self::Id3Tag { inner } => inner.write_to_file(file, id3::Version::Id3v24)?,
+            #[cfg(feature = "id3")]
+            Self::Id3Tag { inner } => dsd::write_id3(inner, file)?,
+            #[cfg(feature = "metaflac")]
             Self::VorbisFlacTag { inner } => {
                 // this is needed because metaflac doesn't provide a clean way to write without a
                 // path
@@ -199,8 +472,11 @@ impl Tag {
                 file.rewind()?; // rewind to the beginning of the file
                 file.write_all(&data)?; // dump the contents of the vec to the file
             }
+            #[cfg(feature = "mp4ameta")]
             Self::Mp4Tag { inner } => inner.write_to(file)?,
+            #[cfg(feature = "opusmeta")]
             Self::OpusTag { inner } => inner.write_to(file)?,
+            #[cfg(feature = "oggmeta")]
             Self::OggTag { inner } => inner.write_to(file)?,
         }
 
@@ -214,13 +490,21 @@ impl Tag {
     /// This method can error if one of the internal write methods fails. If that happens, the
     /// inner error will contain more information.
     pub fn write_to_vec(&mut self, vec: &mut Vec<u8>) -> Result<()> {
+        let backend = self.backend();
+        self.write_to_vec_impl(vec)
+            .map_err(|e| e.with_backend(backend))
+    }
+
+    fn write_to_vec_impl(&mut self, vec: &mut Vec<u8>) -> Result<()> {
         // we have to clone the vec because id3 and mp4ameta don't implement their traits for
         // Cursor<&mut Vec<u8>>, only Cursor<Vec<u8>>
         let cloned = vec.clone();
         let mut cursor = Cursor::new(cloned);
 
         match self {
-            Self::Id3Tag { inner } => inner.write_to_file(&mut cursor, id3::Version::Id3v24)?,
+            #[cfg(feature = "id3")]
+            Self::Id3Tag { inner } => dsd::write_id3(inner, &mut cursor)?,
+            #[cfg(feature = "metaflac")]
             Self::VorbisFlacTag { inner } => {
                 // TODO: Do this
                 let mut data: Vec<u8> = Vec::new();
@@ -234,8 +518,11 @@ impl Tag {
                 cursor.rewind()?; // rewind to the beginning of the cursor
                 cursor.write_all(&data)?;
             }
+            #[cfg(feature = "mp4ameta")]
             Self::Mp4Tag { inner } => inner.write_to(&mut cursor)?,
+            #[cfg(feature = "opusmeta")]
             Self::OpusTag { inner } => inner.write_to(&mut cursor)?,
+            #[cfg(feature = "oggmeta")]
             Self::OggTag { inner } => inner.write_to(&mut cursor)?,
         }
 
@@ -244,6 +531,7 @@ impl Tag {
     }
 
     /// Creates an empty set of tags in the ID3 format.
+    #[cfg(feature = "id3")]
     #[must_use]
     pub fn new_empty_id3() -> Self {
         Self::Id3Tag {
@@ -252,6 +540,7 @@ impl Tag {
     }
 
     /// Creates an empty set of tags in the FLAC format.
+    #[cfg(feature = "metaflac")]
     #[must_use]
     pub fn new_empty_flac() -> Self {
         Self::VorbisFlacTag {
@@ -260,6 +549,7 @@ impl Tag {
     }
 
     /// Creates an empty set of tags in the MP4 format.
+    #[cfg(feature = "mp4ameta")]
     #[must_use]
     pub fn new_empty_mp4() -> Self {
         Self::Mp4Tag {
@@ -268,12 +558,30 @@ impl Tag {
     }
 
     /// Creates an empty set of tags in the Opus format.
+    #[cfg(feature = "opusmeta")]
     #[must_use]
     pub fn new_empty_opus() -> Self {
         Self::OpusTag {
             inner: OpusInternalTag::default(),
         }
     }
+
+    /// The backend used to read and write this tag.
+    #[must_use]
+    pub fn backend(&self) -> Backend {
+        match self {
+            #[cfg(feature = "id3")]
+            Self::Id3Tag { .. } => Backend::Id3,
+            #[cfg(feature = "metaflac")]
+            Self::VorbisFlacTag { .. } => Backend::Metaflac,
+            #[cfg(feature = "mp4ameta")]
+            Self::Mp4Tag { .. } => Backend::Mp4ameta,
+            #[cfg(feature = "opusmeta")]
+            Self::OpusTag { .. } => Backend::Opusmeta,
+            #[cfg(feature = "oggmeta")]
+            Self::OggTag { .. } => Backend::Oggmeta,
+        }
+    }
 }
 
 impl Tag {
@@ -282,6 +590,7 @@ impl Tag {
     #[must_use]
     pub fn get_album_info(&self) -> Option<Album> {
         match self {
+            #[cfg(feature = "id3")]
             Self::Id3Tag { inner } => {
                 let cover = inner
                     .pictures()
@@ -294,6 +603,7 @@ impl Tag {
                     cover,
                 })
             }
+            #[cfg(feature = "metaflac")]
             Self::VorbisFlacTag { inner } => {
                 let cover = inner
                     .pictures()
@@ -314,6 +624,7 @@ impl Tag {
                     cover,
                 })
             }
+            #[cfg(feature = "mp4ameta")]
             Self::Mp4Tag { inner } => {
                 let cover = inner.artwork().map(Picture::from);
                 Some(Album {
@@ -322,6 +633,7 @@ impl Tag {
                     cover,
                 })
             }
+            #[cfg(feature = "opusmeta")]
             Self::OpusTag { inner } => {
                 let cover = inner
                     .get_picture_type(opusmeta::picture::PictureType::CoverFront)
@@ -338,6 +650,7 @@ impl Tag {
                     cover,
                 })
             }
+            #[cfg(feature = "oggmeta")]
             Self::OggTag { inner } => {
                 let cover = inner
                     .pictures
@@ -368,6 +681,7 @@ impl Tag {
     /// Supported MIME types are: `image/bmp`, `image/jpeg`, `image/png`
     pub fn set_album_info(&mut self, album: Album) -> Result<()> {
         match self {
+            #[cfg(feature = "id3")]
             Self::Id3Tag { inner } => {
                 if let Some(title) = album.title {
                     inner.set_album(title);
@@ -385,6 +699,7 @@ impl Tag {
                     });
                 }
             }
+            #[cfg(feature = "metaflac")]
             Self::VorbisFlacTag { inner } => {
                 if let Some(title) = album.title {
                     inner.set_vorbis("ALBUM", vec![title]);
@@ -404,6 +719,7 @@ impl Tag {
                     );
                 }
             }
+            #[cfg(feature = "mp4ameta")]
             Self::Mp4Tag { inner } => {
                 if let Some(title) = album.title {
                     inner.set_album(title);
@@ -413,9 +729,14 @@ impl Tag {
                 }
 
                 if let Some(picture) = album.cover {
-                    inner.set_artwork(picture.try_into()?);
+                    inner.set_artwork(
+                        picture
+                            .try_into()
+                            .map_err(|e: Error| e.with_field(Field::AlbumCover))?,
+                    );
                 }
             }
+            #[cfg(feature = "opusmeta")]
             Self::OpusTag { inner } => {
                 if let Some(title) = album.title {
                     inner.add_one("ALBUM".into(), title);
@@ -433,9 +754,12 @@ impl Tag {
                 );
 
                 if let Some(pic) = opus_pic {
-                    inner.add_picture(&pic)?;
+                    inner
+                        .add_picture(&pic)
+                        .map_err(|e| Error::from(e).with_field(Field::AlbumCover))?;
                 }
             }
+            #[cfg(feature = "oggmeta")]
             Self::OggTag { inner } => {
                 if let Some(title) = album.title {
                     inner.comments.insert("album".into(), vec![title]);
@@ -447,7 +771,13 @@ impl Tag {
                 }
                 if let Some(picture) = album.cover {
                     // Try to decode the image to obtain width/height and color depth
-                    inner.pictures.push(picture.data.as_slice().try_into()?);
+                    inner.pictures.push(
+                        picture
+                            .data
+                            .as_slice()
+                            .try_into()
+                            .map_err(|e| Error::from(e).with_field(Field::AlbumCover))?,
+                    );
                 }
             }
         }
@@ -457,11 +787,13 @@ impl Tag {
     /// Removes all album infofrom the audio track.
     pub fn remove_all_album_info(&mut self) {
         match self {
+            #[cfg(feature = "id3")]
             Self::Id3Tag { inner } => {
                 inner.remove_album();
                 inner.remove_album_artist();
                 inner.remove_picture_by_type(id3::frame::PictureType::CoverFront);
             }
+            #[cfg(feature = "metaflac")]
             Self::VorbisFlacTag { inner } => {
                 inner.remove_vorbis("ALBUM");
                 inner.remove_vorbis("ALBUMARTIST");
@@ -470,11 +802,13 @@ impl Tag {
 
                 inner.remove_picture_type(metaflac::block::PictureType::CoverFront);
             }
+            #[cfg(feature = "mp4ameta")]
             Self::Mp4Tag { inner } => {
                 inner.remove_album();
                 inner.remove_album_artists();
                 inner.remove_artworks();
             }
+            #[cfg(feature = "opusmeta")]
             Self::OpusTag { inner } => {
                 inner.remove_entries(&"ALBUM".into());
                 inner.remove_entries(&"ALBUMARTIST".into());
@@ -482,6 +816,7 @@ impl Tag {
 
                 let _ = inner.remove_picture_type(opusmeta::picture::PictureType::CoverFront);
             }
+            #[cfg(feature = "oggmeta")]
             Self::OggTag { inner } => {
                 inner.comments.remove("ALBUM");
                 inner.comments.remove("ALBUM_ARTIST");
@@ -490,14 +825,43 @@ impl Tag {
         }
     }
 
+    /// Writes the album cover to `path`, e.g. to provide a `folder.jpg` for media servers.
+    /// Returns `false` if the tag has no cover.
+    ///
+    /// With the `image` feature enabled, covers larger than `size_hint` in either dimension are
+    /// downscaled and saved as jpeg. Otherwise `size_hint` is ignored and the cover is written as is.
+    ///
+    /// # Errors
+    /// This function errors if the file cannot be written.
+    pub fn export_cover<P: AsRef<Path>>(&self, path: P, size_hint: Option<u32>) -> Result<bool> {
+        let Some(cover) = self.get_album_info().and_then(|a| a.cover) else {
+            return Ok(false);
+        };
+
+        #[cfg(feature = "image")]
+        let cover = size_hint
+            .and_then(|size| cover.downscaled(size))
+            .unwrap_or(cover);
+        #[cfg(not(feature = "image"))]
+        let _ = size_hint;
+
+        cover.save_to(path)?;
+        Ok(true)
+    }
+
     /// Gets the title.
     #[must_use]
     pub fn title(&self) -> Option<&str> {
         match self {
+            #[cfg(feature = "id3")]
             Self::Id3Tag { inner } => inner.title(),
+            #[cfg(feature = "metaflac")]
             Self::VorbisFlacTag { inner } => inner.get_vorbis("TITLE")?.next(),
+            #[cfg(feature = "mp4ameta")]
             Self::Mp4Tag { inner } => inner.title(),
+            #[cfg(feature = "opusmeta")]
             Self::OpusTag { inner } => inner.get_one(&"TITLE".into()).map(String::as_str),
+            #[cfg(feature = "oggmeta")]
             Self::OggTag { inner } => inner
                 .comments
                 .get("TITLE")
@@ -509,10 +873,15 @@ impl Tag {
     /// Sets the title.
     pub fn set_title(&mut self, title: &str) {
         match self {
+            #[cfg(feature = "id3")]
             Self::Id3Tag { inner } => inner.set_title(title),
+            #[cfg(feature = "metaflac")]
             Self::VorbisFlacTag { inner } => inner.set_vorbis("TITLE", vec![title]),
+            #[cfg(feature = "mp4ameta")]
             Self::Mp4Tag { inner } => inner.set_title(title),
+            #[cfg(feature = "opusmeta")]
             Self::OpusTag { inner } => inner.add_one("TITLE".into(), title.into()),
+            #[cfg(feature = "oggmeta")]
             Self::OggTag { inner } => inner
                 .comments
                 .entry("TITLE".into())
@@ -524,12 +893,17 @@ impl Tag {
     /// Removes any title fields from the file.
     pub fn remove_title(&mut self) {
         match self {
+            #[cfg(feature = "id3")]
             Self::Id3Tag { inner } => inner.remove_title(),
+            #[cfg(feature = "metaflac")]
             Self::VorbisFlacTag { inner } => inner.remove_vorbis("TITLE"),
+            #[cfg(feature = "mp4ameta")]
             Self::Mp4Tag { inner } => inner.remove_title(),
+            #[cfg(feature = "opusmeta")]
             Self::OpusTag { inner } => {
                 inner.remove_entries(&"TITLE".into());
             }
+            #[cfg(feature = "oggmeta")]
             Self::OggTag { inner } => {
                 inner.comments.remove("TITLE");
             }
@@ -541,7 +915,9 @@ impl Tag {
     #[must_use]
     pub fn artist(&self) -> Option<String> {
         match self {
+            #[cfg(feature = "id3")]
             Self::Id3Tag { inner } => inner.artist().map(std::string::ToString::to_string),
+            #[cfg(feature = "metaflac")]
             Self::VorbisFlacTag { inner } => Some(
                 inner
                     .get_vorbis("ARTIST")?
@@ -549,8 +925,11 @@ impl Tag {
                     .join("; "),
             )
             .filter(|s| !s.is_empty()),
+            #[cfg(feature = "mp4ameta")]
             Self::Mp4Tag { inner } => inner.artist().map(std::string::ToString::to_string),
+            #[cfg(feature = "opusmeta")]
             Self::OpusTag { inner } => Some(inner.get(&"ARTIST".into())?.join("; ")),
+            #[cfg(feature = "oggmeta")]
             Self::OggTag { inner } => Some(inner.comments.get("ARTIST")?.join("; ")),
         }
     }
@@ -558,13 +937,18 @@ impl Tag {
     /// Sets the artist (note: NOT the album artist!)
     pub fn set_artist(&mut self, artist: &str) {
         match self {
+            #[cfg(feature = "id3")]
             Self::Id3Tag { inner } => inner.set_artist(artist),
+            #[cfg(feature = "metaflac")]
             Self::VorbisFlacTag { inner } => inner.set_vorbis("ARTIST", vec![artist]),
+            #[cfg(feature = "mp4ameta")]
             Self::Mp4Tag { inner } => inner.set_artist(artist),
+            #[cfg(feature = "opusmeta")]
             Self::OpusTag { inner } => {
                 inner.remove_entries(&"ARTIST".into());
                 inner.add_one("ARTIST".into(), artist.into());
             }
+            #[cfg(feature = "oggmeta")]
             Self::OggTag { inner } => {
                 inner.comments.remove("ARTIST");
                 inner.comments.insert("ARTIST".into(), vec![artist.into()]);
@@ -575,12 +959,17 @@ impl Tag {
     /// Removes the artist (note: NOT the album artist!)
     pub fn remove_artist(&mut self) {
         match self {
+            #[cfg(feature = "id3")]
             Self::Id3Tag { inner } => inner.remove_artist(),
+            #[cfg(feature = "metaflac")]
             Self::VorbisFlacTag { inner } => inner.remove_vorbis("ARTIST"),
+            #[cfg(feature = "mp4ameta")]
             Self::Mp4Tag { inner } => inner.remove_artists(),
+            #[cfg(feature = "opusmeta")]
             Self::OpusTag { inner } => {
                 inner.remove_entries(&"ARTIST".into());
             }
+            #[cfg(feature = "oggmeta")]
             Self::OggTag { inner } => {
                 inner.comments.remove("ARTIST");
             }
@@ -593,20 +982,25 @@ impl Tag {
     #[must_use]
     pub fn date(&self) -> Option<Timestamp> {
         match self {
+            #[cfg(feature = "id3")]
             Self::Id3Tag { inner } => inner.date_released().map(std::convert::Into::into),
+            #[cfg(feature = "metaflac")]
             Self::VorbisFlacTag { inner } => inner
                 .get_vorbis("DATE")?
                 .next()
                 .and_then(|s| Timestamp::from_str(s).ok()),
+            #[cfg(feature = "mp4ameta")]
             Self::Mp4Tag { inner } => inner
                 .data()
                 .find(|data| matches!(data.0.fourcc().unwrap_or_default(), DATE_FOURCC))
                 .map(|data| -> Option<Timestamp> {
                     Timestamp::from_str(data.1.clone().into_string()?.as_str()).ok()
                 })?,
+            #[cfg(feature = "opusmeta")]
             Self::OpusTag { inner } => inner
                 .get_one(&"DATE".into())
                 .and_then(|s| Timestamp::from_str(s).ok()),
+            #[cfg(feature = "oggmeta")]
             Self::OggTag { inner } => inner
                 .comments
                 .get("DATE")
@@ -616,51 +1010,30 @@ impl Tag {
 
     /// Sets the date
     /// # Format-specific
-    /// In id3, this method corresponds to the `date_released` field.
+    /// In id3, this method corresponds to the `date_released` field. ID3 has no timezones, so
+    /// timestamps with an offset are stored converted to UTC. All other formats store the
+    /// timestamp in ISO 8601 as given.
     pub fn set_date(&mut self, timestamp: Timestamp) {
         match self {
+            #[cfg(feature = "id3")]
             Self::Id3Tag { inner } => inner.set_date_released(timestamp.into()),
-            Self::VorbisFlacTag { inner } => inner.set_vorbis(
-                "DATE",
-                vec![format!(
-                    "{:04}-{:02}-{:02}",
-                    timestamp.year,
-                    timestamp.month.unwrap_or_default(),
-                    timestamp.day.unwrap_or_default()
-                )],
-            ),
-            Self::Mp4Tag { inner } => inner.set_data(
-                DATE_FOURCC,
-                Mp4Data::Utf8(format!(
-                    "{:04}-{:02}-{:02}",
-                    timestamp.year,
-                    timestamp.month.unwrap_or_default(),
-                    timestamp.day.unwrap_or_default()
-                )),
-            ),
+            #[cfg(feature = "metaflac")]
+            Self::VorbisFlacTag { inner } => inner.set_vorbis("DATE", vec![timestamp.to_string()]),
+            #[cfg(feature = "mp4ameta")]
+            Self::Mp4Tag { inner } => {
+                inner.set_data(DATE_FOURCC, Mp4Data::Utf8(timestamp.to_string()));
+            }
+            #[cfg(feature = "opusmeta")]
             Self::OpusTag { inner } => {
                 inner.remove_entries(&"DATE".into());
-                inner.add_one(
-                    "DATE".into(),
-                    format!(
-                        "{:04}-{:02}-{:02}",
-                        timestamp.year,
-                        timestamp.month.unwrap_or_default(),
-                        timestamp.day.unwrap_or_default()
-                    ),
-                );
+                inner.add_one("DATE".into(), timestamp.to_string());
             }
+            #[cfg(feature = "oggmeta")]
             Self::OggTag { inner } => {
                 inner.comments.remove("DATE");
-                inner.comments.insert(
-                    "DATE".into(),
-                    vec![format!(
-                        "{:04}-{:02}-{:02}",
-                        timestamp.year,
-                        timestamp.month.unwrap_or_default(),
-                        timestamp.day.unwrap_or_default()
-                    )],
-                );
+                inner
+                    .comments
+                    .insert("DATE".into(), vec![timestamp.to_string()]);
             }
         }
     }
@@ -670,18 +1043,102 @@ impl Tag {
     /// In id3, this method corresponds to the `date_released` field.
     pub fn remove_date(&mut self) {
         match self {
+            #[cfg(feature = "id3")]
             Self::Id3Tag { inner } => inner.remove_date_released(),
+            #[cfg(feature = "metaflac")]
             Self::VorbisFlacTag { inner } => inner.remove_vorbis("DATE"),
+            #[cfg(feature = "mp4ameta")]
             Self::Mp4Tag { inner } => inner.remove_data_of(&DATE_FOURCC),
+            #[cfg(feature = "opusmeta")]
             Self::OpusTag { inner } => {
                 inner.remove_entries(&"DATE".into());
             }
+            #[cfg(feature = "oggmeta")]
             Self::OggTag { inner } => {
                 inner.comments.remove("DATE");
             }
         }
     }
 
+    /// Gets the genres
+    /// # Format-specific
+    /// In id3 these are the values of the `TCON` frame, in mp4 the standard (`gnre`) and custom
+    /// (`©gen`) genre atoms and in the Vorbis based formats all `GENRE` comments.
+    #[must_use]
+    pub fn genres(&self) -> Vec<String> {
+        match self {
+            #[cfg(feature = "id3")]
+            Self::Id3Tag { inner } => inner
+                .genres()
+                .unwrap_or_default()
+                .into_iter()
+                .map(str::to_owned)
+                .collect(),
+            #[cfg(feature = "metaflac")]
+            Self::VorbisFlacTag { inner } => inner
+                .get_vorbis("GENRE")
+                .map(|v| v.map(str::to_owned).collect())
+                .unwrap_or_default(),
+            #[cfg(feature = "mp4ameta")]
+            Self::Mp4Tag { inner } => inner.genres().map(str::to_owned).collect(),
+            #[cfg(feature = "opusmeta")]
+            Self::OpusTag { inner } => inner.get(&"GENRE".into()).cloned().unwrap_or_default(),
+            #[cfg(feature = "oggmeta")]
+            Self::OggTag { inner } => inner.comments.get("GENRE").cloned().unwrap_or_default(),
+        }
+    }
+
+    /// Sets the genres, replacing any existing ones
+    /// # Format-specific
+    /// In mp4 the genres are always stored as custom genres (`©gen`).
+    pub fn set_genres(&mut self, genres: &[&str]) {
+        match self {
+            #[cfg(feature = "id3")]
+            Self::Id3Tag { inner } => inner.set_text_values("TCON", genres.iter().copied()),
+            #[cfg(feature = "metaflac")]
+            Self::VorbisFlacTag { inner } => inner.set_vorbis("GENRE", genres.to_vec()),
+            #[cfg(feature = "mp4ameta")]
+            Self::Mp4Tag { inner } => {
+                inner.set_genres(genres.iter().map(|&g| g.to_owned()));
+            }
+            #[cfg(feature = "opusmeta")]
+            Self::OpusTag { inner } => {
+                inner.remove_entries(&"GENRE".into());
+                inner.add_many(
+                    "GENRE".into(),
+                    genres.iter().map(|&g| g.to_owned()).collect(),
+                );
+            }
+            #[cfg(feature = "oggmeta")]
+            Self::OggTag { inner } => {
+                inner.comments.insert(
+                    "GENRE".into(),
+                    genres.iter().map(|&g| g.to_owned()).collect(),
+                );
+            }
+        }
+    }
+
+    /// Removes all genres
+    pub fn remove_genres(&mut self) {
+        match self {
+            #[cfg(feature = "id3")]
+            Self::Id3Tag { inner } => inner.remove_genre(),
+            #[cfg(feature = "metaflac")]
+            Self::VorbisFlacTag { inner } => inner.remove_vorbis("GENRE"),
+            #[cfg(feature = "mp4ameta")]
+            Self::Mp4Tag { inner } => inner.remove_genres(),
+            #[cfg(feature = "opusmeta")]
+            Self::OpusTag { inner } => {
+                inner.remove_entries(&"GENRE".into());
+            }
+            #[cfg(feature = "oggmeta")]
+            Self::OggTag { inner } => {
+                inner.comments.remove("GENRE");
+            }
+        }
+    }
+
     /// Copies the information of this [`Tag`] to another. The target [`Tag`] can be any of the
     /// supported formats.
     pub fn copy_to(&self, other: &mut Self) {
@@ -701,6 +1158,11 @@ impl Tag {
         if let Some(date) = self.date() {
             other.set_date(date);
         }
+
+        let genres = self.genres();
+        if !genres.is_empty() {
+            other.set_genres(&genres.iter().map(String::as_str).collect::<Vec<_>>());
+        }
     }
 
     /// Gets lyrics
@@ -708,17 +1170,23 @@ impl Tag {
     #[must_use]
     pub fn lyrics(&self) -> Option<String> {
         match self {
+            #[cfg(feature = "id3")]
             Self::Id3Tag { inner } => Some(inner.lyrics().map(|l| l.text.clone()).collect()),
+            #[cfg(feature = "metaflac")]
             Self::VorbisFlacTag { inner } => Some(inner.get_vorbis("LYRICS")?.collect()),
+            #[cfg(feature = "mp4ameta")]
             Self::Mp4Tag { inner } => Some(inner.userdata.lyrics()?.to_owned()),
-            Self::OpusTag { inner } => Some(inner.get_one(&"LYRICS".into())?.to_string()),
-            Self::OggTag { inner } => Some(inner.comments.get("LYRICS")?.first()?.to_string()),
+            #[cfg(feature = "opusmeta")]
+            Self::OpusTag { inner } => Some(inner.get_one(&"LYRICS".into())?.clone()),
+            #[cfg(feature = "oggmeta")]
+            Self::OggTag { inner } => Some(inner.comments.get("LYRICS")?.first()?.clone()),
         }
     }
 
     /// Sets lyrics
     pub fn set_lyrics(&mut self, lyrics: &str) {
         match self {
+            #[cfg(feature = "id3")]
             Self::Id3Tag { inner } => {
                 inner.add_frame(id3::frame::Lyrics {
                     lang: String::new(),
@@ -726,12 +1194,16 @@ impl Tag {
                     text: lyrics.to_string(),
                 });
             }
+            #[cfg(feature = "metaflac")]
             Self::VorbisFlacTag { inner } => inner.set_vorbis("LYRICS", vec![lyrics]),
+            #[cfg(feature = "mp4ameta")]
             Self::Mp4Tag { inner } => inner.set_lyrics(lyrics),
+            #[cfg(feature = "opusmeta")]
             Self::OpusTag { inner } => {
                 inner.remove_entries(&"LYRICS".into());
                 inner.add_one("LYRICS".into(), lyrics.into());
             }
+            #[cfg(feature = "oggmeta")]
             Self::OggTag { inner } => {
                 inner.comments.remove("LYRICS");
                 inner.comments.insert("LYRICS".into(), vec![lyrics.into()]);
@@ -742,69 +1214,399 @@ impl Tag {
     /// Removes lyrics
     pub fn remove_lyrics(&mut self) {
         match self {
+            #[cfg(feature = "id3")]
             Self::Id3Tag { inner } => inner.remove_all_lyrics(),
+            #[cfg(feature = "metaflac")]
             Self::VorbisFlacTag { inner } => inner.remove_vorbis("LYRICS"),
+            #[cfg(feature = "mp4ameta")]
             Self::Mp4Tag { inner } => inner.remove_lyrics(),
+            #[cfg(feature = "opusmeta")]
             Self::OpusTag { inner } => {
                 inner.remove_entries(&"LYRICS".into());
             }
+            #[cfg(feature = "oggmeta")]
             Self::OggTag { inner } => {
                 inner.comments.remove("LYRICS");
             }
         }
     }
 
+    /// Gets the encoder, i.e. the software and settings used for encoding.
+    /// # Format-specific
+    /// In id3 this is the `TSSE` frame, in mp4 the `©too` atom and in the Vorbis based formats the
+    /// `ENCODER` comment.
+    #[must_use]
+    pub fn encoder(&self) -> Option<String> {
+        self.text_field(&ENCODER_FIELD)
+    }
+
+    /// Sets the encoder
+    pub fn set_encoder(&mut self, encoder: &str) {
+        self.set_text_field(&ENCODER_FIELD, encoder);
+    }
+
+    /// Removes the encoder
+    pub fn remove_encoder(&mut self) {
+        self.remove_text_field(&ENCODER_FIELD);
+    }
+
+    /// Gets the copyright text
+    /// # Format-specific
+    /// In id3 this is the `TCOP` frame, in mp4 the `cprt` atom and in the Vorbis based formats the
+    /// `COPYRIGHT` comment.
+    #[must_use]
+    pub fn copyright(&self) -> Option<String> {
+        self.text_field(&COPYRIGHT_FIELD)
+    }
+
+    /// Sets the copyright text
+    pub fn set_copyright(&mut self, copyright: &str) {
+        self.set_text_field(&COPYRIGHT_FIELD, copyright);
+    }
+
+    /// Removes the copyright text
+    pub fn remove_copyright(&mut self) {
+        self.remove_text_field(&COPYRIGHT_FIELD);
+    }
+
+    /// Gets the official artist webpage
+    /// # Format-specific
+    /// In id3 this is the `WOAR` frame, in mp4 the `WEBSITE` freeform atom and in the Vorbis based
+    /// formats the `WEBSITE` comment.
+    #[must_use]
+    pub fn artist_url(&self) -> Option<String> {
+        self.text_field(&ARTIST_URL_FIELD)
+    }
+
+    /// Sets the official artist webpage
+    pub fn set_artist_url(&mut self, url: &str) {
+        self.set_text_field(&ARTIST_URL_FIELD, url);
+    }
+
+    /// Removes the official artist webpage
+    pub fn remove_artist_url(&mut self) {
+        self.remove_text_field(&ARTIST_URL_FIELD);
+    }
+
+    /// Gets the official webpage of the audio source, e.g. the video the audio was taken from.
+    /// # Format-specific
+    /// In id3 this is the `WOAS` frame, in mp4 the `purl` atom and in the Vorbis based formats the
+    /// `PURL` comment, matching what yt-dlp writes.
+    #[must_use]
+    pub fn source_url(&self) -> Option<String> {
+        self.text_field(&SOURCE_URL_FIELD)
+    }
+
+    /// Sets the official audio source webpage
+    pub fn set_source_url(&mut self, url: &str) {
+        self.set_text_field(&SOURCE_URL_FIELD, url);
+    }
+
+    /// Removes the official audio source webpage
+    pub fn remove_source_url(&mut self) {
+        self.remove_text_field(&SOURCE_URL_FIELD);
+    }
+
+    /// Gets the number of tracks on the album
+    /// # Format-specific
+    /// In id3 this is the second half of the `TRCK` frame, in mp4 part of the `trkn` atom and in
+    /// the Vorbis based formats the `TRACKTOTAL` comment.
+    #[must_use]
+    pub fn total_tracks(&self) -> Option<u32> {
+        match self {
+            #[cfg(feature = "id3")]
+            Self::Id3Tag { inner } => inner.total_tracks(),
+            #[cfg(feature = "mp4ameta")]
+            Self::Mp4Tag { inner } => inner.total_tracks().map(u32::from),
+            #[cfg(feature = "metaflac")]
+            Self::VorbisFlacTag { inner } => inner.get_vorbis("TRACKTOTAL")?.next()?.parse().ok(),
+            #[cfg(feature = "opusmeta")]
+            Self::OpusTag { inner } => inner.get_one(&"TRACKTOTAL".into())?.parse().ok(),
+            #[cfg(feature = "oggmeta")]
+            Self::OggTag { inner } => inner.comments.get("TRACKTOTAL")?.first()?.parse().ok(),
+        }
+    }
+
+    /// Sets the number of tracks on the album
+    pub fn set_total_tracks(&mut self, total_tracks: u32) {
+        match self {
+            #[cfg(feature = "id3")]
+            Self::Id3Tag { inner } => inner.set_total_tracks(total_tracks),
+            #[cfg(feature = "mp4ameta")]
+            Self::Mp4Tag { inner } => {
+                inner.set_total_tracks(u16::try_from(total_tracks).unwrap_or(u16::MAX));
+            }
+            #[cfg(feature = "metaflac")]
+            Self::VorbisFlacTag { inner } => {
+                inner.set_vorbis("TRACKTOTAL", vec![total_tracks.to_string()]);
+            }
+            #[cfg(feature = "opusmeta")]
+            Self::OpusTag { inner } => {
+                inner.remove_entries(&"TRACKTOTAL".into());
+                inner.add_one("TRACKTOTAL".into(), total_tracks.to_string());
+            }
+            #[cfg(feature = "oggmeta")]
+            Self::OggTag { inner } => {
+                inner
+                    .comments
+                    .insert("TRACKTOTAL".into(), vec![total_tracks.to_string()]);
+            }
+        }
+    }
+
+    /// Removes the number of tracks on the album
+    pub fn remove_total_tracks(&mut self) {
+        match self {
+            #[cfg(feature = "id3")]
+            Self::Id3Tag { inner } => inner.remove_total_tracks(),
+            #[cfg(feature = "mp4ameta")]
+            Self::Mp4Tag { inner } => inner.remove_total_tracks(),
+            #[cfg(feature = "metaflac")]
+            Self::VorbisFlacTag { inner } => inner.remove_vorbis("TRACKTOTAL"),
+            #[cfg(feature = "opusmeta")]
+            Self::OpusTag { inner } => {
+                inner.remove_entries(&"TRACKTOTAL".into());
+            }
+            #[cfg(feature = "oggmeta")]
+            Self::OggTag { inner } => {
+                inner.comments.remove("TRACKTOTAL");
+            }
+        }
+    }
+
+    /// Whether the file is marked as a podcast episode
+    /// # Format-specific
+    /// In id3 this is the presence of the `PCST` frame, in mp4 the `pcst` atom and in the Vorbis
+    /// based formats a `PODCAST` comment of `1`.
+    #[must_use]
+    pub fn podcast(&self) -> bool {
+        match self {
+            #[cfg(feature = "id3")]
+            Self::Id3Tag { inner } => inner.get("PCST").is_some(),
+            #[cfg(feature = "mp4ameta")]
+            Self::Mp4Tag { inner } => inner
+                .bytes_of(&mp4ameta::ident::PODCAST)
+                .next()
+                .and_then(|v| v.first())
+                .is_some_and(|&v| v == 1),
+            #[allow(unreachable_patterns)]
+            _ => self.text_field(&PODCAST_FIELD).as_deref() == Some("1"),
+        }
+    }
+
+    /// Marks the file as a podcast episode
+    pub fn set_podcast(&mut self) {
+        match self {
+            #[cfg(feature = "id3")]
+            Self::Id3Tag { inner } => {
+                inner.add_frame(id3::frame::Frame::with_content(
+                    "PCST",
+                    id3::frame::Content::Unknown(id3::frame::Unknown {
+                        data: vec![0; 4],
+                        version: id3::Version::Id3v24,
+                    }),
+                ));
+            }
+            #[cfg(feature = "mp4ameta")]
+            Self::Mp4Tag { inner } => {
+                inner.set_data(mp4ameta::ident::PODCAST, Mp4Data::BeSigned(vec![1]));
+            }
+            #[allow(unreachable_patterns)]
+            _ => self.set_text_field(&PODCAST_FIELD, "1"),
+        }
+    }
+
+    /// Removes the podcast marker
+    pub fn remove_podcast(&mut self) {
+        self.remove_text_field(&PODCAST_FIELD);
+    }
+
+    /// Gets the url of the podcast feed the episode belongs to
+    /// # Format-specific
+    /// In id3 this is the `WFED` frame and in the Vorbis based formats the `PODCASTURL` comment.
+    /// In mp4 this is the `purl` atom, which is shared with [`Tag::source_url`].
+    #[must_use]
+    pub fn feed_url(&self) -> Option<String> {
+        self.text_field(&FEED_URL_FIELD)
+    }
+
+    /// Sets the podcast feed url
+    pub fn set_feed_url(&mut self, url: &str) {
+        self.set_text_field(&FEED_URL_FIELD, url);
+    }
+
+    /// Removes the podcast feed url
+    pub fn remove_feed_url(&mut self) {
+        self.remove_text_field(&FEED_URL_FIELD);
+    }
+
+    /// Gets the globally unique id of the podcast episode
+    /// # Format-specific
+    /// In id3 this is the `TGID` frame, in mp4 the `egid` atom and in the Vorbis based formats the
+    /// `PODCASTID` comment.
+    #[must_use]
+    pub fn episode_id(&self) -> Option<String> {
+        self.text_field(&EPISODE_ID_FIELD)
+    }
+
+    /// Sets the podcast episode id
+    pub fn set_episode_id(&mut self, id: &str) {
+        self.set_text_field(&EPISODE_ID_FIELD, id);
+    }
+
+    /// Removes the podcast episode id
+    pub fn remove_episode_id(&mut self) {
+        self.remove_text_field(&EPISODE_ID_FIELD);
+    }
+
+    /// Gets the podcast category
+    /// # Format-specific
+    /// In id3 this is the `TCAT` frame, in mp4 the `catg` atom and in the Vorbis based formats the
+    /// `CATEGORY` comment.
+    #[must_use]
+    pub fn category(&self) -> Option<String> {
+        self.text_field(&CATEGORY_FIELD)
+    }
+
+    /// Sets the podcast category
+    pub fn set_category(&mut self, category: &str) {
+        self.set_text_field(&CATEGORY_FIELD, category);
+    }
+
+    /// Removes the podcast category
+    pub fn remove_category(&mut self) {
+        self.remove_text_field(&CATEGORY_FIELD);
+    }
+
+    fn text_field(&self, field: &TextField) -> Option<String> {
+        match self {
+            #[cfg(feature = "id3")]
+            Self::Id3Tag { inner } => inner
+                .get(field.id3)
+                .and_then(|f| f.content().text().or_else(|| f.content().link()))
+                .map(str::to_owned),
+            #[cfg(feature = "metaflac")]
+            Self::VorbisFlacTag { inner } => {
+                inner.get_vorbis(field.vorbis)?.next().map(str::to_owned)
+            }
+            #[cfg(feature = "mp4ameta")]
+            Self::Mp4Tag { inner } => inner.strings_of(&field.mp4).next().map(str::to_owned),
+            #[cfg(feature = "opusmeta")]
+            Self::OpusTag { inner } => inner.get_one(&field.vorbis.into()).cloned(),
+            #[cfg(feature = "oggmeta")]
+            Self::OggTag { inner } => inner.comments.get(field.vorbis)?.first().cloned(),
+        }
+    }
+
+    fn set_text_field(&mut self, field: &TextField, value: &str) {
+        match self {
+            #[cfg(feature = "id3")]
+            Self::Id3Tag { inner } => {
+                let frame = if field.id3.starts_with('W') {
+                    id3::frame::Frame::link(field.id3, value)
+                } else {
+                    id3::frame::Frame::text(field.id3, value)
+                };
+                inner.add_frame(frame);
+            }
+            #[cfg(feature = "metaflac")]
+            Self::VorbisFlacTag { inner } => inner.set_vorbis(field.vorbis, vec![value]),
+            #[cfg(feature = "mp4ameta")]
+            Self::Mp4Tag { inner } => {
+                inner.set_data(field.mp4.clone(), Mp4Data::Utf8(value.into()));
+            }
+            #[cfg(feature = "opusmeta")]
+            Self::OpusTag { inner } => {
+                inner.remove_entries(&field.vorbis.into());
+                inner.add_one(field.vorbis.into(), value.into());
+            }
+            #[cfg(feature = "oggmeta")]
+            Self::OggTag { inner } => {
+                inner
+                    .comments
+                    .insert(field.vorbis.into(), vec![value.into()]);
+            }
+        }
+    }
+
+    fn remove_text_field(&mut self, field: &TextField) {
+        match self {
+            #[cfg(feature = "id3")]
+            Self::Id3Tag { inner } => {
+                inner.remove(field.id3);
+            }
+            #[cfg(feature = "metaflac")]
+            Self::VorbisFlacTag { inner } => inner.remove_vorbis(field.vorbis),
+            #[cfg(feature = "mp4ameta")]
+            Self::Mp4Tag { inner } => inner.remove_data_of(&field.mp4),
+            #[cfg(feature = "opusmeta")]
+            Self::OpusTag { inner } => {
+                inner.remove_entries(&field.vorbis.into());
+            }
+            #[cfg(feature = "oggmeta")]
+            Self::OggTag { inner } => {
+                inner.comments.remove(field.vorbis);
+            }
+        }
+    }
+
     #[must_use]
     /// Gets all comments with the given key.
     pub fn get_comment(&self, key: &str) -> Option<String> {
         match self {
+            #[cfg(feature = "id3")]
             Self::Id3Tag { inner } => inner
                 .extended_texts()
                 .filter(|c| c.description == key)
                 .map(|c| c.value.clone())
                 .next(),
+            #[cfg(feature = "metaflac")]
             Self::VorbisFlacTag { inner } => inner
                 .get_vorbis(key)
                 .map(|c| c.map(String::from).next())
                 .unwrap_or_default(),
+            #[cfg(feature = "mp4ameta")]
             Self::Mp4Tag { inner } => inner
                 .data_of(&FreeformIdent::new_borrowed("com.apple.iTunes", key))
-                .filter_map(|data| match data {
-                    Mp4Data::Utf8(s) => Some(s.clone()),
-                    Mp4Data::Utf16(s) => Some(s.clone()),
+                .find_map(|data| match data {
+                    Mp4Data::Utf8(s) | Mp4Data::Utf16(s) => Some(s.clone()),
                     _ => None,
-                })
-                .next(),
+                }),
+            #[cfg(feature = "opusmeta")]
             Self::OpusTag { inner } => inner
                 .get(&LowercaseString::new(key))
                 .and_then(|f| f.first().cloned()),
-            Self::OggTag { inner } => {
-                unimplemented!()
-            }
+            #[cfg(feature = "oggmeta")]
+            Self::OggTag { inner } => inner.comments.get(key).and_then(|c| c.first().cloned()),
         }
     }
 
     /// Replaces all existing comments matching the key with the new ones.
     pub fn set_comment(&mut self, key: &str, value: String) {
         match self {
+            #[cfg(feature = "id3")]
             Self::Id3Tag { .. } => {
                 self.add_comment(key, value);
             }
+            #[cfg(feature = "metaflac")]
             Self::VorbisFlacTag { inner } => {
                 inner.set_vorbis(key, vec![value]);
             }
+            #[cfg(feature = "mp4ameta")]
             Self::Mp4Tag { inner } => {
                 inner.set_data(
                     FreeformIdent::new_borrowed("com.apple.iTunes", key),
                     Mp4Data::Utf8(value),
                 );
             }
+            #[cfg(feature = "opusmeta")]
             Self::OpusTag { inner } => {
                 inner.remove_entries(&LowercaseString::new(key));
                 inner.add_many(key.into(), vec![value]);
             }
+            #[cfg(feature = "oggmeta")]
             Self::OggTag { inner } => {
-                unimplemented!()
+                inner.comments.insert(key.into(), vec![value]);
             }
         }
     }
@@ -812,12 +1614,14 @@ impl Tag {
     /// Appends or creates a new comment with the key.
     pub fn add_comment(&mut self, key: &str, value: String) {
         match self {
+            #[cfg(feature = "id3")]
             Self::Id3Tag { inner } => {
                 inner.add_frame(id3::frame::ExtendedText {
                     description: key.to_string(),
                     value,
                 });
             }
+            #[cfg(feature = "metaflac")]
             Self::VorbisFlacTag { inner } => {
                 match inner
                     .vorbis_comments_mut()
@@ -832,28 +1636,33 @@ impl Tag {
                     }
                 }
             }
+            #[cfg(feature = "mp4ameta")]
             Self::Mp4Tag { inner } => {
                 inner.add_data(
                     FreeformIdent::new_borrowed("com.apple.iTunes", key),
                     Mp4Data::Utf8(value),
                 );
             }
+            #[cfg(feature = "opusmeta")]
             Self::OpusTag { inner } => {
                 inner.add_one(key.into(), value);
             }
+            #[cfg(feature = "oggmeta")]
             Self::OggTag { inner } => {
-                unimplemented!()
+                inner.comments.entry(key.into()).or_default().push(value);
             }
         }
     }
 
-    /// Removes all comments with the given key.  
+    /// Removes all comments with the given key.\
     /// A `value` may be specified to remove a comment matching the exact key-value pair.
     pub fn remove_comment(&mut self, key: &str, value: Option<&str>) {
         match self {
+            #[cfg(feature = "id3")]
             Self::Id3Tag { inner } => {
                 inner.remove_extended_text(Some(key), value);
             }
+            #[cfg(feature = "metaflac")]
             Self::VorbisFlacTag { inner } => {
                 if let Some(value) = value {
                     inner.remove_vorbis_pair(key, value);
@@ -861,6 +1670,7 @@ impl Tag {
                     inner.remove_vorbis(key);
                 }
             }
+            #[cfg(feature = "mp4ameta")]
             Self::Mp4Tag { inner } => {
                 if let Some(value) = value {
                     inner.retain_data_of(
@@ -877,6 +1687,7 @@ impl Tag {
                     inner.remove_data_of(&FreeformIdent::new_borrowed("com.apple.iTunes", key));
                 }
             }
+            #[cfg(feature = "opusmeta")]
             Self::OpusTag { inner } => {
                 if let Some(mut list) = inner.remove_entries(&LowercaseString::new(key)) {
                     if let Some(value) = value {
@@ -887,19 +1698,245 @@ impl Tag {
                     }
                 }
             }
+            #[cfg(feature = "oggmeta")]
             Self::OggTag { inner } => {
-                unimplemented!()
+                if let Some(value) = value {
+                    if let Some(list) = inner.comments.get_mut(key) {
+                        list.retain(|x| x != value);
+                        if list.is_empty() {
+                            inner.comments.remove(key);
+                        }
+                    }
+                } else {
+                    inner.comments.remove(key);
+                }
             }
         }
     }
+
+    /// Lists the keys of all free-form comments present in the tag.
+    ///
+    /// # Format-specific
+    /// For the Vorbis based formats (flac, opus, ogg) the well-known keys backing title, artist,
+    /// album, date, lyrics, encoder, copyright and the urls are not reported as comments.
+    /// The same applies to the mp4 freeform atoms backing dedicated accessors.
+    #[must_use]
+    pub fn comment_keys(&self) -> Vec<String> {
+        let mut keys: Vec<String> = match self {
+            #[cfg(feature = "id3")]
+            Self::Id3Tag { inner } => inner
+                .extended_texts()
+                .map(|c| c.description.clone())
+                .collect(),
+            #[cfg(feature = "metaflac")]
+            Self::VorbisFlacTag { inner } => inner
+                .vorbis_comments()
+                .map(|c| without_vorbis_fields(c.comments.keys()))
+                .unwrap_or_default(),
+            #[cfg(feature = "mp4ameta")]
+            Self::Mp4Tag { inner } => inner
+                .data()
+                .filter_map(|(ident, _)| match ident {
+                    Mp4DataIdent::Freeform { mean, name }
+                        if mean == "com.apple.iTunes"
+                            && !MP4_FREEFORM_FIELD_NAMES.contains(&name.as_ref()) =>
+                    {
+                        Some(name.to_string())
+                    }
+                    _ => None,
+                })
+                .collect(),
+            #[cfg(feature = "opusmeta")]
+            Self::OpusTag { inner } => without_vorbis_fields(inner.keys()),
+            #[cfg(feature = "oggmeta")]
+            Self::OggTag { inner } => without_vorbis_fields(inner.comments.keys()),
+        };
+
+        keys.sort_unstable();
+        keys.dedup();
+        keys
+    }
+
+    /// Lists the fields of this tag which multitag does not model, neither through a dedicated
+    /// accessor nor as a comment.
+    ///
+    /// These fields are never touched by the setters of this crate: they survive a
+    /// read-modify-write cycle unchanged, no matter which modeled fields were changed.
+    ///
+    /// # Format-specific
+    /// In id3 these are frame ids (`APIC` for pictures other than the front cover), in mp4 atom
+    /// fourccs or `----:mean:name` for freeform atoms outside of `com.apple.iTunes`. The Vorbis
+    /// based formats expose every unknown key as a comment, so only pictures other than the front
+    /// cover are listed, as `PICTURE`.
+    #[must_use]
+    pub fn retained_unknown_fields(&self) -> Vec<String> {
+        let mut fields: Vec<String> = match self {
+            #[cfg(feature = "id3")]
+            Self::Id3Tag { inner } => inner
+                .frames()
+                .filter(|frame| match frame.content().picture() {
+                    Some(pic) => pic.picture_type != id3::frame::PictureType::CoverFront,
+                    None => !ID3_FIELD_FRAMES.contains(&frame.id()),
+                })
+                .map(|frame| frame.id().to_string())
+                .collect(),
+            #[cfg(feature = "metaflac")]
+            Self::VorbisFlacTag { inner } => inner
+                .pictures()
+                .filter(|pic| pic.picture_type != metaflac::block::PictureType::CoverFront)
+                .map(|_| "PICTURE".to_string())
+                .collect(),
+            #[cfg(feature = "mp4ameta")]
+            Self::Mp4Tag { inner } => inner
+                .data()
+                .filter_map(|(ident, _)| match ident {
+                    Mp4DataIdent::Fourcc(fourcc) if !MP4_FIELD_FOURCCS.contains(fourcc) => {
+                        Some(fourcc.to_string())
+                    }
+                    Mp4DataIdent::Freeform { mean, name } if mean != "com.apple.iTunes" => {
+                        Some(format!("----:{mean}:{name}"))
+                    }
+                    _ => None,
+                })
+                .collect(),
+            #[cfg(feature = "opusmeta")]
+            Self::OpusTag { inner } => inner
+                .pictures()
+                .into_iter()
+                .filter(|pic| pic.picture_type != opusmeta::picture::PictureType::CoverFront)
+                .map(|_| "PICTURE".to_string())
+                .collect(),
+            #[cfg(feature = "oggmeta")]
+            Self::OggTag { inner } => inner
+                .pictures
+                .iter()
+                .filter(|pic| !matches!(pic.picture_type, oggmeta::PictureType::FrontCover))
+                .map(|_| "PICTURE".to_string())
+                .collect(),
+        };
+
+        fields.sort_unstable();
+        fields
+    }
+
+    /// Compares this tag to `other` and returns every [`Field`] whose value differs.
+    ///
+    /// An empty result means writing `other` in place of `self` would not change any metadata.
+    /// Both tags may be of different formats, in which case only the common fields are compared.
+    #[must_use]
+    pub fn diff(&self, other: &Self) -> Vec<Field> {
+        let mut changes = Vec::new();
+
+        if self.title() != other.title() {
+            changes.push(Field::Title);
+        }
+        if self.artist() != other.artist() {
+            changes.push(Field::Artist);
+        }
+
+        let album = self.get_album_info().unwrap_or_default();
+        let other_album = other.get_album_info().unwrap_or_default();
+        if album.title != other_album.title {
+            changes.push(Field::AlbumTitle);
+        }
+        if album.artist != other_album.artist {
+            changes.push(Field::AlbumArtist);
+        }
+        if album.cover != other_album.cover {
+            changes.push(Field::AlbumCover);
+        }
+
+        if self.date() != other.date() {
+            changes.push(Field::Date);
+        }
+        if self.genres() != other.genres() {
+            changes.push(Field::Genres);
+        }
+        if self.lyrics().filter(|l| !l.is_empty()) != other.lyrics().filter(|l| !l.is_empty()) {
+            changes.push(Field::Lyrics);
+        }
+        if self.encoder() != other.encoder() {
+            changes.push(Field::Encoder);
+        }
+        if self.copyright() != other.copyright() {
+            changes.push(Field::Copyright);
+        }
+        if self.artist_url() != other.artist_url() {
+            changes.push(Field::ArtistUrl);
+        }
+        if self.source_url() != other.source_url() {
+            changes.push(Field::SourceUrl);
+        }
+        if self.total_tracks() != other.total_tracks() {
+            changes.push(Field::TotalTracks);
+        }
+        if self.podcast() != other.podcast() {
+            changes.push(Field::Podcast);
+        }
+        if self.feed_url() != other.feed_url() {
+            changes.push(Field::FeedUrl);
+        }
+        if self.episode_id() != other.episode_id() {
+            changes.push(Field::EpisodeId);
+        }
+        if self.category() != other.category() {
+            changes.push(Field::Category);
+        }
+
+        let mut keys = self.comment_keys();
+        keys.extend(other.comment_keys());
+        keys.sort_unstable();
+        keys.dedup();
+        for key in keys {
+            if self.get_comment(&key) != other.get_comment(&key) {
+                changes.push(Field::Comment(key));
+            }
+        }
+
+        // id3 compares frames regardless of their order, which catches frames like UFID that
+        // have no dedicated accessor.
+        #[cfg(feature = "id3")]
+        #[allow(irrefutable_let_patterns)]
+        if let (Self::Id3Tag { inner }, Self::Id3Tag { inner: other }) = (self, other) {
+            if changes.is_empty() && inner != other {
+                changes.push(Field::Other);
+            }
+        }
+
+        changes
+    }
+}
+
+#[cfg(any(feature = "metaflac", feature = "opusmeta", feature = "oggmeta"))]
+fn without_vorbis_fields<'a>(
+    keys: impl Iterator<Item = &'a (impl AsRef<str> + 'a + ?Sized)>,
+) -> Vec<String> {
+    keys.map(AsRef::as_ref)
+        .filter(|k| {
+            !VORBIS_FIELD_KEYS
+                .iter()
+                .any(|field| field.eq_ignore_ascii_case(k))
+        })
+        .map(str::to_string)
+        .collect()
 }
 
 #[cfg(test)]
+#[cfg_attr(
+    not(any(
+        feature = "id3",
+        feature = "metaflac",
+        feature = "mp4ameta",
+        feature = "opusmeta"
+    )),
+    allow(dead_code, unused_macros)
+)]
 mod tests {
     use super::*;
+    use crate::data::Precision;
 
-    const TEST_FILE: &str = "empty.";
-    const INPUT_PATH: &str = "testin";
+    pub(crate) const TEST_FILE: &str = "empty.";
+    pub(crate) const INPUT_PATH: &str = "testin";
     const OUTPUT_PATH: &str = "testout";
 
     macro_rules! tag_tests {
@@ -908,7 +1945,7 @@ mod tests {
         mod $name {
             #[test]
             fn test_set_comment() {
-                let in_file = std::env::current_dir().unwrap().join(crate::tests::INPUT_PATH).join(format!("{}{}", crate::tests::TEST_FILE, stringify!($name)));
+                let in_file = crate::testdata::input_file(stringify!($name));
                 let out_file = std::env::current_dir().unwrap().join(crate::tests::OUTPUT_PATH);
                 std::fs::create_dir_all(&out_file).unwrap();
                 let out_file = out_file.join(format!("{}{}", "add_comment.", stringify!($name)));
@@ -928,7 +1965,7 @@ mod tests {
 
             #[test]
             fn test_remove_comment() {
-                let in_file = std::env::current_dir().unwrap().join(crate::tests::INPUT_PATH).join(format!("{}{}", crate::tests::TEST_FILE, stringify!($name)));
+                let in_file = crate::testdata::input_file(stringify!($name));
                 let out_file = std::env::current_dir().unwrap().join(crate::tests::OUTPUT_PATH);
                 std::fs::create_dir_all(&out_file).unwrap();
                 let out_file = out_file.join(format!("{}{}", "remove_comment.", stringify!($name)));
@@ -947,10 +1984,562 @@ mod tests {
                 let tag = crate::Tag::read_from_path(&out_file).unwrap();
                 assert_eq!(tag.get_comment("Test Key"), None);
             }
+
+            #[test]
+            fn test_diff() {
+                let in_file = crate::testdata::input_file(stringify!($name));
+
+                let original = crate::Tag::read_from_path(&in_file).unwrap();
+                let mut tag = crate::Tag::read_from_path(&in_file).unwrap();
+                assert!(original.diff(&tag).is_empty());
+
+                tag.set_title("Title");
+                tag.set_comment("Test Key", "Comment Value".to_string());
+                let changes = original.diff(&tag);
+                assert_eq!(changes.len(), 2);
+                assert_eq!(changes[0], crate::data::Field::Title);
+                // vorbis based formats normalize the key casing
+                assert!(matches!(&changes[1], crate::data::Field::Comment(k) if k.eq_ignore_ascii_case("Test Key")));
+            }
+
+            #[test]
+            fn test_archival_fields() {
+                let in_file = crate::testdata::input_file(stringify!($name));
+                let out_file = std::env::current_dir().unwrap().join(crate::tests::OUTPUT_PATH);
+                std::fs::create_dir_all(&out_file).unwrap();
+                let out_file = out_file.join(format!("{}{}", "archival_fields.", stringify!($name)));
+                _ = std::fs::remove_file(&out_file);
+
+                let mut tag = crate::Tag::read_from_path(&in_file).unwrap();
+                tag.set_encoder("Lavf60.16.100");
+                tag.set_copyright("2024 Some Label");
+                tag.set_artist_url("https://example.com/artist");
+                tag.set_source_url("https://www.youtube.com/watch?v=dQw4w9WgXcQ");
+                std::fs::copy(&in_file, &out_file).unwrap();
+                tag.write_to_path(&out_file).unwrap();
+
+                // Assert
+                let mut tag = crate::Tag::read_from_path(&out_file).unwrap();
+                assert_eq!(tag.encoder().as_deref(), Some("Lavf60.16.100"));
+                assert_eq!(tag.copyright().as_deref(), Some("2024 Some Label"));
+                assert_eq!(tag.artist_url().as_deref(), Some("https://example.com/artist"));
+                assert_eq!(tag.source_url().as_deref(), Some("https://www.youtube.com/watch?v=dQw4w9WgXcQ"));
+                assert!(tag.comment_keys().is_empty());
+
+                tag.remove_artist_url();
+                assert_eq!(tag.artist_url(), None);
+            }
+
+            #[test]
+            fn test_total_tracks() {
+                let in_file = crate::testdata::input_file(stringify!($name));
+                let out_file = std::env::current_dir().unwrap().join(crate::tests::OUTPUT_PATH);
+                std::fs::create_dir_all(&out_file).unwrap();
+                let out_file = out_file.join(format!("{}{}", "total_tracks.", stringify!($name)));
+                _ = std::fs::remove_file(&out_file);
+
+                let mut tag = crate::Tag::read_from_path(&in_file).unwrap();
+                let original = crate::Tag::read_from_path(&in_file).unwrap();
+                tag.set_total_tracks(12);
+                assert_eq!(original.diff(&tag), vec![crate::data::Field::TotalTracks]);
+                std::fs::copy(&in_file, &out_file).unwrap();
+                tag.write_to_path(&out_file).unwrap();
+
+                // Assert
+                let mut tag = crate::Tag::read_from_path(&out_file).unwrap();
+                assert_eq!(tag.total_tracks(), Some(12));
+                assert!(tag.comment_keys().is_empty());
+
+                tag.remove_total_tracks();
+                assert_eq!(tag.total_tracks(), None);
+            }
+
+            #[test]
+            fn test_full_date() {
+                let in_file = crate::testdata::input_file(stringify!($name));
+                let out_file = std::env::current_dir().unwrap().join(crate::tests::OUTPUT_PATH);
+                std::fs::create_dir_all(&out_file).unwrap();
+                let out_file = out_file.join(format!("{}{}", "full_date.", stringify!($name)));
+                _ = std::fs::remove_file(&out_file);
+
+                let mut tag = crate::Tag::read_from_path(&in_file).unwrap();
+                tag.set_date("2024-03-09T17:05:42+02:00".parse().unwrap());
+                std::fs::copy(&in_file, &out_file).unwrap();
+                tag.write_to_path(&out_file).unwrap();
+
+                // Assert
+                let tag = crate::Tag::read_from_path(&out_file).unwrap();
+                let expected = if tag.backend() == crate::Backend::Id3 {
+                    "2024-03-09T15:05:42"
+                } else {
+                    "2024-03-09T17:05:42+02:00"
+                };
+                assert_eq!(tag.date().unwrap().to_string(), expected);
+            }
+
+            #[test]
+            fn test_accessor_cycle() {
+                let in_file = crate::testdata::input_file(stringify!($name));
+                let out_file = std::env::current_dir().unwrap().join(crate::tests::OUTPUT_PATH);
+                std::fs::create_dir_all(&out_file).unwrap();
+                let out_file = out_file.join(format!("{}{}", "accessor_cycle.", stringify!($name)));
+                _ = std::fs::remove_file(&out_file);
+
+                let snapshot = crate::testdata::accessor_cycle(&in_file, &out_file);
+                crate::testdata::assert_golden(&format!("accessors.{}", stringify!($name)), &snapshot);
+            }
+
+            #[test]
+            fn test_podcast_fields() {
+                let in_file = crate::testdata::input_file(stringify!($name));
+                let out_file = std::env::current_dir().unwrap().join(crate::tests::OUTPUT_PATH);
+                std::fs::create_dir_all(&out_file).unwrap();
+                let out_file = out_file.join(format!("{}{}", "podcast_fields.", stringify!($name)));
+                _ = std::fs::remove_file(&out_file);
+
+                let mut tag = crate::Tag::read_from_path(&in_file).unwrap();
+                assert!(!tag.podcast());
+                tag.set_podcast();
+                tag.set_feed_url("https://example.com/feed.xml");
+                tag.set_episode_id("episode-42");
+                tag.set_category("Technology");
+                std::fs::copy(&in_file, &out_file).unwrap();
+                tag.write_to_path(&out_file).unwrap();
+
+                // Assert
+                let mut tag = crate::Tag::read_from_path(&out_file).unwrap();
+                assert!(tag.podcast());
+                assert_eq!(tag.feed_url().as_deref(), Some("https://example.com/feed.xml"));
+                assert_eq!(tag.episode_id().as_deref(), Some("episode-42"));
+                assert_eq!(tag.category().as_deref(), Some("Technology"));
+                assert!(tag.comment_keys().is_empty());
+
+                tag.remove_podcast();
+                assert!(!tag.podcast());
+            }
+
+            #[test]
+            fn test_retain_unknown_fields() {
+                let in_file = crate::testdata::input_file(stringify!($name));
+                let out_file = std::env::current_dir().unwrap().join(crate::tests::OUTPUT_PATH);
+                std::fs::create_dir_all(&out_file).unwrap();
+                let out_file = out_file.join(format!("{}{}", "retain_unknown.", stringify!($name)));
+                _ = std::fs::remove_file(&out_file);
+
+                let mut tag = crate::Tag::read_from_path(&in_file).unwrap();
+                crate::tests::add_rich_fields(&mut tag);
+                std::fs::copy(&in_file, &out_file).unwrap();
+                tag.write_to_path(&out_file).unwrap();
+
+                let tag = crate::Tag::read_from_path(&out_file).unwrap();
+                let unknown = tag.retained_unknown_fields();
+                assert!(!unknown.is_empty());
+                let comments = tag.comment_keys();
+
+                // Rewrite the modeled fields the way myousync does
+                let mut tag = crate::Tag::read_from_path(&out_file).unwrap();
+                tag.remove_title();
+                tag.set_title("New Title");
+                tag.remove_artist();
+                tag.set_artist("New Artist");
+                let mut album = tag.get_album_info().unwrap_or_default();
+                album.title = Some("New Album".into());
+                tag.remove_all_album_info();
+                tag.set_album_info(album).unwrap();
+                tag.write_to_path(&out_file).unwrap();
+
+                // Assert
+                let tag = crate::Tag::read_from_path(&out_file).unwrap();
+                assert_eq!(tag.title(), Some("New Title"));
+                assert_eq!(tag.retained_unknown_fields(), unknown);
+                assert_eq!(tag.comment_keys(), comments);
+                assert_eq!(tag.get_comment("youtube_id").as_deref(), Some("dQw4w9WgXcQ"));
+            }
         }
     )*
 }
 }
 
-    tag_tests!(mp3 flac m4a opus);
+    /// Adds modeled fields, a comment and some fields multitag does not model to `tag`.
+    fn add_rich_fields(tag: &mut crate::Tag) {
+        tag.set_title("Title");
+        tag.set_artist("Artist");
+        tag.set_album_info(crate::data::Album {
+            title: Some("Album".into()),
+            artist: Some("Album Artist".into()),
+            cover: None,
+        })
+        .unwrap();
+        tag.set_encoder("Lavf60.16.100");
+        tag.set_comment("youtube_id", "dQw4w9WgXcQ".into());
+
+        match tag {
+            #[cfg(feature = "id3")]
+            crate::Tag::Id3Tag { inner } => {
+                use id3::TagLike;
+                inner.add_frame(id3::frame::Frame::text("TCOM", "Composer"));
+                inner.add_frame(id3::frame::Picture {
+                    mime_type: "image/jpeg".into(),
+                    picture_type: id3::frame::PictureType::CoverBack,
+                    description: String::new(),
+                    data: vec![0xFF, 0xD8, 0xFF, 0xD9],
+                });
+            }
+            #[cfg(feature = "metaflac")]
+            crate::Tag::VorbisFlacTag { inner } => {
+                inner.add_picture(
+                    "image/jpeg",
+                    metaflac::block::PictureType::CoverBack,
+                    vec![0xFF, 0xD8, 0xFF, 0xD9],
+                );
+            }
+            #[cfg(feature = "mp4ameta")]
+            crate::Tag::Mp4Tag { inner } => {
+                inner.set_data(
+                    mp4ameta::Fourcc(*b"\xa9wrt"),
+                    mp4ameta::Data::Utf8("Composer".into()),
+                );
+                inner.set_data(
+                    mp4ameta::FreeformIdent::new_static("com.example", "RATING"),
+                    mp4ameta::Data::Utf8("5".into()),
+                );
+            }
+            #[cfg(feature = "opusmeta")]
+            crate::Tag::OpusTag { inner } => {
+                let mut pic: opusmeta::picture::Picture = crate::data::Picture {
+                    data: vec![0xFF, 0xD8, 0xFF, 0xD9],
+                    mime_type: "image/jpeg".into(),
+                }
+                .into();
+                pic.picture_type = opusmeta::picture::PictureType::CoverBack;
+                inner.add_picture(&pic).unwrap();
+            }
+            #[cfg(feature = "oggmeta")]
+            crate::Tag::OggTag { .. } => {}
+        }
+    }
+
+    #[test]
+    fn test_timestamp_roundtrip() {
+        for input in [
+            "2024",
+            "2024-03",
+            "2024-03-09",
+            "2024-03-09T17:05",
+            "2024-03-09T17:05:42",
+            "2024-03-09T17Z",
+            "2024-03-09T17:05:42Z",
+            "2024-03-09T17:05+05:30",
+            "2024-03-09T17:05:42-08:00",
+        ] {
+            let timestamp: Timestamp = input.parse().unwrap();
+            assert_eq!(timestamp.to_string(), input);
+        }
+        assert_eq!(
+            "2024-03-09 17:05:42+0200".parse::<Timestamp>().unwrap(),
+            "2024-03-09T17:05:42+02:00".parse().unwrap()
+        );
+        assert!("".parse::<Timestamp>().is_err());
+        assert!("20x4-03".parse::<Timestamp>().is_err());
+        assert!("2024-13".parse::<Timestamp>().is_err());
+        assert!("2023-02-29".parse::<Timestamp>().is_err());
+        assert!("2024-03-09T24:00".parse::<Timestamp>().is_err());
+        assert!("2024-03-09T17:05+2".parse::<Timestamp>().is_err());
+    }
+
+    #[test]
+    fn test_timestamp_precision() {
+        let timestamp: Timestamp = "2024-03-09T17:05:42+02:00".parse().unwrap();
+        assert_eq!(timestamp.precision(), Precision::Second);
+        assert_eq!(
+            "2024-03".parse::<Timestamp>().unwrap().precision(),
+            Precision::Month
+        );
+
+        let truncated = timestamp.truncated(Precision::Hour);
+        assert_eq!(truncated.to_string(), "2024-03-09T17+02:00");
+        assert_eq!(
+            timestamp.truncated(Precision::Day).to_string(),
+            "2024-03-09"
+        );
+    }
+
+    #[test]
+    fn test_timestamp_to_utc() {
+        for (input, expected) in [
+            ("2024-03-09T17:05:42+02:00", "2024-03-09T15:05:42Z"),
+            ("2024-03-01T01:00-08:00", "2024-03-01T09:00Z"),
+            ("2024-03-01T01:00+02:00", "2024-02-29T23:00Z"),
+            ("2024-12-31T23:30-01:00", "2025-01-01T00:30Z"),
+            ("2024-03-09T17+05:30", "2024-03-09T11:30Z"),
+            ("2024-03-09T17:05", "2024-03-09T17:05"),
+        ] {
+            let timestamp: Timestamp = input.parse().unwrap();
+            assert_eq!(timestamp.to_utc().to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn test_error_context() {
+        let Err(err) = crate::Tag::read_from_path("Cargo.toml") else {
+            panic!("Cargo.toml should not be readable as audio file");
+        };
+        assert!(matches!(
+            err.kind(),
+            crate::ErrorKind::UnsupportedAudioFormat
+        ));
+        assert_eq!(err.path(), Some(std::path::Path::new("Cargo.toml")));
+        assert_eq!(
+            err.to_string(),
+            "Unsupported audio format (path: Cargo.toml)"
+        );
+    }
+
+    #[cfg(feature = "id3")]
+    #[test]
+    fn test_read_lenient_id3() {
+        fn frame(id: &[u8], body: &[u8]) -> Vec<u8> {
+            let mut frame = id.to_vec();
+            frame.extend_from_slice(&u32::try_from(body.len()).unwrap().to_be_bytes());
+            frame.extend_from_slice(&[0, 0]);
+            frame.extend_from_slice(body);
+            frame
+        }
+
+        let mut frames = frame(b"TIT2", b"\x03Title");
+        frames.extend(frame(b"TPE1", b"\x03Art\xFFist"));
+        // Picture whose declared size reaches past the end of the tag.
+        let mut apic = frame(b"APIC", b"\x00image/jpeg\x00\x03\x00\xFF\xD8");
+        apic[4..8].copy_from_slice(&1000u32.to_be_bytes());
+        frames.extend(apic);
+
+        let size = u32::try_from(frames.len()).unwrap();
+        let mut data = b"ID3\x03\x00\x00".to_vec();
+        data.extend(
+            (0..4)
+                .rev()
+                .map(|i| u8::try_from((size >> (7 * i)) & 0x7F).unwrap()),
+        );
+        data.extend(frames);
+
+        assert!(crate::Tag::read_from("mp3", std::io::Cursor::new(&data)).is_err());
+        let (tag, skipped) =
+            crate::Tag::read_from_lenient("mp3", std::io::Cursor::new(&data)).unwrap();
+        assert_eq!(tag.title(), Some("Title"));
+        assert_eq!(tag.artist(), Some("Art\u{FFFD}ist".to_string()));
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].item, "APIC");
+    }
+
+    #[cfg(feature = "opusmeta")]
+    #[test]
+    fn test_ogg_dispatch() {
+        let opus = crate::testdata::generate("opus");
+        let mut cursor = std::io::Cursor::new(&opus);
+        assert_eq!(
+            crate::Tag::detect_ogg_codec(&mut cursor).unwrap(),
+            crate::data::OggCodec::Opus
+        );
+        assert_eq!(cursor.position(), 0);
+        let tag = crate::Tag::read_from("ogg", cursor).unwrap();
+        assert_eq!(tag.backend(), crate::Backend::Opusmeta);
+
+        let mut flac = b"OggS\x00\x02".to_vec();
+        flac.extend([0; 20]);
+        flac.extend([1, 51]);
+        flac.extend(b"\x7FFLAC\x01\x00");
+        let Err(err) = crate::Tag::read_from("ogg", std::io::Cursor::new(&flac)) else {
+            panic!("flac in ogg should not be readable");
+        };
+        assert!(matches!(
+            err.kind(),
+            crate::ErrorKind::UnsupportedOggCodec(crate::data::OggCodec::Flac)
+        ));
+    }
+
+    #[test]
+    fn test_check_supported() {
+        assert!(!crate::Tag::supports_extension("webm"));
+        assert!(!crate::Tag::supports_extension("mka"));
+
+        let dir = std::env::current_dir().unwrap().join(OUTPUT_PATH);
+        std::fs::create_dir_all(&dir).unwrap();
+        let webm = dir.join("check_supported.webm");
+        std::fs::write(&webm, b"\x1A\x45\xDF\xA3").unwrap();
+        let err = crate::Tag::check_supported(&webm).unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            crate::ErrorKind::UnsupportedAudioFormat
+        ));
+        assert_eq!(err.path(), Some(webm.as_path()));
+
+        #[cfg(feature = "opusmeta")]
+        {
+            assert!(crate::Tag::supports_extension("opus"));
+            let ogg = dir.join("check_supported.ogg");
+            std::fs::write(&ogg, crate::testdata::generate("opus")).unwrap();
+            crate::Tag::check_supported(&ogg).unwrap();
+
+            let mut flac = b"OggS\x00\x02".to_vec();
+            flac.extend([0; 20]);
+            flac.extend([1, 51]);
+            flac.extend(b"\x7FFLAC\x01\x00");
+            std::fs::write(&ogg, flac).unwrap();
+            let err = crate::Tag::check_supported(&ogg).unwrap_err();
+            assert!(matches!(
+                err.kind(),
+                crate::ErrorKind::UnsupportedOggCodec(crate::data::OggCodec::Flac)
+            ));
+        }
+    }
+
+    #[cfg(feature = "id3")]
+    #[test]
+    fn test_dsf_roundtrip() {
+        let mut data = b"DSD ".to_vec();
+        data.extend(28u64.to_le_bytes());
+        data.extend(0u64.to_le_bytes()); // total size, fixed on write
+        data.extend(0u64.to_le_bytes()); // no metadata yet
+        data.extend(b"fmt ");
+        data.extend(20u64.to_le_bytes());
+        data.extend([0; 8]);
+        data.extend(b"data");
+        data.extend(16u64.to_le_bytes());
+        data.extend([0x69; 4]);
+        let audio_end = data.len() as u64;
+
+        let mut tag = crate::Tag::read_from("dsf", std::io::Cursor::new(&data)).unwrap();
+        assert_eq!(tag.title(), None);
+        tag.set_title("Title");
+        tag.write_to_vec(&mut data).unwrap();
+        // Rewriting replaces the tag instead of appending a second one
+        tag.write_to_vec(&mut data).unwrap();
+
+        assert_eq!(data[12..20], (data.len() as u64).to_le_bytes());
+        assert_eq!(data[20..28], audio_end.to_le_bytes());
+        let tag = crate::Tag::read_from("dsf", std::io::Cursor::new(&data)).unwrap();
+        assert_eq!(tag.title(), Some("Title"));
+    }
+
+    #[cfg(feature = "id3")]
+    #[test]
+    fn test_dff_roundtrip() {
+        let mut data = b"FRM8".to_vec();
+        data.extend(0u64.to_be_bytes()); // container size, fixed on write
+        data.extend(b"DSD ");
+        data.extend(b"FVER");
+        data.extend(4u64.to_be_bytes());
+        data.extend([1, 5, 0, 0]);
+        data.extend(b"DSD ");
+        data.extend(3u64.to_be_bytes());
+        data.extend([0x69, 0x69, 0x69, 0]);
+
+        let mut tag = crate::Tag::read_from("dff", std::io::Cursor::new(&data)).unwrap();
+        assert_eq!(tag.title(), None);
+        tag.set_title("Title");
+        tag.write_to_vec(&mut data).unwrap();
+        let first_len = data.len();
+        tag.write_to_vec(&mut data).unwrap();
+
+        assert_eq!(data.len(), first_len);
+        assert_eq!(data[4..12], (data.len() as u64 - 12).to_be_bytes());
+        let tag = crate::Tag::read_from("dff", std::io::Cursor::new(&data)).unwrap();
+        assert_eq!(tag.title(), Some("Title"));
+
+        let mut tag = tag;
+        tag.remove_title();
+        tag.write_to_vec(&mut data).unwrap();
+        assert_eq!(data.len(), 48);
+    }
+
+    #[cfg(feature = "id3")]
+    #[test]
+    fn test_export_cover() {
+        let out_dir = std::env::current_dir().unwrap().join(OUTPUT_PATH);
+        std::fs::create_dir_all(&out_dir).unwrap();
+        let out_file = out_dir.join("export_cover.jpg");
+        _ = std::fs::remove_file(&out_file);
+
+        let mut tag = crate::Tag::new_empty_id3();
+        assert!(!tag.export_cover(&out_file, None).unwrap());
+
+        let cover = crate::data::Picture {
+            data: vec![0xFF, 0xD8, 0xFF, 0xD9],
+            mime_type: "image/jpeg".into(),
+        };
+        tag.set_album_info(crate::data::Album {
+            title: Some("Album".into()),
+            cover: Some(cover.clone()),
+            ..Default::default()
+        })
+        .unwrap();
+
+        // Undecodable pictures are exported unchanged even when downscaling was requested
+        assert!(tag.export_cover(&out_file, Some(16)).unwrap());
+        assert_eq!(std::fs::read(&out_file).unwrap(), cover.data);
+    }
+
+    #[cfg(feature = "image")]
+    #[test]
+    fn test_picture_downscaled() {
+        let mut data = Vec::new();
+        image::RgbImage::new(64, 32)
+            .write_to(
+                &mut std::io::Cursor::new(&mut data),
+                image::ImageFormat::Jpeg,
+            )
+            .unwrap();
+        let picture = crate::data::Picture {
+            data,
+            mime_type: "image/jpeg".into(),
+        };
+
+        assert!(picture.downscaled(64).is_none());
+        let small = picture.downscaled(16).unwrap();
+        let img = image::load_from_memory(&small.data).unwrap();
+        assert_eq!((img.width(), img.height()), (16, 8));
+    }
+
+    #[cfg(feature = "image")]
+    #[test]
+    fn test_picture_reencoded() {
+        let mut data = Vec::new();
+        image::RgbaImage::new(64, 32)
+            .write_to(
+                &mut std::io::Cursor::new(&mut data),
+                image::ImageFormat::Png,
+            )
+            .unwrap();
+        let picture = crate::data::Picture {
+            data,
+            mime_type: "image/png".into(),
+        };
+
+        assert_eq!(picture.dimensions(), Some((64, 32)));
+        let jpeg = picture.reencoded(None, "image/jpeg").unwrap();
+        assert_eq!(jpeg.mime_type, "image/jpeg");
+        let img =
+            image::load_from_memory_with_format(&jpeg.data, image::ImageFormat::Jpeg).unwrap();
+        assert_eq!((img.width(), img.height()), (64, 32));
+
+        let small = picture.reencoded(Some(16), "image/png").unwrap();
+        let img =
+            image::load_from_memory_with_format(&small.data, image::ImageFormat::Png).unwrap();
+        assert_eq!((img.width(), img.height()), (16, 8));
+
+        assert!(picture.reencoded(None, "image/webp").is_none());
+    }
+
+    #[cfg(feature = "id3")]
+    tag_tests!(mp3);
+    #[cfg(feature = "id3")]
+    tag_tests!(wav);
+    #[cfg(feature = "id3")]
+    tag_tests!(aiff);
+    #[cfg(feature = "metaflac")]
+    tag_tests!(flac);
+    #[cfg(feature = "mp4ameta")]
+    tag_tests!(m4a);
+    #[cfg(feature = "opusmeta")]
+    tag_tests!(opus);
 }
@@ -0,0 +1,54 @@
+//! Detection of the codec inside an Ogg container, which decides between the opus and vorbis
+//! backends.
+
+use crate::data::OggCodec;
+use crate::{ErrorKind, Result, Tag};
+use std::io::{Read, Seek, SeekFrom};
+
+/// Length of the fixed part of an Ogg page header, up to and including the segment count.
+const PAGE_HEADER_LEN: usize = 27;
+
+impl Tag {
+    /// Detects the codec of an Ogg file by inspecting the first packet of its first page.
+    ///
+    /// `.ogg` files can contain vorbis, opus or flac streams, which all need different handling.
+    /// [`Tag::read_from`] uses this to pick the backend for the `ogg` extension.
+    /// The reader is left at the position it was at before the call.
+    ///
+    /// # Errors
+    /// This function errors if the reader fails or the data does not start with an Ogg page.
+    pub fn detect_ogg_codec<R: Read + Seek>(mut f_in: R) -> Result<OggCodec> {
+        let start = f_in.stream_position()?;
+        let codec = read_codec(&mut f_in);
+        f_in.seek(SeekFrom::Start(start))?;
+        codec
+    }
+}
+
+fn read_codec(mut f_in: impl Read) -> Result<OggCodec> {
+    let mut header = [0u8; PAGE_HEADER_LEN];
+    f_in.read_exact(&mut header)?;
+    if &header[0..4] != b"OggS" {
+        return Err(ErrorKind::UnsupportedAudioFormat.into());
+    }
+
+    let mut lacing = vec![0u8; usize::from(header[26])];
+    f_in.read_exact(&mut lacing)?;
+
+    // A packet shorter than the longest magic simply matches nothing
+    let mut packet = Vec::new();
+    f_in.take(8).read_to_end(&mut packet)?;
+
+    let codec = if packet.starts_with(b"\x01vorbis") {
+        OggCodec::Vorbis
+    } else if packet.starts_with(b"OpusHead") {
+        OggCodec::Opus
+    } else if packet.starts_with(b"\x7FFLAC") {
+        OggCodec::Flac
+    } else if packet.starts_with(b"\x80theora") {
+        OggCodec::Theora
+    } else {
+        OggCodec::Unknown
+    };
+    Ok(codec)
+}
@@ -0,0 +1,442 @@
+//! Test corpus and golden file harness.
+//!
+//! The input files are generated as the smallest files each backend accepts, so no binaries need
+//! to be committed. Snapshots of the accessors are compared against the golden files in
+//! `testin/golden`, run the tests with `UPDATE_GOLDEN=1` to rewrite them after an intended change.
+//!
+//! Ogg vorbis is not part of the corpus, oggmeta decodes the headers with libtheora and would need
+//! a complete vorbis setup header.
+
+use crate::data::{Album, Picture};
+use crate::tests::{INPUT_PATH, TEST_FILE};
+use crate::Tag;
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+const GOLDEN_PATH: &str = "golden";
+
+/// Extensions generated in this run, so parallel tests don't see half written files.
+static GENERATED: Mutex<Option<HashSet<String>>> = Mutex::new(None);
+
+/// Returns the path of the generated empty input file for `extension`.
+pub(crate) fn input_file(extension: &str) -> PathBuf {
+    let dir = std::env::current_dir().unwrap().join(INPUT_PATH);
+    let path = dir.join(format!("{TEST_FILE}{extension}"));
+
+    let mut generated = GENERATED.lock().unwrap();
+    if generated
+        .get_or_insert_with(HashSet::new)
+        .insert(extension.to_owned())
+    {
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(&path, generate(extension)).unwrap();
+    }
+    path
+}
+
+/// Builds a minimal valid file of the given container without any tags.
+pub(crate) fn generate(extension: &str) -> Vec<u8> {
+    match extension {
+        "mp3" => mp3(),
+        "wav" => wav(),
+        "aiff" => aiff(),
+        "flac" => flac(),
+        "m4a" => m4a(),
+        "opus" => opus(),
+        _ => panic!("No generator for .{extension}"),
+    }
+}
+
+/// A few frames of MPEG-1 layer III, 128 kbit/s, 44.1 kHz, stereo.
+fn mp3() -> Vec<u8> {
+    let mut data = Vec::new();
+    for _ in 0..4 {
+        data.extend([0xFF, 0xFB, 0x90, 0x00]);
+        data.extend([0; 413]);
+    }
+    data
+}
+
+fn wav() -> Vec<u8> {
+    let mut fmt = Vec::new();
+    fmt.extend(1u16.to_le_bytes()); // PCM
+    fmt.extend(2u16.to_le_bytes());
+    fmt.extend(44100u32.to_le_bytes());
+    fmt.extend((44100u32 * 4).to_le_bytes());
+    fmt.extend(4u16.to_le_bytes());
+    fmt.extend(16u16.to_le_bytes());
+
+    let mut body = b"WAVE".to_vec();
+    body.extend(riff_chunk(*b"fmt ", &fmt));
+    body.extend(riff_chunk(*b"data", &[0; 4]));
+    riff_chunk(*b"RIFF", &body)
+}
+
+fn riff_chunk(id: [u8; 4], content: &[u8]) -> Vec<u8> {
+    let mut chunk = id.to_vec();
+    chunk.extend(u32::try_from(content.len()).unwrap().to_le_bytes());
+    chunk.extend(content);
+    chunk
+}
+
+fn aiff() -> Vec<u8> {
+    let mut comm = Vec::new();
+    comm.extend(2u16.to_be_bytes());
+    comm.extend(1u32.to_be_bytes());
+    comm.extend(16u16.to_be_bytes());
+    // 44100 as 80 bit extended float
+    comm.extend([0x40, 0x0E, 0xAC, 0x44, 0, 0, 0, 0, 0, 0]);
+
+    let mut ssnd = vec![0; 8];
+    ssnd.extend([0; 4]);
+
+    let mut body = b"AIFF".to_vec();
+    body.extend(iff_chunk(*b"COMM", &comm));
+    body.extend(iff_chunk(*b"SSND", &ssnd));
+    iff_chunk(*b"FORM", &body)
+}
+
+fn iff_chunk(id: [u8; 4], content: &[u8]) -> Vec<u8> {
+    let mut chunk = id.to_vec();
+    chunk.extend(u32::try_from(content.len()).unwrap().to_be_bytes());
+    chunk.extend(content);
+    chunk
+}
+
+/// Only the mandatory STREAMINFO block, without any frames.
+fn flac() -> Vec<u8> {
+    let mut data = b"fLaC".to_vec();
+    data.extend([0x80, 0, 0, 34]); // last block, STREAMINFO, 34 bytes
+    data.extend(4096u16.to_be_bytes());
+    data.extend(4096u16.to_be_bytes());
+    data.extend([0; 6]); // unknown frame sizes
+
+    // sample rate (20 bits), channels - 1 (3 bits), bits per sample - 1 (5 bits), no samples
+    data.extend((44100u64 << 44 | 1 << 41 | 15 << 36).to_be_bytes());
+    data.extend([0; 16]); // md5
+    data
+}
+
+/// An audio track without any samples.
+fn m4a() -> Vec<u8> {
+    const MATRIX: [u32; 9] = [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000];
+    let matrix: Vec<u8> = MATRIX.iter().flat_map(|v| v.to_be_bytes()).collect();
+
+    let mut ftyp = b"M4A ".to_vec();
+    ftyp.extend(0x200u32.to_be_bytes());
+    ftyp.extend(b"M4A isomiso2");
+
+    let mut mvhd = vec![0; 12];
+    mvhd.extend(1000u32.to_be_bytes()); // timescale
+    mvhd.extend(0u32.to_be_bytes()); // duration
+    mvhd.extend(0x0001_0000u32.to_be_bytes()); // rate
+    mvhd.extend(0x0100u16.to_be_bytes()); // volume
+    mvhd.extend([0; 10]);
+    mvhd.extend(&matrix);
+    mvhd.extend([0; 24]);
+    mvhd.extend(2u32.to_be_bytes()); // next track id
+
+    let mut tkhd = vec![0, 0, 0, 7]; // enabled, in movie, in preview
+    tkhd.extend([0; 8]);
+    tkhd.extend(1u32.to_be_bytes()); // track id
+    tkhd.extend([0; 20]);
+    tkhd.extend(0x0100u16.to_be_bytes()); // volume
+    tkhd.extend([0; 2]);
+    tkhd.extend(&matrix);
+    tkhd.extend([0; 8]);
+
+    let mut media_header = vec![0; 12];
+    media_header.extend(44100u32.to_be_bytes());
+    media_header.extend(0u32.to_be_bytes());
+    media_header.extend([0x55, 0xC4, 0, 0]); // undetermined language
+
+    let mut hdlr = vec![0; 8];
+    hdlr.extend(b"soun");
+    hdlr.extend([0; 13]);
+
+    let url = atom(*b"url ", &[0, 0, 0, 1]);
+    let mut dref = vec![0, 0, 0, 0, 0, 0, 0, 1];
+    dref.extend(url);
+
+    let empty_table = [0; 8];
+    let stbl = [
+        atom(*b"stsd", &empty_table),
+        atom(*b"stts", &empty_table),
+        atom(*b"stsc", &empty_table),
+        atom(*b"stsz", &[0; 12]),
+        atom(*b"stco", &empty_table),
+    ]
+    .concat();
+    let minf = [
+        atom(*b"smhd", &[0; 8]),
+        atom(*b"dinf", &atom(*b"dref", &dref)),
+        atom(*b"stbl", &stbl),
+    ]
+    .concat();
+    let mdia = [
+        atom(*b"mdhd", &media_header),
+        atom(*b"hdlr", &hdlr),
+        atom(*b"minf", &minf),
+    ]
+    .concat();
+    let trak = [atom(*b"tkhd", &tkhd), atom(*b"mdia", &mdia)].concat();
+    let moov = [atom(*b"mvhd", &mvhd), atom(*b"trak", &trak)].concat();
+
+    [
+        atom(*b"ftyp", &ftyp),
+        atom(*b"mdat", &[]),
+        atom(*b"moov", &moov),
+    ]
+    .concat()
+}
+
+fn atom(fourcc: [u8; 4], content: &[u8]) -> Vec<u8> {
+    let mut atom = u32::try_from(content.len() + 8)
+        .unwrap()
+        .to_be_bytes()
+        .to_vec();
+    atom.extend(fourcc);
+    atom.extend(content);
+    atom
+}
+
+/// The two opus header pages and a page with a single silent packet.
+fn opus() -> Vec<u8> {
+    let mut head = b"OpusHead".to_vec();
+    head.push(1); // version
+    head.push(2); // channels
+    head.extend(312u16.to_le_bytes()); // pre-skip
+    head.extend(48000u32.to_le_bytes());
+    head.extend(0i16.to_le_bytes()); // gain
+    head.push(0); // channel mapping family
+
+    let vendor = b"multitag";
+    let mut tags = b"OpusTags".to_vec();
+    tags.extend(u32::try_from(vendor.len()).unwrap().to_le_bytes());
+    tags.extend(vendor);
+    tags.extend(0u32.to_le_bytes());
+
+    [
+        ogg_page(0x02, 0, 0, &head),
+        ogg_page(0x00, 0, 1, &tags),
+        ogg_page(0x04, 960, 2, &[0xF8, 0xFF, 0xFE]),
+    ]
+    .concat()
+}
+
+fn ogg_page(header_type: u8, granule: u64, sequence: u32, packet: &[u8]) -> Vec<u8> {
+    assert!(packet.len() < 255, "test packets fit into a single segment");
+
+    let mut page = b"OggS".to_vec();
+    page.push(0); // version
+    page.push(header_type);
+    page.extend(granule.to_le_bytes());
+    page.extend(0x6D74_6167u32.to_le_bytes()); // serial
+    page.extend(sequence.to_le_bytes());
+    page.extend([0; 4]); // checksum, filled in below
+    page.push(1);
+    page.push(u8::try_from(packet.len()).unwrap());
+    page.extend(packet);
+
+    let crc = ogg_crc(&page);
+    page[22..26].copy_from_slice(&crc.to_le_bytes());
+    page
+}
+
+fn ogg_crc(data: &[u8]) -> u32 {
+    data.iter().fold(0u32, |crc, &byte| {
+        (0..8).fold(crc ^ (u32::from(byte) << 24), |crc, _| {
+            if crc & 0x8000_0000 == 0 {
+                crc << 1
+            } else {
+                (crc << 1) ^ 0x04C1_1DB7
+            }
+        })
+    })
+}
+
+/// Compares `snapshot` against the golden file `name`, or rewrites it with `UPDATE_GOLDEN` set.
+pub(crate) fn assert_golden(name: &str, snapshot: &Value) {
+    let path = std::env::current_dir()
+        .unwrap()
+        .join(INPUT_PATH)
+        .join(GOLDEN_PATH)
+        .join(format!("{name}.json"));
+    let actual = serde_json::to_string_pretty(snapshot).unwrap() + "\n";
+
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, actual).unwrap();
+        return;
+    }
+
+    let Ok(expected) = std::fs::read_to_string(&path) else {
+        panic!(
+            "Missing golden file {}, run with UPDATE_GOLDEN=1 to create it",
+            path.display()
+        );
+    };
+    assert_eq!(
+        expected,
+        actual,
+        "Snapshot differs from {}, run with UPDATE_GOLDEN=1 if the change is intended",
+        path.display()
+    );
+}
+
+/// A field with its set/get/remove methods.
+struct Accessor {
+    name: &'static str,
+    set: fn(&mut Tag),
+    get: fn(&Tag) -> Value,
+    remove: fn(&mut Tag),
+}
+
+const ACCESSORS: &[Accessor] = &[
+    Accessor {
+        name: "title",
+        set: |t| t.set_title("Title"),
+        get: |t| json!(t.title()),
+        remove: Tag::remove_title,
+    },
+    Accessor {
+        name: "artist",
+        set: |t| t.set_artist("Artist"),
+        get: |t| json!(t.artist()),
+        remove: Tag::remove_artist,
+    },
+    Accessor {
+        name: "album",
+        set: |t| {
+            t.set_album_info(Album {
+                title: Some("Album".into()),
+                artist: Some("Album Artist".into()),
+                cover: Some(Picture {
+                    data: vec![0xFF, 0xD8, 0xFF, 0xD9],
+                    mime_type: "image/jpeg".into(),
+                }),
+            })
+            .unwrap();
+        },
+        get: |t| {
+            json!(t.get_album_info().map(|a| json!({
+                "title": a.title,
+                "artist": a.artist,
+                "cover": a.cover.map(|c| c.to_string()),
+            })))
+        },
+        remove: Tag::remove_all_album_info,
+    },
+    Accessor {
+        name: "date",
+        set: |t| t.set_date("2024-03-09T17:05:42+02:00".parse().unwrap()),
+        get: |t| json!(t.date().map(|d| d.to_string())),
+        remove: Tag::remove_date,
+    },
+    Accessor {
+        name: "genres",
+        set: |t| t.set_genres(&["Synthpop", "New Wave"]),
+        get: |t| json!(t.genres()),
+        remove: Tag::remove_genres,
+    },
+    Accessor {
+        name: "lyrics",
+        set: |t| t.set_lyrics("Line one\nLine two"),
+        get: |t| json!(t.lyrics()),
+        remove: Tag::remove_lyrics,
+    },
+    Accessor {
+        name: "encoder",
+        set: |t| t.set_encoder("Encoder"),
+        get: |t| json!(t.encoder()),
+        remove: Tag::remove_encoder,
+    },
+    Accessor {
+        name: "copyright",
+        set: |t| t.set_copyright("2024 Label"),
+        get: |t| json!(t.copyright()),
+        remove: Tag::remove_copyright,
+    },
+    Accessor {
+        name: "artist_url",
+        set: |t| t.set_artist_url("https://example.com/artist"),
+        get: |t| json!(t.artist_url()),
+        remove: Tag::remove_artist_url,
+    },
+    Accessor {
+        name: "source_url",
+        set: |t| t.set_source_url("https://example.com/source"),
+        get: |t| json!(t.source_url()),
+        remove: Tag::remove_source_url,
+    },
+    Accessor {
+        name: "total_tracks",
+        set: |t| t.set_total_tracks(12),
+        get: |t| json!(t.total_tracks()),
+        remove: Tag::remove_total_tracks,
+    },
+    Accessor {
+        name: "podcast",
+        set: Tag::set_podcast,
+        get: |t| json!(t.podcast()),
+        remove: Tag::remove_podcast,
+    },
+    Accessor {
+        name: "feed_url",
+        set: |t| t.set_feed_url("https://example.com/feed.xml"),
+        get: |t| json!(t.feed_url()),
+        remove: Tag::remove_feed_url,
+    },
+    Accessor {
+        name: "episode_id",
+        set: |t| t.set_episode_id("episode-1"),
+        get: |t| json!(t.episode_id()),
+        remove: Tag::remove_episode_id,
+    },
+    Accessor {
+        name: "category",
+        set: |t| t.set_category("Music"),
+        get: |t| json!(t.category()),
+        remove: Tag::remove_category,
+    },
+    Accessor {
+        name: "comment",
+        set: |t| t.set_comment("Test Key", "Comment Value".into()),
+        get: |t| json!(t.get_comment("Test Key")),
+        remove: |t| t.remove_comment("Test Key", None),
+    },
+];
+
+/// Runs the set/get/remove cycle of every accessor on a copy of `in_file` at `out_file`.
+///
+/// Each value is read back from the written file, the snapshot also records the comment keys
+/// while the field is set, which shows fields leaking into the free form comments.
+pub(crate) fn accessor_cycle(in_file: &Path, out_file: &Path) -> Value {
+    let mut snapshot = serde_json::Map::new();
+    for accessor in ACCESSORS {
+        std::fs::copy(in_file, out_file).unwrap();
+
+        let mut tag = Tag::read_from_path(out_file).unwrap();
+        (accessor.set)(&mut tag);
+        tag.write_to_path(out_file).unwrap();
+        let mut tag = Tag::read_from_path(out_file).unwrap();
+        let set = (accessor.get)(&tag);
+        let comment_keys = tag.comment_keys();
+
+        (accessor.remove)(&mut tag);
+        tag.write_to_path(out_file).unwrap();
+        let tag = Tag::read_from_path(out_file).unwrap();
+
+        snapshot.insert(
+            accessor.name.into(),
+            json!({
+                "set": set,
+                "comment_keys": comment_keys,
+                "removed": (accessor.get)(&tag),
+            }),
+        );
+    }
+    Value::Object(snapshot)
+}
@@ -0,0 +1,121 @@
+//! Identification of downloads by their Chromaprint fingerprint, for tracks the text search on
+//! MusicBrainz cannot find, like remixes and covers with creative titles.
+
+use std::path::Path;
+
+use log::{debug, info};
+use serde::Deserialize;
+use thiserror::Error;
+use tokio::process::Command;
+
+use crate::{MsAcoustid, net::CLIENT, util::limiter::Limiter};
+
+/// AcoustID allows three requests per second
+pub static LIMITER: Limiter = Limiter::new("acoustid", std::time::Duration::from_millis(334));
+
+#[derive(Error, Debug)]
+pub enum AcoustidError {
+    #[error("IO error: {0}")]
+    IOError(#[from] std::io::Error),
+    #[error("fpcalc returned an error: {0}")]
+    CommandError(String),
+    #[error("Failed to parse response: {0}")]
+    JsonError(#[from] serde_json::Error),
+    #[error("Connection error: {0}")]
+    ConnectionError(#[from] reqwest::Error),
+    #[error("AcoustID returned an error: {0}")]
+    Api(String),
+}
+
+#[derive(Debug, Deserialize)]
+struct Fingerprint {
+    duration: f64,
+    fingerprint: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct LookupResponse {
+    status: String,
+    error: Option<LookupError>,
+    #[serde(default)]
+    results: Vec<LookupResult>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LookupError {
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct LookupResult {
+    score: f64,
+    #[serde(default)]
+    recordings: Vec<LookupRecording>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LookupRecording {
+    id: String,
+}
+
+/// Finds the MusicBrainz recording of the file at `path`.
+///
+/// Returns `None` if no fingerprint linked to a recording matches with at least `min_score`.
+pub async fn identify(config: &MsAcoustid, path: &Path) -> Result<Option<String>, AcoustidError> {
+    let fingerprint = fingerprint(config, path).await?;
+
+    let response: LookupResponse = {
+        let _slot = LIMITER.acquire().await;
+        CLIENT
+            .post("https://api.acoustid.org/v2/lookup")
+            .form(&[
+                ("client", config.api_key.as_str()),
+                ("meta", "recordings"),
+                (
+                    "duration",
+                    &(fingerprint.duration.round() as u64).to_string(),
+                ),
+                ("fingerprint", &fingerprint.fingerprint),
+            ])
+            .send()
+            .await?
+            .json()
+            .await?
+    };
+    if response.status != "ok" {
+        let message = response.error.map(|e| e.message).unwrap_or_default();
+        return Err(AcoustidError::Api(message));
+    }
+
+    let best = response
+        .results
+        .into_iter()
+        .filter(|r| r.score >= config.min_score && !r.recordings.is_empty())
+        .max_by(|a, b| a.score.total_cmp(&b.score));
+    let Some(mut best) = best else {
+        debug!("No fingerprint match for {}", path.display());
+        return Ok(None);
+    };
+    let recording = best.recordings.swap_remove(0);
+    info!(
+        "Fingerprint of {} matches recording {} with score {:.2}",
+        path.display(),
+        recording.id,
+        best.score
+    );
+    Ok(Some(recording.id))
+}
+
+async fn fingerprint(config: &MsAcoustid, path: &Path) -> Result<Fingerprint, AcoustidError> {
+    let output = Command::new(&config.fpcalc)
+        .arg("-json")
+        .arg(path)
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Err(AcoustidError::CommandError(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    Ok(serde_json::from_slice(&output.stdout)?)
+}
@@ -0,0 +1,263 @@
+//! Suggestions for completing releases of which only some tracks are synced, and keeping the
+//! album level tags of synced releases consistent.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+};
+
+use axum::http::StatusCode;
+use log::{info, warn};
+use multitag::data::Picture;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    MsState,
+    brainz::{self, BrainzError, BrainzMetadata, BrainzMultiSearch, BrainzTrack},
+    dbdata::{self, DbError, DbResult, FetchStatus, VideoStatus},
+    locale,
+    musicfiles::{self, AlbumTags},
+    util::queue::Priority,
+    yt_api::{self, YTError, YtSearchResult},
+};
+
+/// Number of youtube videos suggested for each missing track.
+const SEARCH_CANDIDATES: u32 = 3;
+
+#[derive(Error, Debug)]
+pub enum AlbumError {
+    #[error("Failed to get release: {0}")]
+    Brainz(#[from] BrainzError),
+    #[error("Failed to search youtube: {0}")]
+    YouTube(#[from] YTError),
+    #[error(transparent)]
+    Db(#[from] DbError),
+}
+
+/// Failed lookups are the fault of the upstream services, only database errors are our own.
+impl From<AlbumError> for (StatusCode, String) {
+    fn from(err: AlbumError) -> Self {
+        let status = match err {
+            AlbumError::Brainz(_) | AlbumError::YouTube(_) => StatusCode::BAD_GATEWAY,
+            AlbumError::Db(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        (status, err.to_string())
+    }
+}
+
+/// A release of which at least two tracks are synced.
+#[derive(Debug, Serialize)]
+pub struct SyncedRelease {
+    pub release_id: String,
+    pub album: Option<String>,
+    pub video_ids: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MissingTracks {
+    pub release_id: String,
+    pub title: String,
+    pub artist: Vec<String>,
+    pub total_tracks: usize,
+    pub missing: Vec<MissingTrack>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MissingTrack {
+    #[serde(flatten)]
+    pub track: BrainzTrack,
+    /// Youtube videos which might contain the track. Only filled when a search was requested.
+    pub candidates: Vec<YtSearchResult>,
+}
+
+/// A video picked to be synced as a track of a release.
+#[derive(Debug, Deserialize)]
+pub struct TrackPick {
+    pub video_id: String,
+    pub recording_id: String,
+}
+
+fn synced_results() -> DbResult<impl Iterator<Item = (String, BrainzMetadata)>> {
+    Ok(dbdata::DB
+        .get_all_videos()?
+        .into_iter()
+        .filter(|v| v.fetch_status != FetchStatus::Disabled)
+        .filter_map(|v| Some((v.video_id, v.override_result.or(v.last_result)?))))
+}
+
+pub fn synced_releases() -> DbResult<Vec<SyncedRelease>> {
+    let mut releases: HashMap<String, SyncedRelease> = HashMap::new();
+    for (video_id, result) in synced_results()? {
+        let Some(release_id) = result.brainz_release_id else {
+            continue;
+        };
+        releases
+            .entry(release_id.clone())
+            .or_insert_with(|| SyncedRelease {
+                release_id,
+                album: result.album,
+                video_ids: Vec::new(),
+            })
+            .video_ids
+            .push(video_id);
+    }
+
+    Ok(releases
+        .into_values()
+        .filter(|r| r.video_ids.len() >= 2)
+        .collect())
+}
+
+/// Lists the tracks of a release which are not synced yet.
+/// With `search` each missing track is also looked up on youtube.
+pub async fn missing_tracks(
+    s: &MsState,
+    release_id: &str,
+    search: bool,
+) -> Result<MissingTracks, AlbumError> {
+    let release = brainz::fetch_release(release_id).await?;
+    let synced: HashSet<String> = synced_results()?
+        .filter_map(|(_, r)| r.brainz_recording_id)
+        .collect();
+
+    let total_tracks = release.tracks.len();
+    let mut missing = Vec::new();
+    for track in release
+        .tracks
+        .into_iter()
+        .filter(|t| !synced.contains(&t.recording_id))
+    {
+        let candidates = if search {
+            let query = format!("{} {}", track.artist.join(" "), track.title);
+            yt_api::search_videos(&s.config, &query, SEARCH_CANDIDATES).await?
+        } else {
+            Vec::new()
+        };
+        missing.push(MissingTrack { track, candidates });
+    }
+
+    Ok(MissingTracks {
+        release_id: release.id,
+        title: release.title,
+        artist: release.artist,
+        total_tracks,
+        missing,
+    })
+}
+
+/// Starts syncing the picked videos, tagged directly with the metadata of their track.
+pub async fn enqueue_tracks(release_id: &str, picks: Vec<TrackPick>) -> Result<(), AlbumError> {
+    let release = brainz::fetch_release(release_id).await?;
+
+    for pick in picks {
+        let Some(track) = release
+            .tracks
+            .iter()
+            .find(|t| t.recording_id == pick.recording_id)
+        else {
+            warn!(
+                "Recording {} is not part of release {}",
+                pick.recording_id, release_id
+            );
+            continue;
+        };
+        if dbdata::DB.get_video(&pick.video_id)?.is_some() {
+            continue;
+        }
+
+        MsState::push_update(&mut VideoStatus {
+            video_id: pick.video_id.clone(),
+            last_query: Some(BrainzMultiSearch {
+                trackid: Some(track.recording_id.clone()),
+                title: track.title.clone(),
+                artist: Some(track.artist.join(", ")),
+                album: Some(release.title.clone()),
+            }),
+            override_result: Some(BrainzMetadata {
+                brainz_recording_id: Some(track.recording_id.clone()),
+                brainz_release_id: Some(release.id.clone()),
+                brainz_artist_ids: track.artist_ids.clone(),
+                title: track.title.clone(),
+                artist: track.artist.clone(),
+                album: Some(release.title.clone()),
+                album_artist: None,
+                release_form: None,
+                date: release.date.clone(),
+                genres: Vec::new(),
+            }),
+            ..Default::default()
+        })?;
+        MsState::enqueue_tagger(pick.video_id, Priority::High);
+    }
+
+    Ok(())
+}
+
+/// Harmonizes the album level tags (album title and artist, date, total tracks and cover) of all
+/// files of a release, so tracks matched weeks apart do not drift.
+///
+/// Only runs once every synced video of the release is categorized.
+pub async fn harmonize_release(s: &MsState, release_id: &str) -> Result<(), AlbumError> {
+    let videos: Vec<VideoStatus> = dbdata::DB
+        .get_all_videos()?
+        .into_iter()
+        .filter(|v| v.fetch_status != FetchStatus::Disabled)
+        .filter(|v| {
+            v.override_result
+                .as_ref()
+                .or(v.last_result.as_ref())
+                .and_then(|r| r.brainz_release_id.as_deref())
+                == Some(release_id)
+        })
+        .collect();
+    if videos.len() < 2
+        || videos
+            .iter()
+            .any(|v| v.fetch_status != FetchStatus::Categorized)
+    {
+        return Ok(());
+    }
+
+    let release = locale::preferred_release(release_id, &s.config.scrape.locale).await?;
+    let files: Vec<PathBuf> = videos
+        .iter()
+        .filter_map(|v| crate::find_file(s, &v.video_id))
+        .collect();
+
+    let tags = AlbumTags {
+        title: release.title,
+        artist: release.artist.join("; "),
+        date: release.date.and_then(|d| d.parse().ok()),
+        total_tracks: u32::try_from(release.tracks.len()).unwrap_or(u32::MAX),
+        cover: canonical_cover(&files),
+    };
+
+    for file in files {
+        match musicfiles::apply_album_to_file(&file, &tags) {
+            Ok(changes) if !changes.is_empty() => {
+                info!("Harmonized {}: {:?}", file.display(), changes);
+            }
+            Ok(_) => {}
+            Err(err) => warn!("Failed to harmonize {}: {:?}", file.display(), err),
+        }
+    }
+
+    Ok(())
+}
+
+/// Picks the cover shared by most files, preferring larger images on a tie.
+fn canonical_cover(files: &[PathBuf]) -> Option<Picture> {
+    let mut covers: Vec<(Picture, usize)> = Vec::new();
+    for cover in files.iter().filter_map(|f| musicfiles::read_cover(f)) {
+        match covers.iter_mut().find(|(c, _)| *c == cover) {
+            Some((_, count)) => *count += 1,
+            None => covers.push((cover, 1)),
+        }
+    }
+
+    covers
+        .into_iter()
+        .max_by_key(|(c, count)| (*count, c.data.len()))
+        .map(|(c, _)| c)
+}
@@ -0,0 +1,193 @@
+//! Payloads of the web API and the websocket, kept apart from the database models.
+//!
+//! Compatibility policy: [`API_VERSION`] is only raised for breaking changes, i.e. when a field
+//! is removed, renamed or changes its meaning. New fields are added without a bump, so clients
+//! must ignore fields they do not know. Payloads carry the version they were built with and
+//! every response has it in the `X-Api-Version` header.
+//!
+//! Nested values like [`BrainzMetadata`] are shared with the database, as they are plain
+//! metadata. Changing their serialized form is a breaking change all the same.
+
+use std::path::PathBuf;
+
+use axum::{http::HeaderValue, response::Response};
+use serde::Serialize;
+
+use crate::{
+    brainz::{BrainzCandidate, BrainzMetadata, BrainzMultiSearch, MatchAttempt},
+    dbdata::{FetchStatus, StatusChange, VideoStatus},
+    errors::ErrorCode,
+    jobs::{self, RetryInfo},
+    removal::Removal,
+    ytdlp::DownloadProgress,
+};
+
+pub const API_VERSION: u32 = 3;
+
+#[derive(Debug, Serialize)]
+pub struct Video {
+    pub video_id: String,
+    pub fetch_time: u64,
+    pub fetch_status: FetchStatus,
+    pub last_update: u64,
+    pub last_query: Option<BrainzMultiSearch>,
+    pub last_result: Option<BrainzMetadata>,
+    pub last_error: Option<String>,
+    /// The kind of `last_error`, to group videos which failed for the same reason
+    pub error_code: Option<ErrorCode>,
+    pub override_query: Option<BrainzMultiSearch>,
+    pub override_result: Option<BrainzMetadata>,
+    /// Set when the video was removed from its playlist
+    pub removal: Option<Removal>,
+    /// Recordings found by the last search, to choose from with `POST /video/{video}/choose_candidate`
+    pub candidates: Vec<BrainzCandidate>,
+    /// How likely the result is right, from 0 to 1. Not set for overridden results
+    pub confidence: Option<f64>,
+    /// Progress of a running download
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub download_progress: Option<DownloadProgress>,
+    /// Automatic retries of a failed video
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry: Option<RetryInfo>,
+}
+
+impl From<&VideoStatus> for Video {
+    fn from(status: &VideoStatus) -> Self {
+        Video {
+            video_id: status.video_id.clone(),
+            fetch_time: status.fetch_time,
+            fetch_status: status.fetch_status,
+            last_update: status.last_update,
+            last_query: status.last_query.clone(),
+            last_result: status.last_result.clone(),
+            last_error: status.last_error.clone(),
+            error_code: status.error_code(),
+            override_query: status.override_query.clone(),
+            override_result: status.override_result.clone(),
+            removal: status.removal.clone(),
+            candidates: status.candidates.clone(),
+            confidence: status.confidence,
+            download_progress: status.download_progress.clone(),
+            retry: matches!(
+                status.fetch_status,
+                FetchStatus::FetchError | FetchStatus::BrainzError
+            )
+            .then(|| jobs::retry_info(&status.video_id))
+            .flatten(),
+        }
+    }
+}
+
+/// A single video, as returned by `GET /video/{video}`.
+#[derive(Debug, Serialize)]
+pub struct VideoDetail {
+    pub api_version: u32,
+    #[serde(flatten)]
+    pub video: Video,
+}
+
+impl VideoDetail {
+    pub fn new(status: &VideoStatus) -> Self {
+        VideoDetail {
+            api_version: API_VERSION,
+            video: status.into(),
+        }
+    }
+}
+
+/// One page of `GET /videos`.
+#[derive(Debug, Serialize)]
+pub struct VideoPage {
+    pub api_version: u32,
+    pub total: u64,
+    pub offset: u32,
+    pub limit: u32,
+    pub videos: Vec<Video>,
+}
+
+/// The MusicBrainz searches of the last tagging attempts of a video, newest first, as returned
+/// by `GET /video/{video}/matches`.
+#[derive(Debug, Serialize)]
+pub struct MatchHistory {
+    pub api_version: u32,
+    pub video_id: String,
+    pub attempts: Vec<MatchAttempt>,
+}
+
+impl MatchHistory {
+    pub fn new(video_id: String, attempts: Vec<MatchAttempt>) -> Self {
+        MatchHistory {
+            api_version: API_VERSION,
+            video_id,
+            attempts,
+        }
+    }
+}
+
+/// The recorded states of a video, newest first, as returned by `GET /video/{video}/history`.
+#[derive(Debug, Serialize)]
+pub struct StatusHistory {
+    pub api_version: u32,
+    pub video_id: String,
+    pub changes: Vec<StatusChange>,
+}
+
+impl StatusHistory {
+    pub fn new(video_id: String, changes: Vec<StatusChange>) -> Self {
+        StatusHistory {
+            api_version: API_VERSION,
+            video_id,
+            changes,
+        }
+    }
+}
+
+/// What `POST /video/{video}/delete` would delete.
+#[derive(Debug, Serialize)]
+pub struct DeleteConfirmation {
+    pub api_version: u32,
+    pub video_id: String,
+    /// Pass as `token` to delete
+    pub token: String,
+    /// The file of the video, the video is only disabled if it has none
+    pub path: Option<PathBuf>,
+    /// Size of the file in bytes
+    pub size: Option<u64>,
+    /// Unix timestamp after which the token is no longer accepted
+    pub expires: i64,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WsKind {
+    /// All videos, sent once after the client authenticated
+    Init,
+    /// Videos which changed
+    Update,
+}
+
+/// A message of the `/ws` websocket.
+#[derive(Debug, Serialize)]
+pub struct WsMessage {
+    pub api_version: u32,
+    pub kind: WsKind,
+    pub videos: Vec<Video>,
+}
+
+impl WsMessage {
+    pub fn new<'a>(kind: WsKind, videos: impl IntoIterator<Item = &'a VideoStatus>) -> Self {
+        WsMessage {
+            api_version: API_VERSION,
+            kind,
+            videos: videos.into_iter().map(Video::from).collect(),
+        }
+    }
+}
+
+/// Adds the `X-Api-Version` header to a response.
+pub async fn version_header(mut response: Response) -> Response {
+    response
+        .headers_mut()
+        .insert("x-api-version", HeaderValue::from(API_VERSION));
+    response
+}
@@ -0,0 +1,238 @@
+//! Long lived API keys for scripts and home automation, sent in the `X-Api-Key` header instead
+//! of signing in.
+//!
+//! Keys act as the user who created them, limited to their scopes: `read` allows fetching data,
+//! `write` changing it. Keys cannot manage keys or sessions, that needs a signed in user. Like
+//! refresh tokens, only the hash of a key is stored, so it is shown once when created.
+
+use std::{fmt, str::FromStr};
+
+use anyhow::{anyhow, bail};
+use axum::http::{HeaderName, Method, StatusCode};
+use chrono::Utc;
+use log::{error, info, warn};
+use rand::distr::{Alphanumeric, SampleString};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    MsState,
+    auth::{self, AuthError, Claims},
+    dbdata,
+};
+
+pub const API_KEY: HeaderName = HeaderName::from_static("x-api-key");
+/// Marks keys, so they are recognized in config files and secret scanners
+const KEY_PREFIX: &str = "msk_";
+
+const USAGE: &str = "Usage: myousync api-key create <name> --user <user> [--scope <read|write>]... [--config <file>]
+       myousync api-key list [--config <file>]
+       myousync api-key revoke <id> [--config <file>]
+
+Keys get the read scope if none is given.";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiScope {
+    /// Requests which only fetch data
+    Read,
+    /// Requests which change anything
+    Write,
+}
+
+impl ApiScope {
+    /// The scope a request with `method` needs.
+    pub fn required_for(method: &Method) -> Self {
+        if method.is_safe() {
+            ApiScope::Read
+        } else {
+            ApiScope::Write
+        }
+    }
+}
+
+impl FromStr for ApiScope {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read" => Ok(ApiScope::Read),
+            "write" => Ok(ApiScope::Write),
+            _ => Err(anyhow!("Unknown scope '{s}'\n\n{USAGE}")),
+        }
+    }
+}
+
+impl fmt::Display for ApiScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ApiScope::Read => "read",
+            ApiScope::Write => "write",
+        })
+    }
+}
+
+/// A key as stored, without the key itself.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ApiKey {
+    pub key_id: String,
+    pub name: String,
+    /// The user the key acts as
+    pub username: String,
+    pub scopes: Vec<ApiScope>,
+    pub created: i64,
+    pub last_used: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateApiKey {
+    pub name: String,
+    #[serde(default = "default_scopes")]
+    pub scopes: Vec<ApiScope>,
+}
+
+/// Returned once when a key is created.
+#[derive(Debug, Serialize)]
+pub struct CreatedApiKey {
+    #[serde(flatten)]
+    pub info: ApiKey,
+    pub key: String,
+}
+
+fn default_scopes() -> Vec<ApiScope> {
+    vec![ApiScope::Read]
+}
+
+pub fn create(
+    username: &str,
+    name: &str,
+    scopes: Vec<ApiScope>,
+) -> Result<CreatedApiKey, (StatusCode, String)> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Name must not be empty".to_string(),
+        ));
+    }
+    if scopes.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "At least one scope is needed".to_string(),
+        ));
+    }
+    if dbdata::DB.get_user(username)?.is_none() {
+        return Err((StatusCode::NOT_FOUND, "User not found".to_string()));
+    }
+
+    let key = format!(
+        "{KEY_PREFIX}{}",
+        Alphanumeric.sample_string(&mut rand::rng(), 40)
+    );
+    let info = ApiKey {
+        key_id: Alphanumeric.sample_string(&mut rand::rng(), 12),
+        name: name.to_owned(),
+        username: username.to_owned(),
+        scopes,
+        created: Utc::now().timestamp(),
+        last_used: None,
+    };
+    dbdata::DB.add_api_key(&info, &auth::hash_token(&key))?;
+    info!(
+        "Created api key {} ({}) of {}",
+        info.key_id, info.name, info.username
+    );
+    Ok(CreatedApiKey { info, key })
+}
+
+/// Checks the key of a request with `method` and returns the claims it acts with.
+pub fn verify(key: &str, method: &Method) -> Result<Claims, AuthError> {
+    let Some(api_key) = dbdata::DB.get_api_key_by_hash(&auth::hash_token(key))? else {
+        warn!("Request with an unknown api key");
+        return Err(AuthError {
+            message: "Invalid api key".to_string(),
+            status_code: StatusCode::UNAUTHORIZED,
+        });
+    };
+    let required = ApiScope::required_for(method);
+    if !api_key.scopes.contains(&required) {
+        return Err(AuthError {
+            message: format!("Api key is missing the {required} scope"),
+            status_code: StatusCode::FORBIDDEN,
+        });
+    }
+    // Only informational, so the request goes on without it
+    if let Err(err) = dbdata::DB.touch_api_key(&api_key.key_id, Utc::now().timestamp()) {
+        error!(
+            "Failed to update the last use of api key {}: {}",
+            api_key.key_id, err
+        );
+    }
+    Ok(Claims {
+        exp: 0,
+        iat: 0,
+        user: api_key.username,
+        sid: None,
+        api_key: Some(api_key.key_id),
+    })
+}
+
+/// Rejects requests made with an api key, for routes which manage credentials.
+pub fn require_user(claims: &Claims) -> Result<(), (StatusCode, String)> {
+    match claims.api_key {
+        Some(_) => Err((
+            StatusCode::FORBIDDEN,
+            "Api keys cannot manage credentials".to_string(),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Runs the `api-key` subcommand.
+pub fn cli(mut args: impl Iterator<Item = String>) -> anyhow::Result<()> {
+    let command = args.next().ok_or_else(|| anyhow!(USAGE))?;
+    let mut positional = Vec::new();
+    let mut user = None;
+    let mut scopes = Vec::new();
+    let mut config = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--user" => user = Some(args.next().ok_or_else(|| anyhow!(USAGE))?),
+            "--scope" => scopes.push(args.next().ok_or_else(|| anyhow!(USAGE))?.parse()?),
+            "--config" => config = Some(args.next().ok_or_else(|| anyhow!(USAGE))?),
+            _ if !arg.starts_with("--") => positional.push(arg),
+            _ => bail!("Unknown option '{arg}'\n\n{USAGE}"),
+        }
+    }
+
+    // Opens the database of the config
+    let _s = MsState::new(&crate::config_path(config));
+    match (command.as_str(), positional.as_slice()) {
+        ("create", [name]) => {
+            let user = user.ok_or_else(|| anyhow!(USAGE))?;
+            if scopes.is_empty() {
+                scopes = default_scopes();
+            }
+            let created = create(&user, name, scopes).map_err(|(_, err)| anyhow!(err))?;
+            println!("{}", created.key);
+        }
+        ("list", []) => {
+            for key in dbdata::DB.get_api_keys(None)? {
+                let scopes = key
+                    .scopes
+                    .iter()
+                    .map(ApiScope::to_string)
+                    .collect::<Vec<_>>()
+                    .join(",");
+                println!("{}\t{}\t{}\t{}", key.key_id, key.name, key.username, scopes);
+            }
+        }
+        ("revoke", [key_id]) => {
+            if !dbdata::DB.delete_api_key(key_id, None)? {
+                bail!("Api key {key_id} not found");
+            }
+            info!("Revoked api key {}", key_id);
+        }
+        _ => bail!(USAGE),
+    }
+    Ok(())
+}
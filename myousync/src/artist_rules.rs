@@ -0,0 +1,145 @@
+//! User rules for artist names, which keep artists MusicBrainz credits inconsistently in one
+//! library folder without overriding every single track.
+//!
+//! Rules are applied to the result of every tagging run, after the MusicBrainz search and before
+//! the file is tagged and moved. Splits run before renames, so the parts of a split name can be
+//! renamed as well.
+
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+
+use crate::brainz::BrainzMetadata;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArtistRuleKind {
+    /// Replaces the artist named `pattern` with `replacement`. Several renames to the same
+    /// replacement merge artists.
+    Rename,
+    /// Splits artist names at `pattern`, like `feat.`, into separate artists
+    Split,
+}
+
+impl ArtistRuleKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ArtistRuleKind::Rename => "rename",
+            ArtistRuleKind::Split => "split",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ArtistRule {
+    pub rule_id: i64,
+    pub kind: ArtistRuleKind,
+    /// Compared ignoring case
+    pub pattern: String,
+    /// The new name of a rename, unused by splits
+    pub replacement: Option<String>,
+    /// Unix timestamp
+    pub created: i64,
+}
+
+/// Body of creating or updating an [`ArtistRule`].
+#[derive(Debug, Deserialize)]
+pub struct ArtistRuleRequest {
+    pub kind: ArtistRuleKind,
+    pub pattern: String,
+    #[serde(default)]
+    pub replacement: Option<String>,
+}
+
+impl ArtistRuleRequest {
+    /// Trims the texts, returning a message for the user if the rule is incomplete.
+    pub fn normalize(mut self) -> Result<Self, String> {
+        self.pattern = self.pattern.trim().to_owned();
+        self.replacement = self
+            .replacement
+            .map(|r| r.trim().to_owned())
+            .filter(|r| !r.is_empty());
+        if self.pattern.is_empty() {
+            return Err("Pattern must not be empty".to_string());
+        }
+        match self.kind {
+            ArtistRuleKind::Rename if self.replacement.is_none() => {
+                Err("A rename needs a replacement".to_string())
+            }
+            ArtistRuleKind::Rename => Ok(self),
+            ArtistRuleKind::Split => {
+                self.replacement = None;
+                Ok(self)
+            }
+        }
+    }
+}
+
+/// Applies `rules` to the track and album artists of `result`.
+///
+/// The credited artist ids stay aligned with the names for renames. A split changing the number
+/// of artists drops the ids, as they no longer match the names.
+pub fn apply(rules: &[ArtistRule], result: &mut BrainzMetadata) {
+    if rules.is_empty() {
+        return;
+    }
+    let splits: Vec<Regex> = rules
+        .iter()
+        .filter(|r| r.kind == ArtistRuleKind::Split)
+        .filter_map(|r| {
+            RegexBuilder::new(&regex::escape(&r.pattern))
+                .case_insensitive(true)
+                .build()
+                .ok()
+        })
+        .collect();
+
+    let mut artists = Vec::new();
+    for artist in &result.artist {
+        let mut parts = vec![artist.clone()];
+        for split in &splits {
+            parts = parts
+                .iter()
+                .flat_map(|part| split.split(part))
+                .map(|part| part.trim().to_owned())
+                .filter(|part| !part.is_empty())
+                .collect();
+        }
+        artists.extend(parts);
+    }
+    if artists.len() != result.artist.len() {
+        result.brainz_artist_ids.clear();
+    }
+
+    for artist in artists.iter_mut() {
+        *artist = rename(rules, artist);
+    }
+    // Renames may have merged artists
+    let mut seen = Vec::new();
+    let mut ids = result.brainz_artist_ids.iter();
+    let mut kept_ids = Vec::new();
+    for artist in artists {
+        let id = ids.next();
+        if seen.contains(&artist) {
+            continue;
+        }
+        kept_ids.extend(id.cloned());
+        seen.push(artist);
+    }
+    if !result.brainz_artist_ids.is_empty() {
+        result.brainz_artist_ids = kept_ids;
+    }
+    result.artist = seen;
+
+    if let Some(album_artist) = &result.album_artist {
+        result.album_artist = Some(rename(rules, album_artist));
+    }
+}
+
+fn rename(rules: &[ArtistRule], artist: &str) -> String {
+    rules
+        .iter()
+        .filter(|r| r.kind == ArtistRuleKind::Rename)
+        .find(|r| r.pattern.to_lowercase() == artist.to_lowercase())
+        .and_then(|r| r.replacement.clone())
+        .unwrap_or_else(|| artist.to_owned())
+}
@@ -0,0 +1,114 @@
+//! Detection of artists whose tracks are spread over several library folders, because
+//! MusicBrainz credited them under different names, like in another script or a translation.
+//!
+//! The credited artist ids decide which tracks belong to the same artist, the names are only
+//! used to pick the folder.
+
+use std::collections::HashMap;
+
+use log::info;
+use serde::Serialize;
+
+use crate::{
+    MsState,
+    dbdata::{self, DbResult, FetchStatus},
+    musicfiles,
+};
+
+/// Tracks of the same artists stored in more than one folder.
+#[derive(Debug, Serialize)]
+pub struct ArtistDuplicate {
+    /// MusicBrainz ids of the credited artists
+    pub artist_ids: Vec<String>,
+    /// The artist names used by most tracks, which a relayout moves all tracks to
+    pub canonical: Vec<String>,
+    pub variants: Vec<ArtistVariant>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ArtistVariant {
+    pub folder: String,
+    pub artist: Vec<String>,
+    pub video_ids: Vec<String>,
+}
+
+pub fn find_duplicates() -> DbResult<Vec<ArtistDuplicate>> {
+    let mut by_ids: HashMap<Vec<String>, HashMap<String, ArtistVariant>> = HashMap::new();
+    for video in dbdata::DB.get_all_videos()? {
+        if video.fetch_status != FetchStatus::Categorized {
+            continue;
+        }
+        let Some(result) = video.override_result.or(video.last_result) else {
+            continue;
+        };
+        if result.brainz_artist_ids.is_empty() {
+            continue;
+        }
+
+        let folder = musicfiles::artist_folder(&result.artist);
+        by_ids
+            .entry(result.brainz_artist_ids)
+            .or_default()
+            .entry(folder.clone())
+            .or_insert_with(|| ArtistVariant {
+                folder,
+                artist: result.artist,
+                video_ids: Vec::new(),
+            })
+            .video_ids
+            .push(video.video_id);
+    }
+
+    let mut duplicates: Vec<ArtistDuplicate> = by_ids
+        .into_iter()
+        .filter(|(_, variants)| variants.len() >= 2)
+        .map(|(artist_ids, variants)| {
+            let mut variants: Vec<ArtistVariant> = variants.into_values().collect();
+            variants.sort_by(|a, b| {
+                b.video_ids
+                    .len()
+                    .cmp(&a.video_ids.len())
+                    .then_with(|| a.folder.cmp(&b.folder))
+            });
+            ArtistDuplicate {
+                artist_ids,
+                canonical: variants[0].artist.clone(),
+                variants,
+            }
+        })
+        .collect();
+    duplicates.sort_by(|a, b| a.canonical.cmp(&b.canonical));
+    Ok(duplicates)
+}
+
+/// Consolidates every duplicate into the folder of its canonical name.
+///
+/// The canonical name is stored as override result of the other tracks, which are then retagged
+/// and moved by the tagger. Returns the number of moved tracks.
+pub fn relayout() -> DbResult<usize> {
+    let mut moved = 0;
+    for duplicate in find_duplicates()? {
+        for variant in &duplicate.variants[1..] {
+            info!(
+                "Moving {} tracks from '{}' to '{}'",
+                variant.video_ids.len(),
+                variant.folder,
+                musicfiles::artist_folder(&duplicate.canonical)
+            );
+            for video_id in &variant.video_ids {
+                MsState::push_override(video_id, |v| {
+                    let Some(mut result) = v.override_result.clone().or(v.last_result.clone())
+                    else {
+                        return false;
+                    };
+                    result.artist = duplicate.canonical.clone();
+                    v.override_result = Some(result);
+                    v.fetch_status = FetchStatus::Fetched;
+                    true
+                })?;
+                moved += 1;
+            }
+        }
+    }
+    Ok(moved)
+}
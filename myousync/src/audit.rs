@@ -0,0 +1,158 @@
+//! Records who changed or deleted what, listed for admins at `GET /admin/audit`.
+//!
+//! Entries keep the request id and client address of the request, so they can be matched with
+//! the request log.
+
+use chrono::Utc;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::Claims,
+    dbdata::{self, DbResult},
+    trace,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    OverrideQuery,
+    OverrideResult,
+    ChooseCandidate,
+    DeleteVideo,
+    RestoreVideo,
+    Reindex,
+    Bulk,
+    AddArtistRule,
+    UpdateArtistRule,
+    DeleteArtistRule,
+    CreateUser,
+    DeleteUser,
+    ChangePassword,
+    CreateApiKey,
+    RevokeApiKey,
+    RevokeSession,
+    ResolveDuplicate,
+    RelayoutArtists,
+    AssignFile,
+}
+
+impl AuditAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AuditAction::OverrideQuery => "override_query",
+            AuditAction::OverrideResult => "override_result",
+            AuditAction::ChooseCandidate => "choose_candidate",
+            AuditAction::DeleteVideo => "delete_video",
+            AuditAction::RestoreVideo => "restore_video",
+            AuditAction::Reindex => "reindex",
+            AuditAction::Bulk => "bulk",
+            AuditAction::AddArtistRule => "add_artist_rule",
+            AuditAction::UpdateArtistRule => "update_artist_rule",
+            AuditAction::DeleteArtistRule => "delete_artist_rule",
+            AuditAction::CreateUser => "create_user",
+            AuditAction::DeleteUser => "delete_user",
+            AuditAction::ChangePassword => "change_password",
+            AuditAction::CreateApiKey => "create_api_key",
+            AuditAction::RevokeApiKey => "revoke_api_key",
+            AuditAction::RevokeSession => "revoke_session",
+            AuditAction::ResolveDuplicate => "resolve_duplicate",
+            AuditAction::RelayoutArtists => "relayout_artists",
+            AuditAction::AssignFile => "assign_file",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub time: i64,
+    /// The user who made the request
+    pub username: String,
+    /// Set if the request was made with this api key
+    pub api_key: Option<String>,
+    pub action: AuditAction,
+    /// What was changed, like a video id or a user name
+    pub target: Option<String>,
+    /// The new values or the selection of the action
+    pub details: Option<serde_json::Value>,
+    pub request_id: Option<String>,
+    pub client_ip: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct AuditQuery {
+    #[serde(default)]
+    pub offset: u32,
+    /// Page size, capped at [`AuditQuery::MAX_LIMIT`]
+    #[serde(default)]
+    pub limit: Option<u32>,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub action: Option<AuditAction>,
+    #[serde(default)]
+    pub target: Option<String>,
+}
+
+impl AuditQuery {
+    pub const DEFAULT_LIMIT: u32 = 50;
+    pub const MAX_LIMIT: u32 = 500;
+
+    pub fn limit(&self) -> u32 {
+        self.limit
+            .unwrap_or(Self::DEFAULT_LIMIT)
+            .min(Self::MAX_LIMIT)
+    }
+}
+
+/// One page of `GET /admin/audit`, newest first.
+#[derive(Debug, Serialize)]
+pub struct AuditPage {
+    pub total: u64,
+    pub offset: u32,
+    pub limit: u32,
+    pub entries: Vec<AuditEntry>,
+}
+
+/// Records `action` on `target` by the user of `claims`.
+///
+/// The change has already been made, so a failed write is only logged.
+pub fn record(
+    claims: &Claims,
+    action: AuditAction,
+    target: Option<&str>,
+    details: Option<serde_json::Value>,
+) {
+    let ctx = trace::current();
+    let entry = AuditEntry {
+        id: 0,
+        time: Utc::now().timestamp(),
+        username: claims.user.clone(),
+        api_key: claims.api_key.clone(),
+        action,
+        target: target.map(str::to_owned),
+        details,
+        request_id: ctx.as_ref().map(|ctx| ctx.id.clone()),
+        client_ip: ctx.and_then(|ctx| ctx.client_ip).map(|ip| ip.to_string()),
+    };
+    info!(
+        "Audit: {} by {} on {}",
+        action.as_str(),
+        entry.username,
+        entry.target.as_deref().unwrap_or("-")
+    );
+    if let Err(err) = dbdata::DB.add_audit_entry(&entry) {
+        error!("Failed to record audit entry: {}", err);
+    }
+}
+
+pub fn list(query: &AuditQuery) -> DbResult<AuditPage> {
+    let (total, entries) = dbdata::DB.get_audit_page(query)?;
+    Ok(AuditPage {
+        total,
+        offset: query.offset,
+        limit: query.limit(),
+        entries,
+    })
+}
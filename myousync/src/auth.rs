@@ -1,28 +1,52 @@
+//! Sign in with access and refresh tokens.
+//!
+//! Signing in starts a session and returns a short lived JWT access token together with a
+//! refresh token. The refresh token is only stored hashed and is replaced on every use of
+//! `/login/refresh`. Revoking a session through `/logout` or the session list rejects its access
+//! tokens right away.
+
 use std::sync::LazyLock;
 
 use axum::{
+    Json,
     body::Body,
     extract::Request,
-    http::{self, StatusCode},
+    http::{self, HeaderMap, StatusCode, header},
     middleware::Next,
     response::{IntoResponse, Response},
-    Json,
 };
-use chrono::{Duration, Utc};
+use chrono::Utc;
 use jsonwebtoken::{DecodingKey, EncodingKey, Header, TokenData, Validation};
+use log::{error, info, warn};
 use rand::distr::{Alphanumeric, SampleString};
+use ring::digest;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 
-use crate::dbdata;
+use crate::{
+    MsWeb, apikeys,
+    dbdata::{self, DbError, DbResult, Session},
+    lockout, password,
+    proxy::ClientInfo,
+};
 
-static SECRET: LazyLock<Box<str>> = LazyLock::new(|| get_server_secret().into_boxed_str());
+static SECRET: LazyLock<Box<str>> = LazyLock::new(|| {
+    get_server_secret()
+        .unwrap_or_else(|err| panic!("Failed to load the server secret: {err}"))
+        .into_boxed_str()
+});
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Claims {
     pub exp: usize,   // Expiry time of the token
     pub iat: usize,   // Issued at time of the token
     pub user: String, // Email associated with the token
+    /// Session the token was issued for
+    #[serde(default)]
+    pub sid: Option<String>,
+    /// Set instead of a session for requests with an api key
+    #[serde(default)]
+    pub api_key: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -31,41 +55,201 @@ pub struct SignInData {
     pub password: String, // Password entered during sign-in
 }
 
+/// Tokens of a session, returned by the sign in and the refresh.
+#[derive(Debug, Serialize)]
+pub struct Tokens {
+    /// Authorizes requests until it expires
+    pub access_token: String,
+    /// Exchanged once at `/login/refresh` for new tokens
+    pub refresh_token: String,
+    /// Seconds until the access token expires
+    pub expires_in: u64,
+}
+
+#[derive(Deserialize)]
+pub struct RefreshData {
+    pub refresh_token: String,
+}
+
+/// A session as listed at `GET /sessions`.
+#[derive(Debug, Serialize)]
+pub struct SessionInfo {
+    #[serde(flatten)]
+    pub session: Session,
+    /// The session of the request
+    pub current: bool,
+}
+
 pub async fn sign_in(
-    Json(user_data): Json<SignInData>, // JSON payload containing sign-in data
-) -> Result<impl IntoResponse, AuthError> {
-    let user = match dbdata::DB.get_user(&user_data.username) {
+    web: &MsWeb,
+    client: &ClientInfo,
+    headers: &HeaderMap,
+    user_data: SignInData, // JSON payload containing sign-in data
+) -> Result<Json<Tokens>, AuthError> {
+    lockout::check(client.ip, &user_data.username)?;
+    let user = match dbdata::DB.get_user(&user_data.username)? {
         Some(user) => user, // User found, proceed with authentication
         None => {
+            warn!(
+                "Sign in of unknown user {} from {}",
+                user_data.username, client.ip
+            );
+            lockout::record_failure(&web.login_limit, client.ip, &user_data.username).await;
             return Err(AuthError {
                 message: "User not found".to_string(),
                 status_code: StatusCode::UNAUTHORIZED,
-            })
+            });
         } // User not found, return unauthorized status
     };
-    if user.password != user_data.password {
+    if !password::verify(&user.password, &user_data.password) {
+        warn!(
+            "Sign in of {} from {} with invalid password",
+            user_data.username, client.ip
+        );
+        lockout::record_failure(&web.login_limit, client.ip, &user_data.username).await;
         return Err(AuthError {
             message: "Invalid password".to_string(),
             status_code: StatusCode::UNAUTHORIZED,
         });
     }
-    let token = encode_jwt(user.username).map_err(|_| AuthError {
-        message: "Internal token error".to_string(),
-        status_code: StatusCode::INTERNAL_SERVER_ERROR,
-    })?; // Handle JWT encoding errors
-    Ok(Json(token))
+    lockout::record_success(&user.username);
+    if password::needs_rehash(&user.password) {
+        dbdata::DB.set_password(&user.username, &password::hash(&user_data.password))?;
+        info!("Upgraded the password hash of {}", user.username);
+    }
+    let user_agent = headers
+        .get(header::USER_AGENT)
+        .and_then(|h| h.to_str().ok());
+    start_session(web, user.username, Some(client), user_agent).map(Json)
+}
+
+/// Starts a new session of `username`.
+pub fn start_session(
+    web: &MsWeb,
+    username: String,
+    client: Option<&ClientInfo>,
+    user_agent: Option<&str>,
+) -> Result<Tokens, AuthError> {
+    let now = Utc::now().timestamp();
+    dbdata::DB.delete_expired_sessions(now)?;
+
+    let refresh_token = new_refresh_token();
+    let session = Session {
+        session_id: Alphanumeric.sample_string(&mut rand::rng(), 16),
+        username,
+        created: now,
+        last_used: now,
+        expires: now + web.refresh_token_ttl.as_secs() as i64,
+        client_ip: client.map(|c| c.ip.to_string()),
+        user_agent: user_agent.map(str::to_owned),
+    };
+    dbdata::DB.add_session(&session, &hash_token(&refresh_token))?;
+    info!(
+        "Started session {} of {}",
+        session.session_id, session.username
+    );
+    issue_tokens(web, &session, refresh_token)
+}
+
+/// `/login/refresh`, exchanges a refresh token for new tokens of its session.
+pub fn refresh(web: &MsWeb, client: &ClientInfo, data: &RefreshData) -> Result<Tokens, AuthError> {
+    let now = Utc::now().timestamp();
+    let Some(mut session) =
+        dbdata::DB.get_session_by_token(&hash_token(&data.refresh_token), now)?
+    else {
+        warn!("Refresh with an invalid token from {}", client.ip);
+        return Err(AuthError {
+            message: "Invalid refresh token".to_string(),
+            status_code: StatusCode::UNAUTHORIZED,
+        });
+    };
+    if dbdata::DB.get_user(&session.username)?.is_none() {
+        dbdata::DB.delete_session(&session.session_id, None)?;
+        return Err(AuthError {
+            message: "You are not an authorized user".to_string(),
+            status_code: StatusCode::UNAUTHORIZED,
+        });
+    }
+
+    let refresh_token = new_refresh_token();
+    session.expires = now + web.refresh_token_ttl.as_secs() as i64;
+    dbdata::DB.renew_session(
+        &session.session_id,
+        &hash_token(&refresh_token),
+        now,
+        session.expires,
+        &client.ip.to_string(),
+    )?;
+    issue_tokens(web, &session, refresh_token)
+}
+
+/// `/logout`, revokes the session of the request.
+pub fn logout(claims: &Claims) -> DbResult<()> {
+    if let Some(sid) = &claims.sid
+        && dbdata::DB.delete_session(sid, Some(&claims.user))?
+    {
+        info!("Ended session {} of {}", sid, claims.user);
+    }
+    Ok(())
 }
 
-pub fn encode_jwt(email: String) -> Result<String, StatusCode> {
+pub fn list_sessions(claims: &Claims, owner: Option<&str>) -> DbResult<Vec<SessionInfo>> {
+    Ok(dbdata::DB
+        .get_sessions(Utc::now().timestamp(), owner)?
+        .into_iter()
+        .map(|session| SessionInfo {
+            current: claims.sid.as_ref() == Some(&session.session_id),
+            session,
+        })
+        .collect())
+}
+
+fn issue_tokens(
+    web: &MsWeb,
+    session: &Session,
+    refresh_token: String,
+) -> Result<Tokens, AuthError> {
+    let ttl = web.access_token_ttl;
+    let access_token =
+        encode_jwt(session.username.clone(), &session.session_id, ttl).map_err(|_| AuthError {
+            message: "Internal token error".to_string(),
+            status_code: StatusCode::INTERNAL_SERVER_ERROR,
+        })?; // Handle JWT encoding errors
+    Ok(Tokens {
+        access_token,
+        refresh_token,
+        expires_in: ttl.as_secs(),
+    })
+}
+
+fn new_refresh_token() -> String {
+    Alphanumeric.sample_string(&mut rand::rng(), 43)
+}
+
+/// Refresh tokens and api keys are random, so a plain hash is enough to not store them readable.
+pub fn hash_token(token: &str) -> String {
+    digest::digest(&digest::SHA256, token.as_bytes())
+        .as_ref()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+fn encode_jwt(
+    email: String,
+    session_id: &str,
+    ttl: std::time::Duration,
+) -> Result<String, StatusCode> {
     let secret: String = SECRET.to_string();
     let now = Utc::now();
-    let expire: chrono::TimeDelta = Duration::hours(24);
-    let exp: usize = (now + expire).timestamp() as usize;
+    let exp: usize = (now + ttl).timestamp() as usize;
     let iat: usize = now.timestamp() as usize;
     let claim = Claims {
         iat,
         exp,
         user: email,
+        sid: Some(session_id.to_owned()),
+        api_key: None,
     };
 
     jsonwebtoken::encode(
@@ -76,7 +260,7 @@ pub fn encode_jwt(email: String) -> Result<String, StatusCode> {
     .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
 }
 
-pub fn decode_jwt(jwt_token: &str) -> Result<TokenData<Claims>, StatusCode> {
+fn decode_jwt(jwt_token: &str) -> Result<TokenData<Claims>, StatusCode> {
     let secret = SECRET.to_string();
     let result: Result<TokenData<Claims>, StatusCode> = jsonwebtoken::decode(
         jwt_token,
@@ -87,17 +271,85 @@ pub fn decode_jwt(jwt_token: &str) -> Result<TokenData<Claims>, StatusCode> {
     result
 }
 
+/// Decodes the access token and checks that its session was not revoked.
+pub fn verify(jwt_token: &str) -> Result<Claims, AuthError> {
+    let claims = decode_jwt(jwt_token)
+        .map_err(|_| AuthError {
+            message: "Unable to decode token".to_string(),
+            status_code: StatusCode::UNAUTHORIZED,
+        })?
+        .claims;
+    if let Some(sid) = &claims.sid
+        && !dbdata::DB.is_session_active(sid, Utc::now().timestamp())?
+    {
+        return Err(AuthError {
+            message: "Session was revoked".to_string(),
+            status_code: StatusCode::UNAUTHORIZED,
+        });
+    }
+    Ok(claims)
+}
+
+/// Whether the session or api key of `claims` was not revoked and its user still exists, checked
+/// again by connections which stay open.
+pub fn is_active(claims: &Claims) -> DbResult<bool> {
+    if let Some(sid) = &claims.sid
+        && !dbdata::DB.is_session_active(sid, Utc::now().timestamp())?
+    {
+        return Ok(false);
+    }
+    if let Some(key_id) = &claims.api_key
+        && !dbdata::DB
+            .get_api_keys(Some(&claims.user))?
+            .iter()
+            .any(|key| &key.key_id == key_id)
+    {
+        return Ok(false);
+    }
+    Ok(dbdata::DB.get_user(&claims.user)?.is_some())
+}
+
 pub struct AuthError {
     pub message: String,
     pub status_code: StatusCode,
 }
 
-pub async fn auth(req: Request, next: Next) -> Result<Response, AuthError> {
+/// The details of the error are only logged, they are not meant for clients.
+impl From<DbError> for AuthError {
+    fn from(err: DbError) -> Self {
+        error!("Database error during authentication: {}", err);
+        AuthError {
+            message: "Internal database error".to_string(),
+            status_code: StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// Checks the JWT or api key of the request and makes its [`Claims`] available as request
+/// extension.
+pub async fn auth(mut req: Request, next: Next) -> Result<Response, AuthError> {
     if req.method() == http::Method::OPTIONS {
         return Ok(next.run(req).await);
     }
 
-    let auth_header = req.headers().get(http::header::AUTHORIZATION);
+    let headers = req.headers().clone();
+    let method = req.method().clone();
+    let claims = dbdata::blocking(move |_| authenticate(&headers, &method)).await?;
+    req.extensions_mut().insert(claims);
+    Ok(next.run(req).await)
+}
+
+/// The [`Claims`] of the JWT or api key in `headers`.
+fn authenticate(headers: &HeaderMap, method: &http::Method) -> Result<Claims, AuthError> {
+    if let Some(key) = headers.get(apikeys::API_KEY) {
+        let key = key.to_str().map_err(|_| AuthError {
+            message: "Invalid api key".to_string(),
+            status_code: StatusCode::UNAUTHORIZED,
+        })?;
+        return apikeys::verify(key, method);
+    }
+
+    let auth_header = headers.get(http::header::AUTHORIZATION);
     let auth_header = match auth_header {
         Some(header) => header.to_str().map_err(|_| AuthError {
             message: "Empty header is not allowed".to_string(),
@@ -107,39 +359,32 @@ pub async fn auth(req: Request, next: Next) -> Result<Response, AuthError> {
             return Err(AuthError {
                 message: "Please add the JWT token to the header".to_string(),
                 status_code: StatusCode::FORBIDDEN,
-            })
+            });
         }
     };
     let mut header = auth_header.split_whitespace();
     let (_bearer, token) = (header.next(), header.next());
-    let token_data = match decode_jwt(token.unwrap()) {
-        Ok(data) => data,
-        Err(_) => {
-            return Err(AuthError {
-                message: "Unable to decode token".to_string(),
-                status_code: StatusCode::UNAUTHORIZED,
-            })
-        }
-    };
+    let claims = verify(token.unwrap_or_default())?;
     // Fetch the user details from the database
-    let _current_user = match dbdata::DB.get_user(&token_data.claims.user) {
+    let _current_user = match dbdata::DB.get_user(&claims.user)? {
         Some(user) => user,
         None => {
             return Err(AuthError {
                 message: "You are not an authorized user".to_string(),
                 status_code: StatusCode::UNAUTHORIZED,
-            })
+            });
         }
     };
-    Ok(next.run(req).await)
+    Ok(claims)
 }
 
-pub fn get_server_secret() -> String {
-    dbdata::DB.get_key("auth_server_secret").unwrap_or_else(|| {
-        let secret = Alphanumeric.sample_string(&mut rand::rng(), 16);
-        dbdata::DB.set_key("auth_server_secret", &secret);
-        secret
-    })
+pub fn get_server_secret() -> DbResult<String> {
+    if let Some(secret) = dbdata::DB.get_key("auth_server_secret")? {
+        return Ok(secret);
+    }
+    let secret = Alphanumeric.sample_string(&mut rand::rng(), 16);
+    dbdata::DB.set_key("auth_server_secret", &secret)?;
+    Ok(secret)
 }
 
 impl IntoResponse for AuthError {
@@ -0,0 +1,181 @@
+//! Regular copies of the sqlite database, configured in `[database.backup]`.
+//!
+//! Backups are named `ytdata-<time>.db` after the time they were taken, so the newest one tells
+//! when the next is due, also across restarts. The `db` subcommand takes a backup from the shell
+//! or restores one while no instance runs. Restoring into PostgreSQL is how a library moves
+//! there from sqlite.
+
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail};
+use axum::http::StatusCode;
+use chrono::{DateTime, NaiveDateTime, TimeDelta, Utc};
+use log::{error, info, warn};
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::{
+    MsBackup, MsState,
+    dbdata::{self, DbError},
+    instance::Instance,
+};
+
+const PREFIX: &str = "ytdata-";
+const TIME_FORMAT: &str = "%Y%m%d-%H%M%S";
+/// Wait after a failed backup before the next try
+const RETRY: Duration = Duration::from_secs(10 * 60);
+
+const USAGE: &str = "Usage: myousync db backup [<file>] [--config <file>]
+       myousync db restore <file> [--config <file>]
+
+Without a file, the backup is written to database.backup.dir. Stop myousync before restoring.
+With database.postgres set, restore takes a sqlite database or backup and copies it over.";
+
+#[derive(Debug, Serialize)]
+pub struct Backup {
+    pub path: PathBuf,
+    pub size: u64,
+    /// Unix timestamp
+    pub created: i64,
+}
+
+#[derive(Error, Debug)]
+pub enum BackupError {
+    #[error("Backups are not configured")]
+    NotConfigured,
+    #[error("Error writing the backup: {0}")]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Db(#[from] DbError),
+}
+
+impl From<BackupError> for (StatusCode, String) {
+    fn from(err: BackupError) -> Self {
+        match err {
+            BackupError::NotConfigured => (StatusCode::CONFLICT, err.to_string()),
+            BackupError::Io(_) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+            BackupError::Db(err) => err.into(),
+        }
+    }
+}
+
+/// Takes a backup into the configured folder and deletes the ones beyond `keep`.
+pub fn backup(config: Option<&MsBackup>) -> Result<Backup, BackupError> {
+    let config = config.ok_or(BackupError::NotConfigured)?;
+    std::fs::create_dir_all(&config.dir)?;
+    let now = Utc::now();
+    let path = config
+        .dir
+        .join(format!("{PREFIX}{}.db", now.format(TIME_FORMAT)));
+    write(&path)?;
+    info!("Backed up the database to {}", path.display());
+
+    for (_, old) in list(&config.dir)?.iter().skip(config.keep) {
+        match std::fs::remove_file(old) {
+            Ok(()) => info!("Deleted the old backup {}", old.display()),
+            Err(err) => warn!("Failed to delete the old backup {}: {}", old.display(), err),
+        }
+    }
+
+    Ok(Backup {
+        size: std::fs::metadata(&path)?.len(),
+        path,
+        created: now.timestamp(),
+    })
+}
+
+/// Writes a backup next to `path` first, so an interrupted backup is never taken for a complete
+/// one.
+fn write(path: &Path) -> Result<(), BackupError> {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+    if let Err(err) = dbdata::DB.backup(&partial) {
+        let _ = std::fs::remove_file(&partial);
+        return Err(err.into());
+    }
+    std::fs::rename(&partial, path)?;
+    Ok(())
+}
+
+/// The backups in `dir`, newest first.
+fn list(dir: &Path) -> std::io::Result<Vec<(DateTime<Utc>, PathBuf)>> {
+    let mut backups = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let time = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix(PREFIX)?.strip_suffix(".db"))
+            .and_then(|time| NaiveDateTime::parse_from_str(time, TIME_FORMAT).ok());
+        if let Some(time) = time {
+            backups.push((time.and_utc(), path));
+        }
+    }
+    backups.sort_by_key(|(time, _)| std::cmp::Reverse(*time));
+    Ok(backups)
+}
+
+/// Takes a backup whenever the newest one is older than the interval, until the process ends.
+pub async fn run(s: &MsState) {
+    let Some(config) = &s.config.database.backup else {
+        return std::future::pending().await;
+    };
+    let interval = TimeDelta::from_std(config.interval).unwrap_or(TimeDelta::MAX);
+    loop {
+        let newest = list(&config.dir)
+            .ok()
+            .and_then(|backups| backups.first().map(|(time, _)| *time));
+        if let Some(due) = newest.and_then(|newest| newest.checked_add_signed(interval)) {
+            tokio::time::sleep((due - Utc::now()).to_std().unwrap_or_default()).await;
+        }
+
+        let config = config.clone();
+        if let Err(err) = dbdata::blocking(move |_| backup(Some(&config))).await {
+            error!("Failed to back up the database: {}", err);
+            tokio::time::sleep(RETRY).await;
+        }
+    }
+}
+
+/// Runs the `db` subcommand.
+pub fn cli(mut args: impl Iterator<Item = String>) -> anyhow::Result<()> {
+    let command = args.next().ok_or_else(|| anyhow!(USAGE))?;
+    let mut positional = Vec::new();
+    let mut config = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => config = Some(args.next().ok_or_else(|| anyhow!(USAGE))?),
+            _ if !arg.starts_with("--") => positional.push(arg),
+            _ => bail!("Unknown option '{arg}'\n\n{USAGE}"),
+        }
+    }
+
+    // Opens the database of the config
+    let s = MsState::new(&crate::config_path(config));
+    match (command.as_str(), positional.as_slice()) {
+        ("backup", []) => {
+            backup(s.config.database.backup.as_ref())?;
+        }
+        ("backup", [file]) => {
+            write(Path::new(file))?;
+            info!("Backed up the database to {}", file);
+        }
+        ("restore", [file]) => {
+            if !Path::new(file).is_file() {
+                bail!("Backup {file} not found");
+            }
+            let instance =
+                Instance::acquire().map_err(|err| anyhow!("{err}, stop it before restoring"))?;
+            let restored = dbdata::DB.restore(Path::new(file));
+            instance.release();
+            restored?;
+            info!("Restored the database from {}", file);
+        }
+        _ => bail!(USAGE),
+    }
+    Ok(())
+}
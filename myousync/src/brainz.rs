@@ -1,17 +1,31 @@
 use std::mem;
 use std::sync::LazyLock;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 use crate::net::CLIENT;
-use crate::{dbdata, util::limiter::Limiter};
+use crate::{MsState, dbdata, trace, util::limiter::Limiter};
+use anyhow::{anyhow, bail};
+use chrono::Utc;
 use log::{debug, error, info};
 use regex::Regex;
 use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-static LIMITER: Limiter = Limiter::new(std::time::Duration::from_millis(1500));
+pub static LIMITER: Limiter = Limiter::new("brainz", std::time::Duration::from_millis(1500));
 const RATE_LIMIT_WAIT: std::time::Duration = std::time::Duration::from_secs(10);
-static SPLIT_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\bft\.?|\bfeat\.?|;|&").unwrap());
+/// Tags of a recording beyond these are rarely genres anymore
+const MAX_GENRES: usize = 3;
+const EVICTION_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+/// Seconds after which a cached response is fetched again, see [`configure_cache`]
+static CACHE_TTL_SECS: AtomicU64 = AtomicU64::new(u64::MAX);
+
+const USAGE: &str = "Usage: myousync brainz purge [--expired] [--config <file>]
+
+Deletes the cached MusicBrainz responses, only those older than scrape.brainz_cache_ttl with
+--expired.";
+static SPLIT_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\bft\.?|\bfeat\.?|;|&").unwrap());
 
 #[derive(Error, Debug)]
 pub enum BrainzError {
@@ -23,9 +37,15 @@ pub enum BrainzError {
     JsonError(#[from] serde_json::Error),
     #[error("No results found")]
     EmptyResult,
+    #[error(transparent)]
+    Db(#[from] dbdata::DbError),
 }
 
-pub async fn fetch_recordings(search: &RecordingSearch) -> Result<BrainzMetadata, BrainzError> {
+/// Searches a recording, recording the search and its candidates in `log`.
+async fn fetch_recordings(
+    search: &RecordingSearch,
+    log: &mut Vec<BrainzSearchLog>,
+) -> Result<BrainzMetadata, BrainzError> {
     let mut parts = Vec::new();
     if let Some(part) = search.title.to_query_part("recording") {
         parts.push(part);
@@ -41,74 +61,249 @@ pub async fn fetch_recordings(search: &RecordingSearch) -> Result<BrainzMetadata
     }
 
     let query = parts.join(" AND ");
-    self::fetch_recordings_url(&query).await
+    self::fetch_recordings_url(&query, log).await
 }
 
-async fn fetch_recordings_by_id(id: &str) -> Result<BrainzMetadata, BrainzError> {
+pub async fn fetch_recordings_by_id(
+    id: &str,
+    log: &mut Vec<BrainzSearchLog>,
+) -> Result<BrainzMetadata, BrainzError> {
     let query = format!("rid:{}", id);
-    fetch_recordings_url(&query).await
+    fetch_recordings_url(&query, log).await
+}
+
+async fn fetch_recordings_url(
+    query: &str,
+    log: &mut Vec<BrainzSearchLog>,
+) -> Result<BrainzMetadata, BrainzError> {
+    let result = search_recordings(query).await;
+    log.push(BrainzSearchLog {
+        query: urlencoding::decode(query)
+            .map(|q| q.into_owned())
+            .unwrap_or_else(|_| query.to_owned()),
+        candidates: result.as_ref().map(Clone::clone).unwrap_or_default(),
+        error: result.as_ref().err().map(ToString::to_string),
+    });
+
+    result?
+        .into_iter()
+        .next()
+        .map(|c| c.metadata)
+        .ok_or(BrainzError::EmptyResult)
 }
 
-async fn fetch_recordings_url(query: &str) -> Result<BrainzMetadata, BrainzError> {
+/// The top recordings for `query`, best first.
+async fn search_recordings(query: &str) -> Result<Vec<BrainzCandidate>, BrainzError> {
     let url = format!(
         "http://musicbrainz.org/ws/2/recording/?limit=3&query={}",
         query
     );
 
-    let response = if let Some(cached_response) = dbdata::DB.try_get_brainz(&url) {
-        cached_response
-    } else {
-        debug!("Fetching brainz data from {}", url);
-        LIMITER.wait_for_next_fetch().await;
-
-        let response = loop {
-            let response = CLIENT
-                .get(&url)
-                .header("User-Agent", "splamy_music_sync/0.1 ( splamyn@gmail.com )")
-                .header("Accept", "application/json")
-                .send()
-                .await?;
-
-            if response.status() == StatusCode::SERVICE_UNAVAILABLE {
-                tokio::time::sleep(RATE_LIMIT_WAIT).await;
-                LIMITER.set_last_fetch_now();
-                continue;
-            }
+    let response = fetch_cached(&url).await?;
+    let data: RecordingResponse = serde_json::from_str(&response)?;
+
+    let candidates = data
+        .recordings
+        .into_iter()
+        .map(|mut recording| BrainzCandidate {
+            score: recording.score,
+            length: recording.length.and_then(|l| u32::try_from(l).ok()),
+            metadata: BrainzMetadata {
+                title: mem::take(&mut recording.title),
+                artist: recording
+                    .artist_credit
+                    .iter_mut()
+                    .map(|a| mem::take(&mut a.name))
+                    .collect(),
+                album: recording
+                    .releases
+                    .get_mut(0)
+                    .map(|r| mem::take(&mut r.title)),
+                brainz_recording_id: Some(mem::take(&mut recording.id)),
+                brainz_release_id: recording.releases.get_mut(0).map(|r| mem::take(&mut r.id)),
+                brainz_artist_ids: artist_ids(&recording.artist_credit),
+                album_artist: None,
+                release_form: None,
+                date: recording
+                    .releases
+                    .first()
+                    .and_then(|r| r.date.clone())
+                    .or_else(|| recording.first_release_date.clone())
+                    .filter(|d| !d.is_empty()),
+                genres: genres(&mut recording.tags),
+            },
+        })
+        .collect();
+    Ok(candidates)
+}
+
+/// Gets the full tracklist of a release.
+pub async fn fetch_release(id: &str) -> Result<BrainzRelease, BrainzError> {
+    let url = format!(
+        "http://musicbrainz.org/ws/2/release/{}?inc=recordings+artist-credits+release-rels&fmt=json",
+        urlencoding::encode(id)
+    );
+
+    let response = fetch_cached(&url).await?;
+    let data: ReleaseResponse = serde_json::from_str(&response)?;
+
+    let tracks = data
+        .media
+        .into_iter()
+        .flat_map(|medium| {
+            medium.tracks.into_iter().map(move |track| BrainzTrack {
+                recording_id: track.recording.id,
+                title: track.title,
+                artist_ids: artist_ids(&track.artist_credit),
+                artist: track.artist_credit.into_iter().map(|a| a.name).collect(),
+                disc: medium.position,
+                position: track.position,
+            })
+        })
+        .collect();
+
+    let forms = data
+        .relations
+        .into_iter()
+        .filter(|r| r.relation_type == "transl-tracklisting")
+        .filter_map(|r| r.release)
+        .map(|r| ReleaseForm::new(r.id, r.text_representation))
+        .collect();
+
+    Ok(BrainzRelease {
+        form: ReleaseForm::new(data.id.clone(), data.text_representation),
+        id: data.id,
+        title: data.title,
+        artist: data.artist_credit.into_iter().map(|a| a.name).collect(),
+        date: data.date.filter(|d| !d.is_empty()),
+        tracks,
+        forms,
+    })
+}
+
+/// The most voted tags of a recording, which are mostly its genres.
+fn genres(tags: &mut [RecordingTag]) -> Vec<String> {
+    tags.sort_by_key(|t| std::cmp::Reverse(t.count));
+    tags.iter()
+        .filter(|t| t.count > 0)
+        .take(MAX_GENRES)
+        .map(|t| t.name.clone())
+        .collect()
+}
+
+/// Ids of the credited artists, empty if any of them is missing so they stay aligned with the
+/// names.
+fn artist_ids(credits: &[ArtistCredit]) -> Vec<String> {
+    credits
+        .iter()
+        .map(|a| a.artist.as_ref().map(|artist| artist.id.clone()))
+        .collect::<Option<_>>()
+        .unwrap_or_default()
+}
+
+/// Sets how long cached responses are used before they are fetched again.
+pub fn configure_cache(ttl: std::time::Duration) {
+    CACHE_TTL_SECS.store(ttl.as_secs(), Ordering::Relaxed);
+}
+
+/// Cached responses fetched before this are expired.
+fn cache_expiry() -> i64 {
+    let ttl = CACHE_TTL_SECS.load(Ordering::Relaxed);
+    Utc::now()
+        .timestamp()
+        .saturating_sub(i64::try_from(ttl).unwrap_or(i64::MAX))
+}
 
-            break response;
-        };
+#[tracing::instrument(name = "brainz_request")]
+async fn fetch_cached(url: &str) -> Result<String, BrainzError> {
+    if let Some(cached_response) = dbdata::DB.try_get_brainz(url, cache_expiry())? {
+        return Ok(cached_response);
+    }
 
-        let text = response.text().await?;
-        dbdata::DB.set_brainz(&url, &text);
+    debug!("Fetching brainz data from {}", url);
+    let _slot = LIMITER.acquire().await;
+
+    let response = loop {
+        let response = CLIENT
+            .get(url)
+            .header("User-Agent", "splamy_music_sync/0.1 ( splamyn@gmail.com )")
+            .header("Accept", "application/json")
+            .send()
+            .await?;
+
+        if response.status() == StatusCode::SERVICE_UNAVAILABLE {
+            tokio::time::sleep(RATE_LIMIT_WAIT).await;
+            LIMITER.set_last_fetch_now();
+            continue;
+        }
 
-        text
+        break response;
     };
 
-    let mut data: RecordingResponse = serde_json::from_str(&response)?;
-
-    if let Some(recording) = data.recordings.get_mut(0) {
-        let metadata = BrainzMetadata {
-            title: mem::take(&mut recording.title),
-            artist: recording
-                .artist_credit
-                .iter_mut()
-                .map(|a| mem::take(&mut a.name))
-                .collect(),
-            album: recording
-                .releases
-                .get_mut(0)
-                .map(|r| mem::take(&mut r.title)),
-            brainz_recording_id: Some(mem::take(&mut recording.id)),
-        };
-        Ok(metadata)
-    } else {
-        Err(BrainzError::EmptyResult)
+    let text = response.text().await?;
+    if let Err(err) = dbdata::DB.set_brainz(url, &text) {
+        error!("Failed to cache brainz response of {}: {}", url, err);
+    }
+
+    Ok(text)
+}
+
+/// Deletes expired responses and evicts the oldest beyond `scrape.brainz_cache_entries`,
+/// until the process ends.
+pub async fn run_cache_eviction(s: &MsState) {
+    let keep = s.config.scrape.brainz_cache_entries;
+    let mut interval = tokio::time::interval(EVICTION_INTERVAL);
+    loop {
+        interval.tick().await;
+        // Tried again on the next interval
+        let evicted = dbdata::blocking(move |db| {
+            Ok::<_, dbdata::DbError>(
+                db.delete_brainz_before(cache_expiry())? + db.evict_brainz(keep)?,
+            )
+        })
+        .await;
+        match evicted {
+            Ok(0) => {}
+            Ok(evicted) => info!("Evicted {} responses from the brainz cache", evicted),
+            Err(err) => error!("Failed to evict from the brainz cache: {}", err),
+        }
+    }
+}
+
+/// Runs the `brainz` subcommand.
+pub fn cli(mut args: impl Iterator<Item = String>) -> anyhow::Result<()> {
+    let command = args.next().ok_or_else(|| anyhow!(USAGE))?;
+    let mut expired = false;
+    let mut config = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--expired" => expired = true,
+            "--config" => config = Some(args.next().ok_or_else(|| anyhow!(USAGE))?),
+            _ => bail!("Unknown option '{arg}'\n\n{USAGE}"),
+        }
+    }
+
+    // Opens the database of the config
+    let _s = MsState::new(&crate::config_path(config));
+    match command.as_str() {
+        "purge" => {
+            let before = if expired { cache_expiry() } else { i64::MAX };
+            let purged = dbdata::DB.delete_brainz_before(before)?;
+            info!("Purged {} responses from the brainz cache", purged);
+        }
+        _ => bail!(USAGE),
     }
+    Ok(())
 }
 
-pub async fn analyze_brainz(dlp: &BrainzMultiSearch) -> Result<BrainzMetadata, BrainzError> {
+/// Searches the recording matching `dlp`, recording every search made in `log`.
+#[tracing::instrument(name = "brainz_search", skip(log))]
+pub async fn analyze_brainz(
+    dlp: &BrainzMultiSearch,
+    log: &mut Vec<BrainzSearchLog>,
+) -> Result<BrainzMetadata, BrainzError> {
     if let Some(trackid) = &dlp.trackid {
-        return fetch_recordings_by_id(trackid).await;
+        return fetch_recordings_by_id(trackid, log).await;
     }
 
     let mut search: Vec<RecordingSearch> = vec![];
@@ -137,9 +332,11 @@ pub async fn analyze_brainz(dlp: &BrainzMultiSearch) -> Result<BrainzMetadata, B
         let parts: Vec<&str> = dlp.title.split(" - ").collect();
 
         fn split_artists(artist: &str) -> impl Iterator<Item = String> + use<'_> {
-            SPLIT_REGEX
-                .split(artist)
-                .map(|s| s.trim().to_string().replace(['(', ')', '[' , ']' , '【', '】'], ""))
+            SPLIT_REGEX.split(artist).map(|s| {
+                s.trim()
+                    .to_string()
+                    .replace(['(', ')', '[', ']', '【', '】'], "")
+            })
         }
 
         search.push(RecordingSearch {
@@ -165,9 +362,15 @@ pub async fn analyze_brainz(dlp: &BrainzMultiSearch) -> Result<BrainzMetadata, B
     }) {
         brainz_res = Some(BrainzMetadata {
             brainz_recording_id: None,
+            brainz_release_id: None,
+            brainz_artist_ids: Vec::new(),
             title: nc_match.title.get_text().unwrap_or(&dlp.title).to_owned(),
             artist: vec!["Nightcore".to_string()],
             album: Some("Nightcore".to_string()),
+            album_artist: None,
+            release_form: None,
+            date: None,
+            genres: Vec::new(),
         });
     }
 
@@ -175,14 +378,14 @@ pub async fn analyze_brainz(dlp: &BrainzMultiSearch) -> Result<BrainzMetadata, B
         for search_opt in search {
             info!("Searching brainz by {:?}", search_opt);
 
-            match self::fetch_recordings(&search_opt).await {
+            match self::fetch_recordings(&search_opt, log).await {
                 Ok(result) => {
                     debug!("Got result with {:?}", result);
                     brainz_res = Some(result);
                     break;
                 }
                 Err(e) => {
-                    error!("Error: {:?}", e);
+                    error!("Error{}: {:?}", trace::request_tag(), e);
                 }
             }
         }
@@ -194,6 +397,51 @@ pub async fn analyze_brainz(dlp: &BrainzMultiSearch) -> Result<BrainzMetadata, B
     brainz_res
 }
 
+/// Takes the metadata of an upload by a "Topic" channel as is, since it comes from the label and
+/// is more reliable than a fuzzy search. MusicBrainz is only asked for the ids, by exact match.
+#[tracing::instrument(name = "brainz_topic", skip(log))]
+pub async fn trusted_topic_result(
+    dlp: &BrainzMultiSearch,
+    album_artist: &str,
+    log: &mut Vec<BrainzSearchLog>,
+) -> BrainzMetadata {
+    let artist: Vec<String> = match &dlp.artist {
+        Some(artist) => artist.split(',').map(|a| a.trim().to_owned()).collect(),
+        None => vec![album_artist.to_owned()],
+    };
+    let mut result = BrainzMetadata {
+        brainz_recording_id: None,
+        brainz_release_id: None,
+        brainz_artist_ids: Vec::new(),
+        title: dlp.title.clone(),
+        artist: artist.clone(),
+        album: dlp.album.clone(),
+        album_artist: Some(album_artist.to_owned()),
+        release_form: None,
+        date: None,
+        genres: Vec::new(),
+    };
+
+    let search = RecordingSearch {
+        title: QTerm::Exact(dlp.title.clone()),
+        artist: artist.into_iter().map(QTerm::Exact).collect(),
+        album: QTerm::exact_option(&dlp.album),
+    };
+    match fetch_recordings(&search, log).await {
+        Ok(found) if found.title.eq_ignore_ascii_case(&result.title) => {
+            result.brainz_recording_id = found.brainz_recording_id;
+            result.brainz_release_id = found.brainz_release_id;
+            result.brainz_artist_ids = found.brainz_artist_ids;
+            result.date = found.date;
+            result.genres = found.genres;
+        }
+        Ok(found) => debug!("Ignoring inexact brainz match {:?}", found),
+        Err(err) => debug!("No exact brainz match: {:?}", err),
+    }
+    info!("Using topic channel metadata {:?}", result);
+    result
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct BrainzMultiSearch {
     pub trackid: Option<String>,
@@ -206,9 +454,61 @@ pub struct BrainzMultiSearch {
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct BrainzMetadata {
     pub brainz_recording_id: Option<String>,
+    #[serde(default)]
+    pub brainz_release_id: Option<String>,
+    /// MusicBrainz ids of the credited artists, in the order of `artist`.
+    /// Empty for manual results and results fetched before the ids were kept.
+    #[serde(default)]
+    pub brainz_artist_ids: Vec<String>,
     pub title: String,
     pub artist: Vec<String>,
     pub album: Option<String>,
+    /// Written as album artist instead of the track artists when set
+    #[serde(default)]
+    pub album_artist: Option<String>,
+    /// The form of the release the titles were taken from, when it was picked by the locale
+    /// preference
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub release_form: Option<ReleaseForm>,
+    /// Release date as `YYYY[-MM[-DD]]`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub date: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub genres: Vec<String>,
+}
+
+/// A recording returned by a search, with how well it matched.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct BrainzCandidate {
+    /// Search score of MusicBrainz from 0 to 100
+    pub score: u32,
+    /// Length of the recording in milliseconds
+    #[serde(default)]
+    pub length: Option<u32>,
+    #[serde(flatten)]
+    pub metadata: BrainzMetadata,
+}
+
+/// One search made while tagging a video, the first candidate of the first successful search
+/// is taken.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct BrainzSearchLog {
+    /// The query in Lucene syntax, as sent to MusicBrainz
+    pub query: String,
+    pub candidates: Vec<BrainzCandidate>,
+    pub error: Option<String>,
+}
+
+/// The searches of one tagging attempt and the result it settled on.
+#[derive(Debug, Clone, Serialize)]
+pub struct MatchAttempt {
+    pub attempt_id: i64,
+    pub video_id: String,
+    /// Unix timestamp
+    pub created: i64,
+    pub query: BrainzMultiSearch,
+    pub searches: Vec<BrainzSearchLog>,
+    pub result: Option<BrainzMetadata>,
 }
 
 #[derive(Debug, Default, Clone)]
@@ -271,29 +571,145 @@ struct RecordingResponse {
 #[serde(rename_all(deserialize = "kebab-case"))]
 struct Recording {
     pub id: String,
+    #[serde(default)]
+    pub score: u32,
     pub title: String,
-    #[expect(dead_code)]
     pub length: Option<i32>,
     pub artist_credit: Vec<ArtistCredit>,
-    #[expect(dead_code)]
     pub first_release_date: Option<String>,
     #[serde(default)]
     pub releases: Vec<Release>,
+    #[serde(default)]
+    pub tags: Vec<RecordingTag>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RecordingTag {
+    pub name: String,
+    pub count: i32,
 }
 
 #[derive(Debug, Deserialize)]
 #[serde(rename_all(deserialize = "kebab-case"))]
 struct ArtistCredit {
     pub name: String,
+    pub artist: Option<CreditedArtist>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CreditedArtist {
+    pub id: String,
 }
 
 #[derive(Debug, Deserialize)]
 #[serde(rename_all(deserialize = "kebab-case"))]
 struct Release {
-    #[expect(dead_code)]
     pub id: String,
     pub title: String,
-    #[expect(dead_code)]
     pub date: Option<String>,
     //media: Vec<Media>,
 }
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BrainzRelease {
+    pub id: String,
+    pub title: String,
+    pub artist: Vec<String>,
+    /// Release date as `YYYY[-MM[-DD]]`
+    pub date: Option<String>,
+    pub tracks: Vec<BrainzTrack>,
+    /// Language and script of this release
+    pub form: ReleaseForm,
+    /// Translations and transliterations of the tracklist, or the original of a pseudo-release
+    pub forms: Vec<ReleaseForm>,
+}
+
+/// A release by the language and script its titles are written in.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ReleaseForm {
+    pub release_id: String,
+    /// ISO 639-3 code, like `jpn`
+    pub language: Option<String>,
+    /// ISO 15924 code, like `Latn`
+    pub script: Option<String>,
+}
+
+impl ReleaseForm {
+    fn new(release_id: String, text: Option<TextRepresentation>) -> Self {
+        let text = text.unwrap_or_default();
+        ReleaseForm {
+            release_id,
+            language: text.language,
+            script: text.script,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BrainzTrack {
+    pub recording_id: String,
+    pub title: String,
+    pub artist: Vec<String>,
+    pub artist_ids: Vec<String>,
+    pub disc: u32,
+    pub position: u32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all(deserialize = "kebab-case"))]
+struct ReleaseResponse {
+    pub id: String,
+    pub title: String,
+    pub date: Option<String>,
+    #[serde(default)]
+    pub artist_credit: Vec<ArtistCredit>,
+    #[serde(default)]
+    pub media: Vec<Medium>,
+    pub text_representation: Option<TextRepresentation>,
+    #[serde(default)]
+    pub relations: Vec<ReleaseRelation>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct TextRepresentation {
+    pub language: Option<String>,
+    pub script: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ReleaseRelation {
+    #[serde(rename = "type")]
+    pub relation_type: String,
+    pub release: Option<RelatedRelease>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all(deserialize = "kebab-case"))]
+struct RelatedRelease {
+    pub id: String,
+    pub text_representation: Option<TextRepresentation>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all(deserialize = "kebab-case"))]
+struct Medium {
+    pub position: u32,
+    #[serde(default)]
+    pub tracks: Vec<Track>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all(deserialize = "kebab-case"))]
+struct Track {
+    pub position: u32,
+    pub title: String,
+    #[serde(default)]
+    pub artist_credit: Vec<ArtistCredit>,
+    pub recording: TrackRecording,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all(deserialize = "kebab-case"))]
+struct TrackRecording {
+    pub id: String,
+}
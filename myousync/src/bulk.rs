@@ -0,0 +1,142 @@
+//! `POST /videos/bulk`, which applies one operation to many videos at once instead of calling
+//! the route of every single video.
+//!
+//! Status changes of all videos are saved in one transaction. Deletes move or remove files and
+//! are done one by one, so some of them can fail while the rest go through.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    MsState,
+    dbdata::{self, DbError, FetchStatus, VideoFilter},
+    util::queue::Priority,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct BulkRequest {
+    #[serde(flatten)]
+    pub operation: BulkOperation,
+    /// The videos to change, if no `filter` is given
+    #[serde(default)]
+    pub video_ids: Option<Vec<String>>,
+    /// Selects the videos like `GET /videos`, ignoring its sorting and pagination
+    #[serde(default)]
+    pub filter: Option<VideoFilter>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(tag = "operation", rename_all = "snake_case")]
+pub enum BulkOperation {
+    /// Downloads videos which failed or are not downloaded again
+    RetryFetch,
+    /// Looks up categorized videos on MusicBrainz again
+    Reindex,
+    /// Deletes the files without the confirmation of single deletes, so it must be forced
+    Delete {
+        #[serde(default)]
+        force: bool,
+    },
+    /// Disables videos, or enables disabled ones again to be downloaded
+    SetDisabled { disabled: bool },
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct BulkResult {
+    /// Videos selected by the request
+    pub matched: usize,
+    pub changed: usize,
+    /// Videos the operation did not apply to, like a retry of a downloaded video
+    pub skipped: usize,
+    /// The error of every video which could not be changed
+    pub failed: BTreeMap<String, String>,
+}
+
+#[derive(Error, Debug)]
+pub enum BulkError {
+    #[error("Either video_ids or filter must be given")]
+    NoSelection,
+    #[error("Only one of video_ids and filter may be given")]
+    BothSelections,
+    #[error("Bulk deletes must be forced")]
+    DeleteNotForced,
+    #[error(transparent)]
+    Db(#[from] DbError),
+}
+
+pub fn run(s: &MsState, request: BulkRequest) -> Result<BulkResult, BulkError> {
+    let video_ids = match (request.video_ids, &request.filter) {
+        (Some(video_ids), None) => video_ids,
+        (None, Some(filter)) => dbdata::DB.get_video_ids(filter)?,
+        (None, None) => return Err(BulkError::NoSelection),
+        (Some(_), Some(_)) => return Err(BulkError::BothSelections),
+    };
+
+    let mut result = BulkResult {
+        matched: video_ids.len(),
+        ..Default::default()
+    };
+    let (changed, priority) = match request.operation {
+        BulkOperation::RetryFetch => (
+            dbdata::DB.modify_video_statuses(&video_ids, &|v| {
+                if v.is_downloaded() {
+                    return false;
+                }
+                v.fetch_status = FetchStatus::NotFetched;
+                true
+            })?,
+            Some(Priority::High),
+        ),
+        BulkOperation::Reindex => (
+            dbdata::DB.modify_video_statuses(&video_ids, &|v| {
+                if v.fetch_status != FetchStatus::Categorized {
+                    return false;
+                }
+                v.fetch_status = FetchStatus::Fetched;
+                true
+            })?,
+            Some(Priority::Low),
+        ),
+        BulkOperation::SetDisabled { disabled } => (
+            dbdata::DB.modify_video_statuses(&video_ids, &|v| {
+                if disabled == (v.fetch_status == FetchStatus::Disabled) {
+                    return false;
+                }
+                v.fetch_status = if disabled {
+                    FetchStatus::Disabled
+                } else {
+                    FetchStatus::NotFetched
+                };
+                true
+            })?,
+            (!disabled).then_some(Priority::High),
+        ),
+        BulkOperation::Delete { force } => {
+            if !force {
+                return Err(BulkError::DeleteNotForced);
+            }
+            for video_id in &video_ids {
+                match crate::delete_video(s, video_id) {
+                    Ok(true) => result.changed += 1,
+                    Ok(false) => result.skipped += 1,
+                    Err(err) => {
+                        result.failed.insert(video_id.clone(), err);
+                    }
+                }
+            }
+            return Ok(result);
+        }
+    };
+
+    result.changed = changed.len();
+    result.skipped = result.matched - result.changed;
+    for video in changed {
+        MsState::push_update_notification(&video);
+        if let Some(priority) = priority {
+            MsState::enqueue_tagger(video.video_id, priority);
+        }
+    }
+    Ok(result)
+}
@@ -0,0 +1,123 @@
+//! Heuristic for how likely a MusicBrainz result is the song of a video, used to hold back
+//! doubtful matches for review instead of writing them into the files.
+
+use crate::brainz::{BrainzMetadata, BrainzMultiSearch};
+
+const TITLE_WEIGHT: f64 = 0.5;
+const ARTIST_WEIGHT: f64 = 0.3;
+const DURATION_WEIGHT: f64 = 0.2;
+/// Length differences up to this are normal for intros, outros and silence
+const DURATION_TOLERANCE_SECS: f64 = 3.0;
+/// Length differences from this on count as a different recording
+const DURATION_MISMATCH_SECS: f64 = 30.0;
+const MIN_CONTAINED_LEN: usize = 3;
+
+/// Scores `result` against the metadata of the video from 0 (unrelated) to 1 (certain).
+///
+/// Compares titles and artists by edit distance and the lengths of video and recording.
+/// Parts which are unknown on either side are left out of the score.
+pub fn score(
+    query: &BrainzMultiSearch,
+    video_duration: Option<f64>,
+    result: &BrainzMetadata,
+    recording_length_ms: Option<u32>,
+) -> f64 {
+    let mut parts = vec![(TITLE_WEIGHT, title_score(query, result))];
+    if let Some(artist) = artist_score(query, result) {
+        parts.push((ARTIST_WEIGHT, artist));
+    }
+    if let (Some(video), Some(recording)) = (video_duration, recording_length_ms) {
+        let delta = (video - f64::from(recording) / 1000.0).abs();
+        let duration = 1.0
+            - ((delta - DURATION_TOLERANCE_SECS)
+                / (DURATION_MISMATCH_SECS - DURATION_TOLERANCE_SECS))
+                .clamp(0.0, 1.0);
+        parts.push((DURATION_WEIGHT, duration));
+    }
+
+    let total_weight: f64 = parts.iter().map(|(weight, _)| weight).sum();
+    parts
+        .iter()
+        .map(|(weight, score)| weight * score)
+        .sum::<f64>()
+        / total_weight
+}
+
+/// Video titles often contain the artist as `Artist - Title`, so each side of the dash is tried
+/// as well.
+fn title_score(query: &BrainzMultiSearch, result: &BrainzMetadata) -> f64 {
+    let mut candidates = vec![query.title.as_str()];
+    if let Some((left, right)) = query.title.split_once(" - ") {
+        candidates.push(left);
+        candidates.push(right);
+    }
+    candidates
+        .into_iter()
+        .map(|title| similarity(title, &result.title))
+        .fold(0.0, f64::max)
+}
+
+fn artist_score(query: &BrainzMultiSearch, result: &BrainzMetadata) -> Option<f64> {
+    if result.artist.is_empty() {
+        return None;
+    }
+    let artists = result.artist.join(", ");
+    match &query.artist {
+        Some(artist) => Some(similarity(artist, &artists)),
+        // Without an artist in the metadata it can only be found in the title
+        None => {
+            let title = normalize(&query.title);
+            result
+                .artist
+                .iter()
+                .map(|a| normalize(a))
+                .any(|a| !a.is_empty() && title.contains(&a))
+                .then_some(1.0)
+        }
+    }
+}
+
+/// 1 for equal texts, 0 for entirely different ones. A text contained in the other counts as
+/// equal, as video titles are often decorated with things like "(Official Video)".
+fn similarity(a: &str, b: &str) -> f64 {
+    let a = normalize(a);
+    let b = normalize(b);
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+    let (short, long) = if a.len() < b.len() {
+        (&a, &b)
+    } else {
+        (&b, &a)
+    };
+    // Single letters are contained in about everything
+    if short.len() >= MIN_CONTAINED_LEN && long.contains(short.as_str()) {
+        return 1.0;
+    }
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    1.0 - levenshtein(&a, &b) as f64 / a.len().max(b.len()) as f64
+}
+
+/// Lowercases and reduces the text to letters and digits separated by single spaces.
+fn normalize(text: &str) -> String {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn levenshtein(a: &[char], b: &[char]) -> usize {
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
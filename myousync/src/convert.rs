@@ -0,0 +1,292 @@
+//! Conversion of downloads into codecs the players of the library can handle.
+
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use log::{info, warn};
+use serde::Deserialize;
+use tokio::process::Command;
+
+use crate::{MsCompatibility, MsState, musicfiles, util::workspace::CONVERTING_INFIX};
+
+#[derive(thiserror::Error, Debug)]
+pub enum ConvertError {
+    #[error("IO error: {0}")]
+    IOError(#[from] std::io::Error),
+    #[error("ffprobe returned an error: {0}")]
+    ProbeError(String),
+    #[error("ffmpeg returned an error: {0}")]
+    CommandError(String),
+    #[error("Compatibility profile '{0}' allows no codecs")]
+    NoTargetCodec(String),
+    #[error("Codec of {0} cannot be encoded")]
+    UnknownCodec(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AudioCodec {
+    Mp3,
+    Aac,
+    Opus,
+    Vorbis,
+    Flac,
+}
+
+impl AudioCodec {
+    fn from_probe(name: &str) -> Option<Self> {
+        match name {
+            "mp3" => Some(AudioCodec::Mp3),
+            "aac" => Some(AudioCodec::Aac),
+            "opus" => Some(AudioCodec::Opus),
+            "vorbis" => Some(AudioCodec::Vorbis),
+            "flac" => Some(AudioCodec::Flac),
+            _ => None,
+        }
+    }
+
+    /// Extension of the container this codec is stored in.
+    pub fn extension(self) -> &'static str {
+        match self {
+            AudioCodec::Mp3 => "mp3",
+            AudioCodec::Aac => "m4a",
+            AudioCodec::Opus => "opus",
+            AudioCodec::Vorbis => "ogg",
+            AudioCodec::Flac => "flac",
+        }
+    }
+
+    fn encoder(self) -> &'static str {
+        match self {
+            AudioCodec::Mp3 => "libmp3lame",
+            AudioCodec::Aac => "aac",
+            AudioCodec::Opus => "libopus",
+            AudioCodec::Vorbis => "libvorbis",
+            AudioCodec::Flac => "flac",
+        }
+    }
+}
+
+impl Display for AudioCodec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            AudioCodec::Mp3 => "mp3",
+            AudioCodec::Aac => "aac",
+            AudioCodec::Opus => "opus",
+            AudioCodec::Vorbis => "vorbis",
+            AudioCodec::Flac => "flac",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Makes the file at `path` playable under `profile`.
+///
+/// Files in an allowed codec but the wrong container are remuxed, files in any other codec are
+/// transcoded to the target codec of the profile. The original is moved to the archive folder of
+/// the profile, or deleted if there is none.
+///
+/// Returns the path of the file to continue with, which is `path` itself when nothing was done.
+pub async fn ensure_compatible(
+    s: &MsState,
+    profile: &MsCompatibility,
+    path: &Path,
+    video_id: &str,
+) -> Result<PathBuf, ConvertError> {
+    let codec = probe_codec(s, path).await?;
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+
+    let (target, copy) = match codec {
+        Some(codec) if profile.allowed_codecs.contains(&codec) => {
+            if codec.extension() == extension {
+                return Ok(path.to_path_buf());
+            }
+            (codec, true)
+        }
+        _ => {
+            let target = profile
+                .target
+                .or_else(|| profile.allowed_codecs.first().copied())
+                .ok_or_else(|| ConvertError::NoTargetCodec(profile.name.clone()))?;
+            (target, false)
+        }
+    };
+
+    info!(
+        "{} {} ({}) to {} for '{}'",
+        if copy { "Remuxing" } else { "Transcoding" },
+        video_id,
+        codec.map_or("unknown codec".to_string(), |c| c.to_string()),
+        target,
+        profile.name
+    );
+
+    let bitrate = (!copy).then_some(profile.bitrate.as_str());
+    let converting = run_ffmpeg(s, path, video_id, target, bitrate, None).await?;
+    if let Err(err) = retire_original(s, profile, path, video_id, extension) {
+        let _ = std::fs::remove_file(&converting);
+        return Err(err);
+    }
+
+    let converted = path.with_extension(target.extension());
+    std::fs::rename(&converting, &converted)?;
+    Ok(converted)
+}
+
+/// Moves the original of a conversion to the archive folder of the profile, or deletes it.
+fn retire_original(
+    s: &MsState,
+    profile: &MsCompatibility,
+    path: &Path,
+    video_id: &str,
+    extension: &str,
+) -> Result<(), ConvertError> {
+    match &profile.archive {
+        Some(archive) => {
+            std::fs::create_dir_all(archive)?;
+            let archived = archive.join(format!("{video_id}.{extension}"));
+            musicfiles::move_file(&s.config.paths, path, &archived)
+                .map_err(std::io::Error::other)?;
+        }
+        None => std::fs::remove_file(path)?,
+    }
+    Ok(())
+}
+
+/// Remuxes a download whose container cannot be tagged, e.g. `.webm`, into the container of its
+/// codec.
+///
+/// The audio stream is copied, so the original is deleted afterwards.
+/// Returns `None` when the codec has no container which can be tagged.
+pub async fn remux_for_tagging(
+    s: &MsState,
+    path: &Path,
+    video_id: &str,
+) -> Result<Option<PathBuf>, ConvertError> {
+    let Some(codec) = probe_codec(s, path).await? else {
+        return Ok(None);
+    };
+    if !multitag::Tag::supports_extension(codec.extension()) {
+        return Ok(None);
+    }
+
+    info!("Remuxing {} ({}) to make it taggable", video_id, codec);
+    let converting = run_ffmpeg(s, path, video_id, codec, None, None).await?;
+    if let Err(err) = std::fs::remove_file(path) {
+        let _ = std::fs::remove_file(&converting);
+        return Err(err.into());
+    }
+
+    let remuxed = path.with_extension(codec.extension());
+    std::fs::rename(&converting, &remuxed)?;
+    Ok(Some(remuxed))
+}
+
+/// Re-encodes `path` in its own codec with the ffmpeg audio `filter` applied, replacing it.
+///
+/// Returns the path of the result, which only differs from `path` when the container did not
+/// match the codec.
+pub async fn apply_filter(
+    s: &MsState,
+    path: &Path,
+    video_id: &str,
+    filter: &str,
+    bitrate: &str,
+) -> Result<PathBuf, ConvertError> {
+    let codec = probe_codec(s, path)
+        .await?
+        .ok_or_else(|| ConvertError::UnknownCodec(video_id.to_owned()))?;
+    let sample_rate = probe_stream(s, path, "sample_rate").await?;
+    // Filters like loudnorm resample, so the original rate is restored
+    let filter = match sample_rate.parse::<u32>() {
+        Ok(rate) => format!("{filter},aresample={rate}"),
+        Err(_) => filter.to_owned(),
+    };
+
+    let converting = run_ffmpeg(s, path, video_id, codec, Some(bitrate), Some(&filter)).await?;
+    if let Err(err) = std::fs::remove_file(path) {
+        let _ = std::fs::remove_file(&converting);
+        return Err(err.into());
+    }
+
+    let filtered = path.with_extension(codec.extension());
+    std::fs::rename(&converting, &filtered)?;
+    Ok(filtered)
+}
+
+/// Converts `path` into a temporary file next to it.
+/// The audio stream is copied when no `bitrate` is given and `target` is not flac, `filter` is
+/// only applied when encoding.
+async fn run_ffmpeg(
+    s: &MsState,
+    path: &Path,
+    video_id: &str,
+    target: AudioCodec,
+    bitrate: Option<&str>,
+    filter: Option<&str>,
+) -> Result<PathBuf, ConvertError> {
+    let stem = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or(video_id);
+    let converting = path.with_file_name(format!("{stem}{CONVERTING_INFIX}{}", target.extension()));
+    let mut cmd = Command::new(&s.config.scrape.ffmpeg);
+    cmd.args(["-nostdin", "-loglevel", "error", "-y"])
+        .arg("-i")
+        .arg(path)
+        .args(["-map", "0:a:0", "-map_metadata", "0", "-vn"]);
+    match bitrate {
+        None => {
+            cmd.args(["-c:a", "copy"]);
+        }
+        Some(bitrate) => {
+            cmd.args(["-c:a", target.encoder()]);
+            if target != AudioCodec::Flac {
+                cmd.args(["-b:a", bitrate]);
+            }
+            if let Some(filter) = filter {
+                cmd.arg("-af").arg(filter);
+            }
+        }
+    }
+    let output = cmd.arg(&converting).output().await?;
+    if !output.status.success() {
+        let _ = std::fs::remove_file(&converting);
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        return Err(ConvertError::CommandError(stderr));
+    }
+
+    // ffmpeg drops embedded pictures for most target containers
+    if let Some(cover) = musicfiles::read_cover(path)
+        && let Err(err) = musicfiles::set_cover(&converting, cover)
+    {
+        warn!("Failed to carry over cover of {}: {:?}", video_id, err);
+    }
+
+    Ok(converting)
+}
+
+async fn probe_codec(s: &MsState, path: &Path) -> Result<Option<AudioCodec>, ConvertError> {
+    let codec = probe_stream(s, path, "codec_name").await?;
+    Ok(AudioCodec::from_probe(&codec))
+}
+
+/// Reads one `entry` of the first audio stream of `path`.
+async fn probe_stream(s: &MsState, path: &Path, entry: &str) -> Result<String, ConvertError> {
+    let output = Command::new(&s.config.scrape.ffprobe)
+        .args(["-v", "error", "-select_streams", "a:0"])
+        .arg("-show_entries")
+        .arg(format!("stream={entry}"))
+        .args(["-of", "csv=p=0"])
+        .arg(path)
+        .output()
+        .await?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        return Err(ConvertError::ProbeError(stderr));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
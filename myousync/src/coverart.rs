@@ -0,0 +1,129 @@
+//! Album art for tagged files: the front cover of the matched release from the Cover Art
+//! Archive, or the video thumbnail when the release has none.
+
+use std::path::Path;
+
+use log::{debug, warn};
+use multitag::data::Picture;
+use reqwest::StatusCode;
+use serde::Deserialize;
+
+use crate::{MsCoverPolicy, dbdata, musicfiles, net::CLIENT};
+
+/// Formats every tag type and player can show
+const SUPPORTED_MIME_TYPES: [&str; 2] = ["image/jpeg", "image/png"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CoverFormat {
+    Jpeg,
+    Png,
+}
+
+impl CoverFormat {
+    fn mime_type(self) -> &'static str {
+        match self {
+            CoverFormat::Jpeg => "image/jpeg",
+            CoverFormat::Png => "image/png",
+        }
+    }
+}
+
+/// Finds the cover to set on the file at `path`.
+///
+/// Returns `None` when the file should keep the cover it has.
+#[tracing::instrument(name = "cover", skip(path))]
+pub async fn find_cover(
+    path: &Path,
+    video_id: &str,
+    release_id: Option<&str>,
+    dlp_thumbnail: Option<&str>,
+) -> Option<Picture> {
+    if let Some(release_id) = release_id {
+        let url = format!("https://coverartarchive.org/release/{release_id}/front-500");
+        match download(&url).await {
+            Ok(Some(cover)) => return Some(cover),
+            Ok(None) => debug!("Release {} has no cover", release_id),
+            Err(err) => warn!("Failed to get cover of release {}: {}", release_id, err),
+        }
+    }
+
+    if musicfiles::read_cover(path).is_some() {
+        return None;
+    }
+
+    // The playlist thumbnails of the youtube api are jpegs, those of yt-dlp often webp
+    let thumbnail = dbdata::DB.get_thumbnail(video_id).unwrap_or_else(|err| {
+        warn!("Failed to get thumbnail url of {}: {}", video_id, err);
+        None
+    });
+    let thumbnails = thumbnail
+        .into_iter()
+        .chain(dlp_thumbnail.map(str::to_owned));
+    for url in thumbnails {
+        match download(&url).await {
+            Ok(Some(cover)) => return Some(cover),
+            Ok(None) => {}
+            Err(err) => warn!("Failed to get thumbnail of {}: {}", video_id, err),
+        }
+    }
+    None
+}
+
+/// Returns `None` if there is no image at `url` or it is in an unsupported format.
+async fn download(url: &str) -> Result<Option<Picture>, reqwest::Error> {
+    let response = CLIENT.get(url).send().await?;
+    if response.status() == StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    let response = response.error_for_status()?;
+
+    let mime_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_owned();
+    if !SUPPORTED_MIME_TYPES.contains(&mime_type.as_str()) {
+        debug!("Skipping {} image at {}", mime_type, url);
+        return Ok(None);
+    }
+
+    let data = response.bytes().await?.to_vec();
+    Ok(Some(Picture { data, mime_type }))
+}
+
+/// Scales down and converts `cover` as configured by `policy`.
+///
+/// Covers which cannot be decoded are returned unchanged. Decoding and encoding takes a while for
+/// large images, so this is better run on a blocking thread.
+pub fn apply_policy(policy: &MsCoverPolicy, cover: Picture) -> Picture {
+    let oversized = policy.max_size.and_then(|max| {
+        let (width, height) = cover.dimensions()?;
+        (width.max(height) > max).then(|| policy.target_size.unwrap_or(max).min(max))
+    });
+    let mime_type = policy.format.map_or_else(
+        || cover.mime_type.clone(),
+        |format| format.mime_type().to_owned(),
+    );
+    if oversized.is_none() && mime_type == cover.mime_type {
+        return cover;
+    }
+
+    match cover.reencoded(oversized, &mime_type) {
+        Some(processed) => {
+            debug!(
+                "Re-encoded {} cover of {} bytes to {} of {} bytes",
+                cover.mime_type,
+                cover.data.len(),
+                processed.mime_type,
+                processed.data.len()
+            );
+            processed
+        }
+        None => {
+            warn!("Failed to re-encode {} cover, keeping it", cover.mime_type);
+            cover
+        }
+    }
+}
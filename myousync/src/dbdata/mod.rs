@@ -0,0 +1,1252 @@
+//! Everything myousync keeps between runs, behind the [`Storage`] trait.
+//!
+//! The data lives in the sqlite file `ytdata.db` next to the config, see [`sqlite`], or in
+//! PostgreSQL when `[database.postgres]` is set, see [`postgres`]. PostgreSQL lets several
+//! instances share a database and suits larger libraries.
+
+mod postgres;
+mod sqlite;
+
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::{
+        LazyLock, OnceLock, RwLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+pub use self::sqlite::{JournalMode, Synchronous};
+use self::{postgres::PostgresStorage, sqlite::SqliteStorage};
+use crate::{
+    MsDatabase, MsPostgres,
+    apikeys::ApiKey,
+    artist_rules::{ArtistRule, ArtistRuleRequest},
+    audit::{AuditEntry, AuditQuery},
+    brainz::{BrainzCandidate, BrainzMetadata, BrainzMultiSearch, BrainzSearchLog, MatchAttempt},
+    duplicates::{Duplicate, DuplicateKeep, DuplicateKind},
+    errors::{self, ErrorCode},
+    flags::{FlagReason, VideoFlag},
+    jobs::{Job, JobState},
+    musicfiles::IndexedFile,
+    pending::PendingMove,
+    removal::Removal,
+    trash::TrashedFile,
+    ytdlp::DownloadProgress,
+};
+
+pub static DB: LazyLock<Box<dyn Storage>> = LazyLock::new(open);
+
+/// Playlist filter value of the videos which were added by hand without a playlist.
+pub const UNSORTED_PLAYLIST: &str = "unsorted";
+static DB_PATH: OnceLock<String> = OnceLock::new();
+static POSTGRES: RwLock<Option<MsPostgres>> = RwLock::new(None);
+/// Tagging attempts kept per video
+const MATCH_HISTORY_LEN: u32 = 10;
+/// Status changes kept per video
+const STATUS_HISTORY_LEN: u32 = 100;
+
+static SLOW_QUERY_MS: AtomicU64 = AtomicU64::new(250);
+static STATS: DbStats = DbStats {
+    busy: AtomicU64::new(0),
+    busy_timeouts: AtomicU64::new(0),
+    slow_queries: AtomicU64::new(0),
+    max_query_us: AtomicU64::new(0),
+};
+
+struct DbStats {
+    /// Statements which found the database locked by another connection
+    busy: AtomicU64,
+    /// Statements which gave up because the lock was held longer than the busy timeout
+    busy_timeouts: AtomicU64,
+    slow_queries: AtomicU64,
+    max_query_us: AtomicU64,
+}
+
+/// A snapshot of the contention on the database.
+#[derive(Debug, Serialize)]
+pub struct DbDiagnostics {
+    pub busy_timeout_ms: u64,
+    pub slow_query_ms: u64,
+    pub busy: u64,
+    pub busy_timeouts: u64,
+    pub slow_queries: u64,
+    pub max_query_us: u64,
+}
+
+#[derive(Error, Debug)]
+pub enum DbError {
+    #[error("Database error: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+    #[error("Database error: {0}")]
+    Postgres(#[from] ::postgres::Error),
+    #[error("Invalid row in the database: {0}")]
+    Row(#[from] serde_rusqlite::Error),
+    #[error("Invalid json in the database: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Invalid value {value} in column {column}")]
+    InvalidValue { column: String, value: i64 },
+    #[error("Invalid database version '{0}'")]
+    InvalidVersion(String),
+    #[error("{0} is not supported with PostgreSQL")]
+    Unsupported(&'static str),
+    #[error("The backup has version {0}, which is newer than this myousync supports")]
+    NewerBackup(u32),
+}
+
+pub type DbResult<T> = Result<T, DbError>;
+
+/// Lets handlers return database errors with `?`, as a 500 which is logged with the request.
+impl From<DbError> for (StatusCode, String) {
+    fn from(err: DbError) -> Self {
+        error!("{}", err);
+        (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+    }
+}
+
+impl IntoResponse for DbError {
+    fn into_response(self) -> Response {
+        <(StatusCode, String)>::from(self).into_response()
+    }
+}
+
+/// Sets the database file, `:memory:` for a database which only lives as long as the process.
+///
+/// Has no effect once the database was opened.
+pub fn use_database(path: &str) {
+    if DB_PATH.set(path.to_owned()).is_err() || LazyLock::get(&DB).is_some() {
+        warn!("Database path set too late, keeping the current database");
+    }
+}
+
+/// The folder of the database file, where other data of myousync is kept as well.
+pub fn data_dir() -> PathBuf {
+    DB_PATH
+        .get()
+        .filter(|p| p.as_str() != ":memory:")
+        .and_then(|p| Path::new(p).parent())
+        .map(Path::to_path_buf)
+        .unwrap_or_default()
+}
+
+/// Sets how long a statement waits for a database locked by another process, and from which
+/// duration on a statement is logged as slow. Both can be changed at any time, the backend,
+/// connections and pragmas only before the database is opened.
+pub fn configure_database(config: &MsDatabase) {
+    SLOW_QUERY_MS.store(config.slow_query.as_millis() as u64, Ordering::Relaxed);
+    sqlite::configure(config);
+    *POSTGRES.write().unwrap() = config.postgres.clone();
+}
+
+/// Opens and upgrades the configured database, which the process cannot run without.
+fn open() -> Box<dyn Storage> {
+    let postgres = POSTGRES.read().unwrap().clone();
+    let storage: DbResult<Box<dyn Storage>> = match postgres {
+        Some(config) => PostgresStorage::open(&config).map(|db| Box::new(db) as _),
+        None => SqliteStorage::open().map(|db| Box::new(db) as _),
+    };
+    storage.unwrap_or_else(|err| panic!("Failed to open the database: {err}"))
+}
+
+/// Runs `f` on the blocking thread pool, for queries in async handlers which can take a while.
+pub async fn blocking<T: Send + 'static>(
+    f: impl FnOnce(&'static dyn Storage) -> T + Send + 'static,
+) -> T {
+    tokio::task::spawn_blocking(move || f(DB.as_ref()))
+        .await
+        .expect("Database task panicked")
+}
+
+pub fn diagnostics() -> DbDiagnostics {
+    DbDiagnostics {
+        busy_timeout_ms: sqlite::busy_timeout().as_millis() as u64,
+        slow_query_ms: SLOW_QUERY_MS.load(Ordering::Relaxed),
+        busy: STATS.busy.load(Ordering::Relaxed),
+        busy_timeouts: STATS.busy_timeouts.load(Ordering::Relaxed),
+        slow_queries: STATS.slow_queries.load(Ordering::Relaxed),
+        max_query_us: STATS.max_query_us.load(Ordering::Relaxed),
+    }
+}
+
+/// Counts a finished statement, and logs it if it was slow.
+fn record_query(sql: &str, duration: Duration) {
+    STATS
+        .max_query_us
+        .fetch_max(duration.as_micros() as u64, Ordering::Relaxed);
+    if duration.as_millis() as u64 >= SLOW_QUERY_MS.load(Ordering::Relaxed) {
+        STATS.slow_queries.fetch_add(1, Ordering::Relaxed);
+        warn!("Slow query took {:?}: {}", duration, sql);
+    }
+}
+
+/// Writes `value` into a column holding json.
+fn to_json<T: Serialize + ?Sized>(value: Option<&T>) -> DbResult<Option<String>> {
+    Ok(value.map(serde_json::to_string).transpose()?)
+}
+
+/// A parameter of a query which is built at runtime, like the filters of a list.
+#[derive(Debug, Clone)]
+enum SqlValue {
+    Integer(i64),
+    Text(String),
+}
+
+/// The differences between the SQL of the backends, for queries which are built at runtime.
+#[derive(Debug, Clone, Copy)]
+enum Dialect {
+    Sqlite,
+    Postgres,
+}
+
+impl Dialect {
+    /// The placeholder of the parameter at `index`, counted from 1.
+    fn param(self, index: usize) -> String {
+        match self {
+            Dialect::Sqlite => format!("?{index}"),
+            Dialect::Postgres => format!("${index}"),
+        }
+    }
+
+    /// Reads the field `field` of the json in the column `column` as text.
+    fn json_field(self, column: &str, field: &str) -> String {
+        match self {
+            Dialect::Sqlite => format!("json_extract({column}, '$.{field}')"),
+            Dialect::Postgres => format!("({column})::jsonb ->> '{field}'"),
+        }
+    }
+
+    /// `LIKE` ignoring the case of ASCII letters.
+    fn like(self) -> &'static str {
+        match self {
+            Dialect::Sqlite => "LIKE",
+            Dialect::Postgres => "ILIKE",
+        }
+    }
+}
+
+/// Where the data lives. All methods block, see [`blocking`] for async code.
+pub trait Storage: Send + Sync {
+    // YT_API
+
+    fn set_yt_dlp(&self, video_id: &str, dlp: &str) -> DbResult<()>;
+
+    fn delete_yt_data(&self, video_id: &str) -> DbResult<()>;
+
+    fn try_get_yt_dlp(&self, video_id: &str) -> DbResult<Option<String>>;
+
+    // PLAYLISTS
+
+    fn try_get_playlist(&self, playlist_id: &str) -> DbResult<Option<Playlist>>;
+
+    fn set_playlist(&self, playlist: &Playlist) -> DbResult<()>;
+
+    fn update_playlist_fetch_time(
+        &self,
+        playlist_id: &str,
+        fetch_time: DateTime<Utc>,
+    ) -> DbResult<()>;
+
+    fn get_playlists(&self) -> DbResult<Vec<PlaylistSummary>>;
+
+    fn get_playlist_summary(&self, playlist_id: &str) -> DbResult<Option<PlaylistSummary>>;
+
+    fn get_playlist_details(&self, playlist_id: &str) -> DbResult<Option<PlaylistDetails>> {
+        let Some(summary) = self.get_playlist_summary(playlist_id)? else {
+            return Ok(None);
+        };
+        let Some(playlist) = self.try_get_playlist(playlist_id)? else {
+            return Ok(None);
+        };
+        Ok(Some(PlaylistDetails {
+            summary,
+            items: playlist.items,
+        }))
+    }
+
+    fn get_thumbnail(&self, video_id: &str) -> DbResult<Option<String>>;
+
+    // YT AUTH
+
+    fn try_get_auth(&self) -> DbResult<Option<AuthData>>;
+
+    fn set_auth(&self, auth: &AuthData) -> DbResult<()>;
+
+    // FILESYSTEM
+
+    /// The files found by the last scan of the library.
+    fn get_indexed_files(&self) -> DbResult<Vec<IndexedFile>>;
+
+    /// Replaces the index with the result of a full scan.
+    fn set_indexed_files(&self, files: &[IndexedFile]) -> DbResult<()>;
+
+    fn get_track_query_override(&self, video_id: &str) -> DbResult<Option<String>>;
+
+    fn get_track_result_override(&self, video_id: &str) -> DbResult<Option<String>>;
+
+    /// Changes the video with `modify` and saves it if that returns true. Returns the saved video.
+    fn modify_video_status(
+        &self,
+        video_id: &str,
+        modify: &dyn Fn(&mut VideoStatus) -> bool,
+    ) -> DbResult<Option<VideoStatus>> {
+        if let Some(mut video) = self.get_video(video_id)? {
+            let save = modify(&mut video);
+            if !save {
+                return Ok(None);
+            }
+            video.update_now();
+            self.set_full_track_status(&video)?;
+            Ok(Some(video))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Like [`Self::modify_video_status`] for many videos, saved in a single transaction.
+    /// Returns the changed videos.
+    fn modify_video_statuses(
+        &self,
+        video_ids: &[String],
+        modify: &dyn Fn(&mut VideoStatus) -> bool,
+    ) -> DbResult<Vec<VideoStatus>>;
+
+    fn get_all_videos(&self) -> DbResult<Vec<VideoStatus>>;
+
+    /// Returns one page of the videos matching `filter`, and the total number of matches.
+    fn get_videos_page(&self, filter: &VideoFilter) -> DbResult<(u64, Vec<VideoStatus>)>;
+
+    /// The ids of all videos matching `filter`, ignoring its sorting and pagination.
+    fn get_video_ids(&self, filter: &VideoFilter) -> DbResult<Vec<String>>;
+
+    fn get_all_ids(&self) -> DbResult<Vec<String>>;
+
+    fn get_video_fetch_status(&self, video_id: &str) -> DbResult<Option<FetchStatus>>;
+
+    fn get_videos_in_status(&self, status: FetchStatus) -> DbResult<Vec<VideoStatus>>;
+
+    fn get_all_unprocessed_ids(&self) -> DbResult<Vec<String>>;
+
+    /// The newest `last_update` of all videos, in seconds.
+    fn get_last_video_update(&self) -> DbResult<Option<u64>>;
+
+    fn get_video(&self, video_id: &str) -> DbResult<Option<VideoStatus>>;
+
+    /// Writes `status`, adding it to the history of the video if its status, error, query or
+    /// result changed.
+    fn set_full_track_status(&self, status: &VideoStatus) -> DbResult<()>;
+
+    fn set_videos_reindex(&self, video_ids: &[String]) -> DbResult<()>;
+
+    // BRAINZ
+
+    /// The cached response of `query` if it was fetched at or after `fetched_after`.
+    fn try_get_brainz(&self, query: &str, fetched_after: i64) -> DbResult<Option<String>>;
+
+    fn set_brainz(&self, query: &str, data: &str) -> DbResult<()>;
+
+    /// Deletes the cached responses fetched before `fetched_before`, returning how many.
+    fn delete_brainz_before(&self, fetched_before: i64) -> DbResult<u64>;
+
+    /// Deletes the least recently fetched responses beyond the newest `keep`, returning how
+    /// many.
+    fn evict_brainz(&self, keep: u64) -> DbResult<u64>;
+
+    /// Stores the searches of a tagging attempt, dropping the oldest attempts of the video
+    /// beyond [`MATCH_HISTORY_LEN`].
+    fn add_match_attempt(
+        &self,
+        video_id: &str,
+        query: &BrainzMultiSearch,
+        searches: &[BrainzSearchLog],
+        result: Option<&BrainzMetadata>,
+    ) -> DbResult<()>;
+
+    /// The stored tagging attempts of a video, newest first.
+    fn get_match_attempts(&self, video_id: &str) -> DbResult<Vec<MatchAttempt>>;
+
+    /// The recorded states of a video, newest first, at most [`STATUS_HISTORY_LEN`].
+    fn get_status_history(&self, video_id: &str) -> DbResult<Vec<StatusChange>>;
+
+    // JOBS
+
+    fn get_job(&self, video_id: &str) -> DbResult<Option<Job>>;
+
+    /// All jobs, the most recently changed first.
+    fn get_jobs(&self) -> DbResult<Vec<Job>>;
+
+    fn get_jobs_in_state(&self, state: JobState) -> DbResult<Vec<Job>>;
+
+    /// Jobs waiting for a retry whose backoff has passed.
+    fn get_due_retries(&self, now: i64) -> DbResult<Vec<Job>>;
+
+    fn set_job(&self, job: &Job) -> DbResult<()>;
+
+    fn delete_job(&self, video_id: &str) -> DbResult<()>;
+
+    /// Puts jobs which were interrupted by a shutdown back into the queue.
+    fn reset_running_jobs(&self) -> DbResult<usize>;
+
+    /// Whether `video_id` is an item of any synced playlist or was added by hand.
+    fn is_video_in_any_playlist(&self, video_id: &str) -> DbResult<bool>;
+
+    /// The synced playlists `video_id` is an item of, and the playlist it was added to by hand.
+    fn get_video_playlist_ids(&self, video_id: &str) -> DbResult<Vec<String>>;
+
+    /// The videos of the playlist, synced and added by hand.
+    fn get_playlist_video_ids(&self, playlist_id: &str) -> DbResult<HashSet<String>>;
+
+    fn get_removed_ids(&self) -> DbResult<Vec<String>>;
+
+    // PENDING MOVES
+
+    fn set_pending_move(&self, pending: &PendingMove) -> DbResult<()>;
+
+    fn get_pending_moves(&self) -> DbResult<Vec<PendingMove>>;
+
+    fn delete_pending_move(&self, video_id: &str) -> DbResult<()>;
+
+    // SOURCES
+
+    /// Returns the youtube video a track of another provider was matched to.
+    fn get_source_match(&self, provider: &str, source_id: &str) -> DbResult<Option<String>>;
+
+    fn set_source_match(&self, provider: &str, source_id: &str, video_id: &str) -> DbResult<()>;
+
+    // MANUAL VIDEOS
+
+    /// Records a video added by hand, sorted into `playlist_id` or [`UNSORTED_PLAYLIST`].
+    fn add_manual_video(
+        &self,
+        video_id: &str,
+        playlist_id: &str,
+        added_by: &str,
+        added: i64,
+    ) -> DbResult<()>;
+
+    // ERRORS
+
+    /// The videos with an error and the code of it, most recently updated first.
+    fn get_video_errors(&self) -> DbResult<Vec<(String, ErrorCode, String)>>;
+
+    // STATS
+
+    /// The number of videos in every status.
+    fn get_status_counts(&self) -> DbResult<Vec<(FetchStatus, u64)>>;
+
+    /// The number of categorized tracks, and of the distinct artists and releases among them.
+    fn get_library_totals(&self) -> DbResult<(u64, u64, u64)>;
+
+    /// The number of videos downloaded on each UTC day since `since`, days without any are left out.
+    fn get_downloads_per_day(&self, since: i64) -> DbResult<Vec<(String, u64)>>;
+
+    /// The most frequent error codes and how many videos have them.
+    fn get_error_counts(&self, limit: u32) -> DbResult<Vec<(ErrorCode, u64)>>;
+
+    /// The total size of the files in the file index, in bytes.
+    fn get_indexed_size(&self) -> DbResult<u64>;
+
+    // TRASH
+
+    fn add_trashed_file(&self, trashed: &TrashedFile) -> DbResult<()>;
+
+    fn get_trashed_file(&self, video_id: &str) -> DbResult<Option<TrashedFile>>;
+
+    /// All trashed files, most recently deleted first.
+    fn get_trashed_files(&self) -> DbResult<Vec<TrashedFile>> {
+        self.get_trashed_files_before(i64::MAX)
+    }
+
+    fn get_trashed_files_before(&self, deleted: i64) -> DbResult<Vec<TrashedFile>>;
+
+    fn delete_trashed_file(&self, video_id: &str) -> DbResult<()>;
+
+    // FLAGS
+
+    fn add_flag(
+        &self,
+        video_id: &str,
+        reason: FlagReason,
+        note: Option<&str>,
+        reported_by: &str,
+        created: i64,
+    ) -> DbResult<VideoFlag>;
+
+    /// All flags which have not been resolved yet, oldest first.
+    fn get_open_flags(&self) -> DbResult<Vec<VideoFlag>>;
+
+    /// Marks a flag as resolved, returns false if there is no open flag with this id.
+    fn resolve_flag(&self, flag_id: i64, resolved: i64) -> DbResult<bool>;
+
+    // ARTIST RULES
+
+    /// All artist rules, in the order they were added.
+    fn get_artist_rules(&self) -> DbResult<Vec<ArtistRule>>;
+
+    fn add_artist_rule(&self, rule: &ArtistRuleRequest, created: i64) -> DbResult<ArtistRule>;
+
+    /// Replaces the rule, returns `None` if there is no rule with this id.
+    fn update_artist_rule(
+        &self,
+        rule_id: i64,
+        rule: &ArtistRuleRequest,
+    ) -> DbResult<Option<ArtistRule>>;
+
+    /// Returns false if there is no rule with this id.
+    fn delete_artist_rule(&self, rule_id: i64) -> DbResult<bool>;
+
+    // DUPLICATES
+
+    /// Another categorized video matched to `recording_id`.
+    fn find_categorized_recording(
+        &self,
+        recording_id: &str,
+        video_id: &str,
+    ) -> DbResult<Option<String>>;
+
+    /// Records a conflict, replacing any open one of the same video.
+    fn add_duplicate(
+        &self,
+        video_id: &str,
+        other_video_id: Option<&str>,
+        kind: DuplicateKind,
+        path: &Path,
+        created: i64,
+    ) -> DbResult<Duplicate>;
+
+    /// All conflicts waiting for a decision, oldest first.
+    fn get_open_duplicates(&self) -> DbResult<Vec<Duplicate>>;
+
+    fn get_open_duplicate(&self, duplicate_id: i64) -> DbResult<Option<Duplicate>>;
+
+    fn resolve_duplicate(
+        &self,
+        duplicate_id: i64,
+        kept: DuplicateKeep,
+        resolved: i64,
+    ) -> DbResult<()>;
+
+    // User
+
+    fn get_user(&self, username: &str) -> DbResult<Option<UserData>>;
+
+    fn get_users(&self) -> DbResult<Vec<User>>;
+
+    fn has_users(&self) -> DbResult<bool>;
+
+    /// Adds the admin only while there are no users at all, returns false otherwise.
+    fn add_first_user(&self, username: &str, password_hash: &str) -> DbResult<bool>;
+
+    /// Adds the user, returns false if it already exists.
+    fn add_user(&self, username: &str, password_hash: &str, admin: bool) -> DbResult<bool>;
+
+    /// Returns false if the user does not exist.
+    fn set_password(&self, username: &str, password_hash: &str) -> DbResult<bool>;
+
+    /// Deletes the user with its sessions and api keys, returns false if it did not exist.
+    fn delete_user(&self, username: &str) -> DbResult<bool>;
+
+    // SESSIONS
+
+    /// Stores a new session, `token_hash` is the hash of its refresh token.
+    fn add_session(&self, session: &Session, token_hash: &str) -> DbResult<()>;
+
+    /// The unexpired session with the refresh token hashed to `token_hash`.
+    fn get_session_by_token(&self, token_hash: &str, now: i64) -> DbResult<Option<Session>>;
+
+    /// Replaces the refresh token of the session and extends it to `expires`.
+    fn renew_session(
+        &self,
+        session_id: &str,
+        token_hash: &str,
+        now: i64,
+        expires: i64,
+        client_ip: &str,
+    ) -> DbResult<()>;
+
+    fn is_session_active(&self, session_id: &str, now: i64) -> DbResult<bool>;
+
+    /// Unexpired sessions, most recently used first. Only the ones of `username` if given.
+    fn get_sessions(&self, now: i64, username: Option<&str>) -> DbResult<Vec<Session>>;
+
+    /// Revokes the session, returns false if it did not exist or does not belong to `username`.
+    fn delete_session(&self, session_id: &str, username: Option<&str>) -> DbResult<bool>;
+
+    /// Revokes all sessions of the user, except `keep`.
+    fn delete_user_sessions(&self, username: &str, keep: Option<&str>) -> DbResult<()>;
+
+    fn delete_expired_sessions(&self, now: i64) -> DbResult<()>;
+
+    // API KEYS
+
+    fn add_api_key(&self, key: &ApiKey, key_hash: &str) -> DbResult<()>;
+
+    fn get_api_key_by_hash(&self, key_hash: &str) -> DbResult<Option<ApiKey>>;
+
+    /// All api keys, or only the ones of `username` if given.
+    fn get_api_keys(&self, username: Option<&str>) -> DbResult<Vec<ApiKey>>;
+
+    fn touch_api_key(&self, key_id: &str, now: i64) -> DbResult<()>;
+
+    /// Revokes the key, returns false if it did not exist or does not belong to `username`.
+    fn delete_api_key(&self, key_id: &str, username: Option<&str>) -> DbResult<bool>;
+
+    // AUDIT LOG
+
+    fn add_audit_entry(&self, entry: &AuditEntry) -> DbResult<()>;
+
+    /// The total of entries matching `query` and its page, newest first.
+    fn get_audit_page(&self, query: &AuditQuery) -> DbResult<(u64, Vec<AuditEntry>)>;
+
+    // LOGIN ATTEMPTS
+
+    fn get_login_attempts(&self) -> DbResult<Vec<LoginAttempts>>;
+
+    fn set_login_attempts(&self, attempts: &LoginAttempts) -> DbResult<()>;
+
+    fn delete_login_attempts(&self, key: &str) -> DbResult<()>;
+
+    /// Takes or renews the lease `key` for `owner`. Returns false while another owner holds a
+    /// lease renewed less than `ttl` seconds ago.
+    fn acquire_lease(&self, key: &str, owner: &str, now: i64, ttl: i64) -> DbResult<bool>;
+
+    /// The owner of the lease `key` and when it was last renewed.
+    fn get_lease(&self, key: &str) -> DbResult<Option<(String, i64)>>;
+
+    fn release_lease(&self, key: &str, owner: &str) -> DbResult<()>;
+
+    fn get_key(&self, key: &str) -> DbResult<Option<String>>;
+
+    fn set_key(&self, key: &str, value: &str) -> DbResult<()>;
+
+    // BACKUP
+
+    /// Writes a copy of the whole database to `target`, while it stays in use.
+    fn backup(&self, target: &Path) -> DbResult<()>;
+
+    /// Replaces all data with the copy at `source` written by [`Storage::backup`].
+    fn restore(&self, source: &Path) -> DbResult<()>;
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuthData {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub expires_at: i64,
+}
+
+pub struct Playlist {
+    pub playlist_id: String,
+    pub etag: String,
+    pub total_results: u32,
+    pub fetch_time: DateTime<Utc>,
+    pub info: PlaylistInfo,
+    pub items: Vec<PlaylistItem>,
+}
+
+/// Details of a playlist as shown on YouTube.
+#[derive(Debug, Clone, Default)]
+pub struct PlaylistInfo {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    /// Name of the channel which owns the playlist
+    pub owner: Option<String>,
+    /// Url of a small thumbnail of the playlist
+    pub thumbnail: Option<String>,
+}
+
+/// A playlist without its items, as listed by the api.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PlaylistSummary {
+    pub playlist_id: String,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub owner: Option<String>,
+    pub thumbnail: Option<String>,
+    pub total_results: u32,
+    /// Unix timestamp of the last sync
+    pub fetch_time: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PlaylistDetails {
+    #[serde(flatten)]
+    pub summary: PlaylistSummary,
+    pub items: Vec<PlaylistItem>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PlaylistItem {
+    pub video_id: String,
+    pub title: String,
+    pub artist: String,
+    /// Length of the video in seconds
+    pub duration: Option<u32>,
+    /// Url of a small thumbnail of the video
+    pub thumbnail: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize, Default)]
+pub enum FetchStatus {
+    #[default]
+    NotFetched = 0,
+    Fetched,
+    FetchError,
+    BrainzError,
+    Categorized,
+    Disabled,
+    /// Was categorized, but its file is gone from the library
+    FileMissing,
+    /// The result scored below `review_threshold` and waits for a user to confirm or fix it
+    NeedsReview,
+    /// Would replace another track in the library and waits for a user to pick one
+    Duplicate,
+}
+
+/// Filters, sorting and pagination for listing videos.
+#[derive(Debug, Default, Deserialize)]
+pub struct VideoFilter {
+    #[serde(default)]
+    pub offset: u32,
+    /// Page size, capped at [`VideoFilter::MAX_LIMIT`]
+    #[serde(default)]
+    pub limit: Option<u32>,
+    #[serde(default)]
+    pub sort: VideoSort,
+    #[serde(default)]
+    pub order: SortOrder,
+    pub status: Option<FetchStatus>,
+    /// Only videos which are part of this playlist
+    pub playlist: Option<String>,
+    /// Matched against the title and artist of the video and its musicbrainz match
+    pub search: Option<String>,
+    pub has_error: Option<bool>,
+    pub error_code: Option<ErrorCode>,
+}
+
+impl VideoFilter {
+    pub const DEFAULT_LIMIT: u32 = 50;
+    pub const MAX_LIMIT: u32 = 500;
+
+    pub fn limit(&self) -> u32 {
+        self.limit
+            .unwrap_or(Self::DEFAULT_LIMIT)
+            .min(Self::MAX_LIMIT)
+    }
+
+    /// The `WHERE` clause over the `status` table aliased as `s`, and its parameters.
+    fn where_clause(&self, dialect: Dialect) -> (String, Vec<SqlValue>) {
+        let mut conditions = Vec::new();
+        let mut params = Vec::new();
+
+        if let Some(status) = self.status {
+            params.push(SqlValue::Integer(status as i64));
+            conditions.push(format!("s.fetch_status = {}", dialect.param(params.len())));
+        }
+        if let Some(playlist) = &self.playlist {
+            params.push(SqlValue::Text(playlist.clone()));
+            let p = dialect.param(params.len());
+            conditions.push(format!(
+                "(EXISTS (SELECT 1 FROM playlist_items p WHERE p.video_id = s.video_id AND p.playlist_id = {p})
+                  OR EXISTS (SELECT 1 FROM manual_videos m WHERE m.video_id = s.video_id AND m.playlist_id = {p}))"
+            ));
+        }
+        if let Some(search) = self
+            .search
+            .as_deref()
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+        {
+            let escaped = search
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_");
+            params.push(SqlValue::Text(format!("%{escaped}%")));
+            let p = dialect.param(params.len());
+            let like = dialect.like();
+            let result = "coalesce(s.override_result, s.last_result)";
+            let title = dialect.json_field(result, "title");
+            let artist = dialect.json_field(result, "artist");
+            conditions.push(format!(
+                "(EXISTS (SELECT 1 FROM playlist_items p WHERE p.video_id = s.video_id
+                    AND (p.title {like} {p} ESCAPE '\\' OR p.artist {like} {p} ESCAPE '\\'))
+                  OR {title} {like} {p} ESCAPE '\\'
+                  OR {artist} {like} {p} ESCAPE '\\')"
+            ));
+        }
+        if let Some(code) = self.error_code {
+            params.push(SqlValue::Text(code.as_str().to_string()));
+            conditions.push(format!("s.error_code = {}", dialect.param(params.len())));
+        }
+        match self.has_error {
+            Some(true) => conditions.push("s.last_error IS NOT NULL".into()),
+            Some(false) => conditions.push("s.last_error IS NULL".into()),
+            None => {}
+        }
+
+        (where_clause(&conditions), params)
+    }
+
+    /// The column the videos are sorted by, over the `status` table aliased as `s`.
+    fn sort_column(&self) -> &'static str {
+        match self.sort {
+            VideoSort::LastUpdate => "s.last_update",
+            VideoSort::FetchTime => "s.fetch_time",
+            VideoSort::VideoId => "s.video_id",
+            VideoSort::Title => {
+                "(SELECT p.title FROM playlist_items p WHERE p.video_id = s.video_id LIMIT 1)"
+            }
+        }
+    }
+}
+
+impl AuditQuery {
+    /// The `WHERE` clause over the `audit_log` table, and its parameters.
+    fn where_clause(&self, dialect: Dialect) -> (String, Vec<SqlValue>) {
+        let mut conditions = Vec::new();
+        let mut params = Vec::new();
+        for (column, value) in [
+            ("username", self.username.clone()),
+            ("action", self.action.map(|a| a.as_str().to_owned())),
+            ("target", self.target.clone()),
+        ] {
+            if let Some(value) = value {
+                params.push(SqlValue::Text(value));
+                conditions.push(format!("{column} = {}", dialect.param(params.len())));
+            }
+        }
+        (where_clause(&conditions), params)
+    }
+}
+
+fn where_clause(conditions: &[String]) -> String {
+    if conditions.is_empty() {
+        String::new()
+    } else {
+        format!("WHERE {}", conditions.join(" AND "))
+    }
+}
+
+impl SortOrder {
+    fn as_sql(self) -> &'static str {
+        match self {
+            SortOrder::Asc => "ASC",
+            SortOrder::Desc => "DESC",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VideoSort {
+    #[default]
+    LastUpdate,
+    FetchTime,
+    VideoId,
+    Title,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+#[derive(Debug, Deserialize, Serialize, Default)]
+pub struct VideoStatus {
+    pub video_id: String,
+    pub fetch_time: u64,
+    pub fetch_status: FetchStatus,
+    pub last_update: u64,
+    pub last_query: Option<BrainzMultiSearch>,
+    pub last_result: Option<BrainzMetadata>,
+    pub last_error: Option<String>,
+    pub override_query: Option<BrainzMultiSearch>,
+    pub override_result: Option<BrainzMetadata>,
+    /// Set when the video was removed from its playlist
+    #[serde(default)]
+    pub removal: Option<Removal>,
+    /// Recordings found by the last successful search, best first
+    #[serde(default)]
+    pub candidates: Vec<BrainzCandidate>,
+    /// How likely the searched result is right, from 0 to 1
+    #[serde(default)]
+    pub confidence: Option<f64>,
+    /// Set when the last tagging found the file already carrying the desired metadata and left
+    /// it untouched
+    #[serde(default)]
+    pub tags_unchanged: bool,
+    /// Progress of a running download, only sent to clients and never stored
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub download_progress: Option<DownloadProgress>,
+}
+
+/// A state of a video in its history, recorded whenever its status, error, query or result
+/// changed.
+#[derive(Debug, Clone, Serialize)]
+pub struct StatusChange {
+    pub history_id: i64,
+    pub video_id: String,
+    /// Unix timestamp
+    pub time: i64,
+    pub fetch_status: FetchStatus,
+    pub error_code: Option<ErrorCode>,
+    pub last_error: Option<String>,
+    /// The query used for the search, the override if one was set
+    pub query: Option<BrainzMultiSearch>,
+    /// The result the file was tagged with, the override if one was set
+    pub result: Option<BrainzMetadata>,
+    pub tags_unchanged: bool,
+}
+
+impl VideoStatus {
+    pub fn error_code(&self) -> Option<ErrorCode> {
+        errors::classify(self.fetch_status, self.last_error.as_deref())
+    }
+
+    pub fn update_now(&mut self) {
+        self.last_update = Utc::now().timestamp() as u64;
+    }
+
+    pub fn is_downloaded(&self) -> bool {
+        self.fetch_status != FetchStatus::NotFetched
+            && self.fetch_status != FetchStatus::FetchError
+            && self.fetch_status != FetchStatus::Disabled
+            && self.fetch_status != FetchStatus::FileMissing
+    }
+}
+
+impl TryFrom<i64> for FetchStatus {
+    type Error = ();
+
+    fn try_from(value: i64) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(FetchStatus::NotFetched),
+            1 => Ok(FetchStatus::Fetched),
+            2 => Ok(FetchStatus::FetchError),
+            3 => Ok(FetchStatus::BrainzError),
+            4 => Ok(FetchStatus::Categorized),
+            5 => Ok(FetchStatus::Disabled),
+            6 => Ok(FetchStatus::FileMissing),
+            7 => Ok(FetchStatus::NeedsReview),
+            8 => Ok(FetchStatus::Duplicate),
+            _ => Err(()),
+        }
+    }
+}
+
+/// A sign in, which lasts as long as its refresh token is renewed.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Session {
+    pub session_id: String,
+    pub username: String,
+    pub created: i64,
+    pub last_used: i64,
+    pub expires: i64,
+    /// Address of the client which last signed in or refreshed
+    pub client_ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct UserData {
+    pub username: String,
+    /// Hash of [`crate::password`], or a readable password of older databases marked with
+    /// [`crate::password::LEGACY_PREFIX`]
+    pub password: String,
+    pub admin: bool,
+}
+
+/// Failed sign ins of a user or address, see [`crate::lockout`].
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct LoginAttempts {
+    /// `user:<name>` or `ip:<address>`
+    pub key: String,
+    /// Failures since `first_failure`
+    pub failures: u32,
+    pub first_failure: i64,
+    pub locked_until: Option<i64>,
+}
+
+/// A user as listed at `GET /users`.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct User {
+    pub username: String,
+    pub admin: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::distr::{Alphanumeric, SampleString};
+
+    use super::*;
+    use crate::apikeys::ApiScope;
+
+    /// Runs `case` against sqlite, and against PostgreSQL when `MYOUSYNC_TEST_POSTGRES_URL` is
+    /// set to a connection string like `host=localhost user=postgres dbname=myousync_test`. The
+    /// PostgreSQL database is shared between runs, so cases only use random names.
+    fn each_backend(case: impl Fn(&dyn Storage)) {
+        let sqlite = SqliteStorage::open_at(":memory:").unwrap();
+        case(&sqlite);
+
+        match std::env::var("MYOUSYNC_TEST_POSTGRES_URL") {
+            Ok(url) => {
+                let postgres = PostgresStorage::open(&MsPostgres {
+                    url,
+                    connections: 2,
+                })
+                .unwrap();
+                case(&postgres);
+            }
+            Err(_) => eprintln!("MYOUSYNC_TEST_POSTGRES_URL is not set, skipping PostgreSQL"),
+        }
+    }
+
+    fn random_name() -> String {
+        Alphanumeric.sample_string(&mut rand::rng(), 12)
+    }
+
+    fn session(username: &str, now: i64) -> Session {
+        Session {
+            session_id: random_name(),
+            username: username.to_owned(),
+            created: now,
+            last_used: now,
+            expires: now + 3600,
+            client_ip: Some("127.0.0.1".to_owned()),
+            user_agent: None,
+        }
+    }
+
+    fn api_key(username: &str, now: i64) -> ApiKey {
+        ApiKey {
+            key_id: random_name(),
+            name: "test".to_owned(),
+            username: username.to_owned(),
+            scopes: vec![ApiScope::Read],
+            created: now,
+            last_used: None,
+        }
+    }
+
+    #[test]
+    fn keys_are_stored_and_replaced() {
+        each_backend(|db| {
+            let key = random_name();
+            assert_eq!(db.get_key(&key).unwrap(), None);
+            db.set_key(&key, "a").unwrap();
+            db.set_key(&key, "b").unwrap();
+            assert_eq!(db.get_key(&key).unwrap().as_deref(), Some("b"));
+        });
+    }
+
+    #[test]
+    fn video_status_is_stored_with_its_history() {
+        each_backend(|db| {
+            let mut status = VideoStatus {
+                video_id: random_name(),
+                fetch_status: FetchStatus::Fetched,
+                tags_unchanged: true,
+                ..Default::default()
+            };
+            db.set_full_track_status(&status).unwrap();
+            // Unchanged, so no new state in the history
+            db.set_full_track_status(&status).unwrap();
+            status.fetch_status = FetchStatus::FetchError;
+            status.last_error = Some("Video unavailable".to_owned());
+            status.tags_unchanged = false;
+            db.set_full_track_status(&status).unwrap();
+
+            let stored = db.get_video(&status.video_id).unwrap().unwrap();
+            assert_eq!(stored.fetch_status, FetchStatus::FetchError);
+            assert_eq!(stored.last_error.as_deref(), Some("Video unavailable"));
+            assert!(!stored.tags_unchanged);
+
+            let history = db.get_status_history(&status.video_id).unwrap();
+            assert_eq!(history.len(), 2);
+            assert_eq!(history[0].fetch_status, FetchStatus::FetchError);
+            assert_eq!(history[1].fetch_status, FetchStatus::Fetched);
+            assert!(history[1].tags_unchanged);
+        });
+    }
+
+    #[test]
+    fn users_are_added_changed_and_deleted() {
+        each_backend(|db| {
+            let name = random_name();
+            assert!(db.add_user(&name, "hash", true).unwrap());
+            assert!(!db.add_user(&name, "other", false).unwrap());
+            assert!(db.set_password(&name, "new").unwrap());
+
+            let user = db.get_user(&name).unwrap().unwrap();
+            assert_eq!(user.password, "new");
+            assert!(user.admin);
+
+            assert!(db.delete_user(&name).unwrap());
+            assert!(db.get_user(&name).unwrap().is_none());
+            assert!(!db.set_password(&name, "new").unwrap());
+        });
+    }
+
+    #[test]
+    fn sessions_are_limited_to_their_owner() {
+        each_backend(|db| {
+            let now = Utc::now().timestamp();
+            let (alice, bob) = (random_name(), random_name());
+            let of_alice = session(&alice, now);
+            let of_bob = session(&bob, now);
+            db.add_session(&of_alice, &random_name()).unwrap();
+            db.add_session(&of_bob, &random_name()).unwrap();
+
+            let sessions = db.get_sessions(now, Some(&alice)).unwrap();
+            assert_eq!(sessions.len(), 1);
+            assert_eq!(sessions[0].session_id, of_alice.session_id);
+
+            assert!(!db.delete_session(&of_bob.session_id, Some(&alice)).unwrap());
+            assert!(db.is_session_active(&of_bob.session_id, now).unwrap());
+            assert!(db.delete_session(&of_bob.session_id, None).unwrap());
+            assert!(!db.is_session_active(&of_bob.session_id, now).unwrap());
+            db.delete_user_sessions(&alice, None).unwrap();
+        });
+    }
+
+    #[test]
+    fn api_keys_are_limited_to_their_owner() {
+        each_backend(|db| {
+            let now = Utc::now().timestamp();
+            let (alice, bob) = (random_name(), random_name());
+            let of_alice = api_key(&alice, now);
+            let of_bob = api_key(&bob, now);
+            let hash = random_name();
+            db.add_api_key(&of_alice, &hash).unwrap();
+            db.add_api_key(&of_bob, &random_name()).unwrap();
+
+            let keys = db.get_api_keys(Some(&alice)).unwrap();
+            assert_eq!(keys.len(), 1);
+            assert_eq!(keys[0].scopes, vec![ApiScope::Read]);
+            let found = db.get_api_key_by_hash(&hash).unwrap().unwrap();
+            assert_eq!(found.key_id, of_alice.key_id);
+
+            assert!(!db.delete_api_key(&of_bob.key_id, Some(&alice)).unwrap());
+            assert!(db.delete_api_key(&of_bob.key_id, None).unwrap());
+            assert!(db.delete_api_key(&of_alice.key_id, Some(&alice)).unwrap());
+        });
+    }
+
+    #[test]
+    fn sqlite_rejects_an_invalid_version() {
+        let dir = std::env::temp_dir().join(format!("myousync-test-{}", random_name()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("ytdata.db");
+        let path = file.to_str().unwrap();
+        SqliteStorage::open_at(path)
+            .unwrap()
+            .set_key("version", "broken")
+            .unwrap();
+
+        let reopened = SqliteStorage::open_at(path);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(matches!(reopened, Err(DbError::InvalidVersion(v)) if v == "broken"));
+    }
+
+    #[test]
+    fn sqlite_upgrade_marks_readable_passwords() {
+        let dir = std::env::temp_dir().join(format!("myousync-test-{}", random_name()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("ytdata.db");
+        let path = file.to_str().unwrap();
+        let hashed = crate::password::hash("secret password");
+        {
+            let db = SqliteStorage::open_at(path).unwrap();
+            db.add_user("hashed", &hashed, false).unwrap();
+            db.add_user("readable", "$secret password", false).unwrap();
+            db.set_key("version", "11").unwrap();
+        }
+
+        let db = SqliteStorage::open_at(path).unwrap();
+        let stored = |name| db.get_user(name).unwrap().unwrap().password;
+        let (hashed_after, readable_after) = (stored("hashed"), stored("readable"));
+        drop(db);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(hashed_after, hashed);
+        assert_eq!(readable_after, "plain$$secret password");
+    }
+
+    #[test]
+    fn postgres_reconnects_lost_connections() {
+        let Ok(url) = std::env::var("MYOUSYNC_TEST_POSTGRES_URL") else {
+            eprintln!("MYOUSYNC_TEST_POSTGRES_URL is not set, skipping PostgreSQL");
+            return;
+        };
+        let name = format!("myousync_test_{}", random_name());
+        let db = PostgresStorage::open(&MsPostgres {
+            url: format!("{url} application_name={name}"),
+            connections: 1,
+        })
+        .unwrap();
+        let key = random_name();
+        db.set_key(&key, "value").unwrap();
+
+        let mut admin = ::postgres::Client::connect(&url, ::postgres::NoTls).unwrap();
+        admin
+            .execute(
+                "SELECT pg_terminate_backend(pid) FROM pg_stat_activity WHERE application_name = $1",
+                &[&name],
+            )
+            .unwrap();
+
+        // The client only notices the lost connection when it fails, the next call reconnects
+        _ = db.get_key(&key);
+        assert_eq!(db.get_key(&key).unwrap().as_deref(), Some("value"));
+    }
+
+    #[test]
+    fn restore_copies_a_sqlite_database() {
+        let Ok(url) = std::env::var("MYOUSYNC_TEST_POSTGRES_URL") else {
+            eprintln!("MYOUSYNC_TEST_POSTGRES_URL is not set, skipping PostgreSQL");
+            return;
+        };
+        let dir = std::env::temp_dir().join(format!("myousync-test-{}", random_name()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("ytdata.db");
+        let sqlite = SqliteStorage::open_at(file.to_str().unwrap()).unwrap();
+        let name = random_name();
+        sqlite.add_user(&name, "hash", true).unwrap();
+        sqlite.set_key(&name, "value").unwrap();
+        drop(sqlite);
+
+        // Restoring replaces all data, so the copy goes into a schema of its own
+        let schema = format!("test_{}", random_name().to_lowercase());
+        let mut admin = ::postgres::Client::connect(&url, ::postgres::NoTls).unwrap();
+        admin
+            .batch_execute(&format!("CREATE SCHEMA {schema}"))
+            .unwrap();
+        let restored = PostgresStorage::open(&MsPostgres {
+            url: format!("{url} options='-c search_path={schema}'"),
+            connections: 1,
+        })
+        .and_then(|db| {
+            db.restore(&file)?;
+            Ok((db.get_user(&name)?, db.get_key(&name)?))
+        });
+        admin
+            .batch_execute(&format!("DROP SCHEMA {schema} CASCADE"))
+            .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let (user, value) = restored.unwrap();
+        assert!(user.unwrap().admin);
+        assert_eq!(value.as_deref(), Some("value"));
+    }
+}
@@ -0,0 +1,1946 @@
+//! Storage in PostgreSQL, used when `[database.postgres]` is set.
+//!
+//! The tables are those of [`super::sqlite`], with `BIGINT` for all numbers and json kept as
+//! text. A new database is created at the current schema, the `version` in `kvp` counts the
+//! upgrades of this schema on its own.
+//!
+//! An existing sqlite database, or a backup of one, moves over with `myousync db restore <file>`
+//! once `[database.postgres]` is set, see [`Storage::restore`].
+
+use std::{
+    collections::HashSet,
+    path::Path,
+    sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Instant,
+};
+
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use postgres::{
+    Client, GenericClient, NoTls, Row,
+    types::{ToSql, Type},
+};
+use serde::{
+    Deserialize, Deserializer,
+    de::{
+        DeserializeOwned, Visitor,
+        value::{MapDeserializer, SeqDeserializer},
+    },
+    forward_to_deserialize_any,
+};
+use serde_json::Value;
+
+use super::{
+    AuthData, DbError, DbResult, Dialect, FetchStatus, LoginAttempts, MATCH_HISTORY_LEN, Playlist,
+    PlaylistInfo, PlaylistItem, PlaylistSummary, STATUS_HISTORY_LEN, Session, SqlValue,
+    StatusChange, Storage, User, UserData, VideoFilter, VideoStatus, record_query,
+    sqlite::SqliteStorage, to_json,
+};
+use crate::{
+    MsPostgres,
+    apikeys::ApiKey,
+    artist_rules::{ArtistRule, ArtistRuleRequest},
+    audit::{AuditEntry, AuditQuery},
+    brainz::{BrainzMetadata, BrainzMultiSearch, BrainzSearchLog, MatchAttempt},
+    duplicates::{Duplicate, DuplicateKeep, DuplicateKind},
+    errors::{self, ErrorCode},
+    flags::{FlagReason, VideoFlag},
+    instance::LEASE_KEY,
+    jobs::{Job, JobState},
+    musicfiles::IndexedFile,
+    pending::PendingMove,
+    trash::TrashedFile,
+    util::queue::Priority,
+};
+
+const SCHEMA_VERSION: u32 = 2;
+/// Keys of `kvp` which describe this database rather than the data, kept when restoring
+const LOCAL_KEYS: [&str; 2] = ["version", LEASE_KEY];
+
+static NEXT_CLIENT: AtomicUsize = AtomicUsize::new(0);
+
+type Params<'a> = [&'a (dyn ToSql + Sync)];
+
+/// Connections to the server, each used by one statement or transaction at a time.
+pub struct PostgresStorage {
+    url: String,
+    clients: Vec<Mutex<Client>>,
+}
+
+/// Runs `f`, which blocks on the runtime of a client. Tokio does not allow that on its worker
+/// threads, so the worker hands its other tasks to another thread meanwhile.
+fn outside_runtime<T>(f: impl FnOnce() -> T) -> T {
+    if tokio::runtime::Handle::try_current().is_ok() {
+        tokio::task::block_in_place(f)
+    } else {
+        f()
+    }
+}
+
+fn query(client: &mut impl GenericClient, sql: &str, params: &Params) -> DbResult<Vec<Row>> {
+    let start = Instant::now();
+    let rows = client.query(sql, params);
+    record_query(sql, start.elapsed());
+    Ok(rows?)
+}
+
+fn execute(client: &mut impl GenericClient, sql: &str, params: &Params) -> DbResult<u64> {
+    let start = Instant::now();
+    let changed = client.execute(sql, params);
+    record_query(sql, start.elapsed());
+    Ok(changed?)
+}
+
+/// A column as listed by `information_schema`.
+#[derive(Deserialize)]
+struct TableColumn {
+    table_name: String,
+    column_name: String,
+    data_type: String,
+    /// Filled from a sequence, like `BIGSERIAL`
+    serial: bool,
+}
+
+/// Replaces the rows of all tables with those of the sqlite database `source`, except the keys
+/// of `kvp` which belong to this database. Columns are converted to the types they have here.
+fn copy_from_sqlite(client: &mut Client, source: &rusqlite::Connection) -> DbResult<()> {
+    let columns = query(
+        client,
+        "SELECT table_name::TEXT, column_name::TEXT, data_type::TEXT,
+                COALESCE(column_default LIKE 'nextval(%', FALSE) AS serial
+         FROM information_schema.columns
+         WHERE table_schema = current_schema()
+         ORDER BY table_name, ordinal_position",
+        &[],
+    )?
+    .iter()
+    .map(from_row::<TableColumn>)
+    .collect::<DbResult<Vec<_>>>()?;
+
+    // In the order they were created, so rows referenced by a foreign key are copied first
+    let tables = source
+        .prepare("SELECT name FROM sqlite_master WHERE type = 'table' ORDER BY rowid")?
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut tx = client.transaction()?;
+    let truncate = columns
+        .iter()
+        .map(|column| column.table_name.as_str())
+        .filter(|table| *table != "kvp")
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .map(|table| format!("\"{table}\""))
+        .collect::<Vec<_>>()
+        .join(", ");
+    tx.batch_execute(&format!("TRUNCATE {truncate} RESTART IDENTITY CASCADE"))?;
+    execute(
+        &mut tx,
+        "DELETE FROM kvp WHERE key <> ALL($1)",
+        &[&LOCAL_KEYS.as_slice()],
+    )?;
+
+    for table in &tables {
+        let present = source
+            .prepare(&format!("SELECT name FROM pragma_table_info('{table}')"))?
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<Result<HashSet<_>, _>>()?;
+        let target: Vec<_> = columns
+            .iter()
+            .filter(|column| column.table_name == *table && present.contains(&column.column_name))
+            .collect();
+        if target.is_empty() {
+            continue;
+        }
+        let names = target
+            .iter()
+            .map(|column| format!("\"{}\"", column.column_name))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let placeholders = (1..=target.len())
+            .map(|i| format!("${i}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let mut select = format!("SELECT {names} FROM \"{table}\"");
+        if table == "kvp" {
+            select += &format!(" WHERE key NOT IN ('{}')", LOCAL_KEYS.join("', '"));
+        }
+        let insert = tx.prepare(&format!(
+            "INSERT INTO \"{table}\" ({names}) VALUES ({placeholders})"
+        ))?;
+
+        let mut statement = source.prepare(&select)?;
+        let mut rows = statement.query([])?;
+        let mut copied = 0u64;
+        while let Some(row) = rows.next()? {
+            let values = target
+                .iter()
+                .enumerate()
+                .map(|(i, column)| {
+                    Ok(match column.data_type.as_str() {
+                        "bigint" => {
+                            Box::new(row.get::<_, Option<i64>>(i)?) as Box<dyn ToSql + Sync>
+                        }
+                        "boolean" => Box::new(row.get::<_, Option<bool>>(i)?),
+                        "double precision" => Box::new(row.get::<_, Option<f64>>(i)?),
+                        _ => Box::new(row.get::<_, Option<String>>(i)?),
+                    })
+                })
+                .collect::<DbResult<Vec<_>>>()?;
+            let params: Vec<_> = values.iter().map(|value| value.as_ref()).collect();
+            tx.execute(&insert, &params)?;
+            copied += 1;
+        }
+
+        for column in target.iter().filter(|column| column.serial) {
+            let name = &column.column_name;
+            tx.execute(
+                &format!(
+                    "SELECT setval(pg_get_serial_sequence($1, $2), COALESCE(MAX(\"{name}\"), 0) + 1, FALSE)
+                     FROM \"{table}\""
+                ),
+                &[table, name],
+            )?;
+        }
+        info!("Copied {} rows into {}", copied, table);
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+fn sql_params(values: &[SqlValue]) -> Vec<&(dyn ToSql + Sync)> {
+    values
+        .iter()
+        .map(|value| match value {
+            SqlValue::Integer(value) => value as &(dyn ToSql + Sync),
+            SqlValue::Text(value) => value,
+        })
+        .collect()
+}
+
+/// Reads a column holding json. `NULL` reads as json `null`, so nullable columns are read into
+/// an `Option`.
+fn json_column<T: DeserializeOwned>(row: &Row, column: &str) -> DbResult<T> {
+    let text = row.try_get::<_, Option<String>>(column)?;
+    Ok(serde_json::from_str(text.as_deref().unwrap_or("null"))?)
+}
+
+fn fetch_status_column(row: &Row, column: &str) -> DbResult<FetchStatus> {
+    let value = row.try_get::<_, i64>(column)?;
+    FetchStatus::try_from(value).map_err(|()| DbError::InvalidValue {
+        column: column.to_owned(),
+        value,
+    })
+}
+
+/// Reads a row into `T` like `serde_rusqlite`, structs by the names of the columns, tuples by
+/// their order and anything else from the first column.
+fn from_row<T: DeserializeOwned>(row: &Row) -> DbResult<T> {
+    Ok(T::deserialize(RowDeserializer(row_values(row)?))?)
+}
+
+/// The values of a row by column name, `NULL` as json `null`.
+fn row_values(row: &Row) -> DbResult<Vec<(String, Value)>> {
+    row.columns()
+        .iter()
+        .enumerate()
+        .map(|(i, column)| {
+            let value = match *column.type_() {
+                Type::BOOL => row.try_get::<_, Option<bool>>(i)?.map(Value::from),
+                Type::INT2 => row.try_get::<_, Option<i16>>(i)?.map(Value::from),
+                Type::INT4 => row.try_get::<_, Option<i32>>(i)?.map(Value::from),
+                Type::INT8 => row.try_get::<_, Option<i64>>(i)?.map(Value::from),
+                Type::FLOAT4 => row.try_get::<_, Option<f32>>(i)?.map(Value::from),
+                Type::FLOAT8 => row.try_get::<_, Option<f64>>(i)?.map(Value::from),
+                _ => row.try_get::<_, Option<String>>(i)?.map(Value::from),
+            };
+            Ok((column.name().to_owned(), value.unwrap_or_default()))
+        })
+        .collect()
+}
+
+struct RowDeserializer(Vec<(String, Value)>);
+
+impl RowDeserializer {
+    fn first(self) -> Value {
+        self.0
+            .into_iter()
+            .next()
+            .map(|(_, value)| value)
+            .unwrap_or_default()
+    }
+}
+
+impl<'de> Deserializer<'de> for RowDeserializer {
+    type Error = serde_json::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.first().deserialize_any(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.first().deserialize_option(visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.first().deserialize_enum(name, variants, visitor)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_seq(SeqDeserializer::new(
+            self.0.into_iter().map(|(_, value)| value),
+        ))
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_map(MapDeserializer::new(self.0.into_iter()))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.deserialize_map(visitor)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+        unit unit_struct newtype_struct identifier ignored_any
+    }
+}
+
+impl PostgresStorage {
+    /// Connects to the server and creates or upgrades the tables.
+    pub(super) fn open(config: &MsPostgres) -> DbResult<Self> {
+        let clients = outside_runtime(|| {
+            (0..config.connections)
+                .map(|_| Client::connect(&config.url, NoTls).map(Mutex::new))
+                .collect::<Result<_, _>>()
+        })?;
+        let storage = Self {
+            url: config.url.clone(),
+            clients,
+        };
+
+        storage.with(|client| {
+            client.batch_execute(
+                "
+                SET client_min_messages = WARNING;
+                BEGIN;
+                CREATE TABLE IF NOT EXISTS ytdata (
+                    video_id TEXT PRIMARY KEY NOT NULL,
+                    snippet TEXT DEFAULT NULL,
+                    ytdlp TEXT DEFAULT NULL
+                );
+                CREATE TABLE IF NOT EXISTS authdata (
+                    access_token TEXT NOT NULL,
+                    refresh_token TEXT NOT NULL,
+                    expires_at BIGINT NOT NULL
+                );
+                CREATE TABLE IF NOT EXISTS playlists (
+                    playlist_id TEXT PRIMARY KEY NOT NULL,
+                    etag TEXT NOT NULL,
+                    total_results BIGINT NOT NULL,
+                    fetch_time BIGINT NOT NULL,
+                    title TEXT DEFAULT NULL,
+                    description TEXT DEFAULT NULL,
+                    owner TEXT DEFAULT NULL,
+                    thumbnail TEXT DEFAULT NULL
+                );
+                CREATE TABLE IF NOT EXISTS playlist_items (
+                    playlist_id TEXT NOT NULL REFERENCES playlists (playlist_id) ON DELETE CASCADE,
+                    video_id TEXT NOT NULL,
+                    title TEXT NOT NULL,
+                    artist TEXT NOT NULL,
+                    duration BIGINT DEFAULT NULL,
+                    thumbnail TEXT DEFAULT NULL,
+                    PRIMARY KEY (playlist_id, video_id)
+                );
+                CREATE TABLE IF NOT EXISTS brainz (
+                    query TEXT PRIMARY KEY NOT NULL,
+                    fetch_time BIGINT NOT NULL,
+                    data TEXT NOT NULL
+                );
+                CREATE INDEX IF NOT EXISTS brainz_fetch_time ON brainz (fetch_time);
+                CREATE TABLE IF NOT EXISTS status (
+                    video_id TEXT PRIMARY KEY NOT NULL,
+                    last_update BIGINT NOT NULL,
+                    fetch_time BIGINT NOT NULL,
+                    fetch_status BIGINT NOT NULL,
+                    last_query TEXT DEFAULT NULL,
+                    last_result TEXT DEFAULT NULL,
+                    override_query TEXT DEFAULT NULL,
+                    override_result TEXT DEFAULT NULL,
+                    last_error TEXT DEFAULT NULL,
+                    removal TEXT DEFAULT NULL,
+                    candidates TEXT DEFAULT NULL,
+                    confidence DOUBLE PRECISION DEFAULT NULL,
+                    error_code TEXT DEFAULT NULL,
+                    tags_unchanged BOOLEAN NOT NULL DEFAULT FALSE
+                );
+                CREATE INDEX IF NOT EXISTS status_fetch_status ON status (fetch_status, last_update);
+                CREATE INDEX IF NOT EXISTS status_last_update ON status (last_update);
+                CREATE INDEX IF NOT EXISTS playlist_items_video ON playlist_items (video_id);
+                CREATE TABLE IF NOT EXISTS users (
+                    username TEXT PRIMARY KEY NOT NULL,
+                    password TEXT NOT NULL,
+                    admin BOOLEAN NOT NULL DEFAULT FALSE
+                );
+                CREATE TABLE IF NOT EXISTS pending_moves (
+                    video_id TEXT PRIMARY KEY NOT NULL,
+                    source TEXT NOT NULL,
+                    target TEXT NOT NULL,
+                    last_error TEXT DEFAULT NULL,
+                    created BIGINT NOT NULL
+                );
+                CREATE TABLE IF NOT EXISTS source_matches (
+                    provider TEXT NOT NULL,
+                    source_id TEXT NOT NULL,
+                    video_id TEXT NOT NULL,
+                    PRIMARY KEY (provider, source_id)
+                );
+                CREATE TABLE IF NOT EXISTS manual_videos (
+                    video_id TEXT PRIMARY KEY NOT NULL,
+                    playlist_id TEXT NOT NULL,
+                    added_by TEXT NOT NULL,
+                    added BIGINT NOT NULL
+                );
+                CREATE TABLE IF NOT EXISTS flags (
+                    flag_id BIGSERIAL PRIMARY KEY,
+                    video_id TEXT NOT NULL,
+                    reason TEXT NOT NULL,
+                    note TEXT DEFAULT NULL,
+                    reported_by TEXT NOT NULL,
+                    created BIGINT NOT NULL,
+                    resolved BIGINT DEFAULT NULL
+                );
+                CREATE TABLE IF NOT EXISTS artist_rules (
+                    rule_id BIGSERIAL PRIMARY KEY,
+                    kind TEXT NOT NULL,
+                    pattern TEXT NOT NULL,
+                    replacement TEXT DEFAULT NULL,
+                    created BIGINT NOT NULL
+                );
+                CREATE TABLE IF NOT EXISTS trash (
+                    video_id TEXT PRIMARY KEY NOT NULL,
+                    original TEXT NOT NULL,
+                    path TEXT NOT NULL,
+                    fetch_status BIGINT NOT NULL,
+                    deleted BIGINT NOT NULL
+                );
+                CREATE TABLE IF NOT EXISTS files (
+                    path TEXT PRIMARY KEY NOT NULL,
+                    video_id TEXT DEFAULT NULL,
+                    mtime BIGINT NOT NULL,
+                    size BIGINT NOT NULL
+                );
+                CREATE TABLE IF NOT EXISTS duplicates (
+                    duplicate_id BIGSERIAL PRIMARY KEY,
+                    video_id TEXT NOT NULL,
+                    other_video_id TEXT DEFAULT NULL,
+                    kind TEXT NOT NULL,
+                    path TEXT NOT NULL,
+                    created BIGINT NOT NULL,
+                    resolved BIGINT DEFAULT NULL,
+                    kept TEXT DEFAULT NULL
+                );
+                CREATE TABLE IF NOT EXISTS jobs (
+                    video_id TEXT PRIMARY KEY NOT NULL,
+                    priority TEXT NOT NULL,
+                    state TEXT NOT NULL,
+                    attempts BIGINT NOT NULL,
+                    next_attempt BIGINT NOT NULL,
+                    last_error TEXT DEFAULT NULL,
+                    created BIGINT NOT NULL,
+                    updated BIGINT NOT NULL
+                );
+                CREATE INDEX IF NOT EXISTS jobs_state ON jobs (state, next_attempt);
+                CREATE TABLE IF NOT EXISTS match_attempts (
+                    attempt_id BIGSERIAL PRIMARY KEY,
+                    video_id TEXT NOT NULL,
+                    created BIGINT NOT NULL,
+                    query TEXT NOT NULL,
+                    searches TEXT NOT NULL,
+                    result TEXT DEFAULT NULL
+                );
+                CREATE INDEX IF NOT EXISTS match_attempts_video ON match_attempts (video_id, attempt_id);
+                CREATE TABLE IF NOT EXISTS sessions (
+                    session_id TEXT PRIMARY KEY NOT NULL,
+                    username TEXT NOT NULL,
+                    token_hash TEXT NOT NULL UNIQUE,
+                    created BIGINT NOT NULL,
+                    last_used BIGINT NOT NULL,
+                    expires BIGINT NOT NULL,
+                    client_ip TEXT DEFAULT NULL,
+                    user_agent TEXT DEFAULT NULL
+                );
+                CREATE TABLE IF NOT EXISTS api_keys (
+                    key_id TEXT PRIMARY KEY NOT NULL,
+                    name TEXT NOT NULL,
+                    username TEXT NOT NULL,
+                    key_hash TEXT NOT NULL UNIQUE,
+                    scopes TEXT NOT NULL,
+                    created BIGINT NOT NULL,
+                    last_used BIGINT DEFAULT NULL
+                );
+                CREATE TABLE IF NOT EXISTS audit_log (
+                    id BIGSERIAL PRIMARY KEY,
+                    time BIGINT NOT NULL,
+                    username TEXT NOT NULL,
+                    api_key TEXT DEFAULT NULL,
+                    action TEXT NOT NULL,
+                    target TEXT DEFAULT NULL,
+                    details TEXT DEFAULT NULL,
+                    request_id TEXT DEFAULT NULL,
+                    client_ip TEXT DEFAULT NULL
+                );
+                CREATE INDEX IF NOT EXISTS audit_log_time ON audit_log (time);
+                CREATE TABLE IF NOT EXISTS status_history (
+                    history_id BIGSERIAL PRIMARY KEY,
+                    video_id TEXT NOT NULL,
+                    time BIGINT NOT NULL,
+                    fetch_status BIGINT NOT NULL,
+                    last_error TEXT DEFAULT NULL,
+                    query TEXT DEFAULT NULL,
+                    result TEXT DEFAULT NULL,
+                    tags_unchanged BOOLEAN NOT NULL DEFAULT FALSE
+                );
+                CREATE INDEX IF NOT EXISTS status_history_video ON status_history (video_id, history_id);
+                CREATE TABLE IF NOT EXISTS login_attempts (
+                    key TEXT PRIMARY KEY NOT NULL,
+                    failures BIGINT NOT NULL,
+                    first_failure BIGINT NOT NULL,
+                    locked_until BIGINT DEFAULT NULL
+                );
+                CREATE TABLE IF NOT EXISTS kvp (
+                    key TEXT PRIMARY KEY NOT NULL,
+                    value TEXT NOT NULL,
+                    last_update BIGINT NOT NULL
+                );
+                COMMIT;",
+            )?;
+            Ok(())
+        })?;
+
+        let cur_ver: u32 = storage
+            .get_key("version")?
+            .map(|v| v.parse().map_err(|_| DbError::InvalidVersion(v)))
+            .transpose()?
+            .unwrap_or(0);
+        if cur_ver < SCHEMA_VERSION {
+            // The tables above are created at the current schema, upgrades of older ones go here
+            info!(
+                "Upgrading database from version {} to {}",
+                cur_ver, SCHEMA_VERSION
+            );
+            if cur_ver < 2 {
+                storage.with(|client| {
+                    client.batch_execute(
+                        "ALTER TABLE status ADD COLUMN IF NOT EXISTS tags_unchanged BOOLEAN NOT NULL DEFAULT FALSE;
+                         ALTER TABLE status_history ADD COLUMN IF NOT EXISTS tags_unchanged BOOLEAN NOT NULL DEFAULT FALSE;",
+                    )?;
+                    Ok(())
+                })?;
+            }
+            storage.set_key("version", &SCHEMA_VERSION.to_string())?;
+        }
+        Ok(storage)
+    }
+
+    /// Runs `f` on a free client, or the one in turn if all are busy. A client whose connection
+    /// was lost connects again first.
+    fn with<T>(&self, f: impl FnOnce(&mut Client) -> DbResult<T>) -> DbResult<T> {
+        let start = NEXT_CLIENT.fetch_add(1, Ordering::Relaxed);
+        let mut client = (0..self.clients.len())
+            .find_map(|i| {
+                self.clients[(start + i) % self.clients.len()]
+                    .try_lock()
+                    .ok()
+            })
+            .unwrap_or_else(|| self.clients[start % self.clients.len()].lock().unwrap());
+        outside_runtime(|| {
+            if client.is_closed() {
+                warn!("Lost the connection to PostgreSQL, reconnecting");
+                *client = Client::connect(&self.url, NoTls)?;
+            }
+            f(&mut client)
+        })
+    }
+
+    fn execute(&self, sql: &str, params: &Params) -> DbResult<u64> {
+        self.with(|client| execute(client, sql, params))
+    }
+
+    fn all<T: DeserializeOwned>(&self, sql: &str, params: &Params) -> DbResult<Vec<T>> {
+        self.with(|client| query(client, sql, params))?
+            .iter()
+            .map(from_row)
+            .collect()
+    }
+
+    fn single<T: DeserializeOwned>(&self, sql: &str, params: &Params) -> DbResult<Option<T>> {
+        let rows = self.with(|client| query(client, sql, params))?;
+        let Some(row) = rows.first() else {
+            return Ok(None);
+        };
+        let columns = row_values(row)?;
+        // A NULL, like the MAX of no rows, reads as no row
+        if columns.iter().all(|(_, value)| value.is_null()) {
+            return Ok(None);
+        }
+        Ok(Some(T::deserialize(RowDeserializer(columns))?))
+    }
+
+    fn set_ytdata(&self, video_id: &str, data: &str, col: &str) -> DbResult<()> {
+        self.execute(
+            &format!(
+                "INSERT INTO ytdata (video_id, {col}) VALUES ($1, $2) ON CONFLICT (video_id) DO UPDATE SET {col} = EXCLUDED.{col}"
+            ),
+            &[&video_id, &data],
+        )?;
+        Ok(())
+    }
+
+    fn get_video_internal(
+        client: &mut impl GenericClient,
+        video_id: &str,
+    ) -> DbResult<Option<VideoStatus>> {
+        query(
+            client,
+            "SELECT * FROM status WHERE video_id = $1",
+            &[&video_id],
+        )?
+        .first()
+        .map(Self::map_video_status)
+        .transpose()
+    }
+
+    fn map_video_status(row: &Row) -> DbResult<VideoStatus> {
+        Ok(VideoStatus {
+            video_id: row.try_get("video_id")?,
+            fetch_time: row.try_get::<_, i64>("fetch_time")? as u64,
+            fetch_status: fetch_status_column(row, "fetch_status")?,
+            last_update: row.try_get::<_, i64>("last_update")? as u64,
+            last_query: json_column(row, "last_query")?,
+            last_result: json_column(row, "last_result")?,
+            last_error: row.try_get("last_error")?,
+            override_query: json_column(row, "override_query")?,
+            override_result: json_column(row, "override_result")?,
+            removal: json_column(row, "removal")?,
+            candidates: json_column::<Option<_>>(row, "candidates")?.unwrap_or_default(),
+            confidence: row.try_get("confidence")?,
+            tags_unchanged: row.try_get("tags_unchanged")?,
+            download_progress: None,
+        })
+    }
+
+    fn query_videos(&self, sql: &str, params: &Params) -> DbResult<Vec<VideoStatus>> {
+        self.with(|client| query(client, sql, params))?
+            .iter()
+            .map(Self::map_video_status)
+            .collect()
+    }
+
+    fn set_full_track_status_internal(
+        client: &mut impl GenericClient,
+        status: &VideoStatus,
+    ) -> DbResult<()> {
+        execute(
+            client,
+            "INSERT INTO status (video_id, last_update, fetch_time, fetch_status, last_query, last_result, override_query, override_result, last_error, removal, candidates, confidence, error_code, tags_unchanged)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)
+             ON CONFLICT (video_id)
+             DO UPDATE SET last_update = $2, fetch_time = $3, fetch_status = $4, last_query = $5, last_result = $6, override_query = $7, override_result = $8, last_error = $9, removal = $10, candidates = $11, confidence = $12, error_code = $13, tags_unchanged = $14",
+            &[
+                &status.video_id,
+                &(status.last_update as i64),
+                &(status.fetch_time as i64),
+                &(status.fetch_status as i64),
+                &to_json(status.last_query.as_ref())?,
+                &to_json(status.last_result.as_ref())?,
+                &to_json(status.override_query.as_ref())?,
+                &to_json(status.override_result.as_ref())?,
+                &status.last_error,
+                &to_json(status.removal.as_ref())?,
+                &to_json((!status.candidates.is_empty()).then_some(&status.candidates))?,
+                &status.confidence,
+                &status.error_code().map(ErrorCode::as_str),
+                &status.tags_unchanged,
+            ],
+        )?;
+        Self::record_history(client, &status.video_id)
+    }
+
+    /// Adds the stored status of `video_id` to its history, unless it matches the last entry.
+    fn record_history(client: &mut impl GenericClient, video_id: &str) -> DbResult<()> {
+        let added = execute(
+            client,
+            "INSERT INTO status_history (video_id, time, fetch_status, last_error, query, result, tags_unchanged)
+             SELECT s.video_id, $2, s.fetch_status, s.last_error, coalesce(s.override_query, s.last_query), coalesce(s.override_result, s.last_result), s.tags_unchanged
+             FROM status s
+             WHERE s.video_id = $1 AND NOT EXISTS (
+                SELECT 1 FROM (SELECT * FROM status_history WHERE video_id = $1 ORDER BY history_id DESC LIMIT 1) h
+                WHERE h.fetch_status = s.fetch_status AND h.last_error IS NOT DISTINCT FROM s.last_error
+                  AND h.query IS NOT DISTINCT FROM coalesce(s.override_query, s.last_query)
+                  AND h.result IS NOT DISTINCT FROM coalesce(s.override_result, s.last_result)
+                  AND h.tags_unchanged = s.tags_unchanged
+             )",
+            &[&video_id, &Utc::now().timestamp()],
+        )?;
+        if added > 0 {
+            execute(
+                client,
+                "DELETE FROM status_history WHERE video_id = $1 AND history_id NOT IN
+                    (SELECT history_id FROM status_history WHERE video_id = $1 ORDER BY history_id DESC LIMIT $2)",
+                &[&video_id, &i64::from(STATUS_HISTORY_LEN)],
+            )?;
+        }
+        Ok(())
+    }
+
+    fn map_trashed_file(row: &Row) -> DbResult<TrashedFile> {
+        Ok(TrashedFile {
+            video_id: row.try_get("video_id")?,
+            original: row.try_get::<_, String>("original")?.into(),
+            path: row.try_get::<_, String>("path")?.into(),
+            fetch_status: fetch_status_column(row, "fetch_status")?,
+            deleted: row.try_get("deleted")?,
+        })
+    }
+
+    fn query_api_keys(&self, sql: &str, params: &Params) -> DbResult<Vec<ApiKey>> {
+        Ok(self
+            .with(|client| query(client, sql, params))?
+            .iter()
+            .filter_map(|row| {
+                Some(ApiKey {
+                    key_id: row.try_get("key_id").ok()?,
+                    name: row.try_get("name").ok()?,
+                    username: row.try_get("username").ok()?,
+                    scopes: json_column(row, "scopes").ok()?,
+                    created: row.try_get("created").ok()?,
+                    last_used: row.try_get("last_used").ok()?,
+                })
+            })
+            .collect())
+    }
+}
+
+impl Storage for PostgresStorage {
+    // YT_API
+
+    fn set_yt_dlp(&self, video_id: &str, dlp: &str) -> DbResult<()> {
+        self.set_ytdata(video_id, dlp, "ytdlp")
+    }
+
+    fn delete_yt_data(&self, video_id: &str) -> DbResult<()> {
+        self.execute("DELETE FROM ytdata WHERE video_id = $1", &[&video_id])?;
+        Ok(())
+    }
+
+    fn try_get_yt_dlp(&self, video_id: &str) -> DbResult<Option<String>> {
+        self.single("SELECT ytdlp FROM ytdata WHERE video_id = $1", &[&video_id])
+    }
+
+    // PLAYLISTS
+
+    fn try_get_playlist(&self, playlist_id: &str) -> DbResult<Option<Playlist>> {
+        self.with(|client| {
+            let Some(row) = query(
+                client,
+                "SELECT playlist_id, etag, total_results, fetch_time, title, description, owner, thumbnail FROM playlists WHERE playlist_id = $1",
+                &[&playlist_id],
+            )?
+            .into_iter()
+            .next() else {
+                return Ok(None);
+            };
+
+            let items = query(
+                client,
+                "SELECT video_id, title, artist, duration, thumbnail FROM playlist_items WHERE playlist_id = $1",
+                &[&playlist_id],
+            )?
+            .iter()
+            .map(|row| {
+                Ok(PlaylistItem {
+                    video_id: row.try_get(0)?,
+                    title: row.try_get(1)?,
+                    artist: row.try_get(2)?,
+                    duration: row.try_get::<_, Option<i64>>(3)?.map(|d| d as u32),
+                    thumbnail: row.try_get(4)?,
+                })
+            })
+            .collect::<DbResult<_>>()?;
+
+            Ok(Some(Playlist {
+                playlist_id: row.try_get(0)?,
+                etag: row.try_get(1)?,
+                total_results: row.try_get::<_, i64>(2)? as u32,
+                fetch_time: DateTime::from_timestamp(row.try_get(3)?, 0).unwrap_or_default(),
+                info: PlaylistInfo {
+                    title: row.try_get(4)?,
+                    description: row.try_get(5)?,
+                    owner: row.try_get(6)?,
+                    thumbnail: row.try_get(7)?,
+                },
+                items,
+            }))
+        })
+    }
+
+    fn set_playlist(&self, playlist: &Playlist) -> DbResult<()> {
+        self.with(|client| {
+            let mut tx = client.transaction()?;
+            execute(
+                &mut tx,
+                "DELETE FROM playlists WHERE playlist_id = $1",
+                &[&playlist.playlist_id],
+            )?;
+            execute(
+                &mut tx,
+                "INSERT INTO playlists (playlist_id, etag, total_results, fetch_time, title, description, owner, thumbnail) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+                &[
+                    &playlist.playlist_id,
+                    &playlist.etag,
+                    &i64::from(playlist.total_results),
+                    &playlist.fetch_time.timestamp(),
+                    &playlist.info.title,
+                    &playlist.info.description,
+                    &playlist.info.owner,
+                    &playlist.info.thumbnail,
+                ],
+            )?;
+
+            let stmt = tx.prepare(
+                "INSERT INTO playlist_items (playlist_id, video_id, title, artist, duration, thumbnail) VALUES ($1, $2, $3, $4, $5, $6)",
+            )?;
+            for item in &playlist.items {
+                tx.execute(
+                    &stmt,
+                    &[
+                        &playlist.playlist_id,
+                        &item.video_id,
+                        &item.title,
+                        &item.artist,
+                        &item.duration.map(i64::from),
+                        &item.thumbnail,
+                    ],
+                )?;
+            }
+
+            tx.commit()?;
+            Ok(())
+        })
+    }
+
+    fn update_playlist_fetch_time(
+        &self,
+        playlist_id: &str,
+        fetch_time: DateTime<Utc>,
+    ) -> DbResult<()> {
+        self.execute(
+            "UPDATE playlists SET fetch_time = $1 WHERE playlist_id = $2",
+            &[&fetch_time.timestamp(), &playlist_id],
+        )?;
+        Ok(())
+    }
+
+    fn get_playlists(&self) -> DbResult<Vec<PlaylistSummary>> {
+        self.all(
+            "SELECT playlist_id, title, description, owner, thumbnail, total_results, fetch_time FROM playlists ORDER BY title",
+            &[],
+        )
+    }
+
+    fn get_playlist_summary(&self, playlist_id: &str) -> DbResult<Option<PlaylistSummary>> {
+        self.single(
+            "SELECT playlist_id, title, description, owner, thumbnail, total_results, fetch_time FROM playlists WHERE playlist_id = $1",
+            &[&playlist_id],
+        )
+    }
+
+    fn get_thumbnail(&self, video_id: &str) -> DbResult<Option<String>> {
+        self.single(
+            "SELECT thumbnail FROM playlist_items WHERE video_id = $1 AND thumbnail IS NOT NULL LIMIT 1",
+            &[&video_id],
+        )
+    }
+
+    // YT AUTH
+
+    fn try_get_auth(&self) -> DbResult<Option<AuthData>> {
+        self.single(
+            "SELECT access_token, refresh_token, expires_at FROM authdata",
+            &[],
+        )
+    }
+
+    fn set_auth(&self, auth: &AuthData) -> DbResult<()> {
+        self.with(|client| {
+            let mut tx = client.transaction()?;
+            execute(&mut tx, "DELETE FROM authdata", &[])?;
+            execute(
+                &mut tx,
+                "INSERT INTO authdata (access_token, refresh_token, expires_at) VALUES ($1, $2, $3)",
+                &[&auth.access_token, &auth.refresh_token, &auth.expires_at],
+            )?;
+            tx.commit()?;
+            Ok(())
+        })
+    }
+
+    // FILESYSTEM
+
+    fn get_indexed_files(&self) -> DbResult<Vec<IndexedFile>> {
+        self.all("SELECT * FROM files", &[])
+    }
+
+    fn set_indexed_files(&self, files: &[IndexedFile]) -> DbResult<()> {
+        self.with(|client| {
+            let mut tx = client.transaction()?;
+            execute(&mut tx, "DELETE FROM files", &[])?;
+            let stmt = tx.prepare(
+                "INSERT INTO files (path, video_id, mtime, size) VALUES ($1, $2, $3, $4)",
+            )?;
+            for file in files {
+                tx.execute(
+                    &stmt,
+                    &[
+                        &file.path.to_string_lossy().as_ref(),
+                        &file.video_id,
+                        &file.mtime,
+                        &file.size,
+                    ],
+                )?;
+            }
+            tx.commit()?;
+            Ok(())
+        })
+    }
+
+    fn get_track_query_override(&self, video_id: &str) -> DbResult<Option<String>> {
+        self.single(
+            "SELECT override_query FROM status WHERE video_id = $1",
+            &[&video_id],
+        )
+    }
+
+    fn get_track_result_override(&self, video_id: &str) -> DbResult<Option<String>> {
+        self.single(
+            "SELECT override_result FROM status WHERE video_id = $1",
+            &[&video_id],
+        )
+    }
+
+    fn modify_video_statuses(
+        &self,
+        video_ids: &[String],
+        modify: &dyn Fn(&mut VideoStatus) -> bool,
+    ) -> DbResult<Vec<VideoStatus>> {
+        self.with(|client| {
+            let mut tx = client.transaction()?;
+            let mut changed = Vec::new();
+            for video_id in video_ids {
+                let Some(mut video) = Self::get_video_internal(&mut tx, video_id)? else {
+                    continue;
+                };
+                if modify(&mut video) {
+                    video.update_now();
+                    Self::set_full_track_status_internal(&mut tx, &video)?;
+                    changed.push(video);
+                }
+            }
+            tx.commit()?;
+            Ok(changed)
+        })
+    }
+
+    fn get_all_videos(&self) -> DbResult<Vec<VideoStatus>> {
+        self.query_videos("SELECT * FROM status", &[])
+    }
+
+    fn get_videos_page(&self, filter: &VideoFilter) -> DbResult<(u64, Vec<VideoStatus>)> {
+        let (where_clause, mut params) = filter.where_clause(Dialect::Postgres);
+        let sort_column = filter.sort_column();
+        let order = filter.order.as_sql();
+
+        let total: u64 = self
+            .single(
+                &format!("SELECT COUNT(*) FROM status s {where_clause}"),
+                &sql_params(&params),
+            )?
+            .unwrap_or_default();
+
+        params.push(SqlValue::Integer(filter.limit().into()));
+        params.push(SqlValue::Integer(filter.offset.into()));
+        let videos = self.query_videos(
+            &format!(
+                "SELECT s.* FROM status s {where_clause} ORDER BY {sort_column} {order}, s.video_id {order} LIMIT ${} OFFSET ${}",
+                params.len() - 1,
+                params.len()
+            ),
+            &sql_params(&params),
+        )?;
+
+        Ok((total, videos))
+    }
+
+    fn get_video_ids(&self, filter: &VideoFilter) -> DbResult<Vec<String>> {
+        let (where_clause, params) = filter.where_clause(Dialect::Postgres);
+        self.all(
+            &format!("SELECT s.video_id FROM status s {where_clause}"),
+            &sql_params(&params),
+        )
+    }
+
+    fn get_all_ids(&self) -> DbResult<Vec<String>> {
+        self.all("SELECT video_id FROM status", &[])
+    }
+
+    fn get_video_fetch_status(&self, video_id: &str) -> DbResult<Option<FetchStatus>> {
+        Ok(self
+            .single::<i64>(
+                "SELECT fetch_status FROM status WHERE video_id = $1",
+                &[&video_id],
+            )?
+            .and_then(|s| FetchStatus::try_from(s).ok()))
+    }
+
+    fn get_videos_in_status(&self, status: FetchStatus) -> DbResult<Vec<VideoStatus>> {
+        self.query_videos(
+            "SELECT * FROM status WHERE fetch_status = $1",
+            &[&(status as i64)],
+        )
+    }
+
+    fn get_all_unprocessed_ids(&self) -> DbResult<Vec<String>> {
+        self.all(
+            "SELECT video_id FROM status WHERE fetch_status IN (0, 1)",
+            &[],
+        )
+    }
+
+    fn get_last_video_update(&self) -> DbResult<Option<u64>> {
+        self.single("SELECT MAX(last_update) FROM status", &[])
+    }
+
+    fn get_video(&self, video_id: &str) -> DbResult<Option<VideoStatus>> {
+        self.with(|client| Self::get_video_internal(client, video_id))
+    }
+
+    fn set_full_track_status(&self, status: &VideoStatus) -> DbResult<()> {
+        self.with(|client| Self::set_full_track_status_internal(client, status))
+    }
+
+    fn set_videos_reindex(&self, video_ids: &[String]) -> DbResult<()> {
+        self.with(|client| {
+            let mut tx = client.transaction()?;
+            let changed = query(
+                &mut tx,
+                "UPDATE status SET fetch_status = 1 WHERE video_id = ANY($1) AND fetch_status = 4 RETURNING video_id",
+                &[&video_ids],
+            )?;
+            for row in changed {
+                Self::record_history(&mut tx, row.try_get(0)?)?;
+            }
+            tx.commit()?;
+            Ok(())
+        })
+    }
+
+    // BRAINZ
+
+    fn try_get_brainz(&self, query: &str, fetched_after: i64) -> DbResult<Option<String>> {
+        self.single(
+            "SELECT data FROM brainz WHERE query = $1 AND fetch_time >= $2",
+            &[&query, &fetched_after],
+        )
+    }
+
+    fn set_brainz(&self, query: &str, data: &str) -> DbResult<()> {
+        self.execute(
+            "INSERT INTO brainz (query, fetch_time, data) VALUES ($1, $2, $3) ON CONFLICT (query) DO UPDATE SET fetch_time = $2, data = $3",
+            &[&query, &Utc::now().timestamp(), &data],
+        )?;
+        Ok(())
+    }
+
+    fn delete_brainz_before(&self, fetched_before: i64) -> DbResult<u64> {
+        self.execute(
+            "DELETE FROM brainz WHERE fetch_time < $1",
+            &[&fetched_before],
+        )
+    }
+
+    fn evict_brainz(&self, keep: u64) -> DbResult<u64> {
+        self.execute(
+            "DELETE FROM brainz WHERE query IN (SELECT query FROM brainz ORDER BY fetch_time DESC OFFSET $1)",
+            &[&(keep as i64)],
+        )
+    }
+
+    fn add_match_attempt(
+        &self,
+        video_id: &str,
+        query: &BrainzMultiSearch,
+        searches: &[BrainzSearchLog],
+        result: Option<&BrainzMetadata>,
+    ) -> DbResult<()> {
+        let query = serde_json::to_string(query)?;
+        let searches = serde_json::to_string(searches)?;
+        let result = to_json(result)?;
+        self.with(|client| {
+            let mut tx = client.transaction()?;
+            execute(
+                &mut tx,
+                "INSERT INTO match_attempts (video_id, created, query, searches, result) VALUES ($1, $2, $3, $4, $5)",
+                &[&video_id, &Utc::now().timestamp(), &query, &searches, &result],
+            )?;
+            execute(
+                &mut tx,
+                "DELETE FROM match_attempts WHERE video_id = $1 AND attempt_id NOT IN
+                    (SELECT attempt_id FROM match_attempts WHERE video_id = $1 ORDER BY attempt_id DESC LIMIT $2)",
+                &[&video_id, &i64::from(MATCH_HISTORY_LEN)],
+            )?;
+            tx.commit()?;
+            Ok(())
+        })
+    }
+
+    fn get_match_attempts(&self, video_id: &str) -> DbResult<Vec<MatchAttempt>> {
+        Ok(self
+            .with(|client| {
+                query(
+                    client,
+                    "SELECT * FROM match_attempts WHERE video_id = $1 ORDER BY attempt_id DESC",
+                    &[&video_id],
+                )
+            })?
+            .iter()
+            .filter_map(|row| {
+                Some(MatchAttempt {
+                    attempt_id: row.try_get("attempt_id").ok()?,
+                    video_id: row.try_get("video_id").ok()?,
+                    created: row.try_get("created").ok()?,
+                    query: json_column(row, "query").ok()?,
+                    searches: json_column(row, "searches").ok()?,
+                    result: json_column(row, "result").ok()?,
+                })
+            })
+            .collect())
+    }
+
+    fn get_status_history(&self, video_id: &str) -> DbResult<Vec<StatusChange>> {
+        self.with(|client| {
+            query(
+                client,
+                "SELECT * FROM status_history WHERE video_id = $1 ORDER BY history_id DESC",
+                &[&video_id],
+            )
+        })?
+        .iter()
+        .map(|row| {
+            let fetch_status = fetch_status_column(row, "fetch_status")?;
+            let last_error: Option<String> = row.try_get("last_error")?;
+            Ok(StatusChange {
+                history_id: row.try_get("history_id")?,
+                video_id: row.try_get("video_id")?,
+                time: row.try_get("time")?,
+                fetch_status,
+                error_code: errors::classify(fetch_status, last_error.as_deref()),
+                last_error,
+                query: json_column(row, "query")?,
+                result: json_column(row, "result")?,
+                tags_unchanged: row.try_get("tags_unchanged")?,
+            })
+        })
+        .collect()
+    }
+
+    // JOBS
+
+    fn get_job(&self, video_id: &str) -> DbResult<Option<Job>> {
+        self.single("SELECT * FROM jobs WHERE video_id = $1", &[&video_id])
+    }
+
+    fn get_jobs(&self) -> DbResult<Vec<Job>> {
+        self.all("SELECT * FROM jobs ORDER BY updated DESC", &[])
+    }
+
+    fn get_jobs_in_state(&self, state: JobState) -> DbResult<Vec<Job>> {
+        self.all(
+            "SELECT * FROM jobs WHERE state = $1 ORDER BY created",
+            &[&state.as_str()],
+        )
+    }
+
+    fn get_due_retries(&self, now: i64) -> DbResult<Vec<Job>> {
+        self.all(
+            "SELECT * FROM jobs WHERE state = $1 AND next_attempt <= $2 ORDER BY next_attempt",
+            &[&JobState::Retrying.as_str(), &now],
+        )
+    }
+
+    fn set_job(&self, job: &Job) -> DbResult<()> {
+        self.execute(
+            "INSERT INTO jobs (video_id, priority, state, attempts, next_attempt, last_error, created, updated)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+             ON CONFLICT (video_id)
+             DO UPDATE SET priority = $2, state = $3, attempts = $4, next_attempt = $5, last_error = $6, updated = $8",
+            &[
+                &job.video_id,
+                &match job.priority {
+                    Priority::Low => "Low",
+                    Priority::High => "High",
+                },
+                &job.state.as_str(),
+                &i64::from(job.attempts),
+                &job.next_attempt,
+                &job.last_error,
+                &job.created,
+                &job.updated,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn delete_job(&self, video_id: &str) -> DbResult<()> {
+        self.execute("DELETE FROM jobs WHERE video_id = $1", &[&video_id])?;
+        Ok(())
+    }
+
+    fn reset_running_jobs(&self) -> DbResult<usize> {
+        Ok(self.execute(
+            "UPDATE jobs SET state = $1 WHERE state = $2",
+            &[&JobState::Queued.as_str(), &JobState::Running.as_str()],
+        )? as usize)
+    }
+
+    fn is_video_in_any_playlist(&self, video_id: &str) -> DbResult<bool> {
+        Ok(self
+            .single::<bool>(
+                "SELECT EXISTS (SELECT 1 FROM playlist_items WHERE video_id = $1)
+                 OR EXISTS (SELECT 1 FROM manual_videos WHERE video_id = $1)",
+                &[&video_id],
+            )?
+            .unwrap_or_default())
+    }
+
+    fn get_video_playlist_ids(&self, video_id: &str) -> DbResult<Vec<String>> {
+        self.all(
+            "SELECT playlist_id FROM playlist_items WHERE video_id = $1
+             UNION SELECT playlist_id FROM manual_videos WHERE video_id = $1",
+            &[&video_id],
+        )
+    }
+
+    fn get_playlist_video_ids(&self, playlist_id: &str) -> DbResult<HashSet<String>> {
+        Ok(self
+            .all::<String>(
+                "SELECT video_id FROM playlist_items WHERE playlist_id = $1
+                 UNION SELECT video_id FROM manual_videos WHERE playlist_id = $1",
+                &[&playlist_id],
+            )?
+            .into_iter()
+            .collect())
+    }
+
+    fn get_removed_ids(&self) -> DbResult<Vec<String>> {
+        self.all("SELECT video_id FROM status WHERE removal IS NOT NULL", &[])
+    }
+
+    // PENDING MOVES
+
+    fn set_pending_move(&self, pending: &PendingMove) -> DbResult<()> {
+        self.execute(
+            "INSERT INTO pending_moves (video_id, source, target, last_error, created) VALUES ($1, $2, $3, $4, $5)
+             ON CONFLICT (video_id) DO UPDATE SET source = $2, target = $3, last_error = $4, created = $5",
+            &[
+                &pending.video_id,
+                &pending.source.to_string_lossy().as_ref(),
+                &pending.target.to_string_lossy().as_ref(),
+                &pending.last_error,
+                &pending.created,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn get_pending_moves(&self) -> DbResult<Vec<PendingMove>> {
+        self.all("SELECT * FROM pending_moves", &[])
+    }
+
+    fn delete_pending_move(&self, video_id: &str) -> DbResult<()> {
+        self.execute(
+            "DELETE FROM pending_moves WHERE video_id = $1",
+            &[&video_id],
+        )?;
+        Ok(())
+    }
+
+    // SOURCES
+
+    fn get_source_match(&self, provider: &str, source_id: &str) -> DbResult<Option<String>> {
+        self.single(
+            "SELECT video_id FROM source_matches WHERE provider = $1 AND source_id = $2",
+            &[&provider, &source_id],
+        )
+    }
+
+    fn set_source_match(&self, provider: &str, source_id: &str, video_id: &str) -> DbResult<()> {
+        self.execute(
+            "INSERT INTO source_matches (provider, source_id, video_id) VALUES ($1, $2, $3)
+             ON CONFLICT (provider, source_id) DO UPDATE SET video_id = $3",
+            &[&provider, &source_id, &video_id],
+        )?;
+        Ok(())
+    }
+
+    // MANUAL VIDEOS
+
+    fn add_manual_video(
+        &self,
+        video_id: &str,
+        playlist_id: &str,
+        added_by: &str,
+        added: i64,
+    ) -> DbResult<()> {
+        self.execute(
+            "INSERT INTO manual_videos (video_id, playlist_id, added_by, added) VALUES ($1, $2, $3, $4)
+             ON CONFLICT (video_id) DO UPDATE SET playlist_id = $2, added_by = $3, added = $4",
+            &[&video_id, &playlist_id, &added_by, &added],
+        )?;
+        Ok(())
+    }
+
+    // ERRORS
+
+    fn get_video_errors(&self) -> DbResult<Vec<(String, ErrorCode, String)>> {
+        self.all(
+            "SELECT video_id, error_code, last_error FROM status
+             WHERE error_code IS NOT NULL
+             ORDER BY last_update DESC",
+            &[],
+        )
+    }
+
+    // STATS
+
+    fn get_status_counts(&self) -> DbResult<Vec<(FetchStatus, u64)>> {
+        Ok(self
+            .all::<(i64, u64)>(
+                "SELECT fetch_status, COUNT(*) FROM status GROUP BY fetch_status",
+                &[],
+            )?
+            .into_iter()
+            .filter_map(|(status, count)| Some((FetchStatus::try_from(status).ok()?, count)))
+            .collect())
+    }
+
+    fn get_library_totals(&self) -> DbResult<(u64, u64, u64)> {
+        Ok(self
+            .single(
+                "WITH library AS (
+                    SELECT coalesce(override_result, last_result)::jsonb AS result FROM status
+                    WHERE fetch_status = $1 AND coalesce(override_result, last_result) IS NOT NULL
+                 )
+                 SELECT
+                    (SELECT COUNT(*) FROM library),
+                    (SELECT COUNT(DISTINCT a.value) FROM library, jsonb_array_elements_text(library.result -> 'artist') a),
+                    (SELECT COUNT(DISTINCT coalesce(result ->> 'brainz_release_id', result ->> 'album'))
+                     FROM library)",
+                &[&(FetchStatus::Categorized as i64)],
+            )?
+            .unwrap_or_default())
+    }
+
+    fn get_downloads_per_day(&self, since: i64) -> DbResult<Vec<(String, u64)>> {
+        self.all(
+            "SELECT to_char(to_timestamp(fetch_time) AT TIME ZONE 'UTC', 'YYYY-MM-DD') AS day, COUNT(*) FROM status
+             WHERE fetch_time >= $1
+             GROUP BY day ORDER BY day",
+            &[&since],
+        )
+    }
+
+    fn get_error_counts(&self, limit: u32) -> DbResult<Vec<(ErrorCode, u64)>> {
+        self.all(
+            "SELECT error_code, COUNT(*) AS count FROM status
+             WHERE error_code IS NOT NULL
+             GROUP BY error_code ORDER BY count DESC LIMIT $1",
+            &[&i64::from(limit)],
+        )
+    }
+
+    fn get_indexed_size(&self) -> DbResult<u64> {
+        Ok(self
+            .single("SELECT SUM(size)::BIGINT FROM files", &[])?
+            .unwrap_or_default())
+    }
+
+    // TRASH
+
+    fn add_trashed_file(&self, trashed: &TrashedFile) -> DbResult<()> {
+        self.execute(
+            "INSERT INTO trash (video_id, original, path, fetch_status, deleted) VALUES ($1, $2, $3, $4, $5)
+             ON CONFLICT (video_id) DO UPDATE SET original = $2, path = $3, fetch_status = $4, deleted = $5",
+            &[
+                &trashed.video_id,
+                &trashed.original.to_string_lossy().as_ref(),
+                &trashed.path.to_string_lossy().as_ref(),
+                &(trashed.fetch_status as i64),
+                &trashed.deleted,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn get_trashed_file(&self, video_id: &str) -> DbResult<Option<TrashedFile>> {
+        self.with(|client| {
+            query(
+                client,
+                "SELECT * FROM trash WHERE video_id = $1",
+                &[&video_id],
+            )
+        })?
+        .first()
+        .map(Self::map_trashed_file)
+        .transpose()
+    }
+
+    fn get_trashed_files_before(&self, deleted: i64) -> DbResult<Vec<TrashedFile>> {
+        self.with(|client| {
+            query(
+                client,
+                "SELECT * FROM trash WHERE deleted < $1 ORDER BY deleted DESC",
+                &[&deleted],
+            )
+        })?
+        .iter()
+        .map(Self::map_trashed_file)
+        .collect()
+    }
+
+    fn delete_trashed_file(&self, video_id: &str) -> DbResult<()> {
+        self.execute("DELETE FROM trash WHERE video_id = $1", &[&video_id])?;
+        Ok(())
+    }
+
+    // FLAGS
+
+    fn add_flag(
+        &self,
+        video_id: &str,
+        reason: FlagReason,
+        note: Option<&str>,
+        reported_by: &str,
+        created: i64,
+    ) -> DbResult<VideoFlag> {
+        let flag_id = self
+            .single(
+                "INSERT INTO flags (video_id, reason, note, reported_by, created) VALUES ($1, $2, $3, $4, $5) RETURNING flag_id",
+                &[&video_id, &reason.as_str(), &note, &reported_by, &created],
+            )?
+            .unwrap_or_default();
+
+        Ok(VideoFlag {
+            flag_id,
+            video_id: video_id.to_owned(),
+            reason,
+            note: note.map(str::to_owned),
+            reported_by: reported_by.to_owned(),
+            created,
+            resolved: None,
+        })
+    }
+
+    fn get_open_flags(&self) -> DbResult<Vec<VideoFlag>> {
+        self.all(
+            "SELECT * FROM flags WHERE resolved IS NULL ORDER BY created",
+            &[],
+        )
+    }
+
+    fn resolve_flag(&self, flag_id: i64, resolved: i64) -> DbResult<bool> {
+        Ok(self.execute(
+            "UPDATE flags SET resolved = $2 WHERE flag_id = $1 AND resolved IS NULL",
+            &[&flag_id, &resolved],
+        )? > 0)
+    }
+
+    // ARTIST RULES
+
+    fn get_artist_rules(&self) -> DbResult<Vec<ArtistRule>> {
+        self.all("SELECT * FROM artist_rules ORDER BY rule_id", &[])
+    }
+
+    fn add_artist_rule(&self, rule: &ArtistRuleRequest, created: i64) -> DbResult<ArtistRule> {
+        let rule_id = self
+            .single(
+                "INSERT INTO artist_rules (kind, pattern, replacement, created) VALUES ($1, $2, $3, $4) RETURNING rule_id",
+                &[&rule.kind.as_str(), &rule.pattern, &rule.replacement, &created],
+            )?
+            .unwrap_or_default();
+
+        Ok(ArtistRule {
+            rule_id,
+            kind: rule.kind,
+            pattern: rule.pattern.clone(),
+            replacement: rule.replacement.clone(),
+            created,
+        })
+    }
+
+    fn update_artist_rule(
+        &self,
+        rule_id: i64,
+        rule: &ArtistRuleRequest,
+    ) -> DbResult<Option<ArtistRule>> {
+        self.single(
+            "UPDATE artist_rules SET kind = $2, pattern = $3, replacement = $4 WHERE rule_id = $1 RETURNING *",
+            &[&rule_id, &rule.kind.as_str(), &rule.pattern, &rule.replacement],
+        )
+    }
+
+    fn delete_artist_rule(&self, rule_id: i64) -> DbResult<bool> {
+        Ok(self.execute("DELETE FROM artist_rules WHERE rule_id = $1", &[&rule_id])? > 0)
+    }
+
+    // DUPLICATES
+
+    fn find_categorized_recording(
+        &self,
+        recording_id: &str,
+        video_id: &str,
+    ) -> DbResult<Option<String>> {
+        self.single(
+            "SELECT video_id FROM status
+             WHERE fetch_status = $1 AND video_id != $2
+               AND coalesce(override_result, last_result)::jsonb ->> 'brainz_recording_id' = $3
+             LIMIT 1",
+            &[&(FetchStatus::Categorized as i64), &video_id, &recording_id],
+        )
+    }
+
+    fn add_duplicate(
+        &self,
+        video_id: &str,
+        other_video_id: Option<&str>,
+        kind: DuplicateKind,
+        path: &Path,
+        created: i64,
+    ) -> DbResult<Duplicate> {
+        let duplicate_id = self.with(|client| {
+            let mut tx = client.transaction()?;
+            execute(
+                &mut tx,
+                "DELETE FROM duplicates WHERE video_id = $1 AND resolved IS NULL",
+                &[&video_id],
+            )?;
+            let row = tx.query_one(
+                "INSERT INTO duplicates (video_id, other_video_id, kind, path, created) VALUES ($1, $2, $3, $4, $5) RETURNING duplicate_id",
+                &[
+                    &video_id,
+                    &other_video_id,
+                    &kind.as_str(),
+                    &path.to_string_lossy().as_ref(),
+                    &created,
+                ],
+            )?;
+            tx.commit()?;
+            Ok(row.try_get(0)?)
+        })?;
+
+        Ok(Duplicate {
+            duplicate_id,
+            video_id: video_id.to_owned(),
+            other_video_id: other_video_id.map(str::to_owned),
+            kind,
+            path: path.to_owned(),
+            created,
+            resolved: None,
+            kept: None,
+        })
+    }
+
+    fn get_open_duplicates(&self) -> DbResult<Vec<Duplicate>> {
+        self.all(
+            "SELECT * FROM duplicates WHERE resolved IS NULL ORDER BY created",
+            &[],
+        )
+    }
+
+    fn get_open_duplicate(&self, duplicate_id: i64) -> DbResult<Option<Duplicate>> {
+        self.single(
+            "SELECT * FROM duplicates WHERE duplicate_id = $1 AND resolved IS NULL",
+            &[&duplicate_id],
+        )
+    }
+
+    fn resolve_duplicate(
+        &self,
+        duplicate_id: i64,
+        kept: DuplicateKeep,
+        resolved: i64,
+    ) -> DbResult<()> {
+        self.execute(
+            "UPDATE duplicates SET resolved = $2, kept = $3 WHERE duplicate_id = $1",
+            &[&duplicate_id, &resolved, &kept.as_str()],
+        )?;
+        Ok(())
+    }
+
+    // User
+
+    fn get_user(&self, username: &str) -> DbResult<Option<UserData>> {
+        self.single(
+            "SELECT username, password, admin FROM users WHERE username = $1",
+            &[&username],
+        )
+    }
+
+    fn get_users(&self) -> DbResult<Vec<User>> {
+        self.all("SELECT username, admin FROM users ORDER BY username", &[])
+    }
+
+    fn has_users(&self) -> DbResult<bool> {
+        Ok(self
+            .single::<String>("SELECT username FROM users LIMIT 1", &[])?
+            .is_some())
+    }
+
+    fn add_first_user(&self, username: &str, password_hash: &str) -> DbResult<bool> {
+        Ok(self.execute(
+            "INSERT INTO users (username, password, admin) SELECT $1::TEXT, $2::TEXT, TRUE WHERE NOT EXISTS (SELECT 1 FROM users)",
+            &[&username, &password_hash],
+        )? > 0)
+    }
+
+    fn add_user(&self, username: &str, password_hash: &str, admin: bool) -> DbResult<bool> {
+        Ok(self.execute(
+            "INSERT INTO users (username, password, admin) VALUES ($1, $2, $3) ON CONFLICT (username) DO NOTHING",
+            &[&username, &password_hash, &admin],
+        )? > 0)
+    }
+
+    fn set_password(&self, username: &str, password_hash: &str) -> DbResult<bool> {
+        Ok(self.execute(
+            "UPDATE users SET password = $2 WHERE username = $1",
+            &[&username, &password_hash],
+        )? > 0)
+    }
+
+    fn delete_user(&self, username: &str) -> DbResult<bool> {
+        self.with(|client| {
+            let mut tx = client.transaction()?;
+            execute(
+                &mut tx,
+                "DELETE FROM sessions WHERE username = $1",
+                &[&username],
+            )?;
+            execute(
+                &mut tx,
+                "DELETE FROM api_keys WHERE username = $1",
+                &[&username],
+            )?;
+            let deleted = execute(
+                &mut tx,
+                "DELETE FROM users WHERE username = $1",
+                &[&username],
+            )? > 0;
+            tx.commit()?;
+            Ok(deleted)
+        })
+    }
+
+    // SESSIONS
+
+    fn add_session(&self, session: &Session, token_hash: &str) -> DbResult<()> {
+        self.execute(
+            "INSERT INTO sessions (session_id, username, token_hash, created, last_used, expires, client_ip, user_agent)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+            &[
+                &session.session_id,
+                &session.username,
+                &token_hash,
+                &session.created,
+                &session.last_used,
+                &session.expires,
+                &session.client_ip,
+                &session.user_agent,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn get_session_by_token(&self, token_hash: &str, now: i64) -> DbResult<Option<Session>> {
+        self.single(
+            "SELECT session_id, username, created, last_used, expires, client_ip, user_agent
+             FROM sessions WHERE token_hash = $1 AND expires > $2",
+            &[&token_hash, &now],
+        )
+    }
+
+    fn renew_session(
+        &self,
+        session_id: &str,
+        token_hash: &str,
+        now: i64,
+        expires: i64,
+        client_ip: &str,
+    ) -> DbResult<()> {
+        self.execute(
+            "UPDATE sessions SET token_hash = $2, last_used = $3, expires = $4, client_ip = $5
+             WHERE session_id = $1",
+            &[&session_id, &token_hash, &now, &expires, &client_ip],
+        )?;
+        Ok(())
+    }
+
+    fn is_session_active(&self, session_id: &str, now: i64) -> DbResult<bool> {
+        Ok(self
+            .single::<String>(
+                "SELECT session_id FROM sessions WHERE session_id = $1 AND expires > $2",
+                &[&session_id, &now],
+            )?
+            .is_some())
+    }
+
+    fn get_sessions(&self, now: i64, username: Option<&str>) -> DbResult<Vec<Session>> {
+        self.all(
+            "SELECT session_id, username, created, last_used, expires, client_ip, user_agent
+             FROM sessions WHERE expires > $1 AND ($2::TEXT IS NULL OR username = $2)
+             ORDER BY last_used DESC",
+            &[&now, &username],
+        )
+    }
+
+    fn delete_session(&self, session_id: &str, username: Option<&str>) -> DbResult<bool> {
+        Ok(self.execute(
+            "DELETE FROM sessions WHERE session_id = $1 AND ($2::TEXT IS NULL OR username = $2)",
+            &[&session_id, &username],
+        )? > 0)
+    }
+
+    fn delete_user_sessions(&self, username: &str, keep: Option<&str>) -> DbResult<()> {
+        self.execute(
+            "DELETE FROM sessions WHERE username = $1 AND session_id IS DISTINCT FROM $2",
+            &[&username, &keep],
+        )?;
+        Ok(())
+    }
+
+    fn delete_expired_sessions(&self, now: i64) -> DbResult<()> {
+        self.execute("DELETE FROM sessions WHERE expires <= $1", &[&now])?;
+        Ok(())
+    }
+
+    // API KEYS
+
+    fn add_api_key(&self, key: &ApiKey, key_hash: &str) -> DbResult<()> {
+        self.execute(
+            "INSERT INTO api_keys (key_id, name, username, key_hash, scopes, created) VALUES ($1, $2, $3, $4, $5, $6)",
+            &[
+                &key.key_id,
+                &key.name,
+                &key.username,
+                &key_hash,
+                &serde_json::to_string(&key.scopes)?,
+                &key.created,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn get_api_key_by_hash(&self, key_hash: &str) -> DbResult<Option<ApiKey>> {
+        Ok(self
+            .query_api_keys("SELECT * FROM api_keys WHERE key_hash = $1", &[&key_hash])?
+            .pop())
+    }
+
+    fn get_api_keys(&self, username: Option<&str>) -> DbResult<Vec<ApiKey>> {
+        self.query_api_keys(
+            "SELECT * FROM api_keys WHERE $1::TEXT IS NULL OR username = $1 ORDER BY created",
+            &[&username],
+        )
+    }
+
+    fn touch_api_key(&self, key_id: &str, now: i64) -> DbResult<()> {
+        self.execute(
+            "UPDATE api_keys SET last_used = $2 WHERE key_id = $1",
+            &[&key_id, &now],
+        )?;
+        Ok(())
+    }
+
+    fn delete_api_key(&self, key_id: &str, username: Option<&str>) -> DbResult<bool> {
+        Ok(self.execute(
+            "DELETE FROM api_keys WHERE key_id = $1 AND ($2::TEXT IS NULL OR username = $2)",
+            &[&key_id, &username],
+        )? > 0)
+    }
+
+    // AUDIT LOG
+
+    fn add_audit_entry(&self, entry: &AuditEntry) -> DbResult<()> {
+        self.execute(
+            "INSERT INTO audit_log (time, username, api_key, action, target, details, request_id, client_ip)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+            &[
+                &entry.time,
+                &entry.username,
+                &entry.api_key,
+                &entry.action.as_str(),
+                &entry.target,
+                &entry.details.as_ref().map(|d| d.to_string()),
+                &entry.request_id,
+                &entry.client_ip,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn get_audit_page(&self, query: &AuditQuery) -> DbResult<(u64, Vec<AuditEntry>)> {
+        let (where_clause, mut params) = query.where_clause(Dialect::Postgres);
+
+        let total: u64 = self
+            .single(
+                &format!("SELECT COUNT(*) FROM audit_log {where_clause}"),
+                &sql_params(&params),
+            )?
+            .unwrap_or_default();
+
+        params.push(SqlValue::Integer(query.limit().into()));
+        params.push(SqlValue::Integer(query.offset.into()));
+        let sql = format!(
+            "SELECT * FROM audit_log {where_clause} ORDER BY id DESC LIMIT ${} OFFSET ${}",
+            params.len() - 1,
+            params.len()
+        );
+        let entries = self
+            .with(|client| self::query(client, &sql, &sql_params(&params)))?
+            .iter()
+            .filter_map(|row| {
+                Some(AuditEntry {
+                    id: row.try_get("id").ok()?,
+                    time: row.try_get("time").ok()?,
+                    username: row.try_get("username").ok()?,
+                    api_key: row.try_get("api_key").ok()?,
+                    action: serde_json::from_value(Value::String(row.try_get("action").ok()?))
+                        .ok()?,
+                    target: row.try_get("target").ok()?,
+                    details: row
+                        .try_get::<_, Option<String>>("details")
+                        .ok()?
+                        .and_then(|d| serde_json::from_str(&d).ok()),
+                    request_id: row.try_get("request_id").ok()?,
+                    client_ip: row.try_get("client_ip").ok()?,
+                })
+            })
+            .collect();
+        Ok((total, entries))
+    }
+
+    // LOGIN ATTEMPTS
+
+    fn get_login_attempts(&self) -> DbResult<Vec<LoginAttempts>> {
+        self.all(
+            "SELECT key, failures, first_failure, locked_until FROM login_attempts",
+            &[],
+        )
+    }
+
+    fn set_login_attempts(&self, attempts: &LoginAttempts) -> DbResult<()> {
+        self.execute(
+            "INSERT INTO login_attempts (key, failures, first_failure, locked_until) VALUES ($1, $2, $3, $4)
+             ON CONFLICT (key) DO UPDATE SET failures = $2, first_failure = $3, locked_until = $4",
+            &[
+                &attempts.key,
+                &i64::from(attempts.failures),
+                &attempts.first_failure,
+                &attempts.locked_until,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn delete_login_attempts(&self, key: &str) -> DbResult<()> {
+        self.execute("DELETE FROM login_attempts WHERE key = $1", &[&key])?;
+        Ok(())
+    }
+
+    fn acquire_lease(&self, key: &str, owner: &str, now: i64, ttl: i64) -> DbResult<bool> {
+        Ok(self.execute(
+            "INSERT INTO kvp (key, value, last_update) VALUES ($1, $2, $3)
+             ON CONFLICT (key) DO UPDATE SET value = $2, last_update = $3
+             WHERE kvp.value = $2 OR kvp.last_update < $3 - $4",
+            &[&key, &owner, &now, &ttl],
+        )? > 0)
+    }
+
+    fn get_lease(&self, key: &str) -> DbResult<Option<(String, i64)>> {
+        self.single("SELECT value, last_update FROM kvp WHERE key = $1", &[&key])
+    }
+
+    fn release_lease(&self, key: &str, owner: &str) -> DbResult<()> {
+        self.execute(
+            "DELETE FROM kvp WHERE key = $1 AND value = $2",
+            &[&key, &owner],
+        )?;
+        Ok(())
+    }
+
+    fn get_key(&self, key: &str) -> DbResult<Option<String>> {
+        self.single("SELECT value FROM kvp WHERE key = $1", &[&key])
+    }
+
+    fn set_key(&self, key: &str, value: &str) -> DbResult<()> {
+        self.execute(
+            "INSERT INTO kvp (key, value, last_update) VALUES ($1, $2, $3) ON CONFLICT (key) DO UPDATE SET value = $2, last_update = $3",
+            &[&key, &value, &Utc::now().timestamp()],
+        )?;
+        Ok(())
+    }
+
+    // BACKUP
+
+    fn backup(&self, _target: &Path) -> DbResult<()> {
+        Err(DbError::Unsupported("Backing up"))
+    }
+
+    /// Takes the data of a sqlite database or backup, which is upgraded to the current version
+    /// first.
+    fn restore(&self, source: &Path) -> DbResult<()> {
+        let source = SqliteStorage::open_copy(source)?;
+        let source = source.connection();
+        self.with(|client| copy_from_sqlite(client, &source))
+    }
+}
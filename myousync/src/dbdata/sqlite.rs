@@ -0,0 +1,2024 @@
+//! The default storage, a sqlite file which is `ytdata.db` unless set with [`super::use_database`].
+
+use std::{
+    collections::HashSet,
+    path::Path,
+    sync::{
+        Mutex, MutexGuard, RwLock,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use rusqlite::{
+    Connection, DatabaseName, OpenFlags, OptionalExtension, Params, Row, ToSql,
+    backup::Progress,
+    params_from_iter,
+    trace::{TraceEvent, TraceEventCodes},
+    types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, Type, ValueRef},
+};
+use serde::{Deserialize, de::DeserializeOwned};
+use serde_rusqlite::from_rows;
+
+use super::{
+    AuthData, DB_PATH, DbError, DbResult, Dialect, FetchStatus, LoginAttempts, MATCH_HISTORY_LEN,
+    Playlist, PlaylistInfo, PlaylistItem, PlaylistSummary, STATS, STATUS_HISTORY_LEN, Session,
+    SqlValue, StatusChange, Storage, User, UserData, VideoFilter, VideoStatus, record_query,
+    to_json,
+};
+use crate::{
+    MsDatabase,
+    apikeys::ApiKey,
+    artist_rules::{ArtistRule, ArtistRuleRequest},
+    audit::{AuditEntry, AuditQuery},
+    brainz::{BrainzMetadata, BrainzMultiSearch, BrainzSearchLog, MatchAttempt},
+    duplicates::{Duplicate, DuplicateKeep, DuplicateKind},
+    errors::{self, ErrorCode},
+    flags::{FlagReason, VideoFlag},
+    jobs::{Job, JobState},
+    musicfiles::IndexedFile,
+    password,
+    pending::PendingMove,
+    trash::TrashedFile,
+    util::queue::Priority,
+};
+
+const DB_VERSION: u32 = 12;
+
+/// Pause between two attempts on a locked database
+const BUSY_RETRY: Duration = Duration::from_millis(10);
+static BUSY_TIMEOUT_MS: AtomicU64 = AtomicU64::new(5000);
+static READ_CONNECTIONS: AtomicUsize = AtomicUsize::new(4);
+static NEXT_READER: AtomicUsize = AtomicUsize::new(0);
+static PRAGMAS: RwLock<Pragmas> = RwLock::new(Pragmas {
+    journal_mode: JournalMode::Wal,
+    synchronous: Synchronous::Normal,
+    foreign_keys: true,
+});
+
+/// How sqlite keeps changes until they are in the database file, see `PRAGMA journal_mode`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JournalMode {
+    Delete,
+    Truncate,
+    Persist,
+    /// Queries do not wait for writes and writes not for queries
+    Wal,
+}
+
+impl JournalMode {
+    fn as_str(self) -> &'static str {
+        match self {
+            JournalMode::Delete => "delete",
+            JournalMode::Truncate => "truncate",
+            JournalMode::Persist => "persist",
+            JournalMode::Wal => "wal",
+        }
+    }
+}
+
+/// How often sqlite waits for changes to reach the disk, see `PRAGMA synchronous`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Synchronous {
+    Off,
+    /// Safe with [`JournalMode::Wal`], a power loss can only undo the last commits
+    Normal,
+    Full,
+    Extra,
+}
+
+impl Synchronous {
+    fn as_str(self) -> &'static str {
+        match self {
+            Synchronous::Off => "off",
+            Synchronous::Normal => "normal",
+            Synchronous::Full => "full",
+            Synchronous::Extra => "extra",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Pragmas {
+    journal_mode: JournalMode,
+    synchronous: Synchronous,
+    foreign_keys: bool,
+}
+
+/// The connection which writes, and read only connections for queries, so reads do not wait
+/// for each other or for a long write on the same connection.
+pub struct SqliteStorage {
+    conn: Mutex<Connection>,
+    /// Empty for an in-memory database, which cannot be shared between connections
+    readers: Vec<Mutex<Connection>>,
+}
+
+/// Applies the sqlite settings of `config`, see [`super::configure_database`].
+pub(super) fn configure(config: &MsDatabase) {
+    BUSY_TIMEOUT_MS.store(config.busy_timeout.as_millis() as u64, Ordering::Relaxed);
+    READ_CONNECTIONS.store(config.read_connections, Ordering::Relaxed);
+    *PRAGMAS.write().unwrap() = Pragmas {
+        journal_mode: config.journal_mode,
+        synchronous: config.synchronous,
+        foreign_keys: config.foreign_keys,
+    };
+}
+
+pub(super) fn busy_timeout() -> Duration {
+    Duration::from_millis(BUSY_TIMEOUT_MS.load(Ordering::Relaxed))
+}
+
+fn open_connection(path: &str, flags: OpenFlags) -> DbResult<Connection> {
+    let conn = Connection::open_with_flags(path, flags)?;
+    conn.busy_handler(Some(on_busy))?;
+    conn.trace_v2(TraceEventCodes::SQLITE_TRACE_PROFILE, Some(on_trace));
+    Ok(conn)
+}
+
+/// Applies the configured pragmas to the write connection. The journal mode is kept in the
+/// database file, the others only last as long as the connection.
+fn apply_pragmas(conn: &Connection, path: &str) -> DbResult<()> {
+    let pragmas = *PRAGMAS.read().unwrap();
+    let journal_mode: String =
+        conn.pragma_update_and_check(None, "journal_mode", pragmas.journal_mode.as_str(), |row| {
+            row.get(0)
+        })?;
+    // In-memory databases always use the memory journal
+    if !journal_mode.eq_ignore_ascii_case(pragmas.journal_mode.as_str()) && path != ":memory:" {
+        warn!(
+            "Database uses the {} journal instead of {}",
+            journal_mode,
+            pragmas.journal_mode.as_str()
+        );
+    }
+    conn.pragma_update(None, "synchronous", pragmas.synchronous.as_str())?;
+    conn.pragma_update(None, "foreign_keys", pragmas.foreign_keys)?;
+    Ok(())
+}
+
+/// Replaces the sqlite busy timeout, so lock waits can be counted.
+/// `attempt` counts the previous calls for the same statement.
+fn on_busy(attempt: i32) -> bool {
+    if attempt == 0 {
+        STATS.busy.fetch_add(1, Ordering::Relaxed);
+    }
+    let timeout = busy_timeout();
+    if BUSY_RETRY * attempt as u32 >= timeout {
+        STATS.busy_timeouts.fetch_add(1, Ordering::Relaxed);
+        warn!("Database stayed locked for {:?}, giving up", timeout);
+        return false;
+    }
+    std::thread::sleep(BUSY_RETRY);
+    true
+}
+
+fn on_trace(event: TraceEvent) {
+    let TraceEvent::Profile(stmt, duration) = event else {
+        return;
+    };
+    record_query(&stmt.sql(), duration);
+}
+
+/// Sets the `error_code` of every status from its `last_error`.
+fn classify_errors(con: &Connection) -> DbResult<()> {
+    let mut stmt = con.prepare(
+        "SELECT video_id, fetch_status, last_error FROM status WHERE last_error IS NOT NULL",
+    )?;
+    let rows = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, FetchStatus>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    for (video_id, fetch_status, last_error) in rows {
+        let code = errors::classify(fetch_status, Some(&last_error));
+        con.execute(
+            "UPDATE status SET error_code = ?2 WHERE video_id = ?1",
+            (video_id, code.map(ErrorCode::as_str)),
+        )?;
+    }
+    Ok(())
+}
+
+/// Reads a column holding json. `NULL` reads as json `null`, so nullable columns are read into
+/// an `Option`.
+fn json_column<T: DeserializeOwned>(row: &Row, column: &str) -> rusqlite::Result<T> {
+    let text = row.get::<_, Option<String>>(column)?;
+    serde_json::from_str(text.as_deref().unwrap_or("null")).map_err(|err| {
+        rusqlite::Error::FromSqlConversionFailure(
+            row.as_ref().column_index(column).unwrap_or_default(),
+            Type::Text,
+            Box::new(err),
+        )
+    })
+}
+
+impl ToSql for SqlValue {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(match self {
+            SqlValue::Integer(value) => ToSqlOutput::from(*value),
+            SqlValue::Text(value) => ToSqlOutput::from(value.as_str()),
+        })
+    }
+}
+
+impl SqliteStorage {
+    /// Opens and upgrades the database.
+    pub(super) fn open() -> DbResult<Self> {
+        Self::open_at(DB_PATH.get().map_or("ytdata.db", String::as_str))
+    }
+
+    /// Opens and upgrades the database at `path`, `:memory:` for one without a file.
+    pub(super) fn open_at(path: &str) -> DbResult<Self> {
+        let conn = open_connection(path, OpenFlags::default())?;
+        apply_pragmas(&conn, path)?;
+        let mut state = Self::upgrade(conn)?;
+
+        // Opened once the tables exist, as they cannot create them
+        if path != ":memory:" {
+            let flags = OpenFlags::SQLITE_OPEN_READ_ONLY
+                | OpenFlags::SQLITE_OPEN_URI
+                | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+            state.readers = (0..READ_CONNECTIONS.load(Ordering::Relaxed))
+                .map(|_| open_connection(path, flags).map(Mutex::new))
+                .collect::<DbResult<_>>()?;
+        }
+        Ok(state)
+    }
+
+    /// Loads the database file at `source` into memory and upgrades it there, leaving the file
+    /// as it is.
+    pub(super) fn open_copy(source: &Path) -> DbResult<Self> {
+        let mut conn = Connection::open_in_memory()?;
+        conn.restore(DatabaseName::Main, source, None::<fn(Progress)>)?;
+        let version: u32 = conn
+            .query_row("SELECT value FROM kvp WHERE key = 'version'", [], |row| {
+                row.get::<_, String>(0)
+            })?
+            .parse()
+            .unwrap_or(0);
+        if version > DB_VERSION {
+            return Err(DbError::NewerBackup(version));
+        }
+        Self::upgrade(conn)
+    }
+
+    /// The write connection, for reading a whole table at once.
+    pub(super) fn connection(&self) -> MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap()
+    }
+
+    /// Creates the missing tables and upgrades them to the current version.
+    fn upgrade(conn: Connection) -> DbResult<Self> {
+        conn.execute_batch(
+            "
+            BEGIN;
+            CREATE TABLE IF NOT EXISTS ytdata (
+                video_id TEXT PRIMARY KEY NOT NULL,
+                snippet TEXT DEFAULT NULL,
+                ytdlp TEXT DEFAULT NULL
+            );
+            CREATE TABLE IF NOT EXISTS authdata (
+                access_token TEXT NOT NULL,
+                refresh_token TEXT NOT NULL,
+                expires_at INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS playlists (
+                playlist_id TEXT PRIMARY KEY NOT NULL,
+                etag TEXT NOT NULL,
+                total_results INTEGER NOT NULL,
+                fetch_time INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS playlist_items (
+                playlist_id TEXT NOT NULL,
+                video_id TEXT NOT NULL,
+                title TEXT NOT NULL,
+                artist TEXT NOT NULL,
+                PRIMARY KEY (playlist_id, video_id),
+                FOREIGN KEY (playlist_id) REFERENCES playlists(playlist_id) ON DELETE CASCADE
+            );
+            CREATE TABLE IF NOT EXISTS brainz (
+                query TEXT PRIMARY KEY NOT NULL,
+                fetch_time INTEGER NOT NULL,
+                data TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS brainz_fetch_time ON brainz (fetch_time);
+            CREATE TABLE IF NOT EXISTS status (
+                video_id TEXT PRIMARY KEY NOT NULL,
+                last_update INTEGER NOT NULL,
+                fetch_time INTEGER NOT NULL,
+                fetch_status INTEGER NOT NULL,
+                last_query TEXT DEFAULT NULL,
+                last_result TEXT DEFAULT NULL,
+                override_query TEXT DEFAULT NULL,
+                override_result TEXT DEFAULT NULL
+            );
+            CREATE INDEX IF NOT EXISTS status_fetch_status ON status (fetch_status, last_update);
+            CREATE INDEX IF NOT EXISTS status_last_update ON status (last_update);
+            CREATE INDEX IF NOT EXISTS playlist_items_video ON playlist_items (video_id);
+            CREATE TABLE IF NOT EXISTS users (
+                username TEXT PRIMARY KEY NOT NULL,
+                password BLOB NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS pending_moves (
+                video_id TEXT PRIMARY KEY NOT NULL,
+                source TEXT NOT NULL,
+                target TEXT NOT NULL,
+                last_error TEXT DEFAULT NULL,
+                created INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS source_matches (
+                provider TEXT NOT NULL,
+                source_id TEXT NOT NULL,
+                video_id TEXT NOT NULL,
+                PRIMARY KEY (provider, source_id)
+            );
+            CREATE TABLE IF NOT EXISTS manual_videos (
+                video_id TEXT PRIMARY KEY NOT NULL,
+                playlist_id TEXT NOT NULL,
+                added_by TEXT NOT NULL,
+                added INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS flags (
+                flag_id INTEGER PRIMARY KEY AUTOINCREMENT,
+                video_id TEXT NOT NULL,
+                reason TEXT NOT NULL,
+                note TEXT DEFAULT NULL,
+                reported_by TEXT NOT NULL,
+                created INTEGER NOT NULL,
+                resolved INTEGER DEFAULT NULL
+            );
+            CREATE TABLE IF NOT EXISTS artist_rules (
+                rule_id INTEGER PRIMARY KEY AUTOINCREMENT,
+                kind TEXT NOT NULL,
+                pattern TEXT NOT NULL,
+                replacement TEXT DEFAULT NULL,
+                created INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS trash (
+                video_id TEXT PRIMARY KEY NOT NULL,
+                original TEXT NOT NULL,
+                path TEXT NOT NULL,
+                fetch_status INTEGER NOT NULL,
+                deleted INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS files (
+                path TEXT PRIMARY KEY NOT NULL,
+                video_id TEXT DEFAULT NULL,
+                mtime INTEGER NOT NULL,
+                size INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS duplicates (
+                duplicate_id INTEGER PRIMARY KEY AUTOINCREMENT,
+                video_id TEXT NOT NULL,
+                other_video_id TEXT DEFAULT NULL,
+                kind TEXT NOT NULL,
+                path TEXT NOT NULL,
+                created INTEGER NOT NULL,
+                resolved INTEGER DEFAULT NULL,
+                kept TEXT DEFAULT NULL
+            );
+            CREATE TABLE IF NOT EXISTS jobs (
+                video_id TEXT PRIMARY KEY NOT NULL,
+                priority TEXT NOT NULL,
+                state TEXT NOT NULL,
+                attempts INTEGER NOT NULL,
+                next_attempt INTEGER NOT NULL,
+                last_error TEXT DEFAULT NULL,
+                created INTEGER NOT NULL,
+                updated INTEGER NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS jobs_state ON jobs (state, next_attempt);
+            CREATE TABLE IF NOT EXISTS match_attempts (
+                attempt_id INTEGER PRIMARY KEY AUTOINCREMENT,
+                video_id TEXT NOT NULL,
+                created INTEGER NOT NULL,
+                query TEXT NOT NULL,
+                searches TEXT NOT NULL,
+                result TEXT DEFAULT NULL
+            );
+            CREATE INDEX IF NOT EXISTS match_attempts_video ON match_attempts (video_id, attempt_id);
+            CREATE TABLE IF NOT EXISTS sessions (
+                session_id TEXT PRIMARY KEY NOT NULL,
+                username TEXT NOT NULL,
+                token_hash TEXT NOT NULL UNIQUE,
+                created INTEGER NOT NULL,
+                last_used INTEGER NOT NULL,
+                expires INTEGER NOT NULL,
+                client_ip TEXT DEFAULT NULL,
+                user_agent TEXT DEFAULT NULL
+            );
+            CREATE TABLE IF NOT EXISTS api_keys (
+                key_id TEXT PRIMARY KEY NOT NULL,
+                name TEXT NOT NULL,
+                username TEXT NOT NULL,
+                key_hash TEXT NOT NULL UNIQUE,
+                scopes TEXT NOT NULL,
+                created INTEGER NOT NULL,
+                last_used INTEGER DEFAULT NULL
+            );
+            CREATE TABLE IF NOT EXISTS audit_log (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                time INTEGER NOT NULL,
+                username TEXT NOT NULL,
+                api_key TEXT DEFAULT NULL,
+                action TEXT NOT NULL,
+                target TEXT DEFAULT NULL,
+                details TEXT DEFAULT NULL,
+                request_id TEXT DEFAULT NULL,
+                client_ip TEXT DEFAULT NULL
+            );
+            CREATE INDEX IF NOT EXISTS audit_log_time ON audit_log (time);
+            CREATE TABLE IF NOT EXISTS status_history (
+                history_id INTEGER PRIMARY KEY AUTOINCREMENT,
+                video_id TEXT NOT NULL,
+                time INTEGER NOT NULL,
+                fetch_status INTEGER NOT NULL,
+                last_error TEXT DEFAULT NULL,
+                query TEXT DEFAULT NULL,
+                result TEXT DEFAULT NULL
+            );
+            CREATE INDEX IF NOT EXISTS status_history_video ON status_history (video_id, history_id);
+            CREATE TABLE IF NOT EXISTS login_attempts (
+                key TEXT PRIMARY KEY NOT NULL,
+                failures INTEGER NOT NULL,
+                first_failure INTEGER NOT NULL,
+                locked_until INTEGER DEFAULT NULL
+            );
+            CREATE TABLE IF NOT EXISTS kvp (
+                key TEXT PRIMARY KEY NOT NULL,
+                value TEXT NOT NULL,
+                last_update INTEGER NOT NULL
+            );
+            COMMIT;",
+        )?;
+
+        let state = Self {
+            conn: Mutex::new(conn),
+            readers: Vec::new(),
+        };
+
+        let cur_ver: u32 = state
+            .get_key("version")?
+            .map(|v| v.parse().map_err(|_| DbError::InvalidVersion(v)))
+            .transpose()?
+            .unwrap_or(0u32);
+
+        if cur_ver < DB_VERSION {
+            info!(
+                "Upgrading database from version {} to {}",
+                cur_ver, DB_VERSION
+            );
+
+            let mut new_ver = cur_ver;
+            if new_ver == 0 {
+                new_ver = 1;
+                {
+                    let con = &state.conn.lock().unwrap();
+                    con.execute(
+                        "ALTER TABLE status ADD COLUMN last_error TEXT DEFAULT NULL",
+                        [],
+                    )?;
+                }
+                state.set_key("version", &new_ver.to_string())?;
+            }
+            if new_ver == 1 {
+                new_ver = 2;
+                {
+                    let con = &state.conn.lock().unwrap();
+                    con.execute(
+                        "ALTER TABLE playlist_items ADD COLUMN duration INTEGER DEFAULT NULL",
+                        [],
+                    )?;
+                }
+                state.set_key("version", &new_ver.to_string())?;
+            }
+            if new_ver == 2 {
+                new_ver = 3;
+                {
+                    let con = &state.conn.lock().unwrap();
+                    con.execute_batch(
+                        "ALTER TABLE playlist_items ADD COLUMN thumbnail TEXT DEFAULT NULL;
+                         UPDATE playlists SET etag = '';",
+                    )?;
+                }
+                state.set_key("version", &new_ver.to_string())?;
+            }
+            if new_ver == 3 {
+                new_ver = 4;
+                {
+                    let con = &state.conn.lock().unwrap();
+                    con.execute_batch(
+                        "ALTER TABLE playlists ADD COLUMN title TEXT DEFAULT NULL;
+                         ALTER TABLE playlists ADD COLUMN description TEXT DEFAULT NULL;
+                         ALTER TABLE playlists ADD COLUMN owner TEXT DEFAULT NULL;
+                         ALTER TABLE playlists ADD COLUMN thumbnail TEXT DEFAULT NULL;
+                         UPDATE playlists SET etag = '';",
+                    )?;
+                }
+                state.set_key("version", &new_ver.to_string())?;
+            }
+            if new_ver == 4 {
+                new_ver = 5;
+                {
+                    let con = &state.conn.lock().unwrap();
+                    con.execute(
+                        "ALTER TABLE status ADD COLUMN removal TEXT DEFAULT NULL",
+                        [],
+                    )?;
+                }
+                state.set_key("version", &new_ver.to_string())?;
+            }
+            if new_ver == 5 {
+                new_ver = 6;
+                {
+                    let con = &state.conn.lock().unwrap();
+                    con.execute(
+                        "ALTER TABLE status ADD COLUMN candidates TEXT DEFAULT NULL",
+                        [],
+                    )?;
+                }
+                state.set_key("version", &new_ver.to_string())?;
+            }
+            if new_ver == 6 {
+                new_ver = 7;
+                {
+                    let con = &state.conn.lock().unwrap();
+                    con.execute(
+                        "ALTER TABLE status ADD COLUMN confidence REAL DEFAULT NULL",
+                        [],
+                    )?;
+                }
+                state.set_key("version", &new_ver.to_string())?;
+            }
+            if new_ver == 7 {
+                new_ver = 8;
+                {
+                    let con = &state.conn.lock().unwrap();
+                    con.execute(
+                        "ALTER TABLE status ADD COLUMN error_code TEXT DEFAULT NULL",
+                        [],
+                    )?;
+                    classify_errors(con)?;
+                }
+                state.set_key("version", &new_ver.to_string())?;
+            }
+            if new_ver == 8 {
+                new_ver = 9;
+                {
+                    // New codes for fetch errors
+                    let con = &state.conn.lock().unwrap();
+                    classify_errors(con)?;
+                }
+                state.set_key("version", &new_ver.to_string())?;
+            }
+            if new_ver == 9 {
+                new_ver = 10;
+                {
+                    // Everyone could manage everything before, so existing users stay admins
+                    let con = &state.conn.lock().unwrap();
+                    con.execute_batch(
+                        "ALTER TABLE users ADD COLUMN admin INTEGER NOT NULL DEFAULT 0;
+                         UPDATE users SET admin = 1;",
+                    )?;
+                }
+                state.set_key("version", &new_ver.to_string())?;
+            }
+            if new_ver == 10 {
+                new_ver = 11;
+                {
+                    let con = &state.conn.lock().unwrap();
+                    con.execute_batch(
+                        "ALTER TABLE status ADD COLUMN tags_unchanged INTEGER NOT NULL DEFAULT 0;
+                         ALTER TABLE status_history ADD COLUMN tags_unchanged INTEGER NOT NULL DEFAULT 0;",
+                    )?;
+                }
+                state.set_key("version", &new_ver.to_string())?;
+            }
+            if new_ver == 11 {
+                new_ver = 12;
+                {
+                    // Passwords which are no hash yet are readable ones from before hashing
+                    let con = &state.conn.lock().unwrap();
+                    let users = con
+                        .prepare("SELECT username, password FROM users")?
+                        .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get(1)?)))?
+                        .collect::<Result<Vec<(String, String)>, _>>()?;
+                    for (username, stored) in users {
+                        if !password::is_hash(&stored) {
+                            con.execute(
+                                "UPDATE users SET password = ?1 WHERE username = ?2",
+                                [format!("{}{stored}", password::LEGACY_PREFIX), username],
+                            )?;
+                        }
+                    }
+                }
+                state.set_key("version", &new_ver.to_string())?;
+            }
+
+            info!("Database upgrade complete");
+        }
+        Ok(state)
+    }
+
+    /// A free read connection, or the one in turn if all are busy. Statements on it do not see
+    /// uncommitted changes of the write connection.
+    fn reader(&self) -> MutexGuard<'_, Connection> {
+        if self.readers.is_empty() {
+            return self.conn.lock().unwrap();
+        }
+        let start = NEXT_READER.fetch_add(1, Ordering::Relaxed);
+        for i in 0..self.readers.len() {
+            if let Ok(conn) = self.readers[(start + i) % self.readers.len()].try_lock() {
+                return conn;
+            }
+        }
+        self.readers[start % self.readers.len()].lock().unwrap()
+    }
+
+    fn set_ytdata(&self, video_id: &str, data: &str, col: &str) -> DbResult<()> {
+        let conn = self.conn.lock().unwrap();
+        let query = format!(
+            "INSERT INTO ytdata (video_id, {col}) VALUES (?1, ?2) ON CONFLICT(video_id) DO UPDATE SET {col} = ?2"
+        );
+        conn.execute(&query, (&video_id, &data))?;
+        Ok(())
+    }
+
+    fn try_get_ytdata(&self, video_id: &str, col: &str) -> DbResult<Option<String>> {
+        let conn = self.reader();
+        let query = format!("SELECT {col} FROM ytdata WHERE video_id = ?1");
+        Ok(conn
+            .query_row(&query, [video_id], |row| row.get::<_, Option<String>>(0))
+            .optional()?
+            .flatten())
+    }
+
+    fn get_video_internal(conn: &Connection, video_id: &str) -> DbResult<Option<VideoStatus>> {
+        Ok(conn
+            .query_row(
+                "SELECT * FROM status WHERE video_id = ?1",
+                [video_id],
+                Self::map_video_status,
+            )
+            .optional()?)
+    }
+
+    fn map_video_status(row: &Row) -> rusqlite::Result<VideoStatus> {
+        Ok(VideoStatus {
+            video_id: row.get("video_id")?,
+            fetch_time: row.get("fetch_time")?,
+            fetch_status: row.get("fetch_status")?,
+            last_update: row.get("last_update")?,
+            last_query: json_column(row, "last_query")?,
+            last_result: json_column(row, "last_result")?,
+            last_error: row.get("last_error")?,
+            override_query: json_column(row, "override_query")?,
+            override_result: json_column(row, "override_result")?,
+            removal: json_column(row, "removal")?,
+            candidates: json_column::<Option<_>>(row, "candidates")?.unwrap_or_default(),
+            confidence: row.get("confidence")?,
+            tags_unchanged: row.get("tags_unchanged")?,
+            download_progress: None,
+        })
+    }
+
+    fn set_full_track_status_internal(conn: &Connection, status: &VideoStatus) -> DbResult<()> {
+        conn
+            .execute(
+                "INSERT INTO status (video_id, last_update, fetch_time, fetch_status, last_query, last_result, override_query, override_result, last_error, removal, candidates, confidence, error_code, tags_unchanged)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)
+                 ON CONFLICT(video_id)
+                 DO UPDATE SET last_update = ?2, fetch_time = ?3, fetch_status = ?4, last_query = ?5, last_result = ?6, override_query = ?7, override_result = ?8, last_error = ?9, removal = ?10, candidates = ?11, confidence = ?12, error_code = ?13, tags_unchanged = ?14",
+                (
+                    &status.video_id,
+                    status.last_update,
+                    status.fetch_time,
+                    status.fetch_status as i64,
+                    to_json(status.last_query.as_ref())?,
+                    to_json(status.last_result.as_ref())?,
+                    to_json(status.override_query.as_ref())?,
+                    to_json(status.override_result.as_ref())?,
+                    status.last_error.as_ref(),
+                    to_json(status.removal.as_ref())?,
+                    to_json((!status.candidates.is_empty()).then_some(&status.candidates))?,
+                    status.confidence,
+                    status.error_code().map(ErrorCode::as_str),
+                    status.tags_unchanged,
+                )
+            )?;
+        Self::record_history(conn, &status.video_id)
+    }
+
+    /// Adds the stored status of `video_id` to its history, unless it matches the last entry.
+    fn record_history(conn: &Connection, video_id: &str) -> DbResult<()> {
+        let added = conn.execute(
+            "INSERT INTO status_history (video_id, time, fetch_status, last_error, query, result, tags_unchanged)
+             SELECT s.video_id, ?2, s.fetch_status, s.last_error, coalesce(s.override_query, s.last_query), coalesce(s.override_result, s.last_result), s.tags_unchanged
+             FROM status s
+             WHERE s.video_id = ?1 AND NOT EXISTS (
+                SELECT 1 FROM (SELECT * FROM status_history WHERE video_id = ?1 ORDER BY history_id DESC LIMIT 1) h
+                WHERE h.fetch_status = s.fetch_status AND h.last_error IS s.last_error
+                  AND h.query IS coalesce(s.override_query, s.last_query)
+                  AND h.result IS coalesce(s.override_result, s.last_result)
+                  AND h.tags_unchanged = s.tags_unchanged
+             )",
+            (video_id, Utc::now().timestamp()),
+        )?;
+        if added > 0 {
+            conn.execute(
+                "DELETE FROM status_history WHERE video_id = ?1 AND history_id NOT IN
+                    (SELECT history_id FROM status_history WHERE video_id = ?1 ORDER BY history_id DESC LIMIT ?2)",
+                (video_id, STATUS_HISTORY_LEN),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn map_trashed_file(row: &Row) -> rusqlite::Result<TrashedFile> {
+        Ok(TrashedFile {
+            video_id: row.get("video_id")?,
+            original: row.get::<_, String>("original")?.into(),
+            path: row.get::<_, String>("path")?.into(),
+            fetch_status: row.get("fetch_status")?,
+            deleted: row.get("deleted")?,
+        })
+    }
+
+    fn query_api_keys<P: Params>(&self, query: &str, params: P) -> DbResult<Vec<ApiKey>> {
+        let conn = self.reader();
+        let mut stmt = conn.prepare(query)?;
+        Ok(stmt
+            .query_map(params, |row| {
+                Ok(ApiKey {
+                    key_id: row.get("key_id")?,
+                    name: row.get("name")?,
+                    username: row.get("username")?,
+                    scopes: json_column(row, "scopes")?,
+                    created: row.get("created")?,
+                    last_used: row.get("last_used")?,
+                })
+            })?
+            .filter_map(|r| r.ok())
+            .collect())
+    }
+
+    // Helper
+
+    fn all<T: serde::de::DeserializeOwned, P: Params>(
+        &self,
+        query: &str,
+        params: P,
+    ) -> DbResult<Vec<T>> {
+        let conn = self.reader();
+        let mut stmt = conn.prepare(query)?;
+        let rows = from_rows::<T>(stmt.query(params)?);
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    fn single<T: serde::de::DeserializeOwned, P: Params>(
+        &self,
+        query: &str,
+        params: P,
+    ) -> DbResult<Option<T>> {
+        let conn = self.reader();
+        let mut stmt = conn.prepare(query)?;
+        let mut rows = from_rows::<T>(stmt.query(params)?);
+        match rows.next() {
+            Some(Ok(row)) => Ok(Some(row)),
+            Some(Err(serde_rusqlite::Error::Rusqlite(err))) => Err(err.into()),
+            // A NULL, like the MAX of no rows, reads as no row
+            Some(Err(_)) | None => Ok(None),
+        }
+    }
+}
+
+impl Storage for SqliteStorage {
+    // YT_API
+
+    fn set_yt_dlp(&self, video_id: &str, dlp: &str) -> DbResult<()> {
+        self.set_ytdata(video_id, dlp, "ytdlp")
+    }
+
+    fn delete_yt_data(&self, video_id: &str) -> DbResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM ytdata WHERE video_id = ?1", [video_id])?;
+        Ok(())
+    }
+
+    fn try_get_yt_dlp(&self, video_id: &str) -> DbResult<Option<String>> {
+        self.try_get_ytdata(video_id, "ytdlp")
+    }
+
+    // PLAYLISTS
+
+    fn try_get_playlist(&self, playlist_id: &str) -> DbResult<Option<Playlist>> {
+        let conn = self.reader();
+        let Some(mut playlist) = conn
+            .query_row(
+                "SELECT playlist_id, etag, total_results, fetch_time, title, description, owner, thumbnail FROM playlists WHERE playlist_id = ?1",
+                [playlist_id],
+                |row| {
+                    Ok(Playlist {
+                        playlist_id: row.get(0)?,
+                        etag: row.get(1)?,
+                        total_results: row.get(2)?,
+                        fetch_time: DateTime::from_timestamp(row.get(3)?, 0).unwrap_or_default(),
+                        info: PlaylistInfo {
+                            title: row.get(4)?,
+                            description: row.get(5)?,
+                            owner: row.get(6)?,
+                            thumbnail: row.get(7)?,
+                        },
+                        items: vec![],
+                    })
+                },
+            )
+            .optional()?
+        else {
+            return Ok(None);
+        };
+
+        let mut stmt = conn
+            .prepare("SELECT video_id, title, artist, duration, thumbnail FROM playlist_items WHERE playlist_id = ?1")?;
+
+        playlist.items = stmt
+            .query_map([playlist_id], |row| {
+                Ok(PlaylistItem {
+                    video_id: row.get(0)?,
+                    title: row.get(1)?,
+                    artist: row.get(2)?,
+                    duration: row.get(3)?,
+                    thumbnail: row.get(4)?,
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+
+        Ok(Some(playlist))
+    }
+
+    fn set_playlist(&self, playlist: &Playlist) -> DbResult<()> {
+        let conn = self.conn.lock().unwrap();
+        let tx = conn.unchecked_transaction()?;
+
+        conn.execute(
+            "DELETE FROM playlists WHERE playlist_id = ?1",
+            (&playlist.playlist_id,),
+        )?;
+
+        conn
+            .execute(
+                "INSERT INTO playlists (playlist_id, etag, total_results, fetch_time, title, description, owner, thumbnail) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                (
+                    &playlist.playlist_id,
+                    &playlist.etag,
+                    playlist.total_results,
+                    playlist.fetch_time.timestamp(),
+                    &playlist.info.title,
+                    &playlist.info.description,
+                    &playlist.info.owner,
+                    &playlist.info.thumbnail,
+                ),
+            )?;
+
+        let mut stmt = conn.prepare(
+            "INSERT INTO playlist_items (playlist_id, video_id, title, artist, duration, thumbnail) VALUES (?1, ?2, ?3, ?4, ?5, ?6)")?;
+
+        for item in &playlist.items {
+            stmt.execute((
+                &playlist.playlist_id,
+                &item.video_id,
+                &item.title,
+                &item.artist,
+                item.duration,
+                &item.thumbnail,
+            ))?;
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn update_playlist_fetch_time(
+        &self,
+        playlist_id: &str,
+        fetch_time: DateTime<Utc>,
+    ) -> DbResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE playlists SET fetch_time = ?1 WHERE playlist_id = ?2",
+            (fetch_time.timestamp(), playlist_id),
+        )?;
+        Ok(())
+    }
+
+    fn get_playlists(&self) -> DbResult<Vec<PlaylistSummary>> {
+        self.all(
+            "SELECT playlist_id, title, description, owner, thumbnail, total_results, fetch_time FROM playlists ORDER BY title",
+            [],
+        )
+    }
+
+    fn get_playlist_summary(&self, playlist_id: &str) -> DbResult<Option<PlaylistSummary>> {
+        self.single(
+            "SELECT playlist_id, title, description, owner, thumbnail, total_results, fetch_time FROM playlists WHERE playlist_id = ?1",
+            [playlist_id],
+        )
+    }
+
+    fn get_thumbnail(&self, video_id: &str) -> DbResult<Option<String>> {
+        self.single(
+            "SELECT thumbnail FROM playlist_items WHERE video_id = ?1 AND thumbnail IS NOT NULL LIMIT 1",
+            [video_id],
+        )
+    }
+
+    // YT AUTH
+
+    fn try_get_auth(&self) -> DbResult<Option<AuthData>> {
+        self.single(
+            "SELECT access_token, refresh_token, expires_at FROM authdata",
+            [],
+        )
+    }
+
+    fn set_auth(&self, auth: &AuthData) -> DbResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM authdata", ())?;
+
+        conn.execute(
+            "INSERT INTO authdata (access_token, refresh_token, expires_at) VALUES (?1, ?2, ?3)",
+            (&auth.access_token, &auth.refresh_token, auth.expires_at),
+        )?;
+        Ok(())
+    }
+
+    // FILESYSTEM
+
+    fn get_indexed_files(&self) -> DbResult<Vec<IndexedFile>> {
+        self.all("SELECT * FROM files", [])
+    }
+
+    fn set_indexed_files(&self, files: &[IndexedFile]) -> DbResult<()> {
+        let conn = self.conn.lock().unwrap();
+        let tx = conn.unchecked_transaction()?;
+        conn.execute("DELETE FROM files", [])?;
+        let mut stmt = conn
+            .prepare("INSERT INTO files (path, video_id, mtime, size) VALUES (?1, ?2, ?3, ?4)")?;
+        for file in files {
+            stmt.execute((
+                file.path.to_string_lossy(),
+                &file.video_id,
+                file.mtime,
+                file.size,
+            ))?;
+        }
+        drop(stmt);
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn get_track_query_override(&self, video_id: &str) -> DbResult<Option<String>> {
+        self.single(
+            "SELECT override_query FROM status WHERE video_id = ?1",
+            [video_id],
+        )
+    }
+
+    fn get_track_result_override(&self, video_id: &str) -> DbResult<Option<String>> {
+        self.single(
+            "SELECT override_result FROM status WHERE video_id = ?1",
+            [video_id],
+        )
+    }
+
+    fn modify_video_statuses(
+        &self,
+        video_ids: &[String],
+        modify: &dyn Fn(&mut VideoStatus) -> bool,
+    ) -> DbResult<Vec<VideoStatus>> {
+        let conn = self.conn.lock().unwrap();
+        let tx = conn.unchecked_transaction()?;
+        let mut changed = Vec::new();
+        for video_id in video_ids {
+            let Some(mut video) = Self::get_video_internal(&conn, video_id)? else {
+                continue;
+            };
+            if modify(&mut video) {
+                video.update_now();
+                Self::set_full_track_status_internal(&conn, &video)?;
+                changed.push(video);
+            }
+        }
+        tx.commit()?;
+        Ok(changed)
+    }
+
+    fn get_all_videos(&self) -> DbResult<Vec<VideoStatus>> {
+        let conn = self.reader();
+        let mut stmt = conn.prepare("SELECT * FROM status")?;
+        let rows = stmt.query_map([], Self::map_video_status)?;
+
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    fn get_videos_page(&self, filter: &VideoFilter) -> DbResult<(u64, Vec<VideoStatus>)> {
+        let (where_clause, mut params) = filter.where_clause(Dialect::Sqlite);
+        let sort_column = filter.sort_column();
+        let order = filter.order.as_sql();
+
+        let conn = self.reader();
+        let total: u64 = conn.query_row(
+            &format!("SELECT COUNT(*) FROM status s {where_clause}"),
+            params_from_iter(&params),
+            |row| row.get(0),
+        )?;
+
+        params.push(SqlValue::Integer(filter.limit().into()));
+        params.push(SqlValue::Integer(filter.offset.into()));
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT s.* FROM status s {where_clause} ORDER BY {sort_column} {order}, s.video_id {order} LIMIT ?{} OFFSET ?{}",
+                params.len() - 1,
+                params.len()
+            ))?;
+        let videos = stmt
+            .query_map(params_from_iter(&params), Self::map_video_status)?
+            .collect::<Result<_, _>>()?;
+
+        Ok((total, videos))
+    }
+
+    fn get_video_ids(&self, filter: &VideoFilter) -> DbResult<Vec<String>> {
+        let (where_clause, params) = filter.where_clause(Dialect::Sqlite);
+        self.all(
+            &format!("SELECT s.video_id FROM status s {where_clause}"),
+            params_from_iter(&params),
+        )
+    }
+
+    fn get_all_ids(&self) -> DbResult<Vec<String>> {
+        self.all("SELECT video_id FROM status", [])
+    }
+
+    fn get_video_fetch_status(&self, video_id: &str) -> DbResult<Option<FetchStatus>> {
+        Ok(self
+            .single::<i64, _>(
+                "SELECT fetch_status FROM status WHERE video_id = ?1",
+                &[video_id],
+            )?
+            .and_then(|s| FetchStatus::try_from(s).ok()))
+    }
+
+    fn get_videos_in_status(&self, status: FetchStatus) -> DbResult<Vec<VideoStatus>> {
+        let conn = self.reader();
+        let mut stmt = conn.prepare("SELECT * FROM status WHERE fetch_status = ?1")?;
+        let rows = stmt.query_map([status as i64], Self::map_video_status)?;
+
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    fn get_all_unprocessed_ids(&self) -> DbResult<Vec<String>> {
+        self.all(
+            "SELECT video_id FROM status WHERE fetch_status IN (0, 1)",
+            [],
+        )
+    }
+
+    fn get_last_video_update(&self) -> DbResult<Option<u64>> {
+        self.single("SELECT MAX(last_update) FROM status", [])
+    }
+
+    fn get_video(&self, video_id: &str) -> DbResult<Option<VideoStatus>> {
+        let conn = self.reader();
+        Self::get_video_internal(&conn, video_id)
+    }
+
+    fn set_full_track_status(&self, status: &VideoStatus) -> DbResult<()> {
+        let conn = self.conn.lock().unwrap();
+        Self::set_full_track_status_internal(&conn, status)
+    }
+
+    fn set_videos_reindex(&self, video_ids: &[String]) -> DbResult<()> {
+        let conn = self.conn.lock().unwrap();
+        let tx = conn.unchecked_transaction()?;
+
+        for video_id in video_ids {
+            if conn.execute(
+                "UPDATE status SET fetch_status = 1 WHERE video_id = ?1 AND fetch_status = 4",
+                (video_id,),
+            )? > 0
+            {
+                Self::record_history(&conn, video_id)?;
+            }
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    // BRAINZ
+
+    fn try_get_brainz(&self, query: &str, fetched_after: i64) -> DbResult<Option<String>> {
+        let conn = self.reader();
+        Ok(conn
+            .query_row(
+                "SELECT data FROM brainz WHERE query = ?1 AND fetch_time >= ?2",
+                (query, fetched_after),
+                |row| row.get::<_, Option<String>>(0),
+            )
+            .optional()?
+            .flatten())
+    }
+
+    fn set_brainz(&self, query: &str, data: &str) -> DbResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn
+            .execute(
+                "INSERT INTO brainz (query, fetch_time, data) VALUES (?1, ?2, ?3) ON CONFLICT(query) DO UPDATE SET fetch_time = ?2, data = ?3",
+                (&query, Utc::now().timestamp(), &data))?;
+        Ok(())
+    }
+
+    fn delete_brainz_before(&self, fetched_before: i64) -> DbResult<u64> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn.execute("DELETE FROM brainz WHERE fetch_time < ?1", [fetched_before])? as u64)
+    }
+
+    fn evict_brainz(&self, keep: u64) -> DbResult<u64> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn.execute(
+            "DELETE FROM brainz WHERE rowid IN (SELECT rowid FROM brainz ORDER BY fetch_time DESC LIMIT -1 OFFSET ?1)",
+            [keep as i64],
+        )? as u64)
+    }
+
+    fn add_match_attempt(
+        &self,
+        video_id: &str,
+        query: &BrainzMultiSearch,
+        searches: &[BrainzSearchLog],
+        result: Option<&BrainzMetadata>,
+    ) -> DbResult<()> {
+        let conn = self.conn.lock().unwrap();
+        let tx = conn.unchecked_transaction()?;
+        tx.execute(
+            "INSERT INTO match_attempts (video_id, created, query, searches, result) VALUES (?1, ?2, ?3, ?4, ?5)",
+            (
+                video_id,
+                Utc::now().timestamp(),
+                serde_json::to_string(query)?,
+                serde_json::to_string(searches)?,
+                to_json(result)?,
+            ),
+        )?;
+        tx.execute(
+            "DELETE FROM match_attempts WHERE video_id = ?1 AND attempt_id NOT IN
+                (SELECT attempt_id FROM match_attempts WHERE video_id = ?1 ORDER BY attempt_id DESC LIMIT ?2)",
+            (video_id, MATCH_HISTORY_LEN),
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn get_match_attempts(&self, video_id: &str) -> DbResult<Vec<MatchAttempt>> {
+        let conn = self.reader();
+        let mut stmt = conn
+            .prepare("SELECT * FROM match_attempts WHERE video_id = ?1 ORDER BY attempt_id DESC")?;
+        Ok(stmt
+            .query_map([video_id], |row| {
+                Ok(MatchAttempt {
+                    attempt_id: row.get("attempt_id")?,
+                    video_id: row.get("video_id")?,
+                    created: row.get("created")?,
+                    query: json_column(row, "query")?,
+                    searches: json_column(row, "searches")?,
+                    result: json_column(row, "result")?,
+                })
+            })?
+            .filter_map(|r| r.ok())
+            .collect())
+    }
+
+    fn get_status_history(&self, video_id: &str) -> DbResult<Vec<StatusChange>> {
+        let conn = self.reader();
+        let mut stmt = conn
+            .prepare("SELECT * FROM status_history WHERE video_id = ?1 ORDER BY history_id DESC")?;
+        Ok(stmt
+            .query_map([video_id], |row| {
+                let fetch_status = row.get("fetch_status")?;
+                let last_error: Option<String> = row.get("last_error")?;
+                Ok(StatusChange {
+                    history_id: row.get("history_id")?,
+                    video_id: row.get("video_id")?,
+                    time: row.get("time")?,
+                    fetch_status,
+                    error_code: errors::classify(fetch_status, last_error.as_deref()),
+                    last_error,
+                    query: json_column(row, "query")?,
+                    result: json_column(row, "result")?,
+                    tags_unchanged: row.get("tags_unchanged")?,
+                })
+            })?
+            .collect::<Result<_, _>>()?)
+    }
+
+    // JOBS
+
+    fn get_job(&self, video_id: &str) -> DbResult<Option<Job>> {
+        self.single("SELECT * FROM jobs WHERE video_id = ?1", [video_id])
+    }
+
+    fn get_jobs(&self) -> DbResult<Vec<Job>> {
+        self.all("SELECT * FROM jobs ORDER BY updated DESC", [])
+    }
+
+    fn get_jobs_in_state(&self, state: JobState) -> DbResult<Vec<Job>> {
+        self.all(
+            "SELECT * FROM jobs WHERE state = ?1 ORDER BY created",
+            [state.as_str()],
+        )
+    }
+
+    fn get_due_retries(&self, now: i64) -> DbResult<Vec<Job>> {
+        self.all(
+            "SELECT * FROM jobs WHERE state = ?1 AND next_attempt <= ?2 ORDER BY next_attempt",
+            (JobState::Retrying.as_str(), now),
+        )
+    }
+
+    fn set_job(&self, job: &Job) -> DbResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO jobs (video_id, priority, state, attempts, next_attempt, last_error, created, updated)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+             ON CONFLICT(video_id)
+             DO UPDATE SET priority = ?2, state = ?3, attempts = ?4, next_attempt = ?5, last_error = ?6, updated = ?8",
+            (
+                &job.video_id,
+                match job.priority {
+                    Priority::Low => "Low",
+                    Priority::High => "High",
+                },
+                job.state.as_str(),
+                job.attempts,
+                job.next_attempt,
+                &job.last_error,
+                job.created,
+                job.updated,
+            ),
+        )?;
+        Ok(())
+    }
+
+    fn delete_job(&self, video_id: &str) -> DbResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM jobs WHERE video_id = ?1", [video_id])?;
+        Ok(())
+    }
+
+    fn reset_running_jobs(&self) -> DbResult<usize> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn.execute(
+            "UPDATE jobs SET state = ?1 WHERE state = ?2",
+            (JobState::Queued.as_str(), JobState::Running.as_str()),
+        )?)
+    }
+
+    fn is_video_in_any_playlist(&self, video_id: &str) -> DbResult<bool> {
+        Ok(self
+            .single::<i64, _>(
+                "SELECT EXISTS (SELECT 1 FROM playlist_items WHERE video_id = ?1)
+                 OR EXISTS (SELECT 1 FROM manual_videos WHERE video_id = ?1)",
+                [video_id],
+            )?
+            .is_some_and(|e| e != 0))
+    }
+
+    fn get_video_playlist_ids(&self, video_id: &str) -> DbResult<Vec<String>> {
+        self.all(
+            "SELECT playlist_id FROM playlist_items WHERE video_id = ?1
+             UNION SELECT playlist_id FROM manual_videos WHERE video_id = ?1",
+            [video_id],
+        )
+    }
+
+    fn get_playlist_video_ids(&self, playlist_id: &str) -> DbResult<HashSet<String>> {
+        Ok(self
+            .all::<String, _>(
+                "SELECT video_id FROM playlist_items WHERE playlist_id = ?1
+             UNION SELECT video_id FROM manual_videos WHERE playlist_id = ?1",
+                [playlist_id],
+            )?
+            .into_iter()
+            .collect())
+    }
+
+    fn get_removed_ids(&self) -> DbResult<Vec<String>> {
+        self.all("SELECT video_id FROM status WHERE removal IS NOT NULL", [])
+    }
+
+    // PENDING MOVES
+
+    fn set_pending_move(&self, pending: &PendingMove) -> DbResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO pending_moves (video_id, source, target, last_error, created) VALUES (?1, ?2, ?3, ?4, ?5)",
+            (
+                &pending.video_id,
+                pending.source.to_string_lossy(),
+                pending.target.to_string_lossy(),
+                &pending.last_error,
+                pending.created,
+            ),
+        )?;
+        Ok(())
+    }
+
+    fn get_pending_moves(&self) -> DbResult<Vec<PendingMove>> {
+        self.all("SELECT * FROM pending_moves", [])
+    }
+
+    fn delete_pending_move(&self, video_id: &str) -> DbResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM pending_moves WHERE video_id = ?1", [video_id])?;
+        Ok(())
+    }
+
+    // SOURCES
+
+    fn get_source_match(&self, provider: &str, source_id: &str) -> DbResult<Option<String>> {
+        self.single(
+            "SELECT video_id FROM source_matches WHERE provider = ?1 AND source_id = ?2",
+            [provider, source_id],
+        )
+    }
+
+    fn set_source_match(&self, provider: &str, source_id: &str, video_id: &str) -> DbResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO source_matches (provider, source_id, video_id) VALUES (?1, ?2, ?3)",
+            (provider, source_id, video_id),
+        )?;
+        Ok(())
+    }
+
+    // MANUAL VIDEOS
+
+    fn add_manual_video(
+        &self,
+        video_id: &str,
+        playlist_id: &str,
+        added_by: &str,
+        added: i64,
+    ) -> DbResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO manual_videos (video_id, playlist_id, added_by, added) VALUES (?1, ?2, ?3, ?4)",
+            (video_id, playlist_id, added_by, added),
+        )?;
+        Ok(())
+    }
+
+    // ERRORS
+
+    fn get_video_errors(&self) -> DbResult<Vec<(String, ErrorCode, String)>> {
+        self.all(
+            "SELECT video_id, error_code, last_error FROM status
+             WHERE error_code IS NOT NULL
+             ORDER BY last_update DESC",
+            [],
+        )
+    }
+
+    // STATS
+
+    fn get_status_counts(&self) -> DbResult<Vec<(FetchStatus, u64)>> {
+        Ok(self
+            .all::<(i64, u64), _>(
+                "SELECT fetch_status, COUNT(*) FROM status GROUP BY fetch_status",
+                [],
+            )?
+            .into_iter()
+            .filter_map(|(status, count)| Some((FetchStatus::try_from(status).ok()?, count)))
+            .collect())
+    }
+
+    fn get_library_totals(&self) -> DbResult<(u64, u64, u64)> {
+        let conn = self.reader();
+        Ok(conn.query_row(
+            "WITH library AS (
+                SELECT coalesce(override_result, last_result) AS result FROM status
+                WHERE fetch_status = ?1 AND coalesce(override_result, last_result) IS NOT NULL
+             )
+             SELECT
+                (SELECT COUNT(*) FROM library),
+                (SELECT COUNT(DISTINCT a.value) FROM library, json_each(library.result, '$.artist') a),
+                (SELECT COUNT(DISTINCT coalesce(json_extract(result, '$.brainz_release_id'), json_extract(result, '$.album')))
+                 FROM library)",
+            [FetchStatus::Categorized as i64],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )?)
+    }
+
+    fn get_downloads_per_day(&self, since: i64) -> DbResult<Vec<(String, u64)>> {
+        self.all(
+            "SELECT date(fetch_time, 'unixepoch') AS day, COUNT(*) FROM status
+             WHERE fetch_time >= ?1
+             GROUP BY day ORDER BY day",
+            [since],
+        )
+    }
+
+    fn get_error_counts(&self, limit: u32) -> DbResult<Vec<(ErrorCode, u64)>> {
+        self.all(
+            "SELECT error_code, COUNT(*) AS count FROM status
+             WHERE error_code IS NOT NULL
+             GROUP BY error_code ORDER BY count DESC LIMIT ?1",
+            [limit],
+        )
+    }
+
+    fn get_indexed_size(&self) -> DbResult<u64> {
+        Ok(self
+            .single("SELECT SUM(size) FROM files", [])?
+            .unwrap_or_default())
+    }
+
+    // TRASH
+
+    fn add_trashed_file(&self, trashed: &TrashedFile) -> DbResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO trash (video_id, original, path, fetch_status, deleted) VALUES (?1, ?2, ?3, ?4, ?5)",
+            (
+                &trashed.video_id,
+                trashed.original.to_string_lossy(),
+                trashed.path.to_string_lossy(),
+                trashed.fetch_status as i64,
+                trashed.deleted,
+            ),
+        )?;
+        Ok(())
+    }
+
+    fn get_trashed_file(&self, video_id: &str) -> DbResult<Option<TrashedFile>> {
+        let conn = self.reader();
+        Ok(conn
+            .query_row(
+                "SELECT * FROM trash WHERE video_id = ?1",
+                [video_id],
+                Self::map_trashed_file,
+            )
+            .optional()?)
+    }
+
+    fn get_trashed_files_before(&self, deleted: i64) -> DbResult<Vec<TrashedFile>> {
+        let conn = self.reader();
+        let mut stmt =
+            conn.prepare("SELECT * FROM trash WHERE deleted < ?1 ORDER BY deleted DESC")?;
+        let rows = stmt.query_map([deleted], Self::map_trashed_file)?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    fn delete_trashed_file(&self, video_id: &str) -> DbResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM trash WHERE video_id = ?1", [video_id])?;
+        Ok(())
+    }
+
+    // FLAGS
+
+    fn add_flag(
+        &self,
+        video_id: &str,
+        reason: FlagReason,
+        note: Option<&str>,
+        reported_by: &str,
+        created: i64,
+    ) -> DbResult<VideoFlag> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO flags (video_id, reason, note, reported_by, created) VALUES (?1, ?2, ?3, ?4, ?5)",
+            (video_id, reason.as_str(), note, reported_by, created),
+        )?;
+
+        Ok(VideoFlag {
+            flag_id: conn.last_insert_rowid(),
+            video_id: video_id.to_owned(),
+            reason,
+            note: note.map(str::to_owned),
+            reported_by: reported_by.to_owned(),
+            created,
+            resolved: None,
+        })
+    }
+
+    fn get_open_flags(&self) -> DbResult<Vec<VideoFlag>> {
+        self.all(
+            "SELECT * FROM flags WHERE resolved IS NULL ORDER BY created",
+            [],
+        )
+    }
+
+    fn resolve_flag(&self, flag_id: i64, resolved: i64) -> DbResult<bool> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn.execute(
+            "UPDATE flags SET resolved = ?2 WHERE flag_id = ?1 AND resolved IS NULL",
+            (flag_id, resolved),
+        )? > 0)
+    }
+
+    // ARTIST RULES
+
+    fn get_artist_rules(&self) -> DbResult<Vec<ArtistRule>> {
+        self.all("SELECT * FROM artist_rules ORDER BY rule_id", [])
+    }
+
+    fn add_artist_rule(&self, rule: &ArtistRuleRequest, created: i64) -> DbResult<ArtistRule> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO artist_rules (kind, pattern, replacement, created) VALUES (?1, ?2, ?3, ?4)",
+            (
+                rule.kind.as_str(),
+                &rule.pattern,
+                &rule.replacement,
+                created,
+            ),
+        )?;
+
+        Ok(ArtistRule {
+            rule_id: conn.last_insert_rowid(),
+            kind: rule.kind,
+            pattern: rule.pattern.clone(),
+            replacement: rule.replacement.clone(),
+            created,
+        })
+    }
+
+    fn update_artist_rule(
+        &self,
+        rule_id: i64,
+        rule: &ArtistRuleRequest,
+    ) -> DbResult<Option<ArtistRule>> {
+        let updated = {
+            let conn = self.conn.lock().unwrap();
+            conn.execute(
+                "UPDATE artist_rules SET kind = ?2, pattern = ?3, replacement = ?4 WHERE rule_id = ?1",
+                (rule_id, rule.kind.as_str(), &rule.pattern, &rule.replacement),
+            )?
+        };
+        if updated == 0 {
+            return Ok(None);
+        }
+        self.single("SELECT * FROM artist_rules WHERE rule_id = ?1", [rule_id])
+    }
+
+    fn delete_artist_rule(&self, rule_id: i64) -> DbResult<bool> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn.execute("DELETE FROM artist_rules WHERE rule_id = ?1", [rule_id])? > 0)
+    }
+
+    // DUPLICATES
+
+    fn find_categorized_recording(
+        &self,
+        recording_id: &str,
+        video_id: &str,
+    ) -> DbResult<Option<String>> {
+        self.single(
+            "SELECT video_id FROM status
+             WHERE fetch_status = ?1 AND video_id != ?2
+               AND json_extract(coalesce(override_result, last_result), '$.brainz_recording_id') = ?3
+             LIMIT 1",
+            (FetchStatus::Categorized as i64, video_id, recording_id),
+        )
+    }
+
+    fn add_duplicate(
+        &self,
+        video_id: &str,
+        other_video_id: Option<&str>,
+        kind: DuplicateKind,
+        path: &Path,
+        created: i64,
+    ) -> DbResult<Duplicate> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "DELETE FROM duplicates WHERE video_id = ?1 AND resolved IS NULL",
+            [video_id],
+        )?;
+        conn.execute(
+            "INSERT INTO duplicates (video_id, other_video_id, kind, path, created) VALUES (?1, ?2, ?3, ?4, ?5)",
+            (
+                video_id,
+                other_video_id,
+                kind.as_str(),
+                path.to_string_lossy(),
+                created,
+            ),
+        )?;
+
+        Ok(Duplicate {
+            duplicate_id: conn.last_insert_rowid(),
+            video_id: video_id.to_owned(),
+            other_video_id: other_video_id.map(str::to_owned),
+            kind,
+            path: path.to_owned(),
+            created,
+            resolved: None,
+            kept: None,
+        })
+    }
+
+    fn get_open_duplicates(&self) -> DbResult<Vec<Duplicate>> {
+        self.all(
+            "SELECT * FROM duplicates WHERE resolved IS NULL ORDER BY created",
+            [],
+        )
+    }
+
+    fn get_open_duplicate(&self, duplicate_id: i64) -> DbResult<Option<Duplicate>> {
+        self.single(
+            "SELECT * FROM duplicates WHERE duplicate_id = ?1 AND resolved IS NULL",
+            [duplicate_id],
+        )
+    }
+
+    fn resolve_duplicate(
+        &self,
+        duplicate_id: i64,
+        kept: DuplicateKeep,
+        resolved: i64,
+    ) -> DbResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE duplicates SET resolved = ?2, kept = ?3 WHERE duplicate_id = ?1",
+            (duplicate_id, resolved, kept.as_str()),
+        )?;
+        Ok(())
+    }
+
+    // User
+
+    fn get_user(&self, username: &str) -> DbResult<Option<UserData>> {
+        self.single(
+            "SELECT username, password, admin FROM users WHERE username = ?1",
+            [username],
+        )
+    }
+
+    fn get_users(&self) -> DbResult<Vec<User>> {
+        self.all("SELECT username, admin FROM users ORDER BY username", [])
+    }
+
+    fn has_users(&self) -> DbResult<bool> {
+        Ok(self
+            .single::<String, _>("SELECT username FROM users LIMIT 1", [])?
+            .is_some())
+    }
+
+    fn add_first_user(&self, username: &str, password_hash: &str) -> DbResult<bool> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn.execute(
+            "INSERT INTO users (username, password, admin) SELECT ?1, ?2, 1 WHERE NOT EXISTS (SELECT 1 FROM users)",
+            (username, password_hash),
+        )? > 0)
+    }
+
+    fn add_user(&self, username: &str, password_hash: &str, admin: bool) -> DbResult<bool> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn.execute(
+            "INSERT INTO users (username, password, admin) VALUES (?1, ?2, ?3) ON CONFLICT(username) DO NOTHING",
+            (username, password_hash, admin),
+        )? > 0)
+    }
+
+    fn set_password(&self, username: &str, password_hash: &str) -> DbResult<bool> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn.execute(
+            "UPDATE users SET password = ?2 WHERE username = ?1",
+            (username, password_hash),
+        )? > 0)
+    }
+
+    fn delete_user(&self, username: &str) -> DbResult<bool> {
+        let conn = self.conn.lock().unwrap();
+        let tx = conn.unchecked_transaction()?;
+        tx.execute("DELETE FROM sessions WHERE username = ?1", [username])?;
+        tx.execute("DELETE FROM api_keys WHERE username = ?1", [username])?;
+        let deleted = tx.execute("DELETE FROM users WHERE username = ?1", [username])? > 0;
+        tx.commit()?;
+        Ok(deleted)
+    }
+
+    // SESSIONS
+
+    fn add_session(&self, session: &Session, token_hash: &str) -> DbResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO sessions (session_id, username, token_hash, created, last_used, expires, client_ip, user_agent)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            (
+                &session.session_id,
+                &session.username,
+                token_hash,
+                session.created,
+                session.last_used,
+                session.expires,
+                &session.client_ip,
+                &session.user_agent,
+            ),
+        )?;
+        Ok(())
+    }
+
+    fn get_session_by_token(&self, token_hash: &str, now: i64) -> DbResult<Option<Session>> {
+        self.single(
+            "SELECT session_id, username, created, last_used, expires, client_ip, user_agent
+             FROM sessions WHERE token_hash = ?1 AND expires > ?2",
+            (token_hash, now),
+        )
+    }
+
+    fn renew_session(
+        &self,
+        session_id: &str,
+        token_hash: &str,
+        now: i64,
+        expires: i64,
+        client_ip: &str,
+    ) -> DbResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE sessions SET token_hash = ?2, last_used = ?3, expires = ?4, client_ip = ?5
+             WHERE session_id = ?1",
+            (session_id, token_hash, now, expires, client_ip),
+        )?;
+        Ok(())
+    }
+
+    fn is_session_active(&self, session_id: &str, now: i64) -> DbResult<bool> {
+        Ok(self
+            .single::<String, _>(
+                "SELECT session_id FROM sessions WHERE session_id = ?1 AND expires > ?2",
+                (session_id, now),
+            )?
+            .is_some())
+    }
+
+    fn get_sessions(&self, now: i64, username: Option<&str>) -> DbResult<Vec<Session>> {
+        self.all(
+            "SELECT session_id, username, created, last_used, expires, client_ip, user_agent
+             FROM sessions WHERE expires > ?1 AND (?2 IS NULL OR username = ?2)
+             ORDER BY last_used DESC",
+            (now, username),
+        )
+    }
+
+    fn delete_session(&self, session_id: &str, username: Option<&str>) -> DbResult<bool> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn.execute(
+            "DELETE FROM sessions WHERE session_id = ?1 AND (?2 IS NULL OR username = ?2)",
+            (session_id, username),
+        )? > 0)
+    }
+
+    fn delete_user_sessions(&self, username: &str, keep: Option<&str>) -> DbResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "DELETE FROM sessions WHERE username = ?1 AND session_id IS NOT ?2",
+            (username, keep),
+        )?;
+        Ok(())
+    }
+
+    fn delete_expired_sessions(&self, now: i64) -> DbResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM sessions WHERE expires <= ?1", [now])?;
+        Ok(())
+    }
+
+    // API KEYS
+
+    fn add_api_key(&self, key: &ApiKey, key_hash: &str) -> DbResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO api_keys (key_id, name, username, key_hash, scopes, created) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            (
+                &key.key_id,
+                &key.name,
+                &key.username,
+                key_hash,
+                serde_json::to_string(&key.scopes)?,
+                key.created,
+            ),
+        )?;
+        Ok(())
+    }
+
+    fn get_api_key_by_hash(&self, key_hash: &str) -> DbResult<Option<ApiKey>> {
+        Ok(self
+            .query_api_keys("SELECT * FROM api_keys WHERE key_hash = ?1", [key_hash])?
+            .pop())
+    }
+
+    fn get_api_keys(&self, username: Option<&str>) -> DbResult<Vec<ApiKey>> {
+        self.query_api_keys(
+            "SELECT * FROM api_keys WHERE ?1 IS NULL OR username = ?1 ORDER BY created",
+            [username],
+        )
+    }
+
+    fn touch_api_key(&self, key_id: &str, now: i64) -> DbResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE api_keys SET last_used = ?2 WHERE key_id = ?1",
+            (key_id, now),
+        )?;
+        Ok(())
+    }
+
+    fn delete_api_key(&self, key_id: &str, username: Option<&str>) -> DbResult<bool> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn.execute(
+            "DELETE FROM api_keys WHERE key_id = ?1 AND (?2 IS NULL OR username = ?2)",
+            (key_id, username),
+        )? > 0)
+    }
+
+    // AUDIT LOG
+
+    fn add_audit_entry(&self, entry: &AuditEntry) -> DbResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO audit_log (time, username, api_key, action, target, details, request_id, client_ip)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            (
+                entry.time,
+                &entry.username,
+                &entry.api_key,
+                entry.action.as_str(),
+                &entry.target,
+                entry.details.as_ref().map(|d| d.to_string()),
+                &entry.request_id,
+                &entry.client_ip,
+            ),
+        )?;
+        Ok(())
+    }
+
+    fn get_audit_page(&self, query: &AuditQuery) -> DbResult<(u64, Vec<AuditEntry>)> {
+        let (where_clause, mut params) = query.where_clause(Dialect::Sqlite);
+
+        let conn = self.reader();
+        let total: u64 = conn.query_row(
+            &format!("SELECT COUNT(*) FROM audit_log {where_clause}"),
+            params_from_iter(&params),
+            |row| row.get(0),
+        )?;
+
+        params.push(SqlValue::Integer(query.limit().into()));
+        params.push(SqlValue::Integer(query.offset.into()));
+        let mut stmt = conn.prepare(&format!(
+            "SELECT * FROM audit_log {where_clause} ORDER BY id DESC LIMIT ?{} OFFSET ?{}",
+            params.len() - 1,
+            params.len()
+        ))?;
+        let entries = stmt
+            .query_map(params_from_iter(&params), |row| {
+                Ok(AuditEntry {
+                    id: row.get("id")?,
+                    time: row.get("time")?,
+                    username: row.get("username")?,
+                    api_key: row.get("api_key")?,
+                    action: serde_json::from_value(serde_json::Value::String(row.get("action")?))
+                        .map_err(|err| {
+                        rusqlite::Error::FromSqlConversionFailure(4, Type::Text, Box::new(err))
+                    })?,
+                    target: row.get("target")?,
+                    details: row
+                        .get::<_, Option<String>>("details")?
+                        .and_then(|d| serde_json::from_str(&d).ok()),
+                    request_id: row.get("request_id")?,
+                    client_ip: row.get("client_ip")?,
+                })
+            })?
+            .filter_map(|r| r.ok())
+            .collect();
+        Ok((total, entries))
+    }
+
+    // LOGIN ATTEMPTS
+
+    fn get_login_attempts(&self) -> DbResult<Vec<LoginAttempts>> {
+        self.all(
+            "SELECT key, failures, first_failure, locked_until FROM login_attempts",
+            [],
+        )
+    }
+
+    fn set_login_attempts(&self, attempts: &LoginAttempts) -> DbResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO login_attempts (key, failures, first_failure, locked_until) VALUES (?1, ?2, ?3, ?4)",
+            (
+                &attempts.key,
+                attempts.failures,
+                attempts.first_failure,
+                attempts.locked_until,
+            ),
+        )?;
+        Ok(())
+    }
+
+    fn delete_login_attempts(&self, key: &str) -> DbResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM login_attempts WHERE key = ?1", [key])?;
+        Ok(())
+    }
+
+    fn acquire_lease(&self, key: &str, owner: &str, now: i64, ttl: i64) -> DbResult<bool> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn.execute(
+            "INSERT INTO kvp (key, value, last_update) VALUES (?1, ?2, ?3)
+             ON CONFLICT(key) DO UPDATE SET value = ?2, last_update = ?3
+             WHERE value = ?2 OR last_update < ?3 - ?4",
+            (key, owner, now, ttl),
+        )? > 0)
+    }
+
+    fn get_lease(&self, key: &str) -> DbResult<Option<(String, i64)>> {
+        let conn = self.reader();
+        Ok(conn
+            .query_row(
+                "SELECT value, last_update FROM kvp WHERE key = ?1",
+                [key],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?)
+    }
+
+    fn release_lease(&self, key: &str, owner: &str) -> DbResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "DELETE FROM kvp WHERE key = ?1 AND value = ?2",
+            (key, owner),
+        )?;
+        Ok(())
+    }
+
+    fn get_key(&self, key: &str) -> DbResult<Option<String>> {
+        self.single("SELECT value FROM kvp WHERE key = ?1", [key])
+    }
+
+    fn set_key(&self, key: &str, value: &str) -> DbResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn
+            .execute(
+                "INSERT INTO kvp (key, value, last_update) VALUES (?1, ?2, ?3) ON CONFLICT(key) DO UPDATE SET value = ?2, last_update = ?3",
+                (&key, &value, Utc::now().timestamp()))?;
+        Ok(())
+    }
+
+    // BACKUP
+
+    fn backup(&self, target: &Path) -> DbResult<()> {
+        self.reader().backup(DatabaseName::Main, target, None)?;
+        Ok(())
+    }
+
+    fn restore(&self, source: &Path) -> DbResult<()> {
+        let backup = Connection::open_with_flags(source, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        let version: u32 = backup
+            .query_row("SELECT value FROM kvp WHERE key = 'version'", [], |row| {
+                row.get::<_, String>(0)
+            })?
+            .parse()
+            .unwrap_or(0);
+        if version > DB_VERSION {
+            return Err(DbError::NewerBackup(version));
+        }
+        drop(backup);
+
+        self.conn
+            .lock()
+            .unwrap()
+            .restore(DatabaseName::Main, source, None::<fn(Progress)>)?;
+        Ok(())
+    }
+}
+
+impl FromSql for FetchStatus {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        let value = value.as_i64()?;
+        FetchStatus::try_from(value).map_err(|()| FromSqlError::OutOfRange(value))
+    }
+}
@@ -0,0 +1,162 @@
+//! Two step deletion of videos, so a single stray request, e.g. from a script, cannot delete a
+//! file.
+//!
+//! The first request only reports what would be deleted, together with a token. Passing the
+//! token back deletes the file, as long as it was not moved in between.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+
+use chrono::Utc;
+use rand::distr::{Alphanumeric, SampleString};
+
+use crate::api::{API_VERSION, DeleteConfirmation};
+
+/// How long a confirmation token can be used
+const TOKEN_TTL: Duration = Duration::from_secs(300);
+
+/// Deletions waiting for confirmation, by token
+static PENDING: LazyLock<Mutex<HashMap<String, PendingDelete>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+struct PendingDelete {
+    video_id: String,
+    path: Option<PathBuf>,
+    expires: Instant,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ConfirmError {
+    /// The token is unknown, expired or for another video
+    InvalidToken,
+    /// The file was moved or deleted since the token was issued
+    FileChanged,
+}
+
+/// Issues a token for deleting `video_id` with its file at `path`.
+pub fn request(video_id: &str, path: Option<&Path>) -> DeleteConfirmation {
+    let token = Alphanumeric.sample_string(&mut rand::rng(), 32);
+    let mut pending = PENDING.lock().unwrap();
+    let now = Instant::now();
+    pending.retain(|_, p| p.expires > now);
+    pending.insert(
+        token.clone(),
+        PendingDelete {
+            video_id: video_id.to_owned(),
+            path: path.map(Path::to_path_buf),
+            expires: now + TOKEN_TTL,
+        },
+    );
+
+    DeleteConfirmation {
+        api_version: API_VERSION,
+        video_id: video_id.to_owned(),
+        token,
+        path: path.map(Path::to_path_buf),
+        size: path.and_then(|p| p.metadata().ok()).map(|m| m.len()),
+        expires: Utc::now().timestamp() + TOKEN_TTL.as_secs() as i64,
+    }
+}
+
+/// A checked token, redeemed with [`Confirmed::consume`] once the video is deleted. Dropping it
+/// without consuming keeps the token usable, e.g. when the deletion failed.
+pub struct Confirmed {
+    token: String,
+    pending: Option<PendingDelete>,
+}
+
+impl Confirmed {
+    pub fn consume(mut self) {
+        self.pending = None;
+    }
+}
+
+impl Drop for Confirmed {
+    fn drop(&mut self) {
+        if let Some(pending) = self.pending.take() {
+            PENDING
+                .lock()
+                .unwrap()
+                .insert(std::mem::take(&mut self.token), pending);
+        }
+    }
+}
+
+/// Checks `token` for deleting `video_id`, whose file is now at `path`.
+///
+/// The token is held back until the returned [`Confirmed`] is consumed or dropped, so it cannot be
+/// used twice at the same time.
+pub fn confirm(
+    token: &str,
+    video_id: &str,
+    path: Option<&Path>,
+) -> Result<Confirmed, ConfirmError> {
+    let pending = PENDING
+        .lock()
+        .unwrap()
+        .remove(token)
+        .filter(|p| p.expires > Instant::now())
+        .ok_or(ConfirmError::InvalidToken)?;
+    let confirmed = Confirmed {
+        token: token.to_owned(),
+        pending: Some(pending),
+    };
+    let pending = confirmed.pending.as_ref().unwrap();
+    if pending.video_id != video_id {
+        return Err(ConfirmError::InvalidToken);
+    }
+    if pending.path.as_deref() != path {
+        return Err(ConfirmError::FileChanged);
+    }
+    Ok(confirmed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_are_checked_against_the_video_and_file() {
+        let path = Path::new("/music/a.mp3");
+        let token = request("a", Some(path)).token;
+
+        assert_eq!(
+            confirm(&token, "b", Some(path)).err(),
+            Some(ConfirmError::InvalidToken)
+        );
+        assert_eq!(
+            confirm(&token, "a", Some(Path::new("/music/b.mp3"))).err(),
+            Some(ConfirmError::FileChanged)
+        );
+        // A failed deletion keeps the token
+        drop(confirm(&token, "a", Some(path)).unwrap());
+        confirm(&token, "a", Some(path)).unwrap().consume();
+        assert_eq!(
+            confirm(&token, "a", Some(path)).err(),
+            Some(ConfirmError::InvalidToken)
+        );
+    }
+
+    #[test]
+    fn expired_tokens_are_rejected_and_forgotten() {
+        let token = "expired".to_owned();
+        PENDING.lock().unwrap().insert(
+            token.clone(),
+            PendingDelete {
+                video_id: "a".to_owned(),
+                path: None,
+                expires: Instant::now() - Duration::from_secs(1),
+            },
+        );
+
+        assert_eq!(
+            confirm(&token, "a", None).err(),
+            Some(ConfirmError::InvalidToken)
+        );
+        assert!(!PENDING.lock().unwrap().contains_key(&token));
+    }
+}
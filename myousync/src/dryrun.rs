@@ -0,0 +1,66 @@
+//! Plans of the tagger while it runs with `--dry-run` or `scrape.dry_run`.
+//!
+//! Videos are downloaded into the temp folder and looked up as usual, but their files are not
+//! converted, tagged or moved. What would have happened is kept here for review instead, and
+//! lost on restart.
+
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+    sync::{LazyLock, Mutex},
+};
+
+use log::info;
+use serde::Serialize;
+
+/// Plans by video id
+static PLANS: LazyLock<Mutex<BTreeMap<String, Plan>>> =
+    LazyLock::new(|| Mutex::new(BTreeMap::new()));
+
+/// What the tagger would do with the file of a video.
+#[derive(Debug, Clone, Serialize)]
+pub struct Plan {
+    pub video_id: String,
+    pub source: PathBuf,
+    /// Where the file would be moved to
+    pub target: PathBuf,
+    /// The tag fields which would be changed. Cover art is not looked up and never listed.
+    pub tag_changes: Vec<String>,
+    /// `false` if the container of the file would have to be remuxed first
+    pub taggable: bool,
+    /// Another file is at `target` already
+    pub target_taken: bool,
+    /// Unix timestamp
+    pub created: i64,
+}
+
+/// Body of `GET /dry_run`.
+#[derive(Debug, Serialize)]
+pub struct DryRun {
+    pub enabled: bool,
+    pub plans: Vec<Plan>,
+}
+
+pub fn record(plan: Plan) {
+    info!(
+        "Dry run: would move {} to {}, changing {:?}",
+        plan.source.display(),
+        plan.target.display(),
+        plan.tag_changes
+    );
+    PLANS.lock().unwrap().insert(plan.video_id.clone(), plan);
+}
+
+/// Whether `video_id` was planned already, so it is not looked up again on every pass.
+pub fn is_planned(video_id: &str) -> bool {
+    PLANS.lock().unwrap().contains_key(video_id)
+}
+
+pub fn plans() -> Vec<Plan> {
+    PLANS.lock().unwrap().values().cloned().collect()
+}
+
+/// Forgets all plans, so the tagger plans every video again.
+pub fn clear() {
+    PLANS.lock().unwrap().clear();
+}
@@ -0,0 +1,143 @@
+//! Videos which would end up as the same track in the library, either because they were matched
+//! to the same MusicBrainz recording or because their files would be moved to the same path.
+//!
+//! Instead of overwriting the file already in the library, the second video is held back until a
+//! user picks which one to keep.
+
+use std::path::{Path, PathBuf};
+
+use chrono::Utc;
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    MsState,
+    dbdata::{self, DbResult, FetchStatus},
+    musicfiles::{self, MetadataTags},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateKind {
+    /// Another categorized video has the same recording
+    Recording,
+    /// The target path is taken by the file of another video, or by a file of no video
+    Path,
+}
+
+impl DuplicateKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DuplicateKind::Recording => "recording",
+            DuplicateKind::Path => "path",
+        }
+    }
+}
+
+/// Which side of a [`Duplicate`] stays in the library.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateKeep {
+    /// The held back video replaces the existing file
+    New,
+    /// The held back video is deleted
+    Existing,
+}
+
+impl DuplicateKeep {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DuplicateKeep::New => "new",
+            DuplicateKeep::Existing => "existing",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Duplicate {
+    pub duplicate_id: i64,
+    /// The video which was held back
+    pub video_id: String,
+    /// The video already in the library, `None` for a file which belongs to no video
+    pub other_video_id: Option<String>,
+    pub kind: DuplicateKind,
+    /// Where the held back video would have been moved
+    pub path: PathBuf,
+    /// Unix timestamp
+    pub created: i64,
+    /// Unix timestamp, `None` while the conflict waits for a decision
+    pub resolved: Option<i64>,
+    pub kept: Option<DuplicateKeep>,
+}
+
+/// Body of `POST /duplicates/{duplicate}/resolve`.
+#[derive(Debug, Deserialize)]
+pub struct ResolveRequest {
+    pub keep: DuplicateKeep,
+}
+
+/// Checks whether moving the file of `video_id` from `source` to `target` would collide with
+/// another track, and records the conflict if so.
+pub fn check(
+    video_id: &str,
+    source: &Path,
+    target: &Path,
+    tags: &MetadataTags,
+) -> DbResult<Option<Duplicate>> {
+    let (kind, other_video_id) = if let Some(recording_id) = &tags.brainz.brainz_recording_id
+        && let Some(other) = dbdata::DB.find_categorized_recording(recording_id, video_id)?
+    {
+        (DuplicateKind::Recording, Some(other))
+    } else if target.exists() && target != source {
+        let owner = multitag::Tag::read_from_path_lenient(target)
+            .ok()
+            .and_then(|(t, _)| t.get_comment("youtube_id"));
+        if owner.as_deref() == Some(video_id) {
+            return Ok(None);
+        }
+        (DuplicateKind::Path, owner)
+    } else {
+        return Ok(None);
+    };
+
+    info!(
+        "Video {} is a duplicate of {} by {}",
+        video_id,
+        other_video_id.as_deref().unwrap_or("an unknown file"),
+        kind.as_str()
+    );
+    Ok(Some(dbdata::DB.add_duplicate(
+        video_id,
+        other_video_id.as_deref(),
+        kind,
+        target,
+        Utc::now().timestamp(),
+    )?))
+}
+
+/// Deletes the side of `duplicate` which is not kept and lets the tagger move the held back
+/// video into the library if it won.
+pub fn resolve(s: &MsState, duplicate: &Duplicate, keep: DuplicateKeep) -> anyhow::Result<()> {
+    match keep {
+        DuplicateKeep::New => {
+            match &duplicate.other_video_id {
+                Some(other) => {
+                    _ = crate::delete_video(s, other);
+                }
+                None if duplicate.path.exists() => {
+                    musicfiles::delete_file(&s.config.paths, &duplicate.path)?;
+                }
+                None => {}
+            }
+            MsState::push_override(&duplicate.video_id, |v| {
+                v.fetch_status = FetchStatus::Fetched;
+                true
+            })?;
+        }
+        DuplicateKeep::Existing => {
+            _ = crate::delete_video(s, &duplicate.video_id);
+        }
+    }
+    dbdata::DB.resolve_duplicate(duplicate.duplicate_id, keep, Utc::now().timestamp())?;
+    Ok(())
+}
@@ -0,0 +1,196 @@
+//! Sorts the free-text `last_error` of videos into codes, so videos which failed for the same
+//! reason can be found and handled together.
+//!
+//! The code is derived from the message and the status of the video whenever a status is saved.
+//! Messages come from yt-dlp, MusicBrainz and the file system, so matching is done on the
+//! phrases those use and falls back to a code per status.
+//!
+//! The code also decides whether a failed job is retried, see [`ErrorCode::retry`].
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::dbdata::{self, DbResult, FetchStatus};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    /// The video or its channel was deleted
+    YtUnavailable,
+    YtPrivate,
+    /// The video is not available in the country of the server
+    YtGeoBlocked,
+    /// YouTube requires a signed in account, see `scrape.cookies_file`
+    YtAgeRestricted,
+    /// YouTube refused the download for too many requests
+    YtRateLimit,
+    /// YouTube could not be reached
+    YtNetwork,
+    /// Any other failed download
+    YtFailed,
+    /// MusicBrainz knows no recording for the video
+    BrainzEmpty,
+    /// MusicBrainz could not be reached or answered with garbage
+    BrainzFailed,
+    TagWriteFailed,
+    /// The file container cannot carry tags
+    Untaggable,
+    FsPermission,
+    FsNoSpace,
+    /// The file is gone from the library
+    FileMissing,
+    /// Waits for a user to pick between two tracks
+    Duplicate,
+    Unknown,
+}
+
+impl ErrorCode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::YtUnavailable => "YT_UNAVAILABLE",
+            ErrorCode::YtPrivate => "YT_PRIVATE",
+            ErrorCode::YtGeoBlocked => "YT_GEO_BLOCKED",
+            ErrorCode::YtAgeRestricted => "YT_AGE_RESTRICTED",
+            ErrorCode::YtRateLimit => "YT_RATE_LIMIT",
+            ErrorCode::YtNetwork => "YT_NETWORK",
+            ErrorCode::YtFailed => "YT_FAILED",
+            ErrorCode::BrainzEmpty => "BRAINZ_EMPTY",
+            ErrorCode::BrainzFailed => "BRAINZ_FAILED",
+            ErrorCode::TagWriteFailed => "TAG_WRITE_FAILED",
+            ErrorCode::Untaggable => "UNTAGGABLE",
+            ErrorCode::FsPermission => "FS_PERMISSION",
+            ErrorCode::FsNoSpace => "FS_NO_SPACE",
+            ErrorCode::FileMissing => "FILE_MISSING",
+            ErrorCode::Duplicate => "DUPLICATE",
+            ErrorCode::Unknown => "UNKNOWN",
+        }
+    }
+
+    /// How a job failing with this code is retried.
+    pub fn retry(self) -> Retry {
+        match self {
+            // Nothing changes on YouTube's side by waiting, only a user can fix these
+            ErrorCode::YtUnavailable
+            | ErrorCode::YtPrivate
+            | ErrorCode::YtGeoBlocked
+            | ErrorCode::YtAgeRestricted
+            | ErrorCode::Untaggable
+            | ErrorCode::FileMissing
+            | ErrorCode::Duplicate => Retry::Never,
+            ErrorCode::YtRateLimit => Retry::Throttled,
+            _ => Retry::Backoff,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Retry {
+    /// The job fails right away and is only run again when requested explicitly
+    Never,
+    /// The job is retried with the exponential backoff of `scrape.retry_backoff`
+    Backoff,
+    /// Like [`Retry::Backoff`], but every retry waits for `scrape.retry_backoff_max`
+    Throttled,
+}
+
+/// The code of `last_error`, `None` if the video has no error.
+pub fn classify(fetch_status: FetchStatus, last_error: Option<&str>) -> Option<ErrorCode> {
+    let message = last_error?.to_lowercase();
+    let has = |phrases: &[&str]| phrases.iter().any(|p| message.contains(p));
+
+    Some(if has(&["permission denied", "os error 13"]) {
+        ErrorCode::FsPermission
+    } else if has(&["no space left", "os error 28"]) {
+        ErrorCode::FsNoSpace
+    } else if has(&["failed to write tags"]) {
+        ErrorCode::TagWriteFailed
+    } else if has(&["cannot be tagged"]) {
+        ErrorCode::Untaggable
+    } else if has(&["file missing"]) {
+        ErrorCode::FileMissing
+    } else if fetch_status == FetchStatus::Duplicate || has(&["duplicate of"]) {
+        ErrorCode::Duplicate
+    } else if fetch_status == FetchStatus::FetchError || has(&["yt-dlp"]) {
+        if has(&[
+            "http error 429",
+            "too many requests",
+            "rate-limit",
+            "rate limit",
+        ]) {
+            ErrorCode::YtRateLimit
+        } else if has(&["private video", "video is private"]) {
+            ErrorCode::YtPrivate
+        } else if has(&[
+            "not made this video available in your country",
+            "not available in your country",
+            "geo restriction",
+            "geo-restricted",
+        ]) {
+            ErrorCode::YtGeoBlocked
+        } else if has(&[
+            "sign in to confirm your age",
+            "age-restricted",
+            "age restricted",
+            "inappropriate for some users",
+        ]) {
+            ErrorCode::YtAgeRestricted
+        } else if has(&[
+            "video unavailable",
+            "has been removed",
+            "is not available",
+            "account associated with this video has been terminated",
+        ]) {
+            ErrorCode::YtUnavailable
+        } else if has(&[
+            "unable to download webpage",
+            "connection refused",
+            "connection reset",
+            "timed out",
+            "name or service not known",
+            "temporary failure in name resolution",
+            "network is unreachable",
+        ]) {
+            ErrorCode::YtNetwork
+        } else {
+            ErrorCode::YtFailed
+        }
+    } else if fetch_status == FetchStatus::BrainzError {
+        if has(&["no results found"]) {
+            ErrorCode::BrainzEmpty
+        } else {
+            ErrorCode::BrainzFailed
+        }
+    } else {
+        ErrorCode::Unknown
+    })
+}
+
+/// The videos failing with one code, as returned by `GET /errors/summary`.
+#[derive(Debug, Serialize)]
+pub struct ErrorGroup {
+    pub code: ErrorCode,
+    pub count: usize,
+    /// The error of the most recently updated video, as an example
+    pub latest_error: String,
+    /// Most recently updated first
+    pub video_ids: Vec<String>,
+}
+
+/// Groups all videos with an error by its code, the largest group first.
+pub fn summary() -> DbResult<Vec<ErrorGroup>> {
+    let mut groups = BTreeMap::<ErrorCode, ErrorGroup>::new();
+    for (video_id, code, last_error) in dbdata::DB.get_video_errors()? {
+        let group = groups.entry(code).or_insert_with(|| ErrorGroup {
+            code,
+            count: 0,
+            latest_error: last_error,
+            video_ids: Vec::new(),
+        });
+        group.count += 1;
+        group.video_ids.push(video_id);
+    }
+    let mut groups: Vec<_> = groups.into_values().collect();
+    groups.sort_by_key(|g| std::cmp::Reverse(g.count));
+    Ok(groups)
+}
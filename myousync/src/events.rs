@@ -0,0 +1,462 @@
+//! Update notifications of the `/ws` websocket and of `GET /events`, which streams the same
+//! messages as server-sent events for setups where websockets do not get through a proxy.
+//!
+//! Every notification gets an id, and the most recent ones are kept, so a reconnecting event
+//! stream continues after the `Last-Event-ID` it received last. Ids of another run of the process
+//! or ones which were dropped already are answered with all videos again, like a new client.
+//!
+//! Websocket clients send their access token first and a renewed one before it expires, the
+//! connection is closed when it expires or its session is revoked. They can subscribe to only a
+//! playlist or only failed videos, see [`Subscription`]. Event streams end the same way, or when
+//! their api key is revoked.
+
+use std::{
+    collections::{HashSet, VecDeque},
+    convert::Infallible,
+    sync::{Arc, LazyLock, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    extract::{
+        Query,
+        ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade},
+    },
+    http::{HeaderMap, Method, StatusCode, header},
+    response::{
+        IntoResponse,
+        sse::{Event, KeepAlive, Sse},
+    },
+};
+use chrono::Utc;
+use futures_util::{Stream, StreamExt, stream};
+use log::{debug, error, warn};
+use rand::distr::{Alphanumeric, SampleString};
+use serde::Deserialize;
+use tokio::{
+    sync::broadcast::error::RecvError,
+    time::{Instant, interval_at, sleep_until},
+};
+
+use crate::{
+    NOTIFY_MUSIC_UPDATE,
+    api::{WsKind, WsMessage},
+    apikeys,
+    auth::{self, Claims},
+    dbdata::{self, DbResult, VideoStatus},
+    ytdlp,
+};
+
+/// Notifications kept for resuming event streams
+const BACKLOG_SIZE: usize = 1000;
+/// How often websockets and event streams check that their session or api key was not revoked
+const SESSION_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// Close code of websockets whose token expired or was rejected
+const CLOSE_UNAUTHORIZED: u16 = 4001;
+/// Close code of websockets whose videos could not be loaded, the client reconnects later
+const CLOSE_INTERNAL_ERROR: u16 = 1011;
+
+/// Identifies this run of the process in event ids.
+static RUN: LazyLock<String> = LazyLock::new(|| Alphanumeric.sample_string(&mut rand::rng(), 8));
+static BACKLOG: Mutex<Backlog> = Mutex::new(Backlog {
+    next_seq: 1,
+    notifications: VecDeque::new(),
+});
+
+struct Backlog {
+    next_seq: u64,
+    notifications: VecDeque<Notification>,
+}
+
+/// A serialized [`WsMessage`] of the kind [`WsKind::Update`].
+#[derive(Debug, Clone)]
+pub struct Notification {
+    pub seq: u64,
+    pub message: Arc<str>,
+    pub topic: Arc<Topic>,
+}
+
+/// What a notification is about, to match it against a [`Subscription`].
+#[derive(Debug)]
+pub struct Topic {
+    pub video_id: String,
+    pub playlists: Vec<String>,
+    pub error: bool,
+}
+
+/// Limits the videos a websocket client is sent, all of them if nothing is set.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Subscription {
+    /// Only videos of this playlist
+    #[serde(default)]
+    pub playlist: Option<String>,
+    /// Only videos which failed
+    #[serde(default)]
+    pub errors_only: bool,
+}
+
+impl Subscription {
+    fn matches(&self, topic: &Topic) -> bool {
+        (!self.errors_only || topic.error)
+            && self
+                .playlist
+                .as_ref()
+                .is_none_or(|playlist| topic.playlists.contains(playlist))
+    }
+}
+
+/// The [`Subscription`] of a client and the videos it was sent.
+struct Subscriber {
+    subscription: Subscription,
+    /// Videos which stopped matching are sent once more, so the client does not keep an
+    /// outdated state of them
+    sent: HashSet<String>,
+}
+
+impl Subscriber {
+    fn new(subscription: Subscription) -> Self {
+        Subscriber {
+            subscription,
+            sent: HashSet::new(),
+        }
+    }
+
+    /// A [`WsKind::Init`] message with the subscribed videos.
+    fn init_message(&mut self) -> DbResult<String> {
+        let playlist_ids = self
+            .subscription
+            .playlist
+            .as_ref()
+            .map(|playlist| dbdata::DB.get_playlist_video_ids(playlist))
+            .transpose()?;
+        let mut videos = dbdata::DB.get_all_videos()?;
+        videos.retain(|video| {
+            (!self.subscription.errors_only || video.error_code().is_some())
+                && playlist_ids
+                    .as_ref()
+                    .is_none_or(|ids| ids.contains(&video.video_id))
+        });
+        self.sent = videos.iter().map(|v| v.video_id.clone()).collect();
+        Ok(videos_message(&mut videos))
+    }
+
+    fn wants(&mut self, topic: &Topic) -> bool {
+        if self.subscription.matches(topic) {
+            self.sent.insert(topic.video_id.clone());
+            true
+        } else {
+            self.sent.remove(&topic.video_id)
+        }
+    }
+}
+
+impl Notification {
+    fn event(&self) -> Event {
+        Event::default()
+            .id(format!("{}-{}", *RUN, self.seq))
+            .data(&*self.message)
+    }
+}
+
+/// Sends an update of `status` to all connected clients.
+pub fn publish_update(status: &VideoStatus) {
+    let message = serde_json::to_string(&WsMessage::new(WsKind::Update, [status])).unwrap();
+    let topic = Topic {
+        video_id: status.video_id.clone(),
+        playlists: dbdata::DB
+            .get_video_playlist_ids(&status.video_id)
+            .unwrap_or_else(|err| {
+                error!("Failed to get playlists of {}: {}", status.video_id, err);
+                Default::default()
+            }),
+        error: status.error_code().is_some(),
+    };
+
+    let mut backlog = BACKLOG.lock().unwrap();
+    let notification = Notification {
+        seq: backlog.next_seq,
+        message: message.into(),
+        topic: Arc::new(topic),
+    };
+    backlog.next_seq += 1;
+    if backlog.notifications.len() == BACKLOG_SIZE {
+        backlog.notifications.pop_front();
+    }
+    backlog.notifications.push_back(notification.clone());
+    // Sent under the lock, so subscribers see the notifications in order of their ids
+    _ = NOTIFY_MUSIC_UPDATE.send(notification);
+}
+
+/// A [`WsKind::Init`] message with all videos.
+pub fn init_message() -> DbResult<String> {
+    Ok(videos_message(&mut dbdata::DB.get_all_videos()?))
+}
+
+fn videos_message(videos: &mut [VideoStatus]) -> String {
+    for video in videos.iter_mut() {
+        video.download_progress = ytdlp::get_progress(&video.video_id);
+    }
+    serde_json::to_string(&WsMessage::new(WsKind::Init, &*videos)).unwrap()
+}
+
+/// Sent by websocket clients as JSON. A message which is not JSON is taken as token.
+#[derive(Debug, Default, Deserialize)]
+struct WsRequest {
+    /// The access token, first to sign in and then renewed before it expires
+    #[serde(default)]
+    token: Option<String>,
+    /// Replaces the subscription and answers with a new [`WsKind::Init`] message
+    #[serde(default)]
+    subscribe: Option<Subscription>,
+}
+
+impl WsRequest {
+    fn parse(text: &str) -> Self {
+        let text = text.trim();
+        if !text.starts_with('{') {
+            return WsRequest {
+                token: Some(text.to_owned()),
+                subscribe: None,
+            };
+        }
+        serde_json::from_str(text).unwrap_or_else(|err| {
+            debug!("Invalid websocket message: {}", err);
+            WsRequest::default()
+        })
+    }
+}
+
+/// `GET /ws`
+pub async fn ws_handler(ws: WebSocketUpgrade) -> impl IntoResponse {
+    ws.on_upgrade(serve_ws)
+}
+
+async fn serve_ws(mut socket: WebSocket) {
+    let request = match socket.recv().await {
+        Some(Ok(Message::Text(text))) => WsRequest::parse(&text),
+        _ => return,
+    };
+    let Some(mut claims) = request.token.and_then(|token| auth::verify(&token).ok()) else {
+        _ = socket.send(Message::Text("Unauthorized".into())).await;
+        return;
+    };
+    if check_access(&claims).await.is_some() {
+        _ = socket.send(Message::Text("Unauthorized".into())).await;
+        return;
+    }
+
+    let mut subscriber = Subscriber::new(request.subscribe.unwrap_or_default());
+    let mut rx = NOTIFY_MUSIC_UPDATE.subscribe();
+    let init = match subscriber.init_message() {
+        Ok(init) => init,
+        Err(err) => {
+            error!("Failed to load videos of websocket: {}", err);
+            close(&mut socket, CLOSE_INTERNAL_ERROR, "Database error").await;
+            return;
+        }
+    };
+    if let Err(err) = socket.send(Message::Text(init.into())).await {
+        debug!("Error sending init message: {:?}", err);
+        return;
+    }
+
+    let mut session_check = interval_at(
+        Instant::now() + SESSION_CHECK_INTERVAL,
+        SESSION_CHECK_INTERVAL,
+    );
+    let close_reason = loop {
+        tokio::select! {
+            notification = rx.recv() => {
+                let notification = match notification {
+                    Ok(notification) => notification,
+                    Err(err) => {
+                        warn!("Error receiving message: {:?}", err);
+                        break None;
+                    }
+                };
+                if subscriber.wants(&notification.topic)
+                    && let Err(err) = socket
+                        .send(Message::Text((*notification.message).into()))
+                        .await
+                {
+                    debug!("Error sending message: {:?}", err);
+                    break None;
+                }
+            }
+            message = socket.recv() => {
+                let text = match message {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break None,
+                    Some(Ok(_)) => continue,
+                };
+                let request = WsRequest::parse(&text);
+                if let Some(token) = request.token {
+                    match auth::verify(&token) {
+                        Ok(renewed) if renewed.user == claims.user => claims = renewed,
+                        _ => break Some((CLOSE_UNAUTHORIZED, "Unauthorized")),
+                    }
+                }
+                if let Some(subscription) = request.subscribe {
+                    subscriber = Subscriber::new(subscription);
+                    let init = match subscriber.init_message() {
+                        Ok(init) => init,
+                        Err(err) => {
+                            error!("Failed to load videos of websocket: {}", err);
+                            break Some((CLOSE_INTERNAL_ERROR, "Database error"));
+                        }
+                    };
+                    if let Err(err) = socket.send(Message::Text(init.into())).await {
+                        debug!("Error sending init message: {:?}", err);
+                        break None;
+                    }
+                }
+            }
+            _ = sleep_until(expiry(&claims)) => break Some((CLOSE_UNAUTHORIZED, "Token expired")),
+            _ = session_check.tick() => {
+                if let Some(reason) = check_access(&claims).await {
+                    break Some((CLOSE_UNAUTHORIZED, reason));
+                }
+            }
+        }
+    };
+
+    if let Some((code, reason)) = close_reason {
+        debug!("Closing websocket of {}: {}", claims.user, reason);
+        close(&mut socket, code, reason).await;
+    }
+    debug!("Client disconnected");
+}
+
+async fn close(socket: &mut WebSocket, code: u16, reason: &'static str) {
+    _ = socket
+        .send(Message::Close(Some(CloseFrame {
+            code,
+            reason: reason.into(),
+        })))
+        .await;
+}
+
+/// Why the access of `claims` ended, if it did.
+async fn check_access(claims: &Claims) -> Option<&'static str> {
+    let checked = claims.clone();
+    // Checked again on the next tick if the database is unavailable
+    match dbdata::blocking(move |_| auth::is_active(&checked)).await {
+        Ok(true) => None,
+        Ok(false) => Some("Session was revoked"),
+        Err(err) => {
+            warn!("Failed to check the access of {}: {}", claims.user, err);
+            None
+        }
+    }
+}
+
+/// When the access token of `claims` expires.
+fn expiry(claims: &Claims) -> Instant {
+    let left = (claims.exp as i64 - Utc::now().timestamp()).max(0);
+    Instant::now() + Duration::from_secs(left as u64)
+}
+
+#[derive(Deserialize)]
+pub struct EventsQuery {
+    /// For clients which cannot set the `Authorization` or `X-Api-Key` header, like `EventSource`
+    token: Option<String>,
+}
+
+/// `GET /events`
+pub async fn sse_handler(
+    headers: HeaderMap,
+    Query(query): Query<EventsQuery>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, (StatusCode, String)> {
+    let claims = if let Some(key) = headers.get(apikeys::API_KEY) {
+        let key = key.to_str().unwrap_or_default();
+        apikeys::verify(key, &Method::GET).map_err(|err| (err.status_code, err.message))?
+    } else {
+        let token = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.split_whitespace().nth(1))
+            .or(query.token.as_deref())
+            .ok_or((StatusCode::FORBIDDEN, "Missing token".to_string()))?;
+        auth::verify(token).map_err(|err| (err.status_code, err.message))?
+    };
+    if check_access(&claims).await.is_some() {
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            "You are not an authorized user".to_string(),
+        ));
+    }
+
+    let last_seq = headers
+        .get("last-event-id")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|id| {
+            id.strip_prefix(RUN.as_str())?
+                .strip_prefix('-')?
+                .parse()
+                .ok()
+        });
+
+    let (rx, resumed, latest) = {
+        let backlog = BACKLOG.lock().unwrap();
+        // Subscribed under the lock, so nothing is missed between the backlog and the channel
+        let rx = NOTIFY_MUSIC_UPDATE.subscribe();
+        let oldest = backlog
+            .notifications
+            .front()
+            .map_or(backlog.next_seq, |n| n.seq);
+        let resumed = last_seq
+            .filter(|&seq| seq + 1 >= oldest && seq < backlog.next_seq)
+            .map(|seq| {
+                debug!("Resuming event stream after {}", seq);
+                backlog
+                    .notifications
+                    .iter()
+                    .filter(|n| n.seq > seq)
+                    .map(Notification::event)
+                    .collect::<Vec<_>>()
+            });
+        (rx, resumed, backlog.next_seq - 1)
+    };
+    let first = match resumed {
+        Some(resumed) => resumed,
+        None => {
+            // Includes the updates up to `latest`, so a stream resumed after it misses nothing.
+            // Later updates may be included too and are sent again, which clients handle fine.
+            let init = Event::default().data(init_message()?);
+            vec![match latest {
+                0 => init,
+                seq => init.id(format!("{}-{}", *RUN, seq)),
+            }]
+        }
+    };
+
+    let updates = stream::unfold(rx, |mut rx| async move {
+        match rx.recv().await {
+            Ok(notification) => Some((notification.event(), rx)),
+            // The client reconnects and resumes from the backlog
+            Err(RecvError::Lagged(_)) | Err(RecvError::Closed) => None,
+        }
+    });
+    let ended = async move {
+        let mut session_check = interval_at(
+            Instant::now() + SESSION_CHECK_INTERVAL,
+            SESSION_CHECK_INTERVAL,
+        );
+        let reason = loop {
+            tokio::select! {
+                // Api keys do not expire
+                _ = sleep_until(expiry(&claims)), if claims.api_key.is_none() => {
+                    break "Token expired";
+                }
+                _ = session_check.tick() => {
+                    if let Some(reason) = check_access(&claims).await {
+                        break reason;
+                    }
+                }
+            }
+        };
+        debug!("Ending event stream of {}: {}", claims.user, reason);
+    };
+    let events = stream::iter(first).chain(updates).take_until(ended).map(Ok);
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
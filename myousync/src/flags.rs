@@ -0,0 +1,117 @@
+//! Tracks reported as badly matched by users, collected in a review queue.
+
+use chrono::Utc;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    MsState,
+    dbdata::{self, DbResult},
+    net::CLIENT,
+    proxy::ClientInfo,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FlagReason {
+    /// The file is tagged as a different song
+    WrongTrack,
+    WrongArtist,
+    WrongAlbum,
+    /// The download is not the song, e.g. a live version or a video with talking
+    BadAudio,
+    Duplicate,
+    Other,
+}
+
+impl FlagReason {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FlagReason::WrongTrack => "wrong_track",
+            FlagReason::WrongArtist => "wrong_artist",
+            FlagReason::WrongAlbum => "wrong_album",
+            FlagReason::BadAudio => "bad_audio",
+            FlagReason::Duplicate => "duplicate",
+            FlagReason::Other => "other",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FlagRequest {
+    pub reason: FlagReason,
+    #[serde(default)]
+    pub note: Option<String>,
+}
+
+/// A report of a badly matched track.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct VideoFlag {
+    pub flag_id: i64,
+    pub video_id: String,
+    pub reason: FlagReason,
+    pub note: Option<String>,
+    pub reported_by: String,
+    /// Unix timestamp
+    pub created: i64,
+    /// Unix timestamp, `None` while the flag is in the review queue
+    pub resolved: Option<i64>,
+}
+
+/// Adds a flag for `video_id` to the review queue and notifies the configured webhook.
+pub async fn flag_video(
+    s: &MsState,
+    client: &ClientInfo,
+    video_id: &str,
+    request: FlagRequest,
+    reported_by: &str,
+) -> DbResult<VideoFlag> {
+    let note = request
+        .note
+        .map(|n| n.trim().to_owned())
+        .filter(|n| !n.is_empty());
+    let flag = dbdata::DB.add_flag(
+        video_id,
+        request.reason,
+        note.as_deref(),
+        reported_by,
+        Utc::now().timestamp(),
+    )?;
+    info!(
+        "Video {} flagged as {} by {}",
+        video_id,
+        flag.reason.as_str(),
+        reported_by
+    );
+
+    if let Some(webhook) = &s.config.web.flag_webhook {
+        let preview_url = client.url(&s.config.web, &format!("/video/{video_id}/preview"));
+        notify(webhook, &flag, &preview_url).await;
+    }
+    Ok(flag)
+}
+
+async fn notify(webhook: &str, flag: &VideoFlag, preview_url: &str) {
+    let video = dbdata::DB.get_video(&flag.video_id).unwrap_or_else(|err| {
+        warn!("Failed to get video {}: {}", flag.video_id, err);
+        None
+    });
+    let title = video
+        .and_then(|v| v.override_result.or(v.last_result))
+        .map(|r| r.title);
+    let body = json!({
+        "event": "video_flagged",
+        "title": title,
+        "preview_url": preview_url,
+        "flag": flag,
+    });
+
+    match CLIENT.post(webhook).json(&body).send().await {
+        Ok(res) if !res.status().is_success() => {
+            warn!("Flag webhook returned {}", res.status());
+        }
+        Ok(_) => {}
+        Err(err) => warn!("Failed to call flag webhook: {}", err),
+    }
+}
@@ -0,0 +1,464 @@
+//! Adoption of libraries downloaded with other tools, run as
+//! `myousync import-from <tubesync|ytdl-sub> <path> [options]`.
+//!
+//! Files are bound to their video by writing the `youtube_id` comment and their existing tags are
+//! trusted, so nothing is downloaded or looked up again. Files outside the music folder are copied
+//! into it, the library of the other tool stays intact unless `--move` is given.
+//!
+//! Files which are already in the music folder are adopted by [`adopt_library`] instead, which
+//! runs once on the first start and on demand.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::LazyLock,
+};
+
+use anyhow::{Context, anyhow, bail};
+use chrono::Utc;
+use log::{debug, error, info, warn};
+use regex::Regex;
+use rusqlite::{Connection, OpenFlags};
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+use crate::{
+    MsState,
+    brainz::BrainzMetadata,
+    dbdata::{self, DbResult, FetchStatus, VideoStatus},
+    musicfiles::{self, MetadataTags},
+};
+
+const USAGE: &str = "Usage: myousync import-from <tubesync|ytdl-sub> <path> [--root <dir>] [--relayout] [--move] [--config <file>]
+
+  tubesync   <path> is the TubeSync sqlite database, --root its download folder
+  ytdl-sub   <path> is an output folder of ytdl-sub
+  --relayout places the files by the artist/album/title layout of myousync
+  --move     moves files from outside the music folder instead of copying them";
+
+/// yt-dlp puts the id in brackets into its default file names
+static ID_IN_NAME: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\[([A-Za-z0-9_-]{11})\]").unwrap());
+
+#[derive(Debug, Clone, Copy)]
+enum ImportSource {
+    Tubesync,
+    YtdlSub,
+}
+
+impl FromStr for ImportSource {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tubesync" => Ok(ImportSource::Tubesync),
+            "ytdl-sub" => Ok(ImportSource::YtdlSub),
+            _ => Err(anyhow!("Unknown import source '{s}'\n\n{USAGE}")),
+        }
+    }
+}
+
+struct ImportOptions {
+    source: ImportSource,
+    path: PathBuf,
+    root: Option<PathBuf>,
+    relayout: bool,
+    move_files: bool,
+}
+
+#[derive(Debug, Default)]
+struct ImportReport {
+    imported: usize,
+    known: usize,
+    missing: usize,
+    failed: usize,
+}
+
+/// A file of the other tool with the video it belongs to.
+struct ImportEntry {
+    video_id: String,
+    path: PathBuf,
+}
+
+/// Parses the arguments after `import-from` and runs the import.
+pub fn cli(mut args: impl Iterator<Item = String>) -> anyhow::Result<()> {
+    let source: ImportSource = args.next().ok_or_else(|| anyhow!(USAGE))?.parse()?;
+    let path = PathBuf::from(args.next().ok_or_else(|| anyhow!(USAGE))?);
+    let mut options = ImportOptions {
+        source,
+        path,
+        root: None,
+        relayout: false,
+        move_files: false,
+    };
+    let mut config = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--root" => options.root = Some(args.next().ok_or_else(|| anyhow!(USAGE))?.into()),
+            "--config" => config = Some(args.next().ok_or_else(|| anyhow!(USAGE))?),
+            "--relayout" => options.relayout = true,
+            "--move" => options.move_files = true,
+            _ => bail!("Unknown option '{arg}'\n\n{USAGE}"),
+        }
+    }
+
+    let s = MsState::new(&crate::config_path(config));
+    let report = run(&s, &options)?;
+    info!(
+        "Imported {} files, {} were already known, {} missing, {} failed",
+        report.imported, report.known, report.missing, report.failed
+    );
+    Ok(())
+}
+
+fn run(s: &MsState, options: &ImportOptions) -> anyhow::Result<ImportReport> {
+    let (entries, root) = match options.source {
+        ImportSource::Tubesync => {
+            let root = options
+                .root
+                .clone()
+                .or_else(|| options.path.parent().map(Path::to_path_buf))
+                .unwrap_or_default();
+            (tubesync_entries(&options.path, &root)?, root)
+        }
+        ImportSource::YtdlSub => (ytdl_sub_entries(&options.path), options.path.clone()),
+    };
+    info!("Found {} downloads to import", entries.len());
+
+    let mut report = ImportReport::default();
+    for entry in entries {
+        if dbdata::DB.get_video(&entry.video_id)?.is_some() {
+            debug!("Video {} is already known, skipping", entry.video_id);
+            report.known += 1;
+            continue;
+        }
+        if !entry.path.is_file() {
+            warn!(
+                "File of {} not found at {}",
+                entry.video_id,
+                entry.path.display()
+            );
+            report.missing += 1;
+            continue;
+        }
+        match import_file(s, options, &root, &entry) {
+            Ok(()) => report.imported += 1,
+            Err(err) => {
+                warn!("Failed to import {}: {:#}", entry.path.display(), err);
+                report.failed += 1;
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Reads the downloaded media of a TubeSync database. Media paths are relative to `root`.
+fn tubesync_entries(db: &Path, root: &Path) -> anyhow::Result<Vec<ImportEntry>> {
+    let conn = Connection::open_with_flags(db, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .with_context(|| format!("Opening {}", db.display()))?;
+    let mut stmt = conn.prepare(
+        "SELECT key, media_file FROM sync_media
+         WHERE downloaded = 1 AND media_file IS NOT NULL AND media_file != ''",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+    })?;
+
+    let mut entries = Vec::new();
+    for row in rows {
+        let (video_id, media_file) = row?;
+        entries.push(ImportEntry {
+            video_id,
+            path: root.join(media_file),
+        });
+    }
+    Ok(entries)
+}
+
+#[derive(Debug, Deserialize)]
+struct YtdlSubArchiveEntry {
+    #[serde(default)]
+    file_names: Vec<String>,
+}
+
+/// Finds the downloads in an output folder of ytdl-sub.
+///
+/// The download archives of its subscriptions map the ids to their files. Audio files which are
+/// not in any archive are matched by the `[id]` in their name.
+fn ytdl_sub_entries(dir: &Path) -> Vec<ImportEntry> {
+    let mut entries: HashMap<PathBuf, String> = HashMap::new();
+    let files: Vec<PathBuf> = WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.into_path())
+        .collect();
+
+    for archive in files.iter().filter(|p| {
+        p.file_name()
+            .and_then(|n| n.to_str())
+            .is_some_and(|n| n.starts_with(".ytdl-sub-") && n.ends_with("-download-archive.json"))
+    }) {
+        let parsed = std::fs::read_to_string(archive)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| {
+                Ok(serde_json::from_str::<HashMap<String, YtdlSubArchiveEntry>>(&json)?)
+            });
+        let parsed = match parsed {
+            Ok(parsed) => parsed,
+            Err(err) => {
+                warn!("Unreadable archive {}: {}", archive.display(), err);
+                continue;
+            }
+        };
+        let base = archive.parent().unwrap_or(dir);
+        for (key, entry) in parsed {
+            let Some(("youtube", video_id)) = key.split_once(' ') else {
+                debug!("Skipping archive entry {}", key);
+                continue;
+            };
+            for file in entry.file_names {
+                let path = base.join(file);
+                if is_audio(&path) {
+                    entries.insert(path, video_id.to_owned());
+                }
+            }
+        }
+    }
+
+    for path in files {
+        if entries.contains_key(&path) || !is_audio(&path) {
+            continue;
+        }
+        let name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default();
+        if let Some(id) = ID_IN_NAME.captures(name) {
+            let video_id = id[1].to_owned();
+            entries.insert(path, video_id);
+        }
+    }
+
+    entries
+        .into_iter()
+        .map(|(path, video_id)| ImportEntry { video_id, path })
+        .collect()
+}
+
+fn is_audio(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(multitag::Tag::supports_extension)
+}
+
+/// The metadata of a file by its existing tags, falling back to the file name for the title.
+fn read_metadata(tag: &multitag::Tag, path: &Path, video_id: &str) -> BrainzMetadata {
+    let stem = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or(video_id);
+    let title = tag
+        .title()
+        .map(str::to_owned)
+        .unwrap_or_else(|| ID_IN_NAME.replace(stem, "").trim().to_owned());
+    let album = tag.get_album_info();
+    BrainzMetadata {
+        brainz_recording_id: musicfiles::read_recording_id(tag),
+        brainz_release_id: None,
+        brainz_artist_ids: Vec::new(),
+        title,
+        artist: tag
+            .artist()
+            .map(|a| a.split("; ").map(str::to_owned).collect())
+            .unwrap_or_default(),
+        album: album.as_ref().and_then(|a| a.title.clone()),
+        album_artist: album.and_then(|a| a.artist),
+        release_form: None,
+        date: None,
+        genres: Vec::new(),
+    }
+}
+
+fn import_file(
+    s: &MsState,
+    options: &ImportOptions,
+    root: &Path,
+    entry: &ImportEntry,
+) -> anyhow::Result<()> {
+    let (tag, _) =
+        multitag::Tag::read_from_path_lenient(&entry.path).context("When reading audiotags")?;
+    let tags = MetadataTags {
+        youtube_id: entry.video_id.clone(),
+        brainz: read_metadata(&tag, &entry.path, &entry.video_id),
+        replaygain: None,
+        cover: None,
+        date_and_genres: false,
+        custom_fields: s.config.custom_fields(&entry.video_id),
+    };
+
+    let music = &s.config.paths.music;
+    let target = if options.relayout {
+        musicfiles::library_path(s, &entry.path, &tags)
+    } else if entry.path.starts_with(music) {
+        entry.path.clone()
+    } else {
+        let relative = entry
+            .path
+            .strip_prefix(root)
+            .unwrap_or(Path::new(entry.path.file_name().unwrap_or_default()));
+        music.join(relative)
+    };
+
+    if target != entry.path {
+        if target.exists() {
+            bail!("{} already exists", target.display());
+        }
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        if entry.path.starts_with(music) {
+            musicfiles::move_file(&s.config.paths, &entry.path, &target)?;
+        } else if options.move_files {
+            if std::fs::rename(&entry.path, &target).is_err() {
+                std::fs::copy(&entry.path, &target)?;
+                std::fs::remove_file(&entry.path)?;
+            }
+        } else {
+            std::fs::copy(&entry.path, &target)?;
+        }
+    }
+    musicfiles::assign_video_id(s, &target, &entry.video_id)?;
+
+    MsState::push_update_state(
+        &mut VideoStatus {
+            video_id: entry.video_id.clone(),
+            fetch_time: Utc::now().timestamp() as u64,
+            last_result: Some(tags.brainz),
+            ..Default::default()
+        },
+        FetchStatus::Categorized,
+    )?;
+    info!("Imported {} as {}", target.display(), entry.video_id);
+    Ok(())
+}
+
+/// Marks the library as adopted in the key-value store, so it is only scanned once by itself.
+const ADOPTED_KEY: &str = "library_adopted";
+
+#[derive(Debug, Default, Serialize)]
+pub struct AdoptReport {
+    /// Files with a `youtube_id` of a video which was not known yet
+    pub adopted: Vec<String>,
+    /// Files without `youtube_id` bound to a known video by their recording id
+    pub bound: Vec<String>,
+    /// Files of videos which were already categorized
+    pub known: usize,
+}
+
+/// Adopts the library on the first start of myousync.
+///
+/// A failed adoption is not marked as done and runs again on the next start.
+pub fn adopt_library_once(s: &MsState) {
+    let adopted = match dbdata::DB.get_key(ADOPTED_KEY) {
+        Ok(adopted) => adopted,
+        Err(err) => {
+            error!("Failed to check whether the library was adopted: {}", err);
+            return;
+        }
+    };
+    if adopted.is_none()
+        && let Err(err) = adopt_library(s)
+    {
+        error!("Failed to adopt the library: {}", err);
+    }
+}
+
+/// Walks the music folder and creates categorized videos for the files in it, so a library which
+/// was built before does not have to be downloaded again.
+///
+/// Files carrying a `youtube_id` become videos with the metadata of their tags. Files with only a
+/// MusicBrainz recording id are bound to the one known video with that recording which has no
+/// file yet.
+pub fn adopt_library(s: &MsState) -> DbResult<AdoptReport> {
+    let mut report = AdoptReport::default();
+    let videos = dbdata::DB.get_all_videos()?;
+    let mut unbound: Vec<&VideoStatus> = videos
+        .iter()
+        .filter(|v| {
+            v.fetch_status != FetchStatus::Categorized && v.fetch_status != FetchStatus::Disabled
+        })
+        .collect();
+
+    for path in WalkDir::new(&s.config.paths.music)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.into_path())
+        .filter(|p| is_audio(p))
+    {
+        let Ok((tag, _)) = multitag::Tag::read_from_path_lenient(&path) else {
+            continue;
+        };
+
+        if let Some(video_id) = tag.get_comment("youtube_id") {
+            let status = videos.iter().find(|v| v.video_id == video_id);
+            if status.is_some_and(|v| v.fetch_status == FetchStatus::Categorized) {
+                report.known += 1;
+                continue;
+            }
+            // Videos known from a playlist are taken as well, their file is already there
+            let mut status = dbdata::DB
+                .get_video(&video_id)?
+                .unwrap_or_else(|| VideoStatus {
+                    video_id: video_id.clone(),
+                    fetch_time: Utc::now().timestamp() as u64,
+                    ..Default::default()
+                });
+            if status.last_result.is_none() {
+                status.last_result = Some(read_metadata(&tag, &path, &video_id));
+            }
+            unbound.retain(|v| v.video_id != video_id);
+            s.file_cache.insert(video_id.clone(), path.clone());
+            MsState::push_update_state(&mut status, FetchStatus::Categorized)?;
+            debug!("Adopted {} as {}", path.display(), video_id);
+            report.adopted.push(video_id);
+        } else if let Some(recording_id) = musicfiles::read_recording_id(&tag) {
+            let mut candidates = unbound.iter().filter(|v| {
+                v.override_result
+                    .as_ref()
+                    .or(v.last_result.as_ref())
+                    .and_then(|r| r.brainz_recording_id.as_ref())
+                    == Some(&recording_id)
+            });
+            let (Some(video), None) = (candidates.next(), candidates.next()) else {
+                continue;
+            };
+            let video_id = video.video_id.clone();
+            if let Err(err) = musicfiles::assign_video_id(s, &path, &video_id) {
+                warn!("Failed to bind {} to {}: {}", path.display(), video_id, err);
+                continue;
+            }
+            unbound.retain(|v| v.video_id != video_id);
+            if let Some(v) = dbdata::DB.modify_video_status(&video_id, &|v| {
+                v.fetch_status = FetchStatus::Categorized;
+                v.last_error = None;
+                true
+            })? {
+                MsState::push_update_notification(&v);
+            }
+            debug!("Bound {} to {} by recording", path.display(), video_id);
+            report.bound.push(video_id);
+        }
+    }
+
+    dbdata::DB.set_key(ADOPTED_KEY, &Utc::now().timestamp().to_string())?;
+    info!(
+        "Adopted library: {} new videos, {} bound by recording, {} already known",
+        report.adopted.len(),
+        report.bound.len(),
+        report.known
+    );
+    Ok(report)
+}
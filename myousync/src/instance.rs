@@ -0,0 +1,92 @@
+//! Detects a second process started against the same database, which would download and move
+//! the same videos as the first one.
+//!
+//! The running instance holds a lease in the database and renews it regularly. A lease which was
+//! not renewed for [`LEASE_TTL`] belongs to a process that died and is taken over.
+
+use std::time::Duration;
+
+use chrono::Utc;
+use log::{error, info, warn};
+use rand::distr::{Alphanumeric, SampleString};
+use serde::Deserialize;
+
+use crate::dbdata;
+
+pub const LEASE_KEY: &str = "instance_lease";
+const HEARTBEAT: Duration = Duration::from_secs(10);
+const LEASE_TTL: Duration = Duration::from_secs(30);
+
+/// What a second instance does while another one holds the database.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SecondInstance {
+    /// Refuses to start
+    #[default]
+    Exit,
+    /// Only serves the web app, without syncing or tagging anything
+    ApiOnly,
+}
+
+/// Identifies this process in the lease.
+pub struct Instance {
+    owner: String,
+}
+
+impl Instance {
+    /// Takes the lease, or returns a message naming the instance which holds it.
+    pub fn acquire() -> Result<Self, String> {
+        let owner = format!(
+            "pid {} ({})",
+            std::process::id(),
+            Alphanumeric.sample_string(&mut rand::rng(), 8)
+        );
+        let now = Utc::now().timestamp();
+        let db_error = |err: dbdata::DbError| format!("Failed to check the instance lease: {err}");
+        if dbdata::DB
+            .acquire_lease(LEASE_KEY, &owner, now, LEASE_TTL.as_secs() as i64)
+            .map_err(db_error)?
+        {
+            info!("Holding the instance lease as {}", owner);
+            return Ok(Instance { owner });
+        }
+        Err(match dbdata::DB.get_lease(LEASE_KEY).map_err(db_error)? {
+            Some((holder, renewed)) => format!(
+                "Another instance, {}, uses this database, last seen {}s ago",
+                holder,
+                now - renewed
+            ),
+            None => "Another instance uses this database".to_string(),
+        })
+    }
+
+    /// Renews the lease, also while the process shuts down.
+    ///
+    /// Returns if another instance took over the lease, e.g. after this process was suspended
+    /// for longer than the lease lasts, so both do not keep working.
+    pub async fn hold(&self) {
+        let mut interval = tokio::time::interval(HEARTBEAT);
+        loop {
+            interval.tick().await;
+            let now = Utc::now().timestamp();
+            // A failed renewal is retried on the next heartbeat, the lease outlasts a few of them
+            match dbdata::DB.acquire_lease(LEASE_KEY, &self.owner, now, LEASE_TTL.as_secs() as i64)
+            {
+                Ok(true) => {}
+                Ok(false) => {
+                    error!("Another instance took over the database, stopping");
+                    return;
+                }
+                Err(err) => warn!("Failed to renew the instance lease: {}", err),
+            }
+        }
+    }
+
+    /// Releases the lease, so the next instance can start right away.
+    pub fn release(&self) {
+        info!("Releasing the instance lease");
+        if let Err(err) = dbdata::DB.release_lease(LEASE_KEY, &self.owner) {
+            error!("Failed to release the instance lease: {}", err);
+        }
+    }
+}
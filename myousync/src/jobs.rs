@@ -0,0 +1,316 @@
+//! Persistent tagger jobs, so queued work survives restarts and failed videos are retried with
+//! an exponential backoff.
+//!
+//! The in-memory queue only decides the order; every queued video also has a row in the `jobs`
+//! table until it was processed successfully or ran out of attempts.
+//!
+//! Database errors are logged and do not stop the queue. A job which could not be saved still
+//! runs, it is only lost on a restart.
+
+use std::time::Duration;
+
+use chrono::Utc;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    MUSIC_TAG_QUEUE, MsScrape, MsState, TRIGGER_MUSIC_TAG,
+    dbdata::{self, FetchStatus},
+    errors::Retry,
+    musicfiles::FileMissing,
+    util::queue::Priority,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Queued,
+    Running,
+    /// Failed, waiting for `next_attempt`
+    Retrying,
+    /// Ran out of attempts, only retried when requested explicitly
+    Failed,
+}
+
+impl JobState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            JobState::Queued => "queued",
+            JobState::Running => "running",
+            JobState::Retrying => "retrying",
+            JobState::Failed => "failed",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Job {
+    pub video_id: String,
+    pub priority: Priority,
+    pub state: JobState,
+    /// Number of failed runs
+    pub attempts: u32,
+    /// Unix timestamp of the earliest next run
+    pub next_attempt: i64,
+    pub last_error: Option<String>,
+    /// Unix timestamp
+    pub created: i64,
+    /// Unix timestamp
+    pub updated: i64,
+}
+
+/// Automatic retries of a failed video, as sent to clients.
+#[derive(Debug, Clone, Serialize)]
+pub struct RetryInfo {
+    /// Number of failed runs
+    pub attempts: u32,
+    /// Unix timestamp of the next run, not set once no more runs follow
+    pub next_attempt: Option<i64>,
+}
+
+/// The retries of `video_id`, `None` if it has no failed job.
+pub fn retry_info(video_id: &str) -> Option<RetryInfo> {
+    let job = match dbdata::DB.get_job(video_id) {
+        Ok(job) => job?,
+        Err(err) => {
+            warn!("Failed to get job of {}: {}", video_id, err);
+            return None;
+        }
+    };
+    match job.state {
+        JobState::Retrying => Some(RetryInfo {
+            attempts: job.attempts,
+            next_attempt: Some(job.next_attempt),
+        }),
+        JobState::Failed => Some(RetryInfo {
+            attempts: job.attempts,
+            next_attempt: None,
+        }),
+        JobState::Queued | JobState::Running => None,
+    }
+}
+
+/// Creates or refreshes the job of `video_id` and queues it.
+///
+/// Jobs waiting for a retry or out of attempts are left alone by [`Priority::Low`] requests, so the
+/// periodic reconciliation does not undo the backoff. [`Priority::High`] requests come from users
+/// and start them over right away.
+pub fn enqueue(video_id: String, priority: Priority) {
+    let now = Utc::now().timestamp();
+    let job = match dbdata::DB.get_job(&video_id) {
+        Ok(job) => job,
+        Err(err) => {
+            error!("Failed to get job of {}: {}", video_id, err);
+            push(video_id, priority);
+            return;
+        }
+    };
+    let job = match job {
+        // Queued again to run once more after the current run
+        Some(job) if job.state == JobState::Running => {
+            push(job.video_id, priority);
+            return;
+        }
+        Some(job)
+            if matches!(job.state, JobState::Retrying | JobState::Failed)
+                && priority == Priority::Low =>
+        {
+            return;
+        }
+        Some(job) => Job {
+            priority: job.priority.max(priority),
+            state: JobState::Queued,
+            attempts: if job.state == JobState::Queued {
+                job.attempts
+            } else {
+                0
+            },
+            next_attempt: now,
+            updated: now,
+            ..job
+        },
+        None => Job {
+            video_id: video_id.clone(),
+            priority,
+            state: JobState::Queued,
+            attempts: 0,
+            next_attempt: now,
+            last_error: None,
+            created: now,
+            updated: now,
+        },
+    };
+    save(&job);
+    push(job.video_id, job.priority);
+}
+
+fn push(video_id: String, priority: Priority) {
+    MUSIC_TAG_QUEUE.push(video_id, priority);
+    _ = TRIGGER_MUSIC_TAG.send(());
+}
+
+fn save(job: &Job) {
+    if let Err(err) = dbdata::DB.set_job(job) {
+        error!("Failed to save job of {}: {}", job.video_id, err);
+    }
+}
+
+/// Queues the jobs left over from the last run.
+pub fn resume() {
+    let (interrupted, queued) = match dbdata::DB
+        .reset_running_jobs()
+        .and_then(|interrupted| Ok((interrupted, dbdata::DB.get_jobs_in_state(JobState::Queued)?)))
+    {
+        Ok(jobs) => jobs,
+        Err(err) => {
+            error!("Failed to resume jobs: {}", err);
+            return;
+        }
+    };
+    if !queued.is_empty() {
+        info!(
+            "Resuming {} queued jobs ({} were interrupted)",
+            queued.len(),
+            interrupted
+        );
+    }
+    for job in queued {
+        push(job.video_id, job.priority);
+    }
+    requeue_due();
+}
+
+/// Queues the jobs whose backoff has passed.
+/// Returns whether other jobs are still waiting for their retry, which is assumed if the
+/// database could not be read so the check is repeated soon.
+pub fn requeue_due() -> bool {
+    let now = Utc::now().timestamp();
+    let due = match dbdata::DB.get_due_retries(now) {
+        Ok(due) => due,
+        Err(err) => {
+            error!("Failed to get due retries: {}", err);
+            return true;
+        }
+    };
+    for mut job in due {
+        info!("Retrying {} (attempt {})", job.video_id, job.attempts + 1);
+        job.state = JobState::Queued;
+        job.updated = now;
+        save(&job);
+        push(job.video_id, job.priority);
+    }
+    dbdata::DB
+        .get_jobs_in_state(JobState::Retrying)
+        .map_or(true, |waiting| !waiting.is_empty())
+}
+
+/// Marks the job of `video_id` as running before it is processed.
+pub fn start(video_id: &str) {
+    let now = Utc::now().timestamp();
+    let mut job = match dbdata::DB.get_job(video_id) {
+        Ok(Some(job)) => job,
+        Ok(None) => return,
+        Err(err) => {
+            error!("Failed to get job of {}: {}", video_id, err);
+            return;
+        }
+    };
+
+    // Retries should fetch again instead of skipping the failed download
+    if job.last_error.is_some()
+        && let Err(err) = dbdata::DB.modify_video_status(video_id, &|v| {
+            if v.fetch_status != FetchStatus::FetchError {
+                return false;
+            }
+            v.fetch_status = FetchStatus::NotFetched;
+            true
+        })
+    {
+        error!("Failed to reset the status of {}: {}", video_id, err);
+    }
+
+    job.state = JobState::Running;
+    job.updated = now;
+    save(&job);
+}
+
+/// Records the outcome of a run, scheduling a retry for failures while attempts are left.
+pub fn finish(policy: &MsScrape, video_id: &str, result: &anyhow::Result<()>) {
+    let Err(err) = result else {
+        if let Err(err) = dbdata::DB.delete_job(video_id) {
+            error!("Failed to delete job of {}: {}", video_id, err);
+        }
+        return;
+    };
+    let mut job = match dbdata::DB.get_job(video_id) {
+        Ok(Some(job)) => job,
+        Ok(None) => return,
+        Err(err) => {
+            error!("Failed to get job of {}: {}", video_id, err);
+            return;
+        }
+    };
+
+    let now = Utc::now().timestamp();
+    job.attempts += 1;
+    job.last_error = Some(err.to_string());
+    job.updated = now;
+    let missing = err.downcast_ref::<FileMissing>();
+    // The status of the video holds the error which caused the failure
+    let status = dbdata::DB.get_video(video_id).unwrap_or_else(|err| {
+        error!("Failed to get video {}: {}", video_id, err);
+        None
+    });
+    let code = status
+        .as_ref()
+        .and_then(|v| v.error_code())
+        .filter(|_| missing.is_none());
+    let retry = code.map_or(Retry::Backoff, |c| c.retry());
+    if missing.is_some_and(|m| !m.redownload) {
+        // Retrying cannot bring the file back
+        job.state = JobState::Failed;
+    } else if let Some(code) = code.filter(|_| retry == Retry::Never) {
+        info!(
+            "Not retrying {}, it failed with {}",
+            video_id,
+            code.as_str()
+        );
+        job.state = JobState::Failed;
+    } else if job.attempts >= policy.max_attempts {
+        warn!(
+            "Giving up on {} after {} attempts: {}",
+            video_id, job.attempts, err
+        );
+        job.state = JobState::Failed;
+    } else if missing.is_some() {
+        // The video was reset to be downloaded again, which does not need to wait
+        job.state = JobState::Queued;
+        save(&job);
+        push(job.video_id, job.priority);
+        return;
+    } else {
+        let delay = if retry == Retry::Throttled {
+            policy.retry_backoff_max
+        } else {
+            backoff(policy, job.attempts)
+        };
+        info!("Retrying {} in {:?}", video_id, delay);
+        job.state = JobState::Retrying;
+        job.next_attempt = now + delay.as_secs() as i64;
+    }
+    save(&job);
+
+    // The status was sent before the retry was scheduled
+    if let Some(status) = status {
+        MsState::push_update_notification(&status);
+    }
+}
+
+/// Doubles `retry_backoff` with every failed attempt, up to `retry_backoff_max`.
+fn backoff(policy: &MsScrape, attempts: u32) -> Duration {
+    policy
+        .retry_backoff
+        .checked_mul(1 << attempts.saturating_sub(1).min(20))
+        .unwrap_or(Duration::MAX)
+        .min(policy.retry_backoff_max)
+}
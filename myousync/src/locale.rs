@@ -0,0 +1,73 @@
+//! Naming by a preferred language or script, for libraries which want all titles in English or
+//! all in their original script.
+//!
+//! MusicBrainz links releases to pseudo-releases which carry a translated or transliterated
+//! tracklist. The form matching the earliest entry of the `locale` list is used for the album,
+//! track title and artist names.
+
+use log::debug;
+
+use crate::brainz::{self, BrainzError, BrainzMetadata, BrainzRelease, ReleaseForm};
+
+/// Position of the first entry of `locale` matching the language or script of `form`.
+fn rank(locale: &[String], form: &ReleaseForm) -> usize {
+    locale
+        .iter()
+        .position(|preferred| {
+            [&form.language, &form.script]
+                .into_iter()
+                .flatten()
+                .any(|code| code.eq_ignore_ascii_case(preferred))
+        })
+        .unwrap_or(locale.len())
+}
+
+/// Gets the form of a release which is preferred by `locale`, the release itself if none of its
+/// forms ranks higher.
+pub async fn preferred_release(
+    release_id: &str,
+    locale: &[String],
+) -> Result<BrainzRelease, BrainzError> {
+    let release = brainz::fetch_release(release_id).await?;
+    if locale.is_empty() {
+        return Ok(release);
+    }
+
+    let own_rank = rank(locale, &release.form);
+    let Some(best) = release
+        .forms
+        .iter()
+        .map(|form| (rank(locale, form), form))
+        .filter(|(rank, _)| *rank < own_rank)
+        .min_by_key(|(rank, _)| *rank)
+        .map(|(_, form)| form.release_id.clone())
+    else {
+        return Ok(release);
+    };
+    debug!("Using release {} as preferred form of {}", best, release_id);
+    brainz::fetch_release(&best).await
+}
+
+/// Replaces the album, title and artist names of `result` with those of the preferred form of its
+/// release and records the form that was used.
+pub async fn localize(result: &mut BrainzMetadata, locale: &[String]) -> Result<(), BrainzError> {
+    if locale.is_empty() {
+        return Ok(());
+    }
+    let Some(release_id) = &result.brainz_release_id else {
+        return Ok(());
+    };
+
+    let release = preferred_release(release_id, locale).await?;
+    if let Some(track) = release
+        .tracks
+        .iter()
+        .find(|t| Some(&t.recording_id) == result.brainz_recording_id.as_ref())
+    {
+        result.title = track.title.clone();
+        result.artist = track.artist.clone();
+    }
+    result.album = Some(release.title);
+    result.release_form = Some(release.form);
+    Ok(())
+}
@@ -0,0 +1,219 @@
+//! Rejects sign ins for a while after repeated failures, against password guessing and
+//! credential stuffing.
+//!
+//! Failures are counted per user and per client address within `web.login_limit.window`. Once
+//! either reaches its limit, sign ins of that user or from that address are rejected for
+//! `web.login_limit.lockout`, even with the right password. The counters are kept in memory and
+//! in the database, so a restart does not reset them.
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{LazyLock, Mutex},
+};
+
+use axum::http::StatusCode;
+use chrono::Utc;
+use log::{error, warn};
+use serde_json::json;
+
+use crate::{
+    MsLoginLimit,
+    auth::AuthError,
+    dbdata::{self, LoginAttempts},
+    net::CLIENT,
+};
+
+static ATTEMPTS: LazyLock<Mutex<HashMap<String, LoginAttempts>>> = LazyLock::new(|| {
+    Mutex::new(
+        dbdata::DB
+            .get_login_attempts()
+            .unwrap_or_else(|err| {
+                error!("Failed to load login attempts: {}", err);
+                Vec::new()
+            })
+            .into_iter()
+            .map(|a| (a.key.clone(), a))
+            .collect(),
+    )
+});
+
+fn user_key(username: &str) -> String {
+    format!("user:{}", username.to_lowercase())
+}
+
+fn ip_key(ip: IpAddr) -> String {
+    format!("ip:{ip}")
+}
+
+/// Rejects the sign in while the user or the address is locked.
+pub fn check(ip: IpAddr, username: &str) -> Result<(), AuthError> {
+    let now = Utc::now().timestamp();
+    let attempts = ATTEMPTS.lock().unwrap();
+    let until = [user_key(username), ip_key(ip)]
+        .iter()
+        .filter_map(|key| attempts.get(key)?.locked_until)
+        .filter(|&until| until > now)
+        .max();
+    match until {
+        Some(until) => Err(AuthError {
+            message: format!(
+                "Too many failed sign ins, try again in {} min",
+                (until - now + 59) / 60
+            ),
+            status_code: StatusCode::TOO_MANY_REQUESTS,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Counts a failed sign in and locks the user or address once they reach their limit.
+pub async fn record_failure(config: &MsLoginLimit, ip: IpAddr, username: &str) {
+    let locked = {
+        let now = Utc::now().timestamp();
+        let mut attempts = ATTEMPTS.lock().unwrap();
+        // Forgets addresses and unknown users which stopped trying
+        attempts.retain(|key, a| {
+            let keep = a.locked_until.is_some_and(|until| until > now)
+                || a.first_failure + config.window.as_secs() as i64 > now;
+            if !keep && let Err(err) = dbdata::DB.delete_login_attempts(key) {
+                error!("Failed to delete login attempts of {}: {}", key, err);
+            }
+            keep
+        });
+
+        [
+            (user_key(username), config.max_user_failures),
+            (ip_key(ip), config.max_ip_failures),
+        ]
+        .into_iter()
+        .filter(|(key, max_failures)| {
+            let a = attempts
+                .entry(key.clone())
+                .or_insert_with(|| LoginAttempts {
+                    key: key.clone(),
+                    failures: 0,
+                    first_failure: now,
+                    locked_until: None,
+                });
+            let locks = count_failure(a, config, *max_failures, now);
+            if let Err(err) = dbdata::DB.set_login_attempts(a) {
+                error!("Failed to store login attempts of {}: {}", key, err);
+            }
+            locks
+        })
+        .map(|(key, _)| key)
+        .collect::<Vec<_>>()
+    };
+
+    for key in locked {
+        warn!(
+            "Locked sign ins of {} for {}s after repeated failures (last from {} as {})",
+            key,
+            config.lockout.as_secs(),
+            ip,
+            username
+        );
+        if let Some(webhook) = &config.webhook {
+            notify(webhook, &key, ip, username).await;
+        }
+    }
+}
+
+/// Counts a failure at `now`, starting over once the window of the first one passed. Returns
+/// true if it reaches `max_failures` and locks.
+fn count_failure(
+    a: &mut LoginAttempts,
+    config: &MsLoginLimit,
+    max_failures: u32,
+    now: i64,
+) -> bool {
+    if a.first_failure + (config.window.as_secs() as i64) <= now {
+        a.failures = 0;
+        a.first_failure = now;
+    }
+    a.failures += 1;
+    let locks = a.failures >= max_failures;
+    if locks {
+        a.failures = 0;
+        a.first_failure = now;
+        a.locked_until = Some(now + config.lockout.as_secs() as i64);
+    }
+    locks
+}
+
+/// Resets the failures of the user after a successful sign in.
+pub fn record_success(username: &str) {
+    let key = user_key(username);
+    if ATTEMPTS.lock().unwrap().remove(&key).is_some()
+        && let Err(err) = dbdata::DB.delete_login_attempts(&key)
+    {
+        error!("Failed to delete login attempts of {}: {}", key, err);
+    }
+}
+
+async fn notify(webhook: &str, key: &str, ip: IpAddr, username: &str) {
+    let body = json!({
+        "event": "login_locked",
+        "locked": key,
+        "ip": ip,
+        "username": username,
+    });
+
+    match CLIENT.post(webhook).json(&body).send().await {
+        Ok(res) if !res.status().is_success() => {
+            warn!("Login lock webhook returned {}", res.status());
+        }
+        Ok(_) => {}
+        Err(err) => warn!("Failed to call login lock webhook: {}", err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn config() -> MsLoginLimit {
+        MsLoginLimit {
+            window: Duration::from_secs(600),
+            lockout: Duration::from_secs(900),
+            ..Default::default()
+        }
+    }
+
+    fn attempts(now: i64) -> LoginAttempts {
+        LoginAttempts {
+            key: user_key("test"),
+            failures: 0,
+            first_failure: now,
+            locked_until: None,
+        }
+    }
+
+    #[test]
+    fn failures_within_the_window_lock() {
+        let config = config();
+        let mut a = attempts(1000);
+        assert!(!count_failure(&mut a, &config, 3, 1000));
+        assert!(!count_failure(&mut a, &config, 3, 1300));
+        assert!(count_failure(&mut a, &config, 3, 1599));
+        assert_eq!(a.locked_until, Some(1599 + 900));
+        assert_eq!(a.failures, 0);
+    }
+
+    #[test]
+    fn failures_after_the_window_start_over() {
+        let config = config();
+        let mut a = attempts(1000);
+        assert!(!count_failure(&mut a, &config, 3, 1000));
+        assert!(!count_failure(&mut a, &config, 3, 1300));
+        // The window of the first failure ended, so this is the first of a new one
+        assert!(!count_failure(&mut a, &config, 3, 1600));
+        assert_eq!((a.failures, a.first_failure), (1, 1600));
+        assert!(!count_failure(&mut a, &config, 3, 2100));
+        assert!(count_failure(&mut a, &config, 3, 2199));
+        assert!(a.locked_until.is_some());
+    }
+}
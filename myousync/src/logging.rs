@@ -0,0 +1,228 @@
+//! Log output as configured in `[logging]`.
+//!
+//! The logger is installed before the config is read, so early messages use the default
+//! settings, and is reconfigured with [`configure`] once the config is known. `RUST_LOG` still
+//! overrides the configured levels.
+
+use std::{
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::RwLock,
+};
+
+use anyhow::Context;
+use chrono::{SecondsFormat, Utc};
+use env_logger::{Builder, Logger, Target, WriteStyle, fmt::Formatter};
+use log::{LevelFilter, Log, Metadata, Record};
+use serde::Deserialize;
+
+use crate::{MsLogging, trace};
+
+static CURRENT: RwLock<Option<Logger>> = RwLock::new(None);
+static LOGGER: Swappable = Swappable;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// Human readable lines, colored on the terminal
+    #[default]
+    Text,
+    /// One JSON object per line, for log collectors like Loki or Elasticsearch
+    Json,
+}
+
+/// Forwards to the [`Logger`] of the current configuration.
+struct Swappable;
+
+impl Log for Swappable {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        CURRENT
+            .read()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|logger| logger.enabled(metadata))
+    }
+
+    fn log(&self, record: &Record) {
+        if let Some(logger) = CURRENT.read().unwrap().as_ref() {
+            logger.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(logger) = CURRENT.read().unwrap().as_ref() {
+            logger.flush();
+        }
+    }
+}
+
+/// Installs the logger with the default settings.
+pub fn init() {
+    replace(builder(&MsLogging::default(), None));
+    log::set_logger(&LOGGER).expect("Logger is only installed once");
+}
+
+/// Switches the output to `config`.
+pub fn configure(config: &MsLogging) -> anyhow::Result<()> {
+    let file = match &config.file {
+        Some(path) => Some(
+            RotatingFile::open(path, config.max_file_size, config.max_files)
+                .with_context(|| format!("Failed to open log file {}", path.display()))?,
+        ),
+        None => None,
+    };
+    replace(builder(config, file));
+    Ok(())
+}
+
+/// Checks the levels of the config, which are only parsed when the logger is built.
+pub fn validate(config: &MsLogging) -> anyhow::Result<()> {
+    LevelFilter::from_str(&config.level)
+        .with_context(|| format!("Invalid log level {}", config.level))?;
+    for (module, level) in &config.modules {
+        LevelFilter::from_str(level)
+            .with_context(|| format!("Invalid log level {} of {}", level, module))?;
+    }
+    Ok(())
+}
+
+fn replace(mut builder: Builder) {
+    let logger = builder.build();
+    log::set_max_level(logger.filter());
+    *CURRENT.write().unwrap() = Some(logger);
+}
+
+fn builder(config: &MsLogging, file: Option<RotatingFile>) -> Builder {
+    let mut builder = match (config.format, &file) {
+        (LogFormat::Json, _) => {
+            let mut builder = Builder::new();
+            builder.format(format_json);
+            builder
+        }
+        // Colog writes colors into files too
+        (LogFormat::Text, Some(_)) => {
+            let mut builder = Builder::new();
+            builder.format(format_plain);
+            builder
+        }
+        (LogFormat::Text, None) => colog::basic_builder(),
+    };
+
+    builder.filter(None, parse_level(&config.level));
+    for (module, level) in &config.modules {
+        let level = parse_level(level);
+        builder.filter_module(module, level);
+        // Allows `brainz` for the module `myousync::brainz`
+        if !module.contains("::") {
+            builder.filter_module(&format!("{}::{}", env!("CARGO_CRATE_NAME"), module), level);
+        }
+    }
+    if let Ok(rust_log) = std::env::var("RUST_LOG") {
+        builder.parse_filters(&rust_log);
+    }
+
+    if let Some(file) = file {
+        builder
+            .target(Target::Pipe(Box::new(file)))
+            .write_style(WriteStyle::Never);
+    }
+    builder
+}
+
+fn parse_level(level: &str) -> LevelFilter {
+    LevelFilter::from_str(level).unwrap_or(LevelFilter::Info)
+}
+
+fn timestamp() -> String {
+    Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+fn format_plain(buf: &mut Formatter, record: &Record) -> io::Result<()> {
+    writeln!(
+        buf,
+        "{} {:<5} {}: {}",
+        timestamp(),
+        record.level(),
+        record.target(),
+        record.args()
+    )
+}
+
+fn format_json(buf: &mut Formatter, record: &Record) -> io::Result<()> {
+    let mut line = serde_json::json!({
+        "timestamp": timestamp(),
+        "level": record.level().as_str(),
+        "target": record.target(),
+        "message": record.args().to_string(),
+    });
+    if let Some(ctx) = trace::current() {
+        line["request_id"] = ctx.id.clone().into();
+    }
+    writeln!(buf, "{}", line)
+}
+
+/// Log file which is moved to `<file>.1` once it reaches its maximum size, shifting older ones
+/// up to `<file>.<max_files>`.
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    max_files: usize,
+}
+
+impl RotatingFile {
+    fn open(path: &Path, max_size: u64, max_files: usize) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(RotatingFile {
+            path: path.to_owned(),
+            size: file.metadata()?.len(),
+            file,
+            max_size,
+            max_files,
+        })
+    }
+
+    fn rotated(&self, index: usize) -> PathBuf {
+        let mut path = OsString::from(&self.path);
+        path.push(format!(".{index}"));
+        path.into()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        for index in (1..self.max_files).rev() {
+            let from = self.rotated(index);
+            if from.exists() {
+                fs::rename(from, self.rotated(index + 1))?;
+            }
+        }
+        if self.max_files > 0 {
+            fs::rename(&self.path, self.rotated(1))?;
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
@@ -0,0 +1,166 @@
+//! EBU R128 loudness analysis with the ffmpeg loudnorm filter.
+//!
+//! Downloads are either tagged with ReplayGain values, so players level them at playback, or
+//! re-encoded to the target loudness. Re-encoding only happens for fresh downloads, so files in
+//! the library do not lose quality with every run of the tagger.
+
+use std::path::{Path, PathBuf};
+
+use log::{debug, info};
+use serde::Deserialize;
+use thiserror::Error;
+use tokio::process::Command;
+
+use crate::{
+    MsLoudness, MsState,
+    convert::{self, ConvertError},
+    musicfiles,
+};
+
+#[derive(Error, Debug)]
+pub enum LoudnessError {
+    #[error("IO error: {0}")]
+    IOError(#[from] std::io::Error),
+    #[error("ffmpeg returned an error: {0}")]
+    CommandError(String),
+    #[error("Unreadable loudnorm output: {0}")]
+    InvalidOutput(String),
+    #[error("Conversion failed: {0}")]
+    Convert(#[from] ConvertError),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LoudnessMode {
+    /// Writes ReplayGain tags, leaving the audio untouched
+    #[default]
+    Tags,
+    /// Re-encodes the audio to the target loudness
+    Normalize,
+}
+
+/// Measured loudness of a track, with the reference level gains are calculated against.
+#[derive(Debug, Clone, Copy)]
+pub struct ReplayGain {
+    /// Integrated loudness in LUFS
+    pub integrated: f64,
+    /// True peak in dBTP
+    pub true_peak: f64,
+    /// Target loudness in LUFS
+    pub reference: f64,
+}
+
+impl ReplayGain {
+    /// Gain in dB which brings the track to the reference loudness.
+    pub fn gain(&self) -> f64 {
+        self.reference - self.integrated
+    }
+
+    /// True peak as linear amplitude, as ReplayGain expects it.
+    pub fn peak(&self) -> f64 {
+        10f64.powf(self.true_peak / 20.0)
+    }
+}
+
+/// Fields of the loudnorm json summary, ffmpeg writes all of them as strings.
+#[derive(Debug, Deserialize)]
+struct LoudnormStats {
+    input_i: String,
+    input_tp: String,
+    input_lra: String,
+    input_thresh: String,
+    target_offset: String,
+}
+
+/// Analyzes the file at `path` and normalizes or tags it as configured.
+///
+/// `fresh` marks files which were not yet moved into the library. Returns the path to continue
+/// with and the gain to write into the tags, if any.
+pub async fn process(
+    s: &MsState,
+    config: &MsLoudness,
+    path: &Path,
+    video_id: &str,
+    fresh: bool,
+) -> Result<(PathBuf, Option<ReplayGain>), LoudnessError> {
+    match config.mode {
+        LoudnessMode::Tags => {
+            if !fresh && musicfiles::has_replaygain(path) {
+                return Ok((path.to_path_buf(), None));
+            }
+            let (gain, _) = analyze(s, config, path).await?;
+            debug!(
+                "Video {} has {:.1} LUFS, gain {:.2} dB",
+                video_id,
+                gain.integrated,
+                gain.gain()
+            );
+            Ok((path.to_path_buf(), Some(gain)))
+        }
+        LoudnessMode::Normalize => {
+            if !fresh {
+                return Ok((path.to_path_buf(), None));
+            }
+            let (gain, stats) = analyze(s, config, path).await?;
+            info!(
+                "Normalizing {} from {:.1} to {:.1} LUFS",
+                video_id, gain.integrated, config.target
+            );
+            let filter = format!(
+                "loudnorm=I={}:TP={}:LRA=11:measured_I={}:measured_TP={}:measured_LRA={}:measured_thresh={}:offset={}:linear=true",
+                config.target,
+                config.true_peak,
+                stats.input_i,
+                stats.input_tp,
+                stats.input_lra,
+                stats.input_thresh,
+                stats.target_offset
+            );
+            let path = convert::apply_filter(s, path, video_id, &filter, &config.bitrate).await?;
+            Ok((path, None))
+        }
+    }
+}
+
+async fn analyze(
+    s: &MsState,
+    config: &MsLoudness,
+    path: &Path,
+) -> Result<(ReplayGain, LoudnormStats), LoudnessError> {
+    let output = Command::new(&s.config.scrape.ffmpeg)
+        .args(["-nostdin", "-hide_banner", "-i"])
+        .arg(path)
+        .args(["-map", "0:a:0", "-af"])
+        .arg(format!(
+            "loudnorm=I={}:TP={}:LRA=11:print_format=json",
+            config.target, config.true_peak
+        ))
+        .args(["-f", "null", "-"])
+        .output()
+        .await?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() {
+        return Err(LoudnessError::CommandError(stderr.trim().to_string()));
+    }
+
+    // The summary is the last thing ffmpeg prints
+    let json = stderr
+        .rfind('{')
+        .map(|start| &stderr[start..])
+        .ok_or_else(|| LoudnessError::InvalidOutput("no summary".to_string()))?;
+    let stats: LoudnormStats = serde_json::from_str(json.trim())
+        .map_err(|err| LoudnessError::InvalidOutput(err.to_string()))?;
+    let parse = |value: &str| {
+        value
+            .parse::<f64>()
+            .ok()
+            .filter(|v| v.is_finite())
+            .ok_or_else(|| LoudnessError::InvalidOutput(format!("'{value}' is not a level")))
+    };
+    let gain = ReplayGain {
+        integrated: parse(&stats.input_i)?,
+        true_peak: parse(&stats.input_tp)?,
+        reference: config.target,
+    };
+    Ok((gain, stats))
+}
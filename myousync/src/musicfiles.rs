@@ -1,19 +1,79 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     fs,
-    path::{Path, PathBuf},
+    path::{Component, Path, PathBuf},
 };
 
-use crate::{MsPaths, MsState, brainz::BrainzMetadata, dbdata};
+use crate::{
+    MsPaths, MsState,
+    brainz::BrainzMetadata,
+    dbdata::{self, DbResult, FetchStatus},
+    loudness::ReplayGain,
+};
 use anyhow::Context;
 use id3::TagLike;
-use log::{error, info};
-use multitag::{self, data::Album};
+use log::{debug, error, info, warn};
+use multitag::{
+    self,
+    data::{Album, Field, Picture, Timestamp},
+};
 use sanitise_file_name::sanitise_with_options;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
 use walkdir::WalkDir;
 
-pub fn apply_metadata_to_file(path: &Path, tags: &MetadataTags) -> anyhow::Result<()> {
-    let mut tag = multitag::Tag::read_from_path(path).context("When reading audiotags")?;
+/// The file of a downloaded video is neither in the temp folder nor in the library.
+#[derive(Error, Debug)]
+#[error("File of video {video_id} is missing")]
+pub struct FileMissing {
+    pub video_id: String,
+    /// The video was reset to be downloaded again
+    pub redownload: bool,
+}
+
+/// Applies `tags` to the file at `path`.
+///
+/// Returns the fields which were changed. When the file already carries the desired metadata
+/// nothing is written and the returned list is empty.
+///
+/// Malformed tags are read leniently; any items which could not be recovered are dropped and the
+/// tag is rewritten.
+#[tracing::instrument(name = "write_tags", skip(tags))]
+pub fn apply_metadata_to_file(path: &Path, tags: &MetadataTags) -> anyhow::Result<Vec<Field>> {
+    let (mut tag, changes) = build_tag(path, tags)?;
+    if changes.is_empty() {
+        return Ok(changes);
+    }
+
+    let unknown = tag.retained_unknown_fields();
+    if !unknown.is_empty() {
+        debug!(
+            "Retaining unmodeled fields in {}: {:?}",
+            path.display(),
+            unknown
+        );
+    }
+
+    tag.write_to_path(path)?;
+    Ok(changes)
+}
+
+/// The fields [`apply_metadata_to_file`] would change, without writing anything.
+pub fn planned_metadata_changes(path: &Path, tags: &MetadataTags) -> anyhow::Result<Vec<Field>> {
+    build_tag(path, tags).map(|(_, changes)| changes)
+}
+
+/// The tag of the file at `path` with `tags` applied, and the fields that differ from the file.
+fn build_tag(path: &Path, tags: &MetadataTags) -> anyhow::Result<(multitag::Tag, Vec<Field>)> {
+    let (mut tag, skipped) =
+        multitag::Tag::read_from_path_lenient(path).context("When reading audiotags")?;
+    for item in &skipped {
+        warn!(
+            "Dropping unreadable tag item in {}: {}",
+            path.display(),
+            item
+        );
+    }
 
     tag.remove_title();
     tag.set_title(&tags.brainz.title);
@@ -21,10 +81,31 @@ pub fn apply_metadata_to_file(path: &Path, tags: &MetadataTags) -> anyhow::Resul
     tag.set_artist(&tags.brainz.artist.join("; "));
     let mut album = tag.get_album_info().unwrap_or(Album::default());
     album.title = Some(tags.brainz.album.clone().unwrap_or_default());
-    album.artist = Some(tags.brainz.artist.join("; "));
+    album.artist = Some(
+        tags.brainz
+            .album_artist
+            .clone()
+            .unwrap_or_else(|| tags.brainz.artist.join("; ")),
+    );
+    if tags.cover.is_some() {
+        album.cover = tags.cover.clone();
+    }
     tag.remove_all_album_info();
     tag.set_album_info(album)?;
     tag.set_comment("youtube_id", tags.youtube_id.clone());
+    for (key, value) in &tags.custom_fields {
+        tag.set_comment(key, value.clone());
+    }
+
+    if tags.date_and_genres {
+        if let Some(date) = tags.brainz.date.as_deref().and_then(|d| d.parse().ok()) {
+            tag.set_date(date);
+        }
+        if !tags.brainz.genres.is_empty() {
+            let genres: Vec<&str> = tags.brainz.genres.iter().map(String::as_str).collect();
+            tag.set_genres(&genres);
+        }
+    }
 
     if let Some(brainz_id) = tags.brainz.brainz_recording_id.as_deref() {
         match &mut tag {
@@ -50,61 +131,338 @@ pub fn apply_metadata_to_file(path: &Path, tags: &MetadataTags) -> anyhow::Resul
         }
     }
 
-    tag.write_to_path(path)?;
-    Ok(())
+    if let Some(gain) = &tags.replaygain {
+        set_replaygain(&mut tag, gain);
+    }
+
+    let (original, _) =
+        multitag::Tag::read_from_path_lenient(path).context("When reading audiotags")?;
+    let mut changes = original.diff(&tag);
+    if !skipped.is_empty() {
+        changes.push(Field::Other);
+    }
+    Ok((tag, changes))
 }
 
-pub fn find_local_file(s: &MsState, video_id: &str) -> Option<PathBuf> {
-    let mut cache = s.file_cache.lock().unwrap();
-    if let Some(path) = cache.get(video_id) {
-        if check_file(path, video_id) {
-            return Some(path.clone());
+/// Opus uses its own gain tags relative to -23 LUFS, in Q7.8 fixed point (RFC 7845).
+fn set_replaygain(tag: &mut multitag::Tag, gain: &ReplayGain) {
+    match tag {
+        multitag::Tag::OpusTag { .. } => {
+            let r128 = ((-23.0 - gain.integrated) * 256.0).round();
+            let r128 = r128.clamp(i16::MIN.into(), i16::MAX.into()) as i16;
+            tag.set_comment("R128_TRACK_GAIN", r128.to_string());
+        }
+        _ => {
+            tag.set_comment("REPLAYGAIN_TRACK_GAIN", format!("{:.2} dB", gain.gain()));
+            tag.set_comment("REPLAYGAIN_TRACK_PEAK", format!("{:.6}", gain.peak()));
         }
     }
+}
 
-    if dbdata::DB.get_video_fetch_status(video_id) == Some(dbdata::FetchStatus::Disabled) {
-        return None;
+/// Whether the file at `path` already has a track gain.
+pub fn has_replaygain(path: &Path) -> bool {
+    multitag::Tag::read_from_path_lenient(path).is_ok_and(|(tag, _)| {
+        tag.get_comment("REPLAYGAIN_TRACK_GAIN").is_some()
+            || tag.get_comment("R128_TRACK_GAIN").is_some()
+    })
+}
+
+/// Album level metadata, shared by all tracks of a release.
+pub struct AlbumTags {
+    pub title: String,
+    pub artist: String,
+    pub date: Option<Timestamp>,
+    pub total_tracks: u32,
+    /// Cover to apply. Files keep their own cover when this is `None`.
+    pub cover: Option<Picture>,
+}
+
+/// Applies the album level `tags` to the file at `path`, leaving all track level fields alone.
+///
+/// Returns the fields which were changed, like [`apply_metadata_to_file`].
+pub fn apply_album_to_file(path: &Path, tags: &AlbumTags) -> anyhow::Result<Vec<Field>> {
+    let (mut tag, _) =
+        multitag::Tag::read_from_path_lenient(path).context("When reading audiotags")?;
+
+    let mut album = tag.get_album_info().unwrap_or(Album::default());
+    album.title = Some(tags.title.clone());
+    album.artist = Some(tags.artist.clone());
+    if tags.cover.is_some() {
+        album.cover = tags.cover.clone();
     }
+    tag.remove_all_album_info();
+    tag.set_album_info(album)?;
+    if let Some(date) = tags.date {
+        tag.set_date(date);
+    }
+    tag.set_total_tracks(tags.total_tracks);
 
-    cache.clear();
-    info!("Rebuilding file cache");
-    create_cache(&s.config.paths.music, &mut cache);
-    if let Some(migrate) = &s.config.paths.migrate {
-        info!("Rebuilding migrate cache");
-        create_cache(migrate, &mut cache);
+    let (original, _) =
+        multitag::Tag::read_from_path_lenient(path).context("When reading audiotags")?;
+    let changes = original.diff(&tag);
+    if changes.is_empty() {
+        return Ok(changes);
     }
-    info!("Cache rebuilt with {} entries", cache.len());
 
-    if let Some(path) = cache.get(video_id) {
-        return Some(path.clone());
+    tag.write_to_path(path)?;
+    Ok(changes)
+}
+
+pub fn read_cover(path: &Path) -> Option<Picture> {
+    let (tag, _) = multitag::Tag::read_from_path_lenient(path).ok()?;
+    tag.get_album_info()?.cover
+}
+
+/// Replaces the cover of the file at `path`.
+pub fn set_cover(path: &Path, cover: Picture) -> anyhow::Result<()> {
+    let (mut tag, _) =
+        multitag::Tag::read_from_path_lenient(path).context("When reading audiotags")?;
+    let mut album = tag.get_album_info().unwrap_or(Album::default());
+    album.cover = Some(cover);
+    tag.remove_all_album_info();
+    tag.set_album_info(album)?;
+    tag.write_to_path(path)?;
+    Ok(())
+}
+
+pub fn find_local_file(s: &MsState, video_id: &str) -> Option<PathBuf> {
+    let generation = s.file_cache.generation();
+    if let Some(path) = s.file_cache.get(video_id)
+        && check_file(&path, video_id)
+    {
+        return Some(path);
+    }
+
+    match dbdata::DB.get_video_fetch_status(video_id) {
+        Ok(Some(FetchStatus::Disabled)) => return None,
+        Ok(_) => {}
+        Err(err) => warn!("Failed to get status of {}: {}", video_id, err),
     }
 
-    None
+    rebuild_file_cache(s, generation);
+    s.file_cache.get(video_id)
 }
 
-fn create_cache(path: &Path, map: &mut HashMap<String, PathBuf>) {
-    map.extend(
-        WalkDir::new(path)
+fn rebuild_file_cache(s: &MsState, generation: u64) {
+    s.file_cache.rebuild(generation, || {
+        // Without the index every file is read again, which is only slower
+        let mut index: HashMap<PathBuf, IndexedFile> = dbdata::DB
+            .get_indexed_files()
+            .unwrap_or_else(|err| {
+                error!("Failed to load the file index: {}", err);
+                Vec::new()
+            })
             .into_iter()
-            .filter_map(|p| p.ok())
-            .filter(|p| p.file_type().is_file())
-            .map(|f| f.into_path())
-            .flat_map(|p| multitag::Tag::read_from_path(&p).ok().map(|t| (t, p)))
-            .flat_map(|(t, p)| t.get_comment("youtube_id").map(|y| (y, p))),
+            .map(|f| (f.path.clone(), f))
+            .collect();
+        let mut files = Vec::new();
+        info!("Rebuilding file cache");
+        create_cache(&s.config.paths.music, &mut index, &mut files);
+        if let Some(migrate) = &s.config.paths.migrate {
+            info!("Rebuilding migrate cache");
+            create_cache(migrate, &mut index, &mut files);
+        }
+        if let Err(err) = dbdata::DB.set_indexed_files(&files) {
+            error!("Failed to save the file index: {}", err);
+        }
+        let cache = cache_from_index(files);
+        info!("Cache rebuilt with {} entries", cache.len());
+        cache
+    });
+}
+
+/// Fills the file cache from the index of the last scan without walking the library.
+///
+/// Entries which went stale while myousync was not running are caught when their file is looked
+/// up, which rescans the library.
+pub fn load_file_cache(s: &MsState) {
+    let files = match dbdata::DB.get_indexed_files() {
+        Ok(files) if !files.is_empty() => files,
+        Ok(_) => return,
+        Err(err) => {
+            error!("Failed to load the file index: {}", err);
+            return;
+        }
+    };
+    s.file_cache
+        .rebuild(s.file_cache.generation(), || cache_from_index(files));
+    info!(
+        "Loaded {} files from the file index",
+        s.file_cache.status().entries
     );
 }
 
+fn cache_from_index(files: Vec<IndexedFile>) -> HashMap<String, PathBuf> {
+    files
+        .into_iter()
+        .filter_map(|f| Some((f.video_id?, f.path)))
+        .collect()
+}
+
+/// Rescans the library and returns the categorized videos which have no file in it.
+pub fn find_missing_files(s: &MsState) -> DbResult<Vec<String>> {
+    rebuild_file_cache(s, s.file_cache.generation());
+    Ok(dbdata::DB
+        .get_all_videos()?
+        .into_iter()
+        .filter(|v| v.fetch_status == FetchStatus::Categorized)
+        .filter(|v| s.file_cache.get(&v.video_id).is_none())
+        .map(|v| v.video_id)
+        .collect())
+}
+
+/// The MusicBrainz recording id written by [`apply_metadata_to_file`].
+pub fn read_recording_id(tag: &multitag::Tag) -> Option<String> {
+    match tag {
+        multitag::Tag::Id3Tag { inner } => inner
+            .unique_file_identifiers()
+            .find(|u| u.owner_identifier == "http://musicbrainz.org")
+            .and_then(|u| String::from_utf8(u.identifier.clone()).ok()),
+        multitag::Tag::OpusTag { .. } => tag.get_comment("musicbrainz_trackid"),
+        multitag::Tag::Mp4Tag { .. } => tag.get_comment("MusicBrainz Track Id"),
+        multitag::Tag::VorbisFlacTag { .. } => tag.get_comment("MUSICBRAINZ_TRACKID"),
+        multitag::Tag::OggTag { .. } => None,
+    }
+}
+
+/// A file found by a scan of the library, remembered so the next scan only reads the tags of
+/// files which changed since.
+#[derive(Debug, Clone, Deserialize)]
+pub struct IndexedFile {
+    pub path: PathBuf,
+    /// `None` for files without a `youtube_id`
+    pub video_id: Option<String>,
+    /// Nanoseconds since the unix epoch
+    pub mtime: i64,
+    pub size: i64,
+}
+
+fn create_cache(
+    path: &Path,
+    index: &mut HashMap<PathBuf, IndexedFile>,
+    files: &mut Vec<IndexedFile>,
+) {
+    let mut read = 0;
+    for entry in WalkDir::new(path)
+        .into_iter()
+        .filter_map(|p| p.ok())
+        .filter(|p| p.file_type().is_file())
+    {
+        let Ok(meta) = entry.metadata() else {
+            continue;
+        };
+        let mtime = meta
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|d| d.as_nanos() as i64)
+            .unwrap_or_default();
+        let size = meta.len() as i64;
+        let path = entry.into_path();
+        if let Some(known) = index.remove(&path)
+            && known.mtime == mtime
+            && known.size == size
+        {
+            files.push(known);
+            continue;
+        }
+        read += 1;
+        let video_id = multitag::Tag::read_from_path_lenient(&path)
+            .ok()
+            .and_then(|(t, _)| t.get_comment("youtube_id"));
+        files.push(IndexedFile {
+            path,
+            video_id,
+            mtime,
+            size,
+        });
+    }
+    debug!("Read the tags of {} new or changed files", read);
+}
+
 fn check_file(path: &Path, video_id: &str) -> bool {
-    multitag::Tag::read_from_path(path)
+    multitag::Tag::read_from_path_lenient(path)
         .ok()
-        .and_then(|t| t.get_comment("youtube_id"))
+        .and_then(|(t, _)| t.get_comment("youtube_id"))
         .map(|y| y == video_id)
         .unwrap_or(false)
 }
 
-pub fn move_file_to_library(s: &MsState, path: &Path, tags: &MetadataTags) -> anyhow::Result<()> {
+/// A file waiting in the migrate folder.
+#[derive(Debug, Serialize)]
+pub struct MigrateFile {
+    /// Path relative to the migrate folder
+    pub file: String,
+    /// The video the file is bound to, `None` if it has not been identified yet
+    pub video_id: Option<String>,
+    /// Status of the bound video, `None` if the video is not tracked
+    pub fetch_status: Option<FetchStatus>,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+}
+
+pub fn list_migrate_files(paths: &MsPaths) -> DbResult<Vec<MigrateFile>> {
+    let Some(migrate) = &paths.migrate else {
+        return Ok(Vec::new());
+    };
+
+    WalkDir::new(migrate)
+        .into_iter()
+        .filter_map(|p| p.ok())
+        .filter(|p| p.file_type().is_file())
+        .map(|f| {
+            let path = f.into_path();
+            let tag = multitag::Tag::read_from_path_lenient(&path)
+                .ok()
+                .map(|(t, _)| t);
+            let video_id = tag.as_ref().and_then(|t| t.get_comment("youtube_id"));
+            let fetch_status = match &video_id {
+                Some(video_id) => dbdata::DB.get_video_fetch_status(video_id)?,
+                None => None,
+            };
+            Ok(MigrateFile {
+                file: path
+                    .strip_prefix(migrate)
+                    .unwrap_or(&path)
+                    .to_string_lossy()
+                    .into_owned(),
+                fetch_status,
+                title: tag.as_ref().and_then(|t| t.title().map(str::to_owned)),
+                artist: tag.as_ref().and_then(|t| t.artist()),
+                video_id,
+            })
+        })
+        .collect()
+}
+
+/// Resolves a path relative to the migrate folder, refusing anything that would escape it.
+pub fn resolve_migrate_file(paths: &MsPaths, file: &str) -> Option<PathBuf> {
+    let migrate = paths.migrate.as_ref()?;
+    let file = Path::new(file);
+    if !file.components().all(|c| matches!(c, Component::Normal(_))) {
+        return None;
+    }
+    let path = migrate.join(file);
+    path.is_file().then_some(path)
+}
+
+/// Binds the file at `path` to `video_id` by writing the id into its tags,
+/// so it is picked up when the video gets processed.
+pub fn assign_video_id(s: &MsState, path: &Path, video_id: &str) -> anyhow::Result<()> {
+    let (mut tag, _) =
+        multitag::Tag::read_from_path_lenient(path).context("When reading audiotags")?;
+    tag.set_comment("youtube_id", video_id.to_owned());
+    tag.write_to_path(path)?;
+
+    s.file_cache.insert(video_id.to_owned(), path.to_owned());
+    Ok(())
+}
+
+/// Where [`move_file_to_library`] places the file at `path`: `artist/album/title.ext` inside the
+/// music folder.
+pub fn library_path(s: &MsState, path: &Path, tags: &MetadataTags) -> PathBuf {
     let clean_title = sanitize_default(&tags.brainz.title);
-    let clean_artist = sanitize_default(&tags.brainz.artist.join("; "));
+    let clean_artist = artist_folder(&tags.brainz.artist);
     let clean_album = &tags
         .brainz
         .album
@@ -115,41 +473,57 @@ pub fn move_file_to_library(s: &MsState, path: &Path, tags: &MetadataTags) -> an
     let orig_extenstion = path.extension().and_then(|e| e.to_str()).unwrap_or("mp3");
 
     let mut new_path = s.config.paths.music.clone();
+    if let Some(subfolder) = s
+        .config
+        .playlist_of(&tags.youtube_id)
+        .and_then(|p| p.subfolder.as_ref())
+    {
+        new_path.push(subfolder);
+    }
     new_path.push(clean_artist);
     new_path.push(clean_album);
+    new_path.push(format!("{}.{}", &clean_title, &orig_extenstion));
+    new_path
+}
 
-    std::fs::create_dir_all(&new_path)
+/// Moves the file at `path` to `new_path`, as returned by [`library_path`].
+pub fn move_file_to_library(
+    s: &MsState,
+    path: &Path,
+    new_path: &Path,
+    tags: &MetadataTags,
+) -> anyhow::Result<()> {
+    let _lock = s.file_cache.lock(&tags.youtube_id);
+    let new_dir = new_path.parent().unwrap_or(&s.config.paths.music);
+    std::fs::create_dir_all(new_dir)
         .map_err(|e| anyhow::anyhow!("Error creating directory: {}", e))?;
 
-    if let Some(dir_perm) = &s.config.paths.dir_permissions {
-        if let Err(err) = fs::set_permissions(&new_path, dir_perm.clone()) {
-            error!(
-                "Failed to apply permissions on '{}' to {:?}: {}",
-                &new_path.to_string_lossy(),
-                dir_perm,
-                err
-            );
-        }
+    if let Some(dir_perm) = &s.config.paths.dir_permissions
+        && let Err(err) = fs::set_permissions(new_dir, dir_perm.clone())
+    {
+        error!(
+            "Failed to apply permissions on '{}' to {:?}: {}",
+            &new_dir.to_string_lossy(),
+            dir_perm,
+            err
+        );
     }
 
-    new_path.push(format!("{}.{}", &clean_title, &orig_extenstion));
-
-    move_file(&s.config.paths, path, &new_path)?;
+    move_file(&s.config.paths, path, new_path)?;
 
-    if let Some(perm) = &s.config.paths.file_permissions {
-        if let Err(err) = fs::set_permissions(&new_path, perm.clone()) {
-            error!(
-                "Failed to apply permissions on '{}' to {:?}: {}",
-                &new_path.to_string_lossy(),
-                perm,
-                err
-            );
-        }
+    if let Some(perm) = &s.config.paths.file_permissions
+        && let Err(err) = fs::set_permissions(new_path, perm.clone())
+    {
+        error!(
+            "Failed to apply permissions on '{}' to {:?}: {}",
+            &new_path.to_string_lossy(),
+            perm,
+            err
+        );
     }
 
-    let mut cache = s.file_cache.lock().unwrap();
-    cache.remove(&tags.youtube_id);
-    cache.insert(tags.youtube_id.clone(), new_path);
+    s.file_cache
+        .insert(tags.youtube_id.clone(), new_path.to_owned());
 
     Ok(())
 }
@@ -168,7 +542,7 @@ pub fn delete_file(s: &MsPaths, path: &Path) -> anyhow::Result<()> {
     }
 }
 
-fn move_file(s: &MsPaths, path: &Path, new_path: &Path) -> anyhow::Result<()> {
+pub fn move_file(s: &MsPaths, path: &Path, new_path: &Path) -> anyhow::Result<()> {
     match std::fs::rename(path, new_path) {
         Ok(_) => {
             cleanup_directory(s, path);
@@ -220,6 +594,11 @@ static SANITIZE_OPTIONS: sanitise_file_name::Options<Option<char>> = sanitise_fi
     ..sanitise_file_name::Options::DEFAULT
 };
 
+/// Name of the library folder holding the tracks of `artist`.
+pub fn artist_folder(artist: &[String]) -> String {
+    sanitize_default(&artist.join("; "))
+}
+
 fn sanitize_default(s: &str) -> String {
     sanitise_with_options(s, &SANITIZE_OPTIONS)
 }
@@ -227,4 +606,12 @@ fn sanitize_default(s: &str) -> String {
 pub struct MetadataTags {
     pub youtube_id: String,
     pub brainz: BrainzMetadata,
+    /// Written when set, existing gain tags are kept otherwise
+    pub replaygain: Option<ReplayGain>,
+    /// Replaces the cover when set, the file keeps its cover otherwise
+    pub cover: Option<Picture>,
+    /// Writes the date and genres of `brainz`, the file keeps its own otherwise
+    pub date_and_genres: bool,
+    /// Written as comments, see [`MsConfig::custom_fields`](crate::MsConfig::custom_fields)
+    pub custom_fields: BTreeMap<String, String>,
 }
@@ -1,4 +1,4 @@
-use std::sync::LazyLock;
 use reqwest::Client;
+use std::sync::LazyLock;
 
-pub static CLIENT: LazyLock<Client> = LazyLock::new(|| Client::new());
+pub static CLIENT: LazyLock<Client> = LazyLock::new(Client::new);
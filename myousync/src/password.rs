@@ -0,0 +1,194 @@
+//! Password hashing for the `users` table.
+//!
+//! Passwords are hashed with Argon2id and stored as PHC strings, which carry their parameters.
+//! Older hashes, PBKDF2 as `pbkdf2-sha256$<iterations>$<salt>$<hash>` and the readable passwords
+//! of the first databases, are still accepted. They are replaced on the next sign in, like
+//! Argon2 hashes with other parameters than configured in `[passwords]`. Readable passwords are
+//! marked as `plain$<password>` by the database upgrade, any other value never matches.
+
+use std::{num::NonZeroU32, sync::RwLock};
+
+use anyhow::anyhow;
+use argon2::{
+    Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version,
+    password_hash::SaltString,
+};
+use ring::{
+    pbkdf2,
+    rand::{SecureRandom, SystemRandom},
+};
+
+use crate::MsPasswords;
+
+const PBKDF2_SCHEME: &str = "pbkdf2-sha256";
+/// Marks the readable passwords of databases from before hashing
+pub const LEGACY_PREFIX: &str = "plain$";
+const SALT_LEN: usize = 16;
+/// Shorter passwords are rejected when set through the api
+pub const MIN_LENGTH: usize = 8;
+
+static PARAMS: RwLock<Params> = RwLock::new(Params::DEFAULT);
+
+/// Uses the work factor of `config` for new hashes.
+pub fn configure(config: &MsPasswords) -> anyhow::Result<()> {
+    *PARAMS.write().unwrap() = params(config)?;
+    Ok(())
+}
+
+/// Checks the work factor of `config`.
+pub fn validate(config: &MsPasswords) -> anyhow::Result<()> {
+    params(config).map(|_| ())
+}
+
+fn params(config: &MsPasswords) -> anyhow::Result<Params> {
+    Params::new(
+        config.memory_kib,
+        config.iterations,
+        config.parallelism,
+        None,
+    )
+    .map_err(|err| anyhow!("Invalid passwords settings: {err}"))
+}
+
+fn argon2() -> Argon2<'static> {
+    Argon2::new(
+        Algorithm::Argon2id,
+        Version::V0x13,
+        PARAMS.read().unwrap().clone(),
+    )
+}
+
+/// Hashes `password` with a new random salt.
+pub fn hash(password: &str) -> String {
+    let mut salt = [0u8; SALT_LEN];
+    SystemRandom::new()
+        .fill(&mut salt)
+        .expect("System random is available");
+    let salt = SaltString::encode_b64(&salt).expect("Salt has a valid length");
+    argon2()
+        .hash_password(password.as_bytes(), &salt)
+        .expect("Parameters are validated")
+        .to_string()
+}
+
+/// Checks `password` against a hash of [`hash`] or an older one.
+pub fn verify(stored: &str, password: &str) -> bool {
+    if let Ok(hash) = PasswordHash::new(stored)
+        && hash.algorithm == Algorithm::Argon2id.ident()
+    {
+        return argon2().verify_password(password.as_bytes(), &hash).is_ok();
+    }
+    if let Some(plain) = stored.strip_prefix(LEGACY_PREFIX) {
+        return plain == password;
+    }
+    match stored
+        .strip_prefix(PBKDF2_SCHEME)
+        .and_then(|r| r.strip_prefix('$'))
+    {
+        Some(rest) => verify_pbkdf2(rest, password),
+        None => false,
+    }
+}
+
+/// Whether `stored` should be replaced by a new [`hash`], as it is an older kind of hash or
+/// uses other parameters than configured.
+pub fn needs_rehash(stored: &str) -> bool {
+    let Ok(hash) = PasswordHash::new(stored) else {
+        return true;
+    };
+    if hash.algorithm != Algorithm::Argon2id.ident() {
+        return true;
+    }
+    Params::try_from(&hash).map_or(true, |params| {
+        let current = PARAMS.read().unwrap();
+        params.m_cost() != current.m_cost()
+            || params.t_cost() != current.t_cost()
+            || params.p_cost() != current.p_cost()
+    })
+}
+
+/// Whether `stored` is an Argon2 or PBKDF2 hash rather than a readable password, by its format.
+pub fn is_hash(stored: &str) -> bool {
+    PasswordHash::new(stored).is_ok_and(|hash| {
+        hash.algorithm == Algorithm::Argon2id.ident() && hash.salt.is_some() && hash.hash.is_some()
+    }) || stored
+        .strip_prefix(PBKDF2_SCHEME)
+        .and_then(|r| r.strip_prefix('$'))
+        .and_then(parse_pbkdf2)
+        .is_some()
+}
+
+/// Splits `<iterations>$<salt>$<hash>` of a PBKDF2 hash.
+fn parse_pbkdf2(hash: &str) -> Option<(NonZeroU32, Vec<u8>, Vec<u8>)> {
+    let mut parts = hash.split('$');
+    let (Some(iterations), Some(salt), Some(hash), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return None;
+    };
+    Some((
+        iterations.parse().ok().and_then(NonZeroU32::new)?,
+        from_hex(salt)?,
+        from_hex(hash)?,
+    ))
+}
+
+fn verify_pbkdf2(hash: &str, password: &str) -> bool {
+    let Some((iterations, salt, hash)) = parse_pbkdf2(hash) else {
+        return false;
+    };
+    pbkdf2::verify(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        iterations,
+        &salt,
+        password.as_bytes(),
+        &hash,
+    )
+    .is_ok()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verify_accepts_hashes_and_marked_passwords() {
+        let stored = hash("secret password");
+        assert!(verify(&stored, "secret password"));
+        assert!(!verify(&stored, "other password"));
+        assert!(!needs_rehash(&stored));
+
+        let legacy = format!("{LEGACY_PREFIX}secret password");
+        assert!(verify(&legacy, "secret password"));
+        assert!(!verify(&legacy, "other password"));
+        assert!(needs_rehash(&legacy));
+    }
+
+    #[test]
+    fn hashes_are_told_apart_by_their_format() {
+        assert!(is_hash(&hash("secret password")));
+        assert!(is_hash("pbkdf2-sha256$1000$00ff$abcd"));
+        assert!(!is_hash("$secret password"));
+        assert!(!is_hash("$argon2id$broken"));
+        assert!(!is_hash("pbkdf2-sha256$secret password"));
+        assert!(!is_hash("secret password"));
+    }
+
+    #[test]
+    fn verify_rejects_unknown_formats() {
+        assert!(!verify("secret password", "secret password"));
+        assert!(!verify("$argon2id$broken", "$argon2id$broken"));
+        assert!(!verify("pbkdf2-sha256$1$zz$zz", "pbkdf2-sha256$1$zz$zz"));
+        assert!(!verify("", ""));
+    }
+}
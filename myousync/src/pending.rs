@@ -0,0 +1,101 @@
+//! Write-ahead records of moves into the library, so a crash between moving a file and storing
+//! the new status of its video is reconciled on the next start.
+
+use std::path::{Path, PathBuf};
+
+use chrono::Utc;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    MsState,
+    dbdata::{self, FetchStatus, VideoStatus},
+    musicfiles::{self, MetadataTags},
+};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PendingMove {
+    pub video_id: String,
+    pub source: PathBuf,
+    pub target: PathBuf,
+    /// Stored with the status once the move is done, e.g. for files which could not be tagged
+    pub last_error: Option<String>,
+    /// Unix timestamp
+    pub created: i64,
+}
+
+/// Moves the file of `status` to `target` in the library and marks the video as categorized.
+///
+/// The move is recorded before the file is touched, and the record is only removed once the new
+/// status is stored.
+#[tracing::instrument(name = "move", skip(s, status, tags))]
+pub fn move_to_library(
+    s: &MsState,
+    status: &mut VideoStatus,
+    source: &Path,
+    target: &Path,
+    tags: &MetadataTags,
+) -> anyhow::Result<()> {
+    dbdata::DB.set_pending_move(&PendingMove {
+        video_id: status.video_id.clone(),
+        source: source.to_owned(),
+        target: target.to_owned(),
+        last_error: status.last_error.clone(),
+        created: Utc::now().timestamp(),
+    })?;
+
+    if let Err(err) = musicfiles::move_file_to_library(s, source, target, tags) {
+        if let Err(err) = dbdata::DB.delete_pending_move(&status.video_id) {
+            error!(
+                "Failed to delete pending move of {}: {}",
+                status.video_id, err
+            );
+        }
+        return Err(err);
+    }
+    // Left pending if the status cannot be stored, so the next start completes the move
+    MsState::push_update_state(status, FetchStatus::Categorized)?;
+    dbdata::DB.delete_pending_move(&status.video_id)?;
+    Ok(())
+}
+
+/// Reconciles the moves interrupted by the last shutdown.
+///
+/// Moves whose file arrived in the library are completed. All others are dropped, their job
+/// processes the video again. Moves which cannot be reconciled now are kept for the next start.
+pub fn recover(s: &MsState) {
+    let pending_moves = match dbdata::DB.get_pending_moves() {
+        Ok(pending_moves) => pending_moves,
+        Err(err) => {
+            error!("Failed to get pending moves: {}", err);
+            return;
+        }
+    };
+    for pending in pending_moves {
+        if pending.target.exists() && !pending.source.exists() {
+            info!(
+                "Completing interrupted move of {} to {}",
+                pending.video_id,
+                pending.target.display()
+            );
+            s.file_cache
+                .insert(pending.video_id.clone(), pending.target.clone());
+            if let Err(err) = dbdata::DB.modify_video_status(&pending.video_id, &|v| {
+                v.fetch_status = FetchStatus::Categorized;
+                v.last_error = pending.last_error.clone();
+                true
+            }) {
+                error!("Failed to complete move of {}: {}", pending.video_id, err);
+                continue;
+            }
+        } else {
+            info!("Discarding interrupted move of {}", pending.video_id);
+        }
+        if let Err(err) = dbdata::DB.delete_pending_move(&pending.video_id) {
+            error!(
+                "Failed to delete pending move of {}: {}",
+                pending.video_id, err
+            );
+        }
+    }
+}
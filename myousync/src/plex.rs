@@ -0,0 +1,267 @@
+//! Keeps Plex playlists in sync with the synced playlists.
+//!
+//! Plex knows the tracks by the paths it sees, which differ from ours when it runs in another
+//! container or on another machine, so paths are rewritten by the configured prefixes first.
+
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{LazyLock, Mutex},
+};
+
+use log::{debug, info, warn};
+use reqwest::{Method, RequestBuilder};
+use serde::{Deserialize, de::DeserializeOwned};
+use thiserror::Error;
+
+use crate::{
+    MsPathRewrite, MsPlex, MsState,
+    dbdata::{self, FetchStatus, Playlist},
+    musicfiles,
+    net::CLIENT,
+    trace,
+};
+
+/// Rating keys of the music tracks known to Plex, by file path as Plex sees it
+static TRACKS: LazyLock<Mutex<HashMap<String, String>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Error, Debug)]
+pub enum PlexError {
+    #[error("Request failed: {0}")]
+    Connection(#[from] reqwest::Error),
+    #[error("Unexpected response from Plex")]
+    UnexpectedResponse,
+    #[error(transparent)]
+    Db(#[from] dbdata::DbError),
+}
+
+/// Replaces the items of the Plex playlist `title` with the library tracks of `playlist`, in
+/// playlist order. The Plex playlist is created if it does not exist yet.
+pub async fn sync_playlist(s: &MsState, title: &str, playlist: &Playlist) -> Result<(), PlexError> {
+    let Some(plex) = &s.config.plex else {
+        return Ok(());
+    };
+
+    let mut paths = Vec::new();
+    for item in &playlist.items {
+        if dbdata::DB.get_video_fetch_status(&item.video_id)? != Some(FetchStatus::Categorized) {
+            continue;
+        }
+        if let Some(path) = musicfiles::find_local_file(s, &item.video_id) {
+            paths.push(rewrite_path(&plex.path_rewrite, &path));
+        }
+    }
+
+    if paths
+        .iter()
+        .any(|p| !TRACKS.lock().unwrap().contains_key(p))
+    {
+        refresh_tracks(plex).await?;
+    }
+    let keys: Vec<String> = {
+        let tracks = TRACKS.lock().unwrap();
+        paths
+            .iter()
+            .filter_map(|p| {
+                let key = tracks.get(p);
+                if key.is_none() {
+                    debug!("Plex does not know {} yet", p);
+                }
+                key.cloned()
+            })
+            .collect()
+    };
+
+    let playlists: MediaContainer = get(plex, "/playlists?playlistType=audio").await?;
+    let existing = playlists
+        .media_container
+        .metadata
+        .into_iter()
+        .find(|p| p.title.as_deref() == Some(title));
+
+    let Some(existing) = existing else {
+        if keys.is_empty() {
+            return Ok(());
+        }
+        info!(
+            "Creating Plex playlist {} with {} tracks",
+            title,
+            keys.len()
+        );
+        let uri = items_uri(plex, &keys).await?;
+        request(plex, Method::POST, "/playlists")
+            .query(&[
+                ("type", "audio"),
+                ("title", title),
+                ("smart", "0"),
+                ("uri", &uri),
+            ])
+            .send()
+            .await?
+            .error_for_status()?;
+        return Ok(());
+    };
+
+    let items_path = format!("/playlists/{}/items", existing.rating_key);
+    let current: MediaContainer = get(plex, &items_path).await?;
+    let current_keys: Vec<String> = current
+        .media_container
+        .metadata
+        .into_iter()
+        .map(|m| m.rating_key)
+        .collect();
+    if current_keys == keys {
+        debug!("Plex playlist {} is up to date", title);
+        return Ok(());
+    }
+
+    info!("Updating Plex playlist {} to {} tracks", title, keys.len());
+    request(plex, Method::DELETE, &items_path)
+        .send()
+        .await?
+        .error_for_status()?;
+    if !keys.is_empty() {
+        let uri = items_uri(plex, &keys).await?;
+        request(plex, Method::PUT, &items_path)
+            .query(&[("uri", &uri)])
+            .send()
+            .await?
+            .error_for_status()?;
+    }
+    Ok(())
+}
+
+/// Checks that the server is reachable and accepts the token.
+pub async fn check(plex: &MsPlex) -> Result<(), PlexError> {
+    let identity: MediaContainer = get(plex, "/identity").await?;
+    identity
+        .media_container
+        .machine_identifier
+        .map(|_| ())
+        .ok_or(PlexError::UnexpectedResponse)
+}
+
+/// Maps a path of the music library to the path Plex sees, using the first matching prefix.
+fn rewrite_path(rewrites: &[MsPathRewrite], path: &Path) -> String {
+    for rewrite in rewrites {
+        if let Ok(rest) = path.strip_prefix(&rewrite.from) {
+            let rest = rest.to_string_lossy().replace('\\', "/");
+            return format!("{}/{}", rewrite.to.trim_end_matches('/'), rest);
+        }
+    }
+    path.to_string_lossy().into_owned()
+}
+
+async fn refresh_tracks(plex: &MsPlex) -> Result<(), PlexError> {
+    let sections: MediaContainer = get(plex, "/library/sections").await?;
+    let mut tracks = HashMap::new();
+    for section in sections
+        .media_container
+        .directory
+        .iter()
+        .filter(|d| d.kind == "artist")
+    {
+        let all: MediaContainer = get(
+            plex,
+            &format!("/library/sections/{}/all?type=10", section.key),
+        )
+        .await?;
+        for track in all.media_container.metadata {
+            for part in track.media.iter().flat_map(|m| &m.part) {
+                tracks.insert(part.file.clone(), track.rating_key.clone());
+            }
+        }
+    }
+    info!("Plex knows {} music files", tracks.len());
+    *TRACKS.lock().unwrap() = tracks;
+    Ok(())
+}
+
+/// Drops the known track keys after files were moved, so the next sync asks Plex again.
+pub fn forget_tracks() {
+    TRACKS.lock().unwrap().clear();
+}
+
+/// Builds the uri Plex expects for adding items to a playlist.
+async fn items_uri(plex: &MsPlex, keys: &[String]) -> Result<String, PlexError> {
+    let identity: MediaContainer = get(plex, "/identity").await?;
+    let machine = identity
+        .media_container
+        .machine_identifier
+        .ok_or(PlexError::UnexpectedResponse)?;
+    Ok(format!(
+        "server://{}/com.plexapp.plugins.library/library/metadata/{}",
+        machine,
+        keys.join(",")
+    ))
+}
+
+fn request(plex: &MsPlex, method: Method, path: &str) -> RequestBuilder {
+    CLIENT
+        .request(
+            method,
+            format!("{}{}", plex.url.trim_end_matches('/'), path),
+        )
+        .header("X-Plex-Token", &plex.token)
+        .header("Accept", "application/json")
+}
+
+async fn get<T: DeserializeOwned>(plex: &MsPlex, path: &str) -> Result<T, PlexError> {
+    let response = request(plex, Method::GET, path)
+        .send()
+        .await?
+        .error_for_status()?;
+    response.json().await.map_err(|err| {
+        warn!(
+            "Unexpected Plex response for {}{}: {:?}",
+            path,
+            trace::request_tag(),
+            err
+        );
+        PlexError::UnexpectedResponse
+    })
+}
+
+#[derive(Debug, Deserialize)]
+struct MediaContainer {
+    #[serde(rename = "MediaContainer")]
+    media_container: MediaContainerInner,
+}
+
+#[derive(Debug, Deserialize)]
+struct MediaContainerInner {
+    #[serde(rename = "machineIdentifier")]
+    machine_identifier: Option<String>,
+    #[serde(rename = "Directory", default)]
+    directory: Vec<PlexDirectory>,
+    #[serde(rename = "Metadata", default)]
+    metadata: Vec<PlexMetadata>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PlexDirectory {
+    key: String,
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct PlexMetadata {
+    #[serde(rename = "ratingKey")]
+    rating_key: String,
+    title: Option<String>,
+    #[serde(rename = "Media", default)]
+    media: Vec<PlexMedia>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PlexMedia {
+    #[serde(rename = "Part", default)]
+    part: Vec<PlexPart>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PlexPart {
+    file: String,
+}
@@ -0,0 +1,96 @@
+//! Support for running behind a reverse proxy, which forwards the client address, scheme and
+//! host in `X-Forwarded-*` headers.
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{HeaderMap, header},
+    middleware::Next,
+    response::Response,
+};
+
+use crate::{MsState, MsWeb};
+
+/// Where a request came from, available as request extension on every route.
+#[derive(Debug, Clone)]
+pub struct ClientInfo {
+    /// Address of the client, behind trusted proxies the forwarded one
+    pub ip: IpAddr,
+    /// `http` or `https`, as used by the client
+    pub scheme: String,
+    /// Host the client connected to, if known
+    pub host: Option<String>,
+}
+
+impl ClientInfo {
+    /// Builds the absolute url the client would use for `path` of the app, including the base
+    /// path. Returns just the path when the host is unknown.
+    pub fn url(&self, web: &MsWeb, path: &str) -> String {
+        match &self.host {
+            Some(host) => format!("{}://{}{}{}", self.scheme, host, web.base_path, path),
+            None => format!("{}{}", web.base_path, path),
+        }
+    }
+}
+
+/// Resolves the [`ClientInfo`] of the request.
+/// Forwarded headers are only honored when the direct peer is one of `web.trusted_proxies`.
+pub async fn client_info(State(s): State<MsState>, mut req: Request, next: Next) -> Response {
+    let peer = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED), |c| c.0.ip());
+    let info = resolve(&s.config.web, peer, req.headers());
+    req.extensions_mut().insert(info);
+    next.run(req).await
+}
+
+fn resolve(web: &MsWeb, peer: IpAddr, headers: &HeaderMap) -> ClientInfo {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.trim().to_owned())
+            .filter(|v| !v.is_empty())
+    };
+    let host = header(header::HOST.as_str());
+    let scheme = if web.tls_cert.is_some() {
+        "https"
+    } else {
+        "http"
+    };
+
+    if !web.trusted_proxies.contains(&peer) {
+        return ClientInfo {
+            ip: peer,
+            scheme: scheme.to_string(),
+            host,
+        };
+    }
+
+    // Each proxy appends the address it got the request from, so the client is the rightmost
+    // address which is not one of our proxies
+    let forwarded: Vec<IpAddr> = header("x-forwarded-for")
+        .iter()
+        .flat_map(|v| v.split(','))
+        .filter_map(|ip| ip.trim().parse().ok())
+        .collect();
+    let ip = forwarded
+        .iter()
+        .rev()
+        .find(|ip| !web.trusted_proxies.contains(ip))
+        .or(forwarded.first())
+        .copied()
+        .unwrap_or(peer);
+
+    // Only the first value is from the proxy facing the client
+    let first = |v: String| v.split(',').next().unwrap_or_default().trim().to_owned();
+    ClientInfo {
+        ip,
+        scheme: header("x-forwarded-proto")
+            .map(first)
+            .unwrap_or_else(|| scheme.to_string()),
+        host: header("x-forwarded-host").map(first).or(host),
+    }
+}
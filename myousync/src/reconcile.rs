@@ -0,0 +1,148 @@
+//! Re-binds videos to their files after the library was reorganized outside of myousync.
+//!
+//! Files are found by the youtube id in their tags wherever they are now. Files which lost that
+//! id, e.g. because another tagger rewrote them, are bound by their MusicBrainz recording id
+//! if exactly one video without a file has that recording.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use log::{info, warn};
+use serde::Serialize;
+use walkdir::WalkDir;
+
+use crate::{
+    MsState,
+    dbdata::{self, DbResult, FetchStatus, VideoStatus},
+    musicfiles, plex,
+};
+
+#[derive(Debug, Default, Serialize)]
+pub struct ReconcileReport {
+    /// Categorized videos whose file was found at a new path
+    pub moved: Vec<String>,
+    /// Videos marked as missing which have a file again
+    pub recovered: Vec<String>,
+    /// Videos bound to a file without youtube id by their recording id
+    pub bound: Vec<String>,
+    /// Categorized videos which still have no file
+    pub missing: Vec<String>,
+}
+
+/// Rescans the library, updates the file cache and the status of every video whose file was
+/// moved, found again or bound by its recording.
+pub fn reconcile(s: &MsState) -> DbResult<ReconcileReport> {
+    let videos: Vec<VideoStatus> = dbdata::DB
+        .get_all_videos()?
+        .into_iter()
+        .filter(|v| {
+            v.fetch_status == FetchStatus::Categorized || v.fetch_status == FetchStatus::FileMissing
+        })
+        .collect();
+    let known: HashMap<String, PathBuf> = videos
+        .iter()
+        .filter_map(|v| Some((v.video_id.clone(), s.file_cache.get(&v.video_id)?)))
+        .collect();
+
+    let mut by_id = HashMap::new();
+    let mut unbound = Vec::new();
+    scan(&s.config.paths.music, &mut by_id, &mut unbound);
+    if let Some(migrate) = &s.config.paths.migrate {
+        scan(migrate, &mut by_id, &mut unbound);
+    }
+    info!(
+        "Reconciling {} bound and {} unbound files",
+        by_id.len(),
+        unbound.len()
+    );
+    s.file_cache
+        .rebuild(s.file_cache.generation(), || by_id.clone());
+
+    let mut report = ReconcileReport::default();
+    for (recording_id, path) in unbound {
+        let mut candidates = videos.iter().filter(|v| {
+            !by_id.contains_key(&v.video_id)
+                && v.override_result
+                    .as_ref()
+                    .or(v.last_result.as_ref())
+                    .and_then(|r| r.brainz_recording_id.as_ref())
+                    == Some(&recording_id)
+        });
+        let (Some(video), None) = (candidates.next(), candidates.next()) else {
+            continue;
+        };
+        if let Err(err) = musicfiles::assign_video_id(s, &path, &video.video_id) {
+            warn!(
+                "Failed to bind {} to {}: {}",
+                path.display(),
+                video.video_id,
+                err
+            );
+            continue;
+        }
+        info!(
+            "Bound {} to {} by recording",
+            path.display(),
+            video.video_id
+        );
+        by_id.insert(video.video_id.clone(), path);
+        report.bound.push(video.video_id.clone());
+    }
+
+    for video in &videos {
+        let Some(path) = by_id.get(&video.video_id) else {
+            if video.fetch_status == FetchStatus::Categorized {
+                report.missing.push(video.video_id.clone());
+            }
+            continue;
+        };
+        if video.fetch_status == FetchStatus::FileMissing {
+            if let Some(v) = dbdata::DB.modify_video_status(&video.video_id, &|v| {
+                v.fetch_status = FetchStatus::Categorized;
+                v.last_error = None;
+                true
+            })? {
+                MsState::push_update_notification(&v);
+            }
+            report.recovered.push(video.video_id.clone());
+        } else if known.get(&video.video_id) != Some(path)
+            && !report.bound.contains(&video.video_id)
+        {
+            report.moved.push(video.video_id.clone());
+        }
+    }
+
+    if !report.moved.is_empty() || !report.recovered.is_empty() || !report.bound.is_empty() {
+        plex::forget_tracks();
+    }
+    info!(
+        "Reconciled library: {} moved, {} recovered, {} bound, {} missing",
+        report.moved.len(),
+        report.recovered.len(),
+        report.bound.len(),
+        report.missing.len()
+    );
+    Ok(report)
+}
+
+/// Collects the files below `path` by their youtube id, and the recording ids of files without
+/// one.
+fn scan(path: &Path, by_id: &mut HashMap<String, PathBuf>, unbound: &mut Vec<(String, PathBuf)>) {
+    for path in WalkDir::new(path)
+        .into_iter()
+        .filter_map(|p| p.ok())
+        .filter(|p| p.file_type().is_file())
+        .map(|f| f.into_path())
+    {
+        let Ok((tag, _)) = multitag::Tag::read_from_path_lenient(&path) else {
+            continue;
+        };
+        if let Some(video_id) = tag.get_comment("youtube_id") {
+            by_id.insert(video_id, path);
+        } else if let Some(recording_id) = musicfiles::read_recording_id(&tag) {
+            unbound.push((recording_id, path));
+        }
+    }
+}
@@ -0,0 +1,133 @@
+//! Mirroring of items which were removed from a synced playlist.
+
+use std::{collections::HashSet, path::Path};
+
+use chrono::Utc;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    MsPlaylist, MsState,
+    dbdata::{self, DbResult, FetchStatus, Playlist},
+    find_file, musicfiles,
+};
+
+/// What happens to the tracks of items removed from a playlist.
+/// Tracks which are still part of another playlist are always kept.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RemovalAction {
+    /// Removals are ignored
+    #[default]
+    Keep,
+    /// Only recorded in the status of the video
+    Mark,
+    /// The file is moved to the archive folder and the video disabled
+    Archive,
+    /// The file is deleted and the video disabled
+    Delete,
+}
+
+/// Records why a video was removed, stored with its status.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Removal {
+    pub playlist_id: String,
+    pub action: RemovalAction,
+    /// Unix timestamp
+    pub time: i64,
+}
+
+/// Applies the removal action of `config` to the items of `previous` which are gone from
+/// `current`.
+pub fn mirror_removals(
+    s: &MsState,
+    config: &MsPlaylist,
+    previous: &Playlist,
+    current: &Playlist,
+) -> DbResult<()> {
+    if config.on_removed == RemovalAction::Keep {
+        return Ok(());
+    }
+
+    let current_ids: HashSet<&str> = current.items.iter().map(|i| i.video_id.as_str()).collect();
+    for item in &previous.items {
+        if current_ids.contains(item.video_id.as_str())
+            || dbdata::DB.is_video_in_any_playlist(&item.video_id)?
+        {
+            continue;
+        }
+        let Some(mut status) = dbdata::DB.get_video(&item.video_id)? else {
+            continue;
+        };
+        if status.removal.is_some() || status.fetch_status == FetchStatus::Disabled {
+            continue;
+        }
+
+        // A dry run only records the removal and leaves the file alone
+        let action = if s.config.scrape.dry_run {
+            RemovalAction::Mark
+        } else {
+            config.on_removed
+        };
+        info!(
+            "Video {} was removed from {}, applying {:?}",
+            item.video_id, config.id, action
+        );
+        if let Err(err) = apply(s, action, &item.video_id) {
+            error!("Error removing video {}: {:?}", item.video_id, err);
+            status.last_error = Some(err.to_string());
+            MsState::push_update(&mut status)?;
+            continue;
+        }
+
+        status.removal = Some(Removal {
+            playlist_id: config.id.clone(),
+            action,
+            time: Utc::now().timestamp(),
+        });
+        if action != RemovalAction::Mark {
+            status.fetch_status = FetchStatus::Disabled;
+        }
+        MsState::push_update(&mut status)?;
+    }
+    Ok(())
+}
+
+fn apply(s: &MsState, action: RemovalAction, video_id: &str) -> anyhow::Result<()> {
+    match action {
+        RemovalAction::Keep | RemovalAction::Mark => Ok(()),
+        RemovalAction::Archive => {
+            let archive = s
+                .config
+                .paths
+                .archive
+                .as_deref()
+                .ok_or_else(|| anyhow::anyhow!("No archive folder configured"))?;
+            let _lock = s.file_cache.lock(video_id);
+            match find_file(s, video_id) {
+                Some(file) => archive_file(s, archive, &file),
+                None => Ok(()),
+            }
+        }
+        RemovalAction::Delete => {
+            dbdata::DB.delete_yt_data(video_id)?;
+            let _lock = s.file_cache.lock(video_id);
+            match find_file(s, video_id) {
+                Some(file) => musicfiles::delete_file(&s.config.paths, &file),
+                None => Ok(()),
+            }
+        }
+    }
+}
+
+/// Moves `file` into `archive`, keeping its path relative to the music folder.
+fn archive_file(s: &MsState, archive: &Path, file: &Path) -> anyhow::Result<()> {
+    let relative = file
+        .strip_prefix(&s.config.paths.music)
+        .unwrap_or_else(|_| Path::new(file.file_name().unwrap_or_default()));
+    let target = archive.join(relative);
+    if let Some(parent) = target.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    musicfiles::move_file(&s.config.paths, file, &target)
+}
@@ -0,0 +1,212 @@
+//! Sign in, sessions, users and api keys.
+
+use axum::{
+    Extension, Json, Router,
+    extract::{Path, Query},
+    http::StatusCode,
+    middleware,
+};
+use log::info;
+use serde::Deserialize;
+use tower_http::cors::CorsLayer;
+
+use crate::{MsState, apikeys, audit, auth, dbdata, proxy, setup, users};
+
+/// Also serves `/setup`, which works without signing in.
+pub fn routes(s: &MsState, cors_layer: &CorsLayer) -> Router {
+    Router::new()
+        .route(
+            "/login",
+            axum::routing::post({
+                let s = s.clone();
+                async move |Extension(client): Extension<proxy::ClientInfo>,
+                            headers: axum::http::HeaderMap,
+                            Json(user_data): Json<auth::SignInData>| {
+                    auth::sign_in(&s.config.web, &client, &headers, user_data).await
+                }
+            })
+            .layer(cors_layer.clone()),
+        )
+        .route(
+            "/login/refresh",
+            axum::routing::post({
+                let s = s.clone();
+                async move |Extension(client): Extension<proxy::ClientInfo>,
+                            Json(data): Json<auth::RefreshData>| {
+                    auth::refresh(&s.config.web, &client, &data).map(Json)
+                }
+            })
+            .layer(cors_layer.clone()),
+        )
+        .route(
+            "/logout",
+            axum::routing::post(async |Extension(claims): Extension<auth::Claims>| {
+                auth::logout(&claims)?;
+                Ok::<_, dbdata::DbError>(StatusCode::NO_CONTENT)
+            })
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/sessions",
+            axum::routing::get(async |Extension(claims): Extension<auth::Claims>| {
+                let owner = users::managed_owner(&claims)?;
+                Ok::<_, (StatusCode, String)>(Json(auth::list_sessions(&claims, owner)?))
+            })
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/session/{session}/revoke",
+            axum::routing::post(
+                async |Path(session_id): Path<String>,
+                       Extension(claims): Extension<auth::Claims>| {
+                    let owner = users::managed_owner(&claims)?;
+                    if dbdata::DB.delete_session(&session_id, owner)? {
+                        info!("Revoked session {}", session_id);
+                        audit::record(
+                            &claims,
+                            audit::AuditAction::RevokeSession,
+                            Some(&session_id),
+                            None,
+                        );
+                        Ok(StatusCode::NO_CONTENT)
+                    } else {
+                        Err((StatusCode::NOT_FOUND, "Session not found".to_string()))
+                    }
+                },
+            )
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/account/password",
+            axum::routing::post(
+                async |Extension(claims): Extension<auth::Claims>,
+                       Json(request): Json<users::ChangePassword>| {
+                    users::change_password(&claims, &request)?;
+                    audit::record(
+                        &claims,
+                        audit::AuditAction::ChangePassword,
+                        Some(&claims.user),
+                        None,
+                    );
+                    Ok::<_, (StatusCode, String)>(StatusCode::NO_CONTENT)
+                },
+            )
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/users",
+            axum::routing::get(async |Extension(claims): Extension<auth::Claims>| {
+                users::require_admin(&claims)?;
+                Ok::<_, (StatusCode, String)>(Json(dbdata::DB.get_users()?))
+            })
+            .post(
+                async |Extension(claims): Extension<auth::Claims>,
+                       Json(request): Json<users::CreateUser>| {
+                    users::require_admin(&claims)?;
+                    let user = users::create(&request)?;
+                    audit::record(
+                        &claims,
+                        audit::AuditAction::CreateUser,
+                        Some(&user.username),
+                        Some(serde_json::json!({ "admin": user.admin })),
+                    );
+                    Ok::<_, (StatusCode, String)>((StatusCode::CREATED, Json(user)))
+                },
+            )
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/users/{name}",
+            axum::routing::delete(
+                async |Path(name): Path<String>, Extension(claims): Extension<auth::Claims>| {
+                    users::require_admin(&claims)?;
+                    users::delete(&claims, &name)?;
+                    audit::record(&claims, audit::AuditAction::DeleteUser, Some(&name), None);
+                    Ok::<_, (StatusCode, String)>(StatusCode::NO_CONTENT)
+                },
+            )
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/api-keys",
+            axum::routing::get(async |Extension(claims): Extension<auth::Claims>| {
+                let owner = users::managed_owner(&claims)?;
+                Ok::<_, (StatusCode, String)>(Json(dbdata::DB.get_api_keys(owner)?))
+            })
+            .post(
+                async |Extension(claims): Extension<auth::Claims>,
+                       Json(request): Json<apikeys::CreateApiKey>| {
+                    apikeys::require_user(&claims)?;
+                    let created = apikeys::create(&claims.user, &request.name, request.scopes)?;
+                    audit::record(
+                        &claims,
+                        audit::AuditAction::CreateApiKey,
+                        Some(&created.info.key_id),
+                        Some(serde_json::json!({
+                            "name": created.info.name,
+                            "scopes": created.info.scopes,
+                        })),
+                    );
+                    Ok::<_, (StatusCode, String)>(Json(created))
+                },
+            )
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/api-key/{key}/revoke",
+            axum::routing::post(
+                async |Path(key_id): Path<String>, Extension(claims): Extension<auth::Claims>| {
+                    let owner = users::managed_owner(&claims)?;
+                    if dbdata::DB.delete_api_key(&key_id, owner)? {
+                        info!("Revoked api key {}", key_id);
+                        audit::record(
+                            &claims,
+                            audit::AuditAction::RevokeApiKey,
+                            Some(&key_id),
+                            None,
+                        );
+                        Ok(StatusCode::NO_CONTENT)
+                    } else {
+                        Err((StatusCode::NOT_FOUND, "Api key not found".to_string()))
+                    }
+                },
+            )
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/setup",
+            axum::routing::get({
+                let s = s.clone();
+                async move |Query(query): Query<SetupQuery>| {
+                    setup::status(&s, query.test_plex).await.map(Json)
+                }
+            })
+            .post({
+                let s = s.clone();
+                async move |Json(request): Json<setup::SetupRequest>| {
+                    setup::run(&s, request).await.map(Json)
+                }
+            })
+            .layer(cors_layer.clone()),
+        )
+        .route(
+            "/login/check",
+            axum::routing::post(async || "Ok")
+                .layer(cors_layer.clone())
+                .layer(middleware::from_fn(auth::auth)),
+        )
+}
+
+#[derive(Debug, Deserialize)]
+struct SetupQuery {
+    #[serde(default)]
+    test_plex: bool,
+}
@@ -0,0 +1,179 @@
+//! Diagnostics and maintenance, mostly only for admins.
+
+use std::time::Duration;
+
+use axum::{
+    Extension, Json, Router,
+    extract::{Path, Query},
+    http::StatusCode,
+    middleware,
+};
+use duration_str::deserialize_option_duration;
+use serde::Deserialize;
+use tower_http::cors::CorsLayer;
+
+use crate::{
+    MsState, TRIGGER_MUSIC_TAG, audit, auth, backup, dbdata, dryrun, import, reconcile, users,
+    util::limiter::Limiter,
+};
+
+/// `/admin/*` and the plans of a dry run.
+pub fn routes(s: &MsState, cors_layer: &CorsLayer) -> Router {
+    Router::new()
+        .route(
+            "/admin/limiters",
+            axum::routing::get(async |Extension(claims): Extension<auth::Claims>| {
+                users::require_admin(&claims)?;
+                Ok::<_, (StatusCode, String)>(Json(
+                    MsState::limiters().map(Limiter::status).collect::<Vec<_>>(),
+                ))
+            })
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/admin/file_cache",
+            axum::routing::get({
+                let s = s.clone();
+                async move |Extension(claims): Extension<auth::Claims>| {
+                    users::require_admin(&claims)?;
+                    Ok::<_, (StatusCode, String)>(Json(s.file_cache.status()))
+                }
+            })
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/admin/database",
+            axum::routing::get(async |Extension(claims): Extension<auth::Claims>| {
+                users::require_admin(&claims)?;
+                Ok::<_, (StatusCode, String)>(Json(dbdata::diagnostics()))
+            })
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/admin/audit",
+            axum::routing::get(
+                async |Extension(claims): Extension<auth::Claims>,
+                       Query(query): Query<audit::AuditQuery>| {
+                    users::require_admin(&claims)?;
+                    let page = dbdata::blocking(move |_| audit::list(&query)).await?;
+                    Ok::<_, (StatusCode, String)>(Json(page))
+                },
+            )
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/admin/backup",
+            axum::routing::post({
+                let s = s.clone();
+                async move |Extension(claims): Extension<auth::Claims>| {
+                    users::require_admin(&claims)?;
+                    let backup = dbdata::blocking(move |_| {
+                        backup::backup(s.config.database.backup.as_ref())
+                    })
+                    .await?;
+                    Ok::<_, (StatusCode, String)>(Json(backup))
+                }
+            })
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/admin/reconcile",
+            axum::routing::post({
+                let s = s.clone();
+                async move |Extension(claims): Extension<auth::Claims>| {
+                    users::require_admin(&claims)?;
+                    let report = tokio::task::spawn_blocking(move || reconcile::reconcile(&s))
+                        .await
+                        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))??;
+                    Ok::<_, (StatusCode, String)>(Json(report))
+                }
+            })
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/dry_run",
+            axum::routing::get({
+                let s = s.clone();
+                async move || {
+                    Json(dryrun::DryRun {
+                        enabled: s.config.scrape.dry_run,
+                        plans: dryrun::plans(),
+                    })
+                }
+            })
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/dry_run/clear",
+            axum::routing::post(async |Extension(claims): Extension<auth::Claims>| {
+                users::require_admin(&claims)?;
+                dryrun::clear();
+                _ = TRIGGER_MUSIC_TAG.send(());
+                Ok::<_, (StatusCode, String)>(())
+            })
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/admin/adopt",
+            axum::routing::post({
+                let s = s.clone();
+                async move |Extension(claims): Extension<auth::Claims>| {
+                    users::require_admin(&claims)?;
+                    let report = tokio::task::spawn_blocking(move || import::adopt_library(&s))
+                        .await
+                        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))??;
+                    Ok::<_, (StatusCode, String)>(Json(report))
+                }
+            })
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/admin/limiters/{name}",
+            axum::routing::post(
+                async move |Path(name): Path<String>,
+                            Extension(claims): Extension<auth::Claims>,
+                            Json(update): Json<LimiterUpdate>| {
+                    users::require_admin(&claims)?;
+                    let limiter = MsState::limiters()
+                        .find(|l| l.name() == name)
+                        .ok_or((StatusCode::NOT_FOUND, "Limiter not found".to_string()))?;
+                    if update.interval.is_some_and(|i| i > MAX_LIMITER_INTERVAL) {
+                        return Err((
+                            StatusCode::BAD_REQUEST,
+                            "Interval must be at most one day".to_string(),
+                        ));
+                    }
+
+                    limiter.set_override(update.interval);
+                    let stored = update
+                        .interval
+                        .map(|i| i.as_millis().to_string())
+                        .unwrap_or_default();
+                    dbdata::DB.set_key(&MsState::limiter_key(limiter), &stored)?;
+                    Ok(Json(limiter.status()))
+                },
+            )
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+}
+
+/// Runtime change of a limiter, `None` resets it to the configured interval.
+#[derive(Debug, Deserialize)]
+struct LimiterUpdate {
+    #[serde(deserialize_with = "deserialize_option_duration")]
+    #[serde(default)]
+    interval: Option<Duration>,
+}
+
+/// Upper bound for runtime limiter intervals, so a typo cannot stall scraping for years.
+const MAX_LIMITER_INTERVAL: Duration = Duration::from_secs(60 * 60 * 24);
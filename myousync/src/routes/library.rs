@@ -0,0 +1,302 @@
+//! Playlists, the library on disk and its artists and releases.
+
+use axum::{
+    Extension, Json, Router,
+    extract::{Path, Query},
+    http::StatusCode,
+    middleware,
+};
+use chrono::Utc;
+use log::error;
+use serde::Deserialize;
+use tower_http::cors::CorsLayer;
+
+use crate::{
+    MsState, albums, api, artist_rules, artists, audit, auth,
+    brainz::BrainzMetadata,
+    dbdata::{self, FetchStatus},
+    errors, mark_file_missing, musicfiles, stats, users,
+    util::queue::Priority,
+};
+
+use super::clean_result;
+
+/// `/library/*`, `/playlists` and `/migrate`, and the overview of `/stats`.
+pub fn routes(s: &MsState, cors_layer: &CorsLayer) -> Router {
+    Router::new()
+        .route(
+            "/stats",
+            axum::routing::get({
+                let s = s.clone();
+                async move || {
+                    let stats = tokio::task::spawn_blocking(move || stats::collect(&s))
+                        .await
+                        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))??;
+                    Ok::<_, (StatusCode, String)>(Json(stats))
+                }
+            })
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/errors/summary",
+            axum::routing::get(async || errors::summary().map(Json))
+                .layer(cors_layer.clone())
+                .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/trigger_sync",
+            axum::routing::post({
+                async move || {
+                    MsState::trigger_sync();
+                }
+            })
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/playlists",
+            axum::routing::get(async || dbdata::blocking(|db| db.get_playlists()).await.map(Json))
+                .layer(cors_layer.clone())
+                .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/playlists/{playlist}",
+            axum::routing::get(async move |Path(playlist_id): Path<String>| {
+                dbdata::DB
+                    .get_playlist_details(&playlist_id)?
+                    .map(Json)
+                    .ok_or((StatusCode::NOT_FOUND, "Playlist not found".to_string()))
+            })
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/library/integrity",
+            axum::routing::get(async || {
+                let missing =
+                    dbdata::blocking(|db| db.get_videos_in_status(FetchStatus::FileMissing))
+                        .await?;
+                Ok::<_, dbdata::DbError>(Json(
+                    missing.iter().map(api::Video::from).collect::<Vec<_>>(),
+                ))
+            })
+            .post({
+                let s = s.clone();
+                async move || {
+                    let missing = dbdata::blocking({
+                        let s = s.clone();
+                        move |db| {
+                            let missing = musicfiles::find_missing_files(&s)?;
+                            for video_id in &missing {
+                                if let Some(mut status) = db.get_video(video_id)?
+                                    && mark_file_missing(&s, &mut status)?.redownload
+                                {
+                                    MsState::enqueue_tagger(status.video_id, Priority::High);
+                                }
+                            }
+                            Ok::<_, (StatusCode, String)>(missing)
+                        }
+                    })
+                    .await?;
+                    Ok::<_, (StatusCode, String)>(Json(missing))
+                }
+            })
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/migrate",
+            axum::routing::get({
+                let s = s.clone();
+                async move || musicfiles::list_migrate_files(&s.config.paths).map(Json)
+            })
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/migrate/{file}/assign",
+            axum::routing::post({
+                let s = s.clone();
+                async move |Extension(claims): Extension<auth::Claims>,
+                            Path(file): Path<String>,
+                            Json(assign): Json<MigrateAssign>| {
+                    users::require_admin(&claims)?;
+                    let video_id = assign.video_id.trim();
+                    if video_id.is_empty() {
+                        return Err((StatusCode::BAD_REQUEST, "Missing video id".to_string()));
+                    }
+                    let path = musicfiles::resolve_migrate_file(&s.config.paths, &file)
+                        .ok_or_else(|| (StatusCode::NOT_FOUND, "File not found".to_string()))?;
+
+                    musicfiles::assign_video_id(&s, &path, video_id).map_err(|e| {
+                        error!("Error assigning file: {:?}", e);
+                        (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+                    })?;
+                    MsState::assign_video(video_id, assign.result.as_ref().map(clean_result))?;
+                    audit::record(
+                        &claims,
+                        audit::AuditAction::AssignFile,
+                        Some(video_id),
+                        Some(serde_json::json!({ "file": file })),
+                    );
+                    Ok(())
+                }
+            })
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/library/releases",
+            axum::routing::get({
+                let s = s.clone();
+                async move || {
+                    s.require_album_suggestions()?;
+                    Ok::<_, (StatusCode, String)>(Json(albums::synced_releases()?))
+                }
+            })
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/library/artists/duplicates",
+            axum::routing::get(async || {
+                dbdata::blocking(|_| artists::find_duplicates())
+                    .await
+                    .map(Json)
+            })
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/library/artists/duplicates/relayout",
+            axum::routing::post(async |Extension(claims): Extension<auth::Claims>| {
+                users::require_admin(&claims)?;
+                let moved = dbdata::blocking(|_| artists::relayout()).await?;
+                audit::record(
+                    &claims,
+                    audit::AuditAction::RelayoutArtists,
+                    None,
+                    Some(serde_json::json!({ "moved": moved })),
+                );
+                Ok::<_, (StatusCode, String)>(Json(moved))
+            })
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/library/artists/rules",
+            axum::routing::get(async || {
+                dbdata::blocking(|db| db.get_artist_rules()).await.map(Json)
+            })
+            .post(
+                async |Extension(claims): Extension<auth::Claims>,
+                       Json(rule): Json<artist_rules::ArtistRuleRequest>| {
+                    let rule = rule.normalize().map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+                    let rule = dbdata::blocking(move |db| {
+                        db.add_artist_rule(&rule, Utc::now().timestamp())
+                    })
+                    .await?;
+                    audit::record(
+                        &claims,
+                        audit::AuditAction::AddArtistRule,
+                        Some(&rule.rule_id.to_string()),
+                        Some(serde_json::json!(rule)),
+                    );
+                    Ok::<_, (StatusCode, String)>(Json(rule))
+                },
+            )
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/library/artists/rules/{rule}",
+            axum::routing::post(
+                async |Path(rule_id): Path<i64>,
+                       Extension(claims): Extension<auth::Claims>,
+                       Json(rule): Json<artist_rules::ArtistRuleRequest>| {
+                    let rule = rule.normalize().map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+                    let rule = dbdata::blocking(move |db| db.update_artist_rule(rule_id, &rule))
+                        .await?
+                        .ok_or((StatusCode::NOT_FOUND, "Rule not found".to_string()))?;
+                    audit::record(
+                        &claims,
+                        audit::AuditAction::UpdateArtistRule,
+                        Some(&rule.rule_id.to_string()),
+                        Some(serde_json::json!(rule)),
+                    );
+                    Ok::<_, (StatusCode, String)>(Json(rule))
+                },
+            )
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/library/artists/rules/{rule}/delete",
+            axum::routing::post(
+                async move |Path(rule_id): Path<i64>,
+                            Extension(claims): Extension<auth::Claims>| {
+                    if dbdata::blocking(move |db| db.delete_artist_rule(rule_id)).await? {
+                        audit::record(
+                            &claims,
+                            audit::AuditAction::DeleteArtistRule,
+                            Some(&rule_id.to_string()),
+                            None,
+                        );
+                        Ok(())
+                    } else {
+                        Err((StatusCode::NOT_FOUND, "Rule not found".to_string()))
+                    }
+                },
+            )
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/library/releases/{id}/missing",
+            axum::routing::get({
+                let s = s.clone();
+                async move |Path(release_id): Path<String>, Query(query): Query<MissingQuery>| {
+                    s.require_album_suggestions()?;
+                    albums::missing_tracks(&s, &release_id, query.search)
+                        .await
+                        .map(Json)
+                        .map_err(|e| {
+                            error!("Error getting missing tracks: {:?}", e);
+                            <(StatusCode, String)>::from(e)
+                        })
+                }
+            })
+            .post({
+                let s = s.clone();
+                async move |Path(release_id): Path<String>,
+                            Json(picks): Json<Vec<albums::TrackPick>>| {
+                    s.require_album_suggestions()?;
+                    albums::enqueue_tracks(&release_id, picks)
+                        .await
+                        .map_err(|e| {
+                            error!("Error enqueueing tracks: {:?}", e);
+                            <(StatusCode, String)>::from(e)
+                        })
+                }
+            })
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+}
+
+/// Body of `POST /migrate/{file}/assign`.
+#[derive(Debug, Deserialize)]
+struct MigrateAssign {
+    video_id: String,
+    /// Manual metadata to use instead of a brainz lookup
+    #[serde(default)]
+    result: Option<BrainzMetadata>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MissingQuery {
+    /// Search youtube for the missing tracks
+    #[serde(default)]
+    search: bool,
+}
@@ -0,0 +1,51 @@
+//! The api routes, grouped by what they manage.
+
+mod account;
+mod admin;
+mod library;
+mod video;
+
+use axum::Router;
+use tower_http::cors::CorsLayer;
+
+use crate::{MsState, brainz::BrainzMetadata};
+
+/// All api routes, without the websocket, the event stream and the ui.
+pub fn api(s: &MsState, cors_layer: &CorsLayer) -> Router {
+    Router::new()
+        .merge(account::routes(s, cors_layer))
+        .merge(video::routes(s, cors_layer))
+        .merge(library::routes(s, cors_layer))
+        .merge(admin::routes(s, cors_layer))
+}
+
+fn norm_string(s: Option<&str>) -> Option<String> {
+    s.and_then(|s| {
+        let s = s.trim();
+        if s.is_empty() {
+            None
+        } else {
+            Some(s.to_owned())
+        }
+    })
+}
+
+fn clean_result(r: &BrainzMetadata) -> BrainzMetadata {
+    BrainzMetadata {
+        title: r.title.trim().to_owned(),
+        artist: r.artist.iter().map(|s| s.trim().to_owned()).collect(),
+        album: norm_string(r.album.as_deref()),
+        brainz_recording_id: norm_string(r.brainz_recording_id.as_deref()),
+        brainz_release_id: norm_string(r.brainz_release_id.as_deref()),
+        brainz_artist_ids: r.brainz_artist_ids.clone(),
+        album_artist: norm_string(r.album_artist.as_deref()),
+        release_form: r.release_form.clone(),
+        date: norm_string(r.date.as_deref()),
+        genres: r
+            .genres
+            .iter()
+            .map(|g| g.trim().to_owned())
+            .filter(|g| !g.is_empty())
+            .collect(),
+    }
+}
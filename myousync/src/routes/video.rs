@@ -0,0 +1,683 @@
+//! Videos with their overrides, flags, duplicates and the jobs of the tagger.
+
+use axum::{
+    Extension, Json, Router,
+    body::Body,
+    extract::{Path, Query},
+    http::{Request, StatusCode},
+    middleware,
+    response::{IntoResponse, Redirect},
+};
+use chrono::{DateTime, Utc};
+use log::{debug, error, info};
+use serde::Deserialize;
+use tower_http::{cors::CorsLayer, services::ServeFile};
+
+use crate::{
+    MsState, api, audit, auth,
+    brainz::{BrainzMetadata, BrainzMultiSearch},
+    bulk,
+    dbdata::{self, FetchStatus, VideoStatus},
+    delete_video, deletion, duplicates, find_file, flags, proxy, trash, users,
+    util::{http_cache::cached_json, queue::Priority},
+    yt_api, ytdlp,
+};
+
+use super::{clean_result, norm_string};
+
+/// `/videos`, `/video/{video}/*` and the review lists of flags and duplicates.
+pub fn routes(s: &MsState, cors_layer: &CorsLayer) -> Router {
+    Router::new()
+        .route(
+            "/videos",
+            axum::routing::get(
+                async move |headers: axum::http::HeaderMap,
+                            Query(filter): Query<dbdata::VideoFilter>| {
+                    // Any change can move videos in or out of the filtered page
+                    let (filter, last_modified, page) = dbdata::blocking(move |db| {
+                        let last_modified = db.get_last_video_update();
+                        let page = db.get_videos_page(&filter);
+                        (filter, last_modified, page)
+                    })
+                    .await;
+                    let last_modified = last_modified?;
+                    let (total, videos) = page?;
+                    Ok::<_, dbdata::DbError>(cached_json(
+                        &headers,
+                        last_modified.and_then(|t| DateTime::from_timestamp(t as i64, 0)),
+                        &api::VideoPage {
+                            api_version: api::API_VERSION,
+                            total,
+                            offset: filter.offset,
+                            limit: filter.limit(),
+                            videos: videos.iter().map(api::Video::from).collect(),
+                        },
+                    ))
+                },
+            )
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/videos/bulk",
+            axum::routing::post({
+                let s = s.clone();
+                async move |Extension(claims): Extension<auth::Claims>,
+                            Json(request): Json<bulk::BulkRequest>| {
+                    let operation = request.operation;
+                    let result = tokio::task::spawn_blocking(move || bulk::run(&s, request))
+                        .await
+                        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+                        .map_err(|err| match err {
+                            bulk::BulkError::Db(err) => err.into(),
+                            err => (StatusCode::BAD_REQUEST, err.to_string()),
+                        })?;
+                    audit::record(
+                        &claims,
+                        audit::AuditAction::Bulk,
+                        None,
+                        Some(serde_json::json!({
+                            "operation": operation,
+                            "matched": result.matched,
+                            "changed": result.changed,
+                        })),
+                    );
+                    Ok::<_, (StatusCode, String)>(Json(result))
+                }
+            })
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/reindex",
+            axum::routing::post({
+                async move |Extension(claims): Extension<auth::Claims>,
+                            Json(video_ids): Json<Vec<String>>| {
+                    let video_ids = dbdata::blocking(move |db| {
+                        db.set_videos_reindex(&video_ids).map(|()| video_ids)
+                    })
+                    .await?;
+                    audit::record(
+                        &claims,
+                        audit::AuditAction::Reindex,
+                        None,
+                        Some(serde_json::json!({ "video_ids": video_ids })),
+                    );
+                    for video_id in video_ids {
+                        MsState::enqueue_tagger(video_id, Priority::Low);
+                    }
+                    Ok::<_, dbdata::DbError>(())
+                }
+            })
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/video/add",
+            axum::routing::post({
+                let s = s.clone();
+                async move |Extension(claims): Extension<auth::Claims>,
+                            Json(request): Json<AddVideo>| {
+                    let video_id = yt_api::parse_video_id(&request.url).ok_or_else(|| {
+                        (StatusCode::BAD_REQUEST, "Not a youtube video".to_string())
+                    })?;
+                    let playlist_id = match request.playlist {
+                        Some(playlist)
+                            if s.config.scrape.playlists.iter().any(|p| p.id == playlist) =>
+                        {
+                            playlist
+                        }
+                        Some(_) => {
+                            return Err((StatusCode::BAD_REQUEST, "Unknown playlist".to_string()));
+                        }
+                        None => dbdata::UNSORTED_PLAYLIST.to_string(),
+                    };
+                    let status = dbdata::blocking(move |db| {
+                        if db.get_video(&video_id)?.is_some() {
+                            return Err((
+                                StatusCode::CONFLICT,
+                                "Video is already tracked".to_string(),
+                            ));
+                        }
+
+                        info!(
+                            "Video {} added by {} to {}",
+                            video_id, claims.user, playlist_id
+                        );
+                        db.add_manual_video(
+                            &video_id,
+                            &playlist_id,
+                            &claims.user,
+                            Utc::now().timestamp(),
+                        )?;
+                        let mut status = VideoStatus {
+                            video_id: video_id.clone(),
+                            ..Default::default()
+                        };
+                        MsState::push_update(&mut status)?;
+                        MsState::enqueue_tagger(video_id, Priority::High);
+                        Ok(status)
+                    })
+                    .await?;
+                    Ok(Json(status))
+                }
+            })
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/video/{video}",
+            axum::routing::get(
+                async move |headers: axum::http::HeaderMap, Path(video_id): Path<String>| {
+                    let mut video = dbdata::blocking(move |db| db.get_video(&video_id))
+                        .await?
+                        .ok_or((StatusCode::NOT_FOUND, "Video not found".to_string()))?;
+                    video.download_progress = ytdlp::get_progress(&video.video_id);
+                    // The progress of a running download is not covered by last_update
+                    let last_modified = video
+                        .download_progress
+                        .is_none()
+                        .then(|| DateTime::from_timestamp(video.last_update as i64, 0))
+                        .flatten();
+                    Ok::<_, (StatusCode, String)>(cached_json(
+                        &headers,
+                        last_modified,
+                        &api::VideoDetail::new(&video),
+                    ))
+                },
+            )
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/video/{video}/retry_fetch",
+            axum::routing::post({
+                async move |Path(video_id): Path<String>| {
+                    dbdata::blocking(move |_| {
+                        MsState::push_override(&video_id, |v| {
+                            if v.is_downloaded() {
+                                return false;
+                            }
+                            v.fetch_status = FetchStatus::NotFetched;
+                            true
+                        })
+                    })
+                    .await?;
+                    Ok::<_, dbdata::DbError>(())
+                }
+            })
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/video/{video}/query",
+            axum::routing::post({
+                async move |Path(video_id): Path<String>,
+                            Extension(claims): Extension<auth::Claims>,
+                            Json(query): Json<Option<BrainzMultiSearch>>| {
+                    let cleaned_query = query.as_ref().map(|q| BrainzMultiSearch {
+                        trackid: norm_string(q.trackid.as_deref()),
+                        title: q.title.trim().to_owned(),
+                        artist: norm_string(q.artist.as_deref()),
+                        album: norm_string(q.album.as_deref()),
+                    });
+                    let target = video_id.clone();
+                    let changed = dbdata::blocking(move |_| {
+                        MsState::push_override(&target, |v| {
+                            if !v.is_downloaded() {
+                                return false;
+                            }
+                            v.override_query = cleaned_query.clone();
+                            v.fetch_status = FetchStatus::Fetched;
+                            true
+                        })
+                    })
+                    .await?;
+                    if changed {
+                        audit::record(
+                            &claims,
+                            audit::AuditAction::OverrideQuery,
+                            Some(&video_id),
+                            Some(serde_json::json!(query)),
+                        );
+                    }
+                    Ok::<_, dbdata::DbError>(())
+                }
+            })
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/video/{video}/result",
+            axum::routing::post({
+                async move |Path(video_id): Path<String>,
+                            Extension(claims): Extension<auth::Claims>,
+                            Json(result): Json<Option<BrainzMetadata>>| {
+                    let result = result.as_ref().map(clean_result);
+                    let (target, override_result) = (video_id.clone(), result.clone());
+                    let changed = dbdata::blocking(move |_| {
+                        MsState::push_override(&target, |v| {
+                            if !v.is_downloaded() {
+                                return false;
+                            }
+                            v.override_result = override_result.clone();
+                            v.fetch_status = FetchStatus::Fetched;
+                            true
+                        })
+                    })
+                    .await?;
+                    if changed {
+                        audit::record(
+                            &claims,
+                            audit::AuditAction::OverrideResult,
+                            Some(&video_id),
+                            Some(serde_json::json!(result)),
+                        );
+                    }
+                    Ok::<_, dbdata::DbError>(())
+                }
+            })
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/video/{video}/choose_candidate",
+            axum::routing::post(
+                async move |Path(video_id): Path<String>,
+                            Extension(claims): Extension<auth::Claims>,
+                            Json(choice): Json<ChooseCandidate>| {
+                    let target = video_id.clone();
+                    let (result, changed) = dbdata::blocking(move |db| {
+                        let video = db
+                            .get_video(&target)?
+                            .ok_or((StatusCode::NOT_FOUND, "Video not found".to_string()))?;
+                        let candidate = video
+                            .candidates
+                            .iter()
+                            .find(|c| {
+                                c.metadata.brainz_recording_id.as_deref()
+                                    == Some(choice.brainz_recording_id.as_str())
+                            })
+                            .ok_or((StatusCode::NOT_FOUND, "Candidate not found".to_string()))?;
+                        let result = clean_result(&candidate.metadata);
+                        let changed = MsState::push_override(&target, |v| {
+                            if !v.is_downloaded() {
+                                return false;
+                            }
+                            v.override_result = Some(result.clone());
+                            v.fetch_status = FetchStatus::Fetched;
+                            true
+                        })?;
+                        Ok::<_, (StatusCode, String)>((result, changed))
+                    })
+                    .await?;
+                    if changed {
+                        audit::record(
+                            &claims,
+                            audit::AuditAction::ChooseCandidate,
+                            Some(&video_id),
+                            Some(serde_json::json!(result)),
+                        );
+                    }
+                    Ok::<_, (StatusCode, String)>(())
+                },
+            )
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/video/{video}/matches",
+            axum::routing::get(async move |Path(video_id): Path<String>| {
+                dbdata::blocking(move |db| {
+                    if db.get_video(&video_id)?.is_none() {
+                        return Err((StatusCode::NOT_FOUND, "Video not found".to_string()));
+                    }
+                    Ok(Json(api::MatchHistory::new(
+                        video_id.clone(),
+                        db.get_match_attempts(&video_id)?,
+                    )))
+                })
+                .await
+            })
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/video/{video}/history",
+            axum::routing::get(async move |Path(video_id): Path<String>| {
+                dbdata::blocking(move |db| {
+                    if db.get_video(&video_id)?.is_none() {
+                        return Err((StatusCode::NOT_FOUND, "Video not found".to_string()));
+                    }
+                    Ok(Json(api::StatusHistory::new(
+                        video_id.clone(),
+                        db.get_status_history(&video_id)?,
+                    )))
+                })
+                .await
+            })
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/video/{video}/delete",
+            axum::routing::post({
+                let s = s.clone();
+                async move |Path(video_id): Path<String>,
+                            Extension(claims): Extension<auth::Claims>,
+                            Query(query): Query<DeleteQuery>| {
+                    let target = video_id.clone();
+                    let (force, token) = (query.force, query.token);
+                    // The confirmation token, if the deletion was not confirmed yet
+                    let confirmation = dbdata::blocking(move |db| {
+                        let confirmed = if force {
+                            None
+                        } else {
+                            let path = find_file(&s, &target);
+                            let Some(token) = token else {
+                                if db.get_video(&target)?.is_none() {
+                                    return Err((
+                                        StatusCode::NOT_FOUND,
+                                        "Video not found".to_string(),
+                                    ));
+                                }
+                                let request = deletion::request(&target, path.as_deref());
+                                return Ok(Some(Json(request).into_response()));
+                            };
+                            let confirmed = deletion::confirm(&token, &target, path.as_deref())
+                                .map_err(|err| {
+                                    match err {
+                                    deletion::ConfirmError::InvalidToken => (
+                                        StatusCode::FORBIDDEN,
+                                        "Invalid or expired confirmation token".to_string(),
+                                    ),
+                                    deletion::ConfirmError::FileChanged => (
+                                        StatusCode::CONFLICT,
+                                        "The file changed since the confirmation, request a new one"
+                                            .to_string(),
+                                    ),
+                                }
+                                })?;
+                            Some(confirmed)
+                        };
+
+                        match delete_video(&s, &target) {
+                            Ok(true) => {}
+                            Ok(false) => {
+                                return Err((StatusCode::NOT_FOUND, "Video not found".to_string()));
+                            }
+                            Err(err) => return Err((StatusCode::INTERNAL_SERVER_ERROR, err)),
+                        }
+                        if let Some(confirmed) = confirmed {
+                            confirmed.consume();
+                        }
+                        Ok(None)
+                    })
+                    .await?;
+                    if let Some(confirmation) = confirmation {
+                        return Ok(confirmation);
+                    }
+                    audit::record(
+                        &claims,
+                        audit::AuditAction::DeleteVideo,
+                        Some(&video_id),
+                        Some(serde_json::json!({ "force": force })),
+                    );
+                    Ok::<_, (StatusCode, String)>(().into_response())
+                }
+            })
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/video/{video}/restore",
+            axum::routing::post({
+                let s = s.clone();
+                async move |Path(video_id): Path<String>,
+                            Extension(claims): Extension<auth::Claims>| {
+                    let target = video_id.clone();
+                    tokio::task::spawn_blocking(move || trash::restore(&s, &video_id))
+                        .await
+                        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+                        .map_err(|err| match err {
+                            trash::RestoreError::NotTrashed => {
+                                (StatusCode::NOT_FOUND, err.to_string())
+                            }
+                            trash::RestoreError::Occupied(_) => {
+                                (StatusCode::CONFLICT, err.to_string())
+                            }
+                            trash::RestoreError::Io(_) => {
+                                (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+                            }
+                            trash::RestoreError::Db(err) => err.into(),
+                        })?;
+                    audit::record(
+                        &claims,
+                        audit::AuditAction::RestoreVideo,
+                        Some(&target),
+                        None,
+                    );
+                    Ok::<_, (StatusCode, String)>(())
+                }
+            })
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/trash",
+            axum::routing::get(async || {
+                dbdata::blocking(|db| db.get_trashed_files())
+                    .await
+                    .map(Json)
+            })
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/video/{video}/preview",
+            axum::routing::get({
+                let s = s.clone();
+                async move |headers: axum::http::HeaderMap, Path(video_id): Path<String>| {
+                    // The file may still be moved by someone not holding the lock, like a user
+                    // reorganizing the library, so a vanished file is looked up once more
+                    for _ in 0..2 {
+                        let (lock, path) = tokio::task::spawn_blocking({
+                            let s = s.clone();
+                            let video_id = video_id.clone();
+                            move || (s.file_cache.lock(&video_id), find_file(&s, &video_id))
+                        })
+                        .await
+                        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+                        let Some(path) = path else {
+                            break;
+                        };
+
+                        let mut req = Request::new(Body::empty());
+                        *req.headers_mut() = headers.clone();
+                        // Once opened, the file can be moved without breaking the response
+                        let response = ServeFile::new(path).try_call(req).await.map_err(|e| {
+                            error!("Error serving file: {:?}", e);
+                            (
+                                StatusCode::INTERNAL_SERVER_ERROR,
+                                "Error serving file".to_string(),
+                            )
+                        })?;
+                        drop(lock);
+                        if response.status() != StatusCode::NOT_FOUND {
+                            return Ok(response);
+                        }
+                        debug!("File of {} vanished, resolving it again", video_id);
+                    }
+
+                    Err((StatusCode::NOT_FOUND, "File not found".to_string()))
+                }
+            })
+            .layer(cors_layer.clone()), //.layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/video/{video}/flag",
+            axum::routing::post({
+                let s = s.clone();
+                async move |Path(video_id): Path<String>,
+                            Extension(claims): Extension<auth::Claims>,
+                            Extension(client): Extension<proxy::ClientInfo>,
+                            Json(request): Json<flags::FlagRequest>| {
+                    let target = video_id.clone();
+                    if dbdata::blocking(move |db| db.get_video(&target))
+                        .await?
+                        .is_none()
+                    {
+                        return Err((StatusCode::NOT_FOUND, "Video not found".to_string()));
+                    }
+                    Ok(Json(
+                        flags::flag_video(&s, &client, &video_id, request, &claims.user).await?,
+                    ))
+                }
+            })
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/flags",
+            axum::routing::get(async || dbdata::DB.get_open_flags().map(Json))
+                .layer(cors_layer.clone())
+                .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/flags/{flag}/resolve",
+            axum::routing::post(
+                async move |Extension(claims): Extension<auth::Claims>,
+                            Path(flag_id): Path<i64>| {
+                    users::require_admin(&claims)?;
+                    if dbdata::DB.resolve_flag(flag_id, Utc::now().timestamp())? {
+                        Ok(())
+                    } else {
+                        Err((StatusCode::NOT_FOUND, "Flag not found".to_string()))
+                    }
+                },
+            )
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/duplicates",
+            axum::routing::get(async || {
+                dbdata::blocking(|db| db.get_open_duplicates())
+                    .await
+                    .map(Json)
+            })
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/duplicates/{duplicate}/resolve",
+            axum::routing::post({
+                let s = s.clone();
+                async move |Extension(claims): Extension<auth::Claims>,
+                            Path(duplicate_id): Path<i64>,
+                            Json(request): Json<duplicates::ResolveRequest>| {
+                    users::require_admin(&claims)?;
+                    let keep = request.keep;
+                    let duplicate = dbdata::blocking(move |db| {
+                        let Some(duplicate) = db.get_open_duplicate(duplicate_id)? else {
+                            return Err((StatusCode::NOT_FOUND, "Duplicate not found".to_string()));
+                        };
+                        duplicates::resolve(&s, &duplicate, keep)
+                            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+                        Ok(duplicate)
+                    })
+                    .await?;
+                    audit::record(
+                        &claims,
+                        audit::AuditAction::ResolveDuplicate,
+                        Some(&duplicate.video_id),
+                        Some(serde_json::json!({
+                            "duplicate_id": duplicate_id,
+                            "keep": request.keep.as_str(),
+                        })),
+                    );
+                    Ok::<_, (StatusCode, String)>(())
+                }
+            })
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/queue",
+            axum::routing::get({
+                let s = s.clone();
+                async move || Json(s.queue_status())
+            })
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/jobs",
+            axum::routing::get(async || dbdata::DB.get_jobs().map(Json))
+                .layer(cors_layer.clone())
+                .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/jobs/{video}/retry",
+            axum::routing::post(async move |Path(video_id): Path<String>| {
+                if dbdata::DB.get_job(&video_id)?.is_none() {
+                    return Err((StatusCode::NOT_FOUND, "Job not found".to_string()));
+                }
+                MsState::enqueue_tagger(video_id, Priority::High);
+                Ok(())
+            })
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/video/{video}/queue",
+            axum::routing::get({
+                let s = s.clone();
+                async move |Path(video_id): Path<String>| {
+                    s.queue_status()
+                        .into_iter()
+                        .find(|q| q.video_id == video_id)
+                        .map(Json)
+                        .ok_or((StatusCode::NOT_FOUND, "Video not queued".to_string()))
+                }
+            })
+            .layer(cors_layer.clone())
+            .layer(middleware::from_fn(auth::auth)),
+        )
+        .route(
+            "/video/{video}/thumbnail",
+            axum::routing::get(async move |Path(video_id): Path<String>| {
+                dbdata::blocking(move |db| db.get_thumbnail(&video_id))
+                    .await?
+                    .map(|url| Redirect::temporary(&url))
+                    .ok_or((StatusCode::NOT_FOUND, "Thumbnail not found".to_string()))
+            })
+            .layer(cors_layer.clone()),
+        )
+}
+
+/// Query of `POST /video/{video}/delete`. Without either field only a confirmation is returned.
+#[derive(Debug, Deserialize)]
+struct DeleteQuery {
+    /// Token of the confirmation
+    token: Option<String>,
+    /// Deletes without a confirmation
+    #[serde(default)]
+    force: bool,
+}
+
+/// Body of `POST /video/{video}/choose_candidate`.
+#[derive(Debug, Deserialize)]
+struct ChooseCandidate {
+    brainz_recording_id: String,
+}
+
+/// Body of `POST /video/add`.
+#[derive(Debug, Deserialize)]
+struct AddVideo {
+    /// A youtube url or a bare video id
+    url: String,
+    /// One of the synced playlists, by default the video is unsorted
+    #[serde(default)]
+    playlist: Option<String>,
+}
@@ -0,0 +1,93 @@
+//! CORS policy and security headers of the web server.
+//!
+//! Without `web.allowed_origins` the api answers requests of any origin, which suits a ui served
+//! from elsewhere. `web.strict_cors` limits it to the listed origins, none if the list is empty,
+//! and to the headers the ui actually sends.
+
+use axum::{
+    Extension,
+    extract::Request,
+    http::{HeaderValue, Method, header},
+    middleware::Next,
+    response::Response,
+};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+use crate::{MsWeb, apikeys, proxy::ClientInfo, trace};
+
+/// Allows loading the ui and its assets from the app only. Inline scripts and styles are part of
+/// the built ui, images like covers may come from anywhere.
+const CONTENT_SECURITY_POLICY: &str = "default-src 'self'; script-src 'self' 'unsafe-inline'; \
+    style-src 'self' 'unsafe-inline'; img-src 'self' data: https:; media-src 'self'; \
+    connect-src 'self' ws: wss:; frame-ancestors 'none'; base-uri 'self'; form-action 'self'";
+const STRICT_TRANSPORT_SECURITY: &str = "max-age=31536000";
+
+pub fn cors_layer(web: &MsWeb) -> CorsLayer {
+    let origins = web
+        .allowed_origins
+        .iter()
+        .map(|origin| HeaderValue::from_str(origin).expect("Origins are validated in read"))
+        .collect::<Vec<_>>();
+    let allow_origin = if origins.is_empty() && !web.strict_cors {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(origins)
+    };
+    let allow_headers = if web.strict_cors {
+        vec![
+            header::AUTHORIZATION,
+            header::CONTENT_TYPE,
+            header::IF_NONE_MATCH,
+            header::IF_MODIFIED_SINCE,
+            "last-event-id".parse().unwrap(),
+            apikeys::API_KEY,
+        ]
+    } else {
+        vec![header::AUTHORIZATION, "*".parse().unwrap()]
+    };
+
+    CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_headers(allow_headers)
+        .allow_methods(vec![Method::GET, Method::POST, Method::DELETE])
+        .expose_headers([header::ETAG, header::LAST_MODIFIED, trace::REQUEST_ID])
+}
+
+/// Adds the security headers to every response. Needs the [`ClientInfo`] of the request, to only
+/// send HSTS to clients which use https.
+pub async fn security_headers(
+    Extension(client): Extension<ClientInfo>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let mut response = next.run(req).await;
+    let is_html = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("text/html"));
+
+    let headers = response.headers_mut();
+    headers.insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        HeaderValue::from_static("nosniff"),
+    );
+    headers.insert(header::X_FRAME_OPTIONS, HeaderValue::from_static("DENY"));
+    headers.insert(
+        header::REFERRER_POLICY,
+        HeaderValue::from_static("no-referrer"),
+    );
+    if is_html {
+        headers.insert(
+            header::CONTENT_SECURITY_POLICY,
+            HeaderValue::from_static(CONTENT_SECURITY_POLICY),
+        );
+    }
+    if client.scheme == "https" {
+        headers.insert(
+            header::STRICT_TRANSPORT_SECURITY,
+            HeaderValue::from_static(STRICT_TRANSPORT_SECURITY),
+        );
+    }
+    response
+}
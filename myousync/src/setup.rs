@@ -0,0 +1,184 @@
+//! First-run setup, available through the web UI as long as no user exists.
+//!
+//! Creates the admin user, checks that the configured folders are usable and stores the YouTube
+//! client credentials when the config does not provide them.
+
+use std::path::Path;
+
+use axum::http::StatusCode;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    MsState, auth,
+    dbdata::{self, DbResult},
+    password, plex, users, yt_api,
+};
+
+#[derive(Debug, Serialize)]
+pub struct SetupStatus {
+    /// False once a user exists
+    pub required: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub paths: Vec<PathCheck>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub youtube_configured: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub plex: Option<ServiceCheck>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PathCheck {
+    pub name: &'static str,
+    pub path: String,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ServiceCheck {
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetupRequest {
+    pub username: String,
+    pub password: String,
+    pub youtube: Option<SetupYoutube>,
+    /// Connects to the configured Plex server before finishing the setup
+    #[serde(default)]
+    pub test_plex: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetupYoutube {
+    pub client_id: String,
+    pub client_secret: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SetupResult {
+    /// Signs the new admin in right away
+    #[serde(flatten)]
+    pub tokens: auth::Tokens,
+    pub status: SetupStatus,
+}
+
+pub async fn status(s: &MsState, test_plex: bool) -> DbResult<SetupStatus> {
+    if dbdata::DB.has_users()? {
+        return Ok(SetupStatus {
+            required: false,
+            paths: Vec::new(),
+            youtube_configured: None,
+            plex: None,
+        });
+    }
+
+    let mut paths = vec![
+        check_path("music", &s.config.paths.music),
+        check_path("temp", &s.config.paths.temp),
+    ];
+    if let Some(migrate) = &s.config.paths.migrate {
+        paths.push(check_path("migrate", migrate));
+    }
+    if let Some(archive) = &s.config.paths.archive {
+        paths.push(check_path("archive", archive));
+    }
+
+    let plex = match &s.config.plex {
+        Some(config) if test_plex => Some(ServiceCheck {
+            error: plex::check(config).await.err().map(|err| err.to_string()),
+        }),
+        _ => None,
+    };
+
+    Ok(SetupStatus {
+        required: true,
+        paths,
+        youtube_configured: Some(yt_api::client_credentials(&s.config).is_ok()),
+        plex,
+    })
+}
+
+pub async fn run(s: &MsState, request: SetupRequest) -> Result<SetupResult, (StatusCode, String)> {
+    if dbdata::DB.has_users()? {
+        return Err((StatusCode::CONFLICT, "Setup is already done".to_string()));
+    }
+    if request.username.trim().is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Username must not be empty".to_string(),
+        ));
+    }
+    users::check_password(&request.password)?;
+    if let Some(youtube) = &request.youtube {
+        if !s.config.youtube.client_id.is_empty() {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "YouTube credentials are already set in the config".to_string(),
+            ));
+        }
+        if youtube.client_id.trim().is_empty() || youtube.client_secret.trim().is_empty() {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "YouTube client id and secret must not be empty".to_string(),
+            ));
+        }
+    }
+
+    let status = status(s, request.test_plex).await?;
+    if let Some(path) = status.paths.iter().find(|p| p.error.is_some()) {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!(
+                "The {} folder {} is not usable: {}",
+                path.name,
+                path.path,
+                path.error.as_deref().unwrap_or_default()
+            ),
+        ));
+    }
+
+    let username = request.username.trim();
+    if !dbdata::DB.add_first_user(username, &password::hash(&request.password))? {
+        return Err((StatusCode::CONFLICT, "Setup is already done".to_string()));
+    }
+    if let Some(youtube) = &request.youtube {
+        dbdata::DB.set_key(yt_api::CLIENT_ID_KEY, youtube.client_id.trim())?;
+        dbdata::DB.set_key(yt_api::CLIENT_SECRET_KEY, youtube.client_secret.trim())?;
+    }
+    info!("Setup done, created admin user {}", username);
+
+    let tokens = auth::start_session(&s.config.web, username.to_owned(), None, None)
+        .map_err(|err| (err.status_code, err.message))?;
+    Ok(SetupResult {
+        tokens,
+        status: SetupStatus {
+            required: false,
+            youtube_configured: Some(yt_api::client_credentials(&s.config).is_ok()),
+            ..status
+        },
+    })
+}
+
+/// Creates the folder if needed and checks that files can be written to it.
+fn check_path(name: &'static str, path: &Path) -> PathCheck {
+    let probe = path.join(".myousync-setup");
+    let error = std::fs::create_dir_all(path)
+        .and_then(|()| std::fs::write(&probe, b""))
+        .and_then(|()| std::fs::remove_file(&probe))
+        .err()
+        .map(|err| {
+            warn!(
+                "Setup: {} folder {} is not usable: {}",
+                name,
+                path.display(),
+                err
+            );
+            err.to_string()
+        });
+    PathCheck {
+        name,
+        path: path.display().to_string(),
+        error,
+    }
+}
@@ -0,0 +1,59 @@
+//! Stops the process on SIGINT or SIGTERM without losing work.
+//!
+//! On the first signal no new jobs are started, while running ones get `scrape.shutdown_timeout`
+//! to finish. Jobs still running after that are dropped, which kills their yt-dlp process, and
+//! run again on the next start since their rows are still in the `jobs` table. A second signal
+//! exits right away.
+
+use std::{sync::LazyLock, time::Duration};
+
+use log::{info, warn};
+use tokio::sync::watch;
+
+static SHUTDOWN: LazyLock<watch::Sender<bool>> = LazyLock::new(|| watch::channel(false).0);
+
+/// Resolves once a shutdown was requested.
+pub async fn requested() {
+    let mut rx = SHUTDOWN.subscribe();
+    // The sender is static and never dropped
+    _ = rx.wait_for(|requested| *requested).await;
+}
+
+/// Requests a shutdown on the first signal and waits up to `timeout` for running jobs.
+///
+/// Returns when the jobs did not finish in time. The tagger returns on its own once it is done,
+/// which ends the process earlier.
+pub async fn listen(timeout: Duration) {
+    signal().await;
+    info!(
+        "Shutting down, waiting up to {:?} for running jobs. Signal again to exit now.",
+        timeout
+    );
+    SHUTDOWN.send_replace(true);
+
+    tokio::select! {
+        _ = tokio::time::sleep(timeout) => {
+            warn!("Running jobs did not finish in time, stopping them");
+        }
+        _ = signal() => {
+            warn!("Signaled again, exiting now");
+            std::process::exit(130);
+        }
+    }
+}
+
+async fn signal() {
+    #[cfg(unix)]
+    {
+        let mut term = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = term.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        _ = tokio::signal::ctrl_c().await;
+    }
+}
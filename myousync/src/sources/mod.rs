@@ -0,0 +1,82 @@
+//! Providers of the playlists to sync.
+//!
+//! Every provider turns its playlists into a [`Playlist`] whose items are identified by the ids
+//! the rest of the pipeline works with. Providers without downloads of their own, like Spotify,
+//! map their tracks onto youtube videos. Others, like SoundCloud, use prefixed ids which
+//! [`download_target`] resolves for yt-dlp.
+
+mod soundcloud;
+mod spotify;
+mod youtube;
+
+use std::{future::Future, pin::Pin};
+
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::{
+    MsPlaylist, MsState,
+    dbdata::{DbError, Playlist},
+    yt_api::YTError,
+    ytdlp::YtDlpError,
+};
+
+pub type SourceFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, SourceError>> + Send + 'a>>;
+
+#[derive(Error, Debug)]
+pub enum SourceError {
+    #[error("YouTube error: {0}")]
+    YouTube(#[from] YTError),
+    #[error("yt-dlp error: {0}")]
+    YtDlp(#[from] YtDlpError),
+    #[error("Request failed: {0}")]
+    Connection(#[from] reqwest::Error),
+    #[error("Failed to parse response: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("No {0} credentials configured")]
+    MissingCredentials(&'static str),
+    #[error(transparent)]
+    Db(#[from] DbError),
+}
+
+/// The provider a playlist is synced from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceKind {
+    #[default]
+    Youtube,
+    Spotify,
+    Soundcloud,
+}
+
+pub trait SourceProvider: Send + Sync {
+    /// Fetches the current items of the playlist and stores them in the playlist cache.
+    fn get_playlist<'a>(
+        &'a self,
+        s: &'a MsState,
+        playlist_id: &'a str,
+    ) -> SourceFuture<'a, Playlist>;
+}
+
+/// What yt-dlp downloads a video from.
+pub struct DownloadTarget {
+    pub url: String,
+    /// Passed to `--use-extractors`
+    pub extractor: &'static str,
+}
+
+fn provider(kind: SourceKind) -> &'static dyn SourceProvider {
+    match kind {
+        SourceKind::Youtube => &youtube::YoutubeSource,
+        SourceKind::Spotify => &spotify::SpotifySource,
+        SourceKind::Soundcloud => &soundcloud::SoundcloudSource,
+    }
+}
+
+pub async fn get_playlist(s: &MsState, config: &MsPlaylist) -> Result<Playlist, SourceError> {
+    provider(config.provider).get_playlist(s, &config.id).await
+}
+
+pub fn download_target(video_id: &str) -> DownloadTarget {
+    soundcloud::download_target(video_id).unwrap_or_else(|| youtube::download_target(video_id))
+}
@@ -0,0 +1,67 @@
+//! SoundCloud sets, listed and downloaded with the yt-dlp extractors.
+
+use chrono::Utc;
+use log::debug;
+
+use crate::{
+    MsState,
+    dbdata::{self, Playlist, PlaylistInfo, PlaylistItem},
+    ytdlp,
+};
+
+use super::{DownloadTarget, SourceFuture, SourceProvider};
+
+/// Marks the ids of SoundCloud tracks, so they do not clash with youtube ids
+const ID_PREFIX: &str = "sc-";
+
+pub struct SoundcloudSource;
+
+impl SourceProvider for SoundcloudSource {
+    /// `playlist_id` is the path of the set, like `artist/sets/name`.
+    fn get_playlist<'a>(
+        &'a self,
+        s: &'a MsState,
+        playlist_id: &'a str,
+    ) -> SourceFuture<'a, Playlist> {
+        Box::pin(async move {
+            let url = format!("https://soundcloud.com/{}", playlist_id.trim_matches('/'));
+            let set = ytdlp::get_flat_playlist(s, &url).await?;
+            debug!("Got {} tracks from {}", set.entries.len(), url);
+
+            let items: Vec<PlaylistItem> = set
+                .entries
+                .into_iter()
+                .map(|entry| PlaylistItem {
+                    video_id: format!("{ID_PREFIX}{}", entry.id),
+                    title: entry.title.unwrap_or_default(),
+                    artist: entry.uploader.unwrap_or_default(),
+                    duration: entry.duration.map(|d| d as u32),
+                    thumbnail: None,
+                })
+                .collect();
+            let playlist = Playlist {
+                playlist_id: playlist_id.to_owned(),
+                etag: String::new(),
+                total_results: items.len() as u32,
+                fetch_time: Utc::now(),
+                info: PlaylistInfo {
+                    title: set.title,
+                    description: set.description.filter(|d| !d.is_empty()),
+                    owner: set.uploader,
+                    thumbnail: None,
+                },
+                items,
+            };
+            dbdata::DB.set_playlist(&playlist)?;
+            Ok(playlist)
+        })
+    }
+}
+
+pub fn download_target(video_id: &str) -> Option<DownloadTarget> {
+    let track_id = video_id.strip_prefix(ID_PREFIX)?;
+    Some(DownloadTarget {
+        url: format!("https://api.soundcloud.com/tracks/{track_id}"),
+        extractor: "soundcloud",
+    })
+}
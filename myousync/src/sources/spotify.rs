@@ -0,0 +1,242 @@
+//! Spotify playlists. Spotify offers no downloads, so every track is matched to a youtube video by
+//! searching for its artists and title.
+
+use std::sync::Mutex;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use log::{debug, error, info, warn};
+use serde::Deserialize;
+
+use crate::{
+    MsSpotify, MsState,
+    dbdata::{self, Playlist, PlaylistInfo, PlaylistItem},
+    net::CLIENT,
+    ytdlp,
+};
+
+use super::{SourceError, SourceFuture, SourceProvider};
+
+const PROVIDER: &str = "spotify";
+
+/// Access token of the client credentials flow, with its expiry
+static TOKEN: Mutex<Option<(String, DateTime<Utc>)>> = Mutex::new(None);
+
+pub struct SpotifySource;
+
+impl SourceProvider for SpotifySource {
+    fn get_playlist<'a>(
+        &'a self,
+        s: &'a MsState,
+        playlist_id: &'a str,
+    ) -> SourceFuture<'a, Playlist> {
+        Box::pin(get_playlist(s, playlist_id))
+    }
+}
+
+async fn get_playlist(s: &MsState, playlist_id: &str) -> Result<Playlist, SourceError> {
+    let config = s
+        .config
+        .spotify
+        .as_ref()
+        .ok_or(SourceError::MissingCredentials(PROVIDER))?;
+    let token = get_token(config).await?;
+
+    let details: SpotifyPlaylist = CLIENT
+        .get(format!(
+            "https://api.spotify.com/v1/playlists/{playlist_id}"
+        ))
+        .query(&[(
+            "fields",
+            "snapshot_id,name,description,owner(display_name),images",
+        )])
+        .bearer_auth(&token)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    // The snapshot id changes with every edit of the playlist
+    if let Some(cached) = dbdata::DB.try_get_playlist(playlist_id)?
+        && cached.etag == details.snapshot_id
+    {
+        debug!("Found cached playlist by snapshot id");
+        dbdata::DB.update_playlist_fetch_time(playlist_id, Utc::now())?;
+        return Ok(cached);
+    }
+
+    let mut tracks = Vec::new();
+    let mut next = Some(format!(
+        "https://api.spotify.com/v1/playlists/{playlist_id}/tracks?limit=100&fields=next,items(track(id,name,duration_ms,artists(name),album(images)))"
+    ));
+    while let Some(url) = next {
+        let page: SpotifyTracksPage = CLIENT
+            .get(url)
+            .bearer_auth(&token)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        tracks.extend(page.items.into_iter().filter_map(|i| i.track));
+        next = page.next;
+    }
+
+    let mut items = Vec::with_capacity(tracks.len());
+    for track in tracks {
+        let Some(track_id) = track.id else {
+            // Local files of the playlist owner
+            continue;
+        };
+        let artist = track
+            .artists
+            .iter()
+            .map(|a| a.name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let Some(video_id) = match_video(s, &track_id, &artist, &track.name).await? else {
+            continue;
+        };
+        items.push(PlaylistItem {
+            video_id,
+            title: track.name,
+            artist,
+            duration: Some((track.duration_ms / 1000) as u32),
+            thumbnail: track
+                .album
+                .and_then(|a| a.images.into_iter().last())
+                .map(|i| i.url),
+        });
+    }
+
+    let playlist = Playlist {
+        playlist_id: playlist_id.to_owned(),
+        etag: details.snapshot_id,
+        total_results: items.len() as u32,
+        fetch_time: Utc::now(),
+        info: PlaylistInfo {
+            title: Some(details.name),
+            description: details.description.filter(|d| !d.is_empty()),
+            owner: details.owner.and_then(|o| o.display_name),
+            thumbnail: details.images.into_iter().next().map(|i| i.url),
+        },
+        items,
+    };
+    dbdata::DB.set_playlist(&playlist)?;
+    Ok(playlist)
+}
+
+/// Finds the youtube video for a track, remembering the match so each track is searched once.
+async fn match_video(
+    s: &MsState,
+    track_id: &str,
+    artist: &str,
+    title: &str,
+) -> Result<Option<String>, SourceError> {
+    if let Some(video_id) = dbdata::DB.get_source_match(PROVIDER, track_id)? {
+        return Ok(Some(video_id));
+    }
+
+    let query = format!("ytsearch1:{artist} - {title}");
+    let video_id = match ytdlp::get_flat_playlist(s, &query).await {
+        Ok(result) => result.entries.into_iter().next().map(|e| e.id),
+        Err(err) => {
+            warn!("Failed to search youtube for {}: {:?}", track_id, err);
+            return Ok(None);
+        }
+    };
+    match &video_id {
+        Some(video_id) => {
+            info!("Matched spotify track {} to {}", track_id, video_id);
+            if let Err(err) = dbdata::DB.set_source_match(PROVIDER, track_id, video_id) {
+                error!(
+                    "Failed to store match of spotify track {}: {}",
+                    track_id, err
+                );
+            }
+        }
+        None => warn!("No youtube video found for {} - {}", artist, title),
+    }
+    Ok(video_id)
+}
+
+async fn get_token(config: &MsSpotify) -> Result<String, SourceError> {
+    if let Some((token, expires)) = TOKEN.lock().unwrap().as_ref()
+        && *expires > Utc::now()
+    {
+        return Ok(token.clone());
+    }
+
+    let response: SpotifyToken = CLIENT
+        .post("https://accounts.spotify.com/api/token")
+        .basic_auth(&config.client_id, Some(&config.client_secret))
+        .form(&[("grant_type", "client_credentials")])
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    // Renewed a minute early, so it does not expire in the middle of a sync
+    let expires = Utc::now() + TimeDelta::seconds(response.expires_in - 60);
+    *TOKEN.lock().unwrap() = Some((response.access_token.clone(), expires));
+    Ok(response.access_token)
+}
+
+#[derive(Debug, Deserialize)]
+struct SpotifyToken {
+    access_token: String,
+    expires_in: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct SpotifyPlaylist {
+    snapshot_id: String,
+    name: String,
+    description: Option<String>,
+    owner: Option<SpotifyOwner>,
+    #[serde(default)]
+    images: Vec<SpotifyImage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SpotifyOwner {
+    display_name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SpotifyImage {
+    url: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct SpotifyTracksPage {
+    next: Option<String>,
+    items: Vec<SpotifyPlaylistItem>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SpotifyPlaylistItem {
+    track: Option<SpotifyTrack>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SpotifyTrack {
+    id: Option<String>,
+    name: String,
+    duration_ms: u64,
+    #[serde(default)]
+    artists: Vec<SpotifyArtist>,
+    album: Option<SpotifyAlbum>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SpotifyArtist {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct SpotifyAlbum {
+    #[serde(default)]
+    images: Vec<SpotifyImage>,
+}
@@ -0,0 +1,22 @@
+use crate::{MsState, dbdata::Playlist, yt_api};
+
+use super::{DownloadTarget, SourceFuture, SourceProvider};
+
+pub struct YoutubeSource;
+
+impl SourceProvider for YoutubeSource {
+    fn get_playlist<'a>(
+        &'a self,
+        s: &'a MsState,
+        playlist_id: &'a str,
+    ) -> SourceFuture<'a, Playlist> {
+        Box::pin(async move { Ok(yt_api::get_playlist(&s.config, playlist_id).await?) })
+    }
+}
+
+pub fn download_target(video_id: &str) -> DownloadTarget {
+    DownloadTarget {
+        url: format!("https://www.youtube.com/watch?v={video_id}"),
+        extractor: "youtube",
+    }
+}
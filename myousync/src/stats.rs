@@ -0,0 +1,134 @@
+//! Figures of the library and the tagger for a dashboard, as returned by `GET /stats`.
+
+use std::path::{Path, PathBuf};
+
+use chrono::{Days, NaiveDate, Utc};
+use serde::Serialize;
+use walkdir::WalkDir;
+
+use crate::{
+    MsState,
+    dbdata::{self, DbResult, FetchStatus},
+    errors::ErrorCode,
+};
+
+/// Days covered by [`Stats::downloads`]
+const DOWNLOAD_DAYS: u64 = 30;
+/// Codes listed in [`Stats::top_errors`]
+const TOP_ERRORS: u32 = 5;
+
+#[derive(Debug, Serialize)]
+pub struct Stats {
+    /// Categorized tracks
+    pub tracks: u64,
+    pub artists: u64,
+    pub albums: u64,
+    pub disk_usage: Vec<DiskUsage>,
+    pub statuses: Vec<StatusCount>,
+    /// Downloads of every day, oldest first and including today
+    pub downloads: Vec<DayCount>,
+    /// The most frequent error codes, most frequent first
+    pub top_errors: Vec<ErrorCount>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DiskUsage {
+    /// The config key of the folder, like `music`
+    pub name: &'static str,
+    pub path: PathBuf,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StatusCount {
+    pub status: FetchStatus,
+    pub count: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DayCount {
+    /// UTC date as YYYY-MM-DD
+    pub day: String,
+    pub count: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorCount {
+    pub code: ErrorCode,
+    pub count: u64,
+}
+
+/// Walks all configured folders but the music library, whose size is taken from the file index.
+pub fn collect(s: &MsState) -> DbResult<Stats> {
+    let (tracks, artists, albums) = dbdata::DB.get_library_totals()?;
+
+    let paths = &s.config.paths;
+    let mut disk_usage = vec![DiskUsage {
+        name: "music",
+        path: paths.music.clone(),
+        bytes: dbdata::DB.get_indexed_size()?,
+    }];
+    for (name, path) in [
+        ("temp", Some(&paths.temp)),
+        ("migrate", paths.migrate.as_ref()),
+        ("archive", paths.archive.as_ref()),
+        ("trash", paths.trash.as_ref()),
+    ] {
+        if let Some(path) = path {
+            disk_usage.push(DiskUsage {
+                name,
+                path: path.clone(),
+                bytes: folder_size(path),
+            });
+        }
+    }
+
+    let today = Utc::now().date_naive();
+    let first_day = today - Days::new(DOWNLOAD_DAYS - 1);
+    let since = first_day
+        .and_hms_opt(0, 0, 0)
+        .unwrap()
+        .and_utc()
+        .timestamp();
+    let per_day = dbdata::DB.get_downloads_per_day(since)?;
+    let downloads = first_day
+        .iter_days()
+        .take_while(|day| *day <= today)
+        .map(|day: NaiveDate| {
+            let day = day.format("%Y-%m-%d").to_string();
+            let count = per_day
+                .iter()
+                .find(|(d, _)| *d == day)
+                .map_or(0, |(_, count)| *count);
+            DayCount { day, count }
+        })
+        .collect();
+
+    Ok(Stats {
+        tracks,
+        artists,
+        albums,
+        disk_usage,
+        statuses: dbdata::DB
+            .get_status_counts()?
+            .into_iter()
+            .map(|(status, count)| StatusCount { status, count })
+            .collect(),
+        downloads,
+        top_errors: dbdata::DB
+            .get_error_counts(TOP_ERRORS)?
+            .into_iter()
+            .map(|(code, count)| ErrorCount { code, count })
+            .collect(),
+    })
+}
+
+fn folder_size(path: &Path) -> u64 {
+    WalkDir::new(path)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter_map(|entry| entry.metadata().ok())
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len())
+        .sum()
+}
@@ -0,0 +1,67 @@
+//! Exports traces of the tagging pipeline, the sync loops and the web requests over OTLP, to be
+//! inspected in Jaeger, Tempo or any other OpenTelemetry backend.
+//!
+//! Spans are only recorded with `[telemetry]` set. Otherwise they are discarded right away,
+//! as tracing would write them into the log.
+
+use std::sync::Mutex;
+
+use anyhow::Context;
+use log::warn;
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{
+    Resource, runtime,
+    trace::{Sampler, SdkTracerProvider, span_processor_with_async_runtime::BatchSpanProcessor},
+};
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::{Layer, filter::Targets, layer::SubscriberExt};
+
+use crate::MsTelemetry;
+
+static PROVIDER: Mutex<Option<SdkTracerProvider>> = Mutex::new(None);
+
+/// Installs the tracing subscriber, exporting spans if `config` is set.
+pub fn init(config: Option<&MsTelemetry>) -> anyhow::Result<()> {
+    let layer = match config {
+        Some(config) => {
+            let exporter = SpanExporter::builder()
+                .with_http()
+                .with_endpoint(&config.endpoint)
+                .build()
+                .context("Failed to create the OTLP exporter")?;
+            let provider = SdkTracerProvider::builder()
+                .with_span_processor(BatchSpanProcessor::builder(exporter, runtime::Tokio).build())
+                .with_sampler(Sampler::TraceIdRatioBased(config.sample_ratio))
+                .with_resource(
+                    Resource::builder()
+                        .with_service_name(config.service_name.clone())
+                        .build(),
+                )
+                .build();
+            let tracer = provider.tracer(env!("CARGO_CRATE_NAME"));
+            *PROVIDER.lock().unwrap() = Some(provider);
+            tracing_opentelemetry::layer()
+                .with_tracer(tracer)
+                .with_filter(
+                    Targets::new().with_target(env!("CARGO_CRATE_NAME"), LevelFilter::DEBUG),
+                )
+                .boxed()
+        }
+        None => LevelFilter::OFF.boxed(),
+    };
+    tracing::subscriber::set_global_default(tracing_subscriber::registry().with(layer))
+        .context("Failed to install the tracing subscriber")
+}
+
+/// Sends the spans which are not exported yet.
+pub async fn shutdown() {
+    let Some(provider) = PROVIDER.lock().unwrap().take() else {
+        return;
+    };
+    match tokio::task::spawn_blocking(move || provider.shutdown()).await {
+        Ok(Ok(())) => {}
+        Ok(Err(err)) => warn!("Failed to export the remaining spans: {}", err),
+        Err(err) => warn!("Failed to export the remaining spans: {}", err),
+    }
+}
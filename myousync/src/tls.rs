@@ -0,0 +1,123 @@
+//! Serves the web app over https with `web.tls_cert` and `web.tls_key`, for setups without a
+//! reverse proxy which terminates TLS.
+//!
+//! Handshakes run in their own tasks, so a client which never finishes its handshake does not
+//! hold up the others. With `web.http_redirect_port` plain http requests on that port are
+//! redirected to https.
+
+use std::{io, net::SocketAddr, path::Path, sync::Arc, time::Duration};
+
+use anyhow::Context;
+use axum::{
+    Router,
+    http::{StatusCode, Uri, header},
+    response::{IntoResponse, Redirect},
+    serve::Listener,
+};
+use log::{debug, info, warn};
+use rustls_pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+};
+use tokio_rustls::{TlsAcceptor, rustls::ServerConfig, server::TlsStream};
+
+use crate::MsWeb;
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Finished handshakes waiting to be served
+const BACKLOG: usize = 64;
+
+/// Accepts TLS connections on a TCP listener.
+pub struct TlsListener {
+    connections: mpsc::Receiver<(TlsStream<TcpStream>, SocketAddr)>,
+    local_addr: SocketAddr,
+}
+
+impl TlsListener {
+    pub fn new(listener: TcpListener, cert: &Path, key: &Path) -> anyhow::Result<Self> {
+        let acceptor = TlsAcceptor::from(Arc::new(server_config(cert, key)?));
+        let local_addr = listener.local_addr()?;
+        let (tx, connections) = mpsc::channel(BACKLOG);
+        tokio::spawn(async move {
+            loop {
+                let (stream, addr) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(err) => {
+                        warn!("Error accepting connection: {}", err);
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        continue;
+                    }
+                };
+                let acceptor = acceptor.clone();
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                        Ok(Ok(stream)) => _ = tx.send((stream, addr)).await,
+                        Ok(Err(err)) => debug!("TLS handshake with {} failed: {}", addr, err),
+                        Err(_) => debug!("TLS handshake with {} timed out", addr),
+                    }
+                });
+            }
+        });
+        Ok(TlsListener {
+            connections,
+            local_addr,
+        })
+    }
+}
+
+impl Listener for TlsListener {
+    type Io = TlsStream<TcpStream>;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self.connections.recv().await {
+            Some(connection) => connection,
+            // The accept task only ends with the runtime
+            None => std::future::pending().await,
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        Ok(self.local_addr)
+    }
+}
+
+fn server_config(cert: &Path, key: &Path) -> anyhow::Result<ServerConfig> {
+    let certs = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("Failed to read certificates from {}", cert.display()))?;
+    let key = PrivateKeyDer::from_pem_file(key)
+        .with_context(|| format!("Failed to read private key from {}", key.display()))?;
+    let mut config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(config)
+}
+
+/// Redirects all requests on `port` to the https server, returns an error if the port cannot be
+/// bound.
+pub async fn run_redirect(web: &MsWeb, port: u16) -> io::Result<()> {
+    let https_port = web.port;
+    let app = Router::new().fallback(async move |headers: axum::http::HeaderMap, uri: Uri| {
+        let Some(host) = headers
+            .get(header::HOST)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.parse::<axum::http::uri::Authority>().ok())
+        else {
+            return (StatusCode::BAD_REQUEST, "Missing host").into_response();
+        };
+        let path = uri.path_and_query().map_or("/", |p| p.as_str());
+        let target = match https_port {
+            443 => format!("https://{}{}", host.host(), path),
+            port => format!("https://{}:{}{}", host.host(), port, path),
+        };
+        Redirect::permanent(&target).into_response()
+    });
+
+    let listener = TcpListener::bind(format!("0.0.0.0:{port}")).await?;
+    info!("Redirecting http on port {} to https", port);
+    axum::serve(listener, app).await
+}
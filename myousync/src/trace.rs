@@ -0,0 +1,134 @@
+//! Logs every request of the web server with its method, path, status and latency.
+//!
+//! Requests get an id, which is returned in `X-Request-Id` and tagged onto the error logs of the
+//! external services called while handling the request. An id sent by the client or a proxy is
+//! kept, so their logs can be matched with ours.
+
+use std::{net::IpAddr, sync::Arc, time::Duration};
+
+use axum::{
+    body::Body,
+    extract::Request,
+    http::{HeaderName, HeaderValue, Response},
+    middleware::Next,
+};
+use log::{info, warn};
+use rand::distr::{Alphanumeric, SampleString};
+use tower_http::{
+    classify::{ServerErrorsAsFailures, SharedClassifier},
+    trace::TraceLayer,
+};
+use tracing::Span;
+
+use crate::proxy::ClientInfo;
+
+pub const REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+/// Longer ids of clients are replaced
+const MAX_ID_LENGTH: usize = 64;
+
+tokio::task_local! {
+    static CURRENT: Arc<RequestContext>;
+}
+
+/// The request which is handled by the current task, available as request extension too.
+#[derive(Debug)]
+pub struct RequestContext {
+    pub id: String,
+    pub method: String,
+    pub path: String,
+    /// Address of the client, see [`ClientInfo`]
+    pub client_ip: Option<IpAddr>,
+}
+
+/// The context of the request handled by the current task. Work done in spawned tasks and in
+/// the background has none.
+pub fn current() -> Option<Arc<RequestContext>> {
+    CURRENT.try_with(Arc::clone).ok()
+}
+
+/// Appended to error logs, like ` (request 1a2b3c)`, or empty outside of requests.
+pub fn request_tag() -> String {
+    current().map_or_else(String::new, |ctx| format!(" (request {})", ctx.id))
+}
+
+/// Assigns the request id. Must wrap the [`layer`], which logs the request.
+pub async fn request_id(mut req: Request, next: Next) -> axum::response::Response {
+    let id = req
+        .headers()
+        .get(&REQUEST_ID)
+        .and_then(|h| h.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= MAX_ID_LENGTH)
+        .map_or_else(
+            || Alphanumeric.sample_string(&mut rand::rng(), 12),
+            str::to_string,
+        );
+    let ctx = Arc::new(RequestContext {
+        id,
+        method: req.method().to_string(),
+        // Without the query, which can contain a token
+        path: req.uri().path().to_string(),
+        client_ip: req.extensions().get::<ClientInfo>().map(|c| c.ip),
+    });
+    req.extensions_mut().insert(ctx.clone());
+
+    let header = HeaderValue::from_str(&ctx.id).ok();
+    let mut response = CURRENT.scope(ctx, next.run(req)).await;
+    if let Some(header) = header {
+        response.headers_mut().insert(REQUEST_ID, header);
+    }
+    response
+}
+
+type RequestTrace = TraceLayer<
+    SharedClassifier<ServerErrorsAsFailures>,
+    fn(&Request) -> Span,
+    (),
+    fn(&Response<Body>, Duration, &Span),
+    (),
+    (),
+    (),
+>;
+
+/// Opens a span for every request and logs its response.
+pub fn layer() -> RequestTrace {
+    TraceLayer::new_for_http()
+        .make_span_with(make_span as fn(&Request) -> Span)
+        .on_request(())
+        .on_response(log_response as fn(&Response<Body>, Duration, &Span))
+        .on_body_chunk(())
+        .on_eos(())
+        .on_failure(())
+}
+
+fn make_span(req: &Request) -> Span {
+    let id = req
+        .extensions()
+        .get::<Arc<RequestContext>>()
+        .map(|ctx| ctx.id.clone());
+    // Debug, as tracing forwards spans to the log too
+    tracing::debug_span!(
+        "request",
+        method = %req.method(),
+        path = req.uri().path(),
+        request_id = id,
+    )
+}
+
+fn log_response(res: &Response<Body>, latency: Duration, _: &Span) {
+    let Some(ctx) = current() else {
+        return;
+    };
+    let status = res.status();
+    let ms = latency.as_millis();
+    if status.is_server_error() {
+        warn!(
+            "{} {} {} in {}ms (request {})",
+            ctx.method, ctx.path, status, ms, ctx.id
+        );
+    } else {
+        info!(
+            "{} {} {} in {}ms (request {})",
+            ctx.method, ctx.path, status, ms, ctx.id
+        );
+    }
+}
@@ -0,0 +1,159 @@
+//! Deleted tracks are moved into the trash folder instead of being removed, so a deletion can be
+//! undone until the track expires and is purged.
+//!
+//! Every track is kept in a folder named after its video, which avoids collisions between
+//! tracks with the same file name. The download data of the video is only dropped on purge, so
+//! a restored video does not have to be downloaded again.
+
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use chrono::Utc;
+use log::{error, info};
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::{
+    MsState,
+    dbdata::{self, DbError, FetchStatus},
+    musicfiles,
+};
+
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TrashedFile {
+    pub video_id: String,
+    /// Where the file was before it was deleted
+    pub original: PathBuf,
+    /// Where the file is in the trash folder
+    pub path: PathBuf,
+    /// Status of the video before it was deleted, set again on restore
+    pub fetch_status: FetchStatus,
+    /// Unix timestamp
+    pub deleted: i64,
+}
+
+#[derive(Error, Debug)]
+pub enum RestoreError {
+    #[error("Video is not in the trash")]
+    NotTrashed,
+    #[error("{} is taken by another file", .0.display())]
+    Occupied(PathBuf),
+    #[error("Error restoring file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Db(#[from] DbError),
+}
+
+/// Moves `file` of `video_id` into the trash folder.
+pub fn move_to_trash(
+    s: &MsState,
+    trash: &Path,
+    video_id: &str,
+    file: &Path,
+    fetch_status: FetchStatus,
+) -> anyhow::Result<()> {
+    let target = trash
+        .join(video_id)
+        .join(file.file_name().unwrap_or_default());
+    std::fs::create_dir_all(target.parent().unwrap_or(trash))?;
+    musicfiles::move_file(&s.config.paths, file, &target)?;
+    info!("Moved {} to the trash", file.display());
+    dbdata::DB.add_trashed_file(&TrashedFile {
+        video_id: video_id.to_owned(),
+        original: file.to_owned(),
+        path: target,
+        fetch_status,
+        deleted: Utc::now().timestamp(),
+    })?;
+    Ok(())
+}
+
+/// Moves the file of `video_id` back to where it was deleted from and re-enables the video.
+pub fn restore(s: &MsState, video_id: &str) -> Result<(), RestoreError> {
+    let _lock = s.file_cache.lock(video_id);
+    let trashed = dbdata::DB
+        .get_trashed_file(video_id)?
+        .ok_or(RestoreError::NotTrashed)?;
+    if trashed.original.exists() {
+        return Err(RestoreError::Occupied(trashed.original));
+    }
+    if let Some(parent) = trashed.original.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    if std::fs::rename(&trashed.path, &trashed.original).is_err() {
+        // The trash may be on another file system
+        std::fs::copy(&trashed.path, &trashed.original)?;
+        std::fs::remove_file(&trashed.path)?;
+    }
+    remove_video_folder(&trashed);
+    dbdata::DB.delete_trashed_file(video_id)?;
+    s.file_cache
+        .insert(video_id.to_owned(), trashed.original.clone());
+    info!("Restored {} from the trash", trashed.original.display());
+
+    MsState::push_override(video_id, |v| {
+        v.fetch_status = trashed.fetch_status;
+        v.last_error = None;
+        true
+    })?;
+    Ok(())
+}
+
+/// Purges expired tracks from the trash until the process ends.
+pub async fn run(s: &MsState) {
+    let Some(trash) = s
+        .config
+        .paths
+        .trash
+        .as_ref()
+        .filter(|_| !s.config.scrape.dry_run)
+    else {
+        return std::future::pending().await;
+    };
+    let mut interval = tokio::time::interval(PURGE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let retention = Duration::from_secs(u64::from(s.config.paths.trash_days) * 24 * 60 * 60);
+        let before = Utc::now().timestamp() - retention.as_secs() as i64;
+        // Tried again on the next interval
+        let expired = match dbdata::DB.get_trashed_files_before(before) {
+            Ok(expired) => expired,
+            Err(err) => {
+                error!("Failed to get expired files of the trash: {}", err);
+                continue;
+            }
+        };
+        for trashed in expired {
+            if let Err(err) = purge(trash, &trashed) {
+                error!(
+                    "Error purging {} from the trash: {}",
+                    trashed.path.display(),
+                    err
+                );
+            }
+        }
+    }
+}
+
+fn purge(trash: &Path, trashed: &TrashedFile) -> anyhow::Result<()> {
+    // Never delete anything outside of the trash, e.g. after the folder was reconfigured
+    if trashed.path.starts_with(trash) && trashed.path.exists() {
+        std::fs::remove_file(&trashed.path)?;
+    }
+    remove_video_folder(trashed);
+    dbdata::DB.delete_yt_data(&trashed.video_id)?;
+    dbdata::DB.delete_trashed_file(&trashed.video_id)?;
+    info!("Purged {} from the trash", trashed.path.display());
+    Ok(())
+}
+
+fn remove_video_folder(trashed: &TrashedFile) {
+    if let Some(folder) = trashed.path.parent() {
+        // Fails if anything else was put there, which is then kept
+        let _ = std::fs::remove_dir(folder);
+    }
+}
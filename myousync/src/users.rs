@@ -0,0 +1,191 @@
+//! Accounts which can sign in.
+//!
+//! Every user can change their own password at `/account/password`. Admins can add and remove
+//! users through `/users`, like the `user` subcommand does from the shell. The user created by
+//! the setup is an admin.
+
+use std::io::{self, BufRead, Write};
+
+use anyhow::{anyhow, bail};
+use axum::http::StatusCode;
+use log::info;
+use serde::Deserialize;
+
+use crate::{
+    MsState,
+    apikeys::require_user,
+    auth::Claims,
+    dbdata::{self, User},
+    password,
+};
+
+const USAGE: &str = "Usage: myousync user add <name> [--admin] [--config <file>]
+       myousync user password <name> [--config <file>]
+       myousync user remove <name> [--config <file>]
+       myousync user list [--config <file>]
+
+Passwords are read from stdin.";
+
+#[derive(Debug, Deserialize)]
+pub struct ChangePassword {
+    pub old_password: String,
+    pub new_password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateUser {
+    pub username: String,
+    pub password: String,
+    #[serde(default)]
+    pub admin: bool,
+}
+
+/// Rejects requests of users which are not admins, and of api keys.
+pub fn require_admin(claims: &Claims) -> Result<(), (StatusCode, String)> {
+    require_user(claims)?;
+    match dbdata::DB.get_user(&claims.user)? {
+        Some(user) if user.admin => Ok(()),
+        _ => Err((StatusCode::FORBIDDEN, "Only admins can do this".to_string())),
+    }
+}
+
+/// The user whose sessions and api keys `claims` may manage, `None` for admins who manage all.
+pub fn managed_owner(claims: &Claims) -> Result<Option<&str>, (StatusCode, String)> {
+    require_user(claims)?;
+    match dbdata::DB.get_user(&claims.user)? {
+        Some(user) if user.admin => Ok(None),
+        _ => Ok(Some(&claims.user)),
+    }
+}
+
+/// `/account/password`, sets a new password and signs out the other sessions of the user.
+pub fn change_password(
+    claims: &Claims,
+    request: &ChangePassword,
+) -> Result<StatusCode, (StatusCode, String)> {
+    require_user(claims)?;
+    let Some(user) = dbdata::DB.get_user(&claims.user)? else {
+        return Err((StatusCode::NOT_FOUND, "User not found".to_string()));
+    };
+    if !password::verify(&user.password, &request.old_password) {
+        return Err((StatusCode::FORBIDDEN, "Invalid password".to_string()));
+    }
+    check_password(&request.new_password)?;
+
+    dbdata::DB.set_password(&user.username, &password::hash(&request.new_password))?;
+    dbdata::DB.delete_user_sessions(&user.username, claims.sid.as_deref())?;
+    info!("Changed the password of {}", user.username);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub fn create(request: &CreateUser) -> Result<User, (StatusCode, String)> {
+    let username = request.username.trim();
+    if username.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Username must not be empty".to_string(),
+        ));
+    }
+    check_password(&request.password)?;
+    if !dbdata::DB.add_user(username, &password::hash(&request.password), request.admin)? {
+        return Err((StatusCode::CONFLICT, "User already exists".to_string()));
+    }
+    info!(
+        "Created {} {}",
+        if request.admin { "admin" } else { "user" },
+        username
+    );
+    Ok(User {
+        username: username.to_owned(),
+        admin: request.admin,
+    })
+}
+
+/// Deletes the user with its sessions and api keys. Admins cannot delete themselves, so there
+/// is always one left.
+pub fn delete(claims: &Claims, username: &str) -> Result<StatusCode, (StatusCode, String)> {
+    if claims.user == username {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "You cannot delete yourself".to_string(),
+        ));
+    }
+    if !dbdata::DB.delete_user(username)? {
+        return Err((StatusCode::NOT_FOUND, "User not found".to_string()));
+    }
+    info!("Deleted user {}", username);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub(crate) fn check_password(password: &str) -> Result<(), (StatusCode, String)> {
+    if password.chars().count() < password::MIN_LENGTH {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!(
+                "Password must have at least {} characters",
+                password::MIN_LENGTH
+            ),
+        ));
+    }
+    Ok(())
+}
+
+fn read_password() -> anyhow::Result<String> {
+    eprint!("Password: ");
+    io::stderr().flush()?;
+    let mut line = String::new();
+    io::stdin().lock().read_line(&mut line)?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_owned())
+}
+
+/// Runs the `user` subcommand.
+pub fn cli(mut args: impl Iterator<Item = String>) -> anyhow::Result<()> {
+    let command = args.next().ok_or_else(|| anyhow!(USAGE))?;
+    let mut positional = Vec::new();
+    let mut admin = false;
+    let mut config = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--admin" => admin = true,
+            "--config" => config = Some(args.next().ok_or_else(|| anyhow!(USAGE))?),
+            _ if !arg.starts_with("--") => positional.push(arg),
+            _ => bail!("Unknown option '{arg}'\n\n{USAGE}"),
+        }
+    }
+
+    // Opens the database of the config
+    let _s = MsState::new(&crate::config_path(config));
+    match (command.as_str(), positional.as_slice()) {
+        ("add", [name]) => {
+            let request = CreateUser {
+                username: name.clone(),
+                password: read_password()?,
+                admin,
+            };
+            create(&request).map_err(|(_, err)| anyhow!(err))?;
+        }
+        ("password", [name]) => {
+            let new_password = read_password()?;
+            check_password(&new_password).map_err(|(_, err)| anyhow!(err))?;
+            if !dbdata::DB.set_password(name, &password::hash(&new_password))? {
+                bail!("User {name} not found");
+            }
+            dbdata::DB.delete_user_sessions(name, None)?;
+            info!("Changed the password of {}", name);
+        }
+        ("remove", [name]) => {
+            if !dbdata::DB.delete_user(name)? {
+                bail!("User {name} not found");
+            }
+            info!("Deleted user {}", name);
+        }
+        ("list", []) => {
+            for user in dbdata::DB.get_users()? {
+                let role = if user.admin { "admin" } else { "user" };
+                println!("{}\t{}", user.username, role);
+            }
+        }
+        _ => bail!(USAGE),
+    }
+    Ok(())
+}
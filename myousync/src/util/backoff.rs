@@ -0,0 +1,39 @@
+use std::time::Duration;
+
+/// Wait time of a background loop which slows down while there is nothing to do.
+///
+/// Every pass without work doubles the wait, up to `max`. Any work brings it back to the
+/// configured `rate` at once.
+#[derive(Debug, Clone)]
+pub struct IdleBackoff {
+    rate: Duration,
+    max: Duration,
+    current: Duration,
+}
+
+impl IdleBackoff {
+    /// A `max` below `rate` disables the backoff.
+    pub fn new(rate: Duration, max: Duration) -> Self {
+        IdleBackoff {
+            rate,
+            max: max.max(rate),
+            current: rate,
+        }
+    }
+
+    /// Records a pass, returning the wait until the next one.
+    pub fn record(&mut self, busy: bool) -> Duration {
+        if busy {
+            self.reset();
+        } else {
+            self.current = self.current.saturating_mul(2).min(self.max);
+        }
+        self.current
+    }
+
+    /// Returns to the configured rate, returning it.
+    pub fn reset(&mut self) -> Duration {
+        self.current = self.rate;
+        self.current
+    }
+}
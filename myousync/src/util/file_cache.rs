@@ -0,0 +1,238 @@
+use std::{
+    collections::{HashMap, HashSet},
+    hash::{BuildHasher, RandomState},
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Condvar, Mutex, RwLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+const SHARDS: usize = 16;
+
+/// A video id with its new path.
+type Change = (String, PathBuf);
+
+/// Maps video ids to the files in the library which are tagged with them.
+///
+/// Entries are spread over independently locked shards, so lookups never wait for each other.
+/// Rebuilds scan the library without holding any shard lock and swap the result in at once.
+#[derive(Debug)]
+pub struct FileCache {
+    shards: Vec<RwLock<HashMap<String, PathBuf>>>,
+    hasher: RandomState,
+    /// Held during a rebuild, so misses happening at the same time share one scan
+    rebuild: Mutex<()>,
+    /// Incremented after each finished rebuild
+    generation: AtomicU64,
+    /// Changes made while a rebuild is scanning, replayed onto its result
+    journal: Mutex<Option<Vec<Change>>>,
+    read_waits: LockWaits,
+    write_waits: LockWaits,
+    rebuilds: AtomicU64,
+    last_rebuild_ms: AtomicU64,
+    videos: Arc<VideoLocks>,
+}
+
+/// Videos whose file is being moved, deleted or opened right now.
+#[derive(Debug, Default)]
+struct VideoLocks {
+    held: Mutex<HashSet<String>>,
+    released: Condvar,
+}
+
+/// Exclusive access to the file of one video, released on drop.
+///
+/// The lock is advisory and only known inside this process. Everyone who resolves the path of a
+/// video and then touches the file takes it, so no path is handed out while the file is moved.
+#[derive(Debug)]
+pub struct VideoLock {
+    locks: Arc<VideoLocks>,
+    video_id: String,
+}
+
+impl Drop for VideoLock {
+    fn drop(&mut self) {
+        self.locks.held.lock().unwrap().remove(&self.video_id);
+        self.locks.released.notify_all();
+    }
+}
+
+/// A snapshot of a [`FileCache`].
+#[derive(Debug, Serialize)]
+pub struct FileCacheStatus {
+    pub entries: usize,
+    pub rebuilds: u64,
+    pub last_rebuild_ms: u64,
+    pub read_locks: LockWaitStatus,
+    pub write_locks: LockWaitStatus,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LockWaitStatus {
+    pub acquired: u64,
+    pub total_wait_us: u64,
+    pub max_wait_us: u64,
+}
+
+#[derive(Debug, Default)]
+struct LockWaits {
+    acquired: AtomicU64,
+    total_wait_us: AtomicU64,
+    max_wait_us: AtomicU64,
+}
+
+impl LockWaits {
+    fn record(&self, wait: Duration) {
+        let wait_us = wait.as_micros() as u64;
+        self.acquired.fetch_add(1, Ordering::Relaxed);
+        self.total_wait_us.fetch_add(wait_us, Ordering::Relaxed);
+        self.max_wait_us.fetch_max(wait_us, Ordering::Relaxed);
+    }
+
+    fn status(&self) -> LockWaitStatus {
+        LockWaitStatus {
+            acquired: self.acquired.load(Ordering::Relaxed),
+            total_wait_us: self.total_wait_us.load(Ordering::Relaxed),
+            max_wait_us: self.max_wait_us.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Default for FileCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FileCache {
+    pub fn new() -> Self {
+        FileCache {
+            shards: (0..SHARDS).map(|_| RwLock::default()).collect(),
+            hasher: RandomState::new(),
+            rebuild: Mutex::new(()),
+            generation: AtomicU64::new(0),
+            journal: Mutex::new(None),
+            read_waits: LockWaits::default(),
+            write_waits: LockWaits::default(),
+            rebuilds: AtomicU64::new(0),
+            last_rebuild_ms: AtomicU64::new(0),
+            videos: Arc::default(),
+        }
+    }
+
+    /// Blocks until no one else holds the lock of `video_id`, then takes it.
+    pub fn lock(&self, video_id: &str) -> VideoLock {
+        let mut held = self.videos.held.lock().unwrap();
+        while held.contains(video_id) {
+            held = self.videos.released.wait(held).unwrap();
+        }
+        held.insert(video_id.to_owned());
+        VideoLock {
+            locks: self.videos.clone(),
+            video_id: video_id.to_owned(),
+        }
+    }
+
+    fn shard(&self, video_id: &str) -> &RwLock<HashMap<String, PathBuf>> {
+        &self.shards[self.hasher.hash_one(video_id) as usize % SHARDS]
+    }
+
+    pub fn get(&self, video_id: &str) -> Option<PathBuf> {
+        let start = Instant::now();
+        let shard = self.shard(video_id).read().unwrap();
+        self.read_waits.record(start.elapsed());
+        shard.get(video_id).cloned()
+    }
+
+    pub fn insert(&self, video_id: String, path: PathBuf) {
+        if let Some(journal) = self.journal.lock().unwrap().as_mut() {
+            journal.push((video_id.clone(), path.clone()));
+        }
+        let start = Instant::now();
+        let mut shard = self.shard(&video_id).write().unwrap();
+        self.write_waits.record(start.elapsed());
+        shard.insert(video_id, path);
+    }
+
+    /// Drops the entries of the files at or below `path`, returning their video ids.
+    ///
+    /// Unlike [`insert`](Self::insert) this is not replayed onto a running rebuild, a stale
+    /// entry is caught by the tag check of the next lookup.
+    pub fn remove_path(&self, path: &Path) -> Vec<String> {
+        let mut removed = Vec::new();
+        for shard in &self.shards {
+            let start = Instant::now();
+            let mut shard = shard.write().unwrap();
+            self.write_waits.record(start.elapsed());
+            shard.retain(|video_id, p| {
+                let keep = !p.starts_with(path);
+                if !keep {
+                    removed.push(video_id.clone());
+                }
+                keep
+            });
+        }
+        removed
+    }
+
+    /// Number of finished rebuilds, to be read before the lookup which missed.
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    /// Replaces all entries with the result of `scan`.
+    ///
+    /// `seen_generation` is the [`generation`](Self::generation) read before the miss which
+    /// triggered the rebuild. If another rebuild finished since then, its result is used and
+    /// `scan` is not run.
+    pub fn rebuild(&self, seen_generation: u64, scan: impl FnOnce() -> HashMap<String, PathBuf>) {
+        let _rebuilding = self.rebuild.lock().unwrap();
+        if self.generation() != seen_generation {
+            return;
+        }
+
+        let start = Instant::now();
+        *self.journal.lock().unwrap() = Some(Vec::new());
+        let scanned = scan();
+
+        let mut new_shards: Vec<HashMap<String, PathBuf>> = vec![HashMap::new(); SHARDS];
+        for (video_id, path) in scanned {
+            let index = self.hasher.hash_one(&video_id) as usize % SHARDS;
+            new_shards[index].insert(video_id, path);
+        }
+
+        // Taking every shard lock before swapping makes the new state visible all at once
+        let lock_start = Instant::now();
+        let mut shards: Vec<_> = self.shards.iter().map(|s| s.write().unwrap()).collect();
+        self.write_waits.record(lock_start.elapsed());
+
+        let journal = self.journal.lock().unwrap().take().unwrap_or_default();
+        for (video_id, path) in journal {
+            let index = self.hasher.hash_one(&video_id) as usize % SHARDS;
+            new_shards[index].insert(video_id, path);
+        }
+        for (shard, new_shard) in shards.iter_mut().zip(new_shards) {
+            **shard = new_shard;
+        }
+        drop(shards);
+
+        self.generation.fetch_add(1, Ordering::Release);
+        self.rebuilds.fetch_add(1, Ordering::Relaxed);
+        self.last_rebuild_ms
+            .store(start.elapsed().as_millis() as u64, Ordering::Relaxed);
+    }
+
+    pub fn status(&self) -> FileCacheStatus {
+        FileCacheStatus {
+            entries: self.shards.iter().map(|s| s.read().unwrap().len()).sum(),
+            rebuilds: self.rebuilds.load(Ordering::Relaxed),
+            last_rebuild_ms: self.last_rebuild_ms.load(Ordering::Relaxed),
+            read_locks: self.read_waits.status(),
+            write_locks: self.write_waits.status(),
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use std::hash::{DefaultHasher, Hasher};
+
+use axum::{
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+/// Serializes `value` as JSON response which supports conditional requests.
+///
+/// The ETag is a hash of the body, so it changes with anything sent, while `last_modified`
+/// allows clients without ETag support to skip unchanged data at second precision.
+/// Clients are told to revalidate on every use, as the data changes all the time.
+pub fn cached_json<T: Serialize>(
+    headers: &HeaderMap,
+    last_modified: Option<DateTime<Utc>>,
+    value: &T,
+) -> Response {
+    let body = match serde_json::to_vec(value) {
+        Ok(body) => body,
+        Err(err) => return (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    };
+    let mut hasher = DefaultHasher::new();
+    hasher.write(&body);
+    let etag = format!("\"{:016x}\"", hasher.finish());
+
+    let mut response = if is_fresh(headers, &etag, last_modified) {
+        StatusCode::NOT_MODIFIED.into_response()
+    } else {
+        ([(header::CONTENT_TYPE, "application/json")], body).into_response()
+    };
+    let response_headers = response.headers_mut();
+    response_headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+    if let Ok(etag) = HeaderValue::from_str(&etag) {
+        response_headers.insert(header::ETAG, etag);
+    }
+    if let Some(last_modified) = last_modified.and_then(|t| {
+        HeaderValue::from_str(&t.format("%a, %d %b %Y %H:%M:%S GMT").to_string()).ok()
+    }) {
+        response_headers.insert(header::LAST_MODIFIED, last_modified);
+    }
+    response
+}
+
+/// If-None-Match takes precedence over If-Modified-Since, as required by RFC 9110.
+fn is_fresh(headers: &HeaderMap, etag: &str, last_modified: Option<DateTime<Utc>>) -> bool {
+    if let Some(if_none_match) = headers.get(header::IF_NONE_MATCH) {
+        return if_none_match.to_str().is_ok_and(|tags| {
+            tags.split(',')
+                .map(|t| t.trim().trim_start_matches("W/"))
+                .any(|t| t == "*" || t == etag)
+        });
+    }
+
+    let Some(since) = headers
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| DateTime::parse_from_rfc2822(v).ok())
+    else {
+        return false;
+    };
+    last_modified.is_some_and(|modified| modified.timestamp() <= since.timestamp())
+}
@@ -1,36 +1,163 @@
-use std::sync::Mutex;
+use std::{sync::Mutex, time::Duration};
 
 use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::sync::{Semaphore, SemaphorePermit};
 
+/// Limits requests to one service, both by the time between starting requests and by how many
+/// may run at the same time.
 pub struct Limiter {
-    wait_time: std::time::Duration,
+    name: &'static str,
+    /// One permit per request allowed to run at the same time
+    slots: Semaphore,
+    concurrency: Mutex<usize>,
+    wait_time: Mutex<Duration>,
+    /// Interval set at runtime, takes precedence over `wait_time`.
+    override_time: Mutex<Option<Duration>>,
     last_fetch: Mutex<DateTime<Utc>>,
 }
 
+/// A snapshot of a [`Limiter`].
+#[derive(Debug, Serialize)]
+pub struct LimiterStatus {
+    pub name: &'static str,
+    /// Interval currently in use
+    pub interval_ms: u64,
+    /// Interval from the config, used when no override is set
+    pub configured_ms: u64,
+    pub overridden: bool,
+    /// Unix timestamp in milliseconds of the earliest next request
+    pub next_fetch: i64,
+    /// Requests allowed to run at the same time
+    pub concurrency: usize,
+    /// Requests running right now
+    pub running: usize,
+}
+
 impl Limiter {
-    pub const fn new(time: std::time::Duration) -> Self {
+    pub const fn new(name: &'static str, time: Duration) -> Self {
         Limiter {
-            wait_time: time,
+            name,
+            slots: Semaphore::const_new(1),
+            concurrency: Mutex::new(1),
+            wait_time: Mutex::new(time),
+            override_time: Mutex::new(None),
             last_fetch: Mutex::new(DateTime::<Utc>::MIN_UTC),
         }
     }
 
-    pub async fn wait_for_next_fetch(&self) {
-		self.wait_for_next_fetch_of_time(self.wait_time).await
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.override_time
+            .lock()
+            .unwrap()
+            .unwrap_or_else(|| *self.wait_time.lock().unwrap())
+    }
+
+    /// Sets the configured interval.
+    pub fn configure(&self, time: Duration) {
+        *self.wait_time.lock().unwrap() = time;
     }
 
-    pub async fn wait_for_next_fetch_of_time(&self, wait_time: std::time::Duration) {
-        let wait_time = chrono::Duration::from_std(wait_time).unwrap();
-        let mut last_fetch = self.last_fetch.lock().unwrap();
-        let elapsed = Utc::now() - *last_fetch;
-        if elapsed < wait_time {
-            let wait_time = wait_time - elapsed;
-            tokio::time::sleep(wait_time.to_std().unwrap()).await;
+    /// Sets how many requests may run at the same time.
+    /// Meant to be called on startup, while no request is running.
+    pub fn configure_concurrency(&self, concurrency: usize) {
+        let concurrency = concurrency.max(1);
+        let mut current = self.concurrency.lock().unwrap();
+        if concurrency > *current {
+            self.slots.add_permits(concurrency - *current);
+        } else {
+            self.slots.forget_permits(*current - concurrency);
+        }
+        *current = concurrency;
+    }
+
+    /// Overrides the configured interval, `None` goes back to it.
+    pub fn set_override(&self, time: Option<Duration>) {
+        *self.override_time.lock().unwrap() = time;
+    }
+
+    pub fn status(&self) -> LimiterStatus {
+        let interval = self.interval();
+        let last_fetch = *self.last_fetch.lock().unwrap();
+        let next_fetch = chrono::Duration::from_std(interval)
+            .ok()
+            .and_then(|i| last_fetch.checked_add_signed(i))
+            .unwrap_or(DateTime::<Utc>::MAX_UTC);
+        let concurrency = *self.concurrency.lock().unwrap();
+        LimiterStatus {
+            name: self.name,
+            interval_ms: interval.as_millis() as u64,
+            configured_ms: self.wait_time.lock().unwrap().as_millis() as u64,
+            overridden: self.override_time.lock().unwrap().is_some(),
+            next_fetch: next_fetch.max(Utc::now()).timestamp_millis(),
+            concurrency,
+            running: concurrency.saturating_sub(self.slots.available_permits()),
+        }
+    }
+
+    /// Waits for a free slot and then for the next request to be allowed.
+    /// The slot is held until the returned permit is dropped.
+    pub async fn acquire(&self) -> SemaphorePermit<'_> {
+        let permit = self
+            .slots
+            .acquire()
+            .await
+            .expect("limiter semaphore is never closed");
+        self.wait_for_next_fetch().await;
+        permit
+    }
+
+    pub async fn wait_for_next_fetch(&self) {
+        let wait_time = chrono::Duration::from_std(self.interval()).unwrap();
+        let sleep_time = {
+            let mut last_fetch = self.last_fetch.lock().unwrap();
+            let now = Utc::now();
+            let next_fetch = (*last_fetch + wait_time).max(now);
+            *last_fetch = next_fetch;
+            next_fetch - now
+        };
+        if sleep_time > chrono::Duration::zero() {
+            tokio::time::sleep(sleep_time.to_std().unwrap()).await;
         }
-        *last_fetch = Utc::now();
     }
 
     pub fn set_last_fetch_now(&self) {
         *self.last_fetch.lock().unwrap() = Utc::now();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn status_counts_running_requests() {
+        let limiter = Limiter::new("test", Duration::ZERO);
+        limiter.configure_concurrency(3);
+
+        let status = limiter.status();
+        assert_eq!(status.concurrency, 3);
+        assert_eq!(status.running, 0);
+
+        let first = limiter.acquire().await;
+        let _second = limiter.acquire().await;
+        assert_eq!(limiter.status().running, 2);
+        drop(first);
+        assert_eq!(limiter.status().running, 1);
+    }
+
+    #[test]
+    fn status_reports_override() {
+        let limiter = Limiter::new("test", Duration::from_millis(1500));
+        limiter.set_override(Some(Duration::from_millis(200)));
+
+        let status = limiter.status();
+        assert_eq!(status.interval_ms, 200);
+        assert_eq!(status.configured_ms, 1500);
+        assert!(status.overridden);
+    }
+}
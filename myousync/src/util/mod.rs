@@ -1 +1,6 @@
+pub mod backoff;
+pub mod file_cache;
+pub mod http_cache;
 pub mod limiter;
+pub mod queue;
+pub mod workspace;
@@ -0,0 +1,160 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    hash::Hash,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Which lane of a [`UniqueQueue`] an item waits in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+pub enum Priority {
+    /// Bulk work which may wait behind everything else.
+    Low,
+    /// Work somebody is actively waiting for, served ahead of [`Priority::Low`].
+    High,
+}
+
+/// A FIFO queue with two priority lanes which ignores items that are already waiting in it.
+///
+/// High priority items jump ahead of low priority ones, but only for a limited time:
+/// a low priority item which waited longer than the `max_low_wait` given to [`UniqueQueue::pop`]
+/// is served next, so bulk work cannot starve.
+pub struct UniqueQueue<T> {
+    inner: Mutex<UniqueQueueInner<T>>,
+}
+
+struct UniqueQueueInner<T> {
+    high: VecDeque<(T, Instant)>,
+    low: VecDeque<(T, Instant)>,
+    pending: HashMap<T, Priority>,
+}
+
+/// A snapshot of an item waiting in a [`UniqueQueue`].
+#[derive(Debug, Clone, Serialize)]
+pub struct QueueEntry<T> {
+    pub item: T,
+    pub priority: Priority,
+    /// Zero based position in the order the items would be popped right now.
+    pub position: usize,
+}
+
+impl<T: Clone + Eq + Hash> UniqueQueue<T> {
+    pub fn new() -> Self {
+        UniqueQueue {
+            inner: Mutex::new(UniqueQueueInner {
+                high: VecDeque::new(),
+                low: VecDeque::new(),
+                pending: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Returns false if the item was already queued with the same or a higher priority.
+    /// An item already queued with a lower priority is moved to the higher lane.
+    pub fn push(&self, item: T, priority: Priority) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        match inner.pending.get(&item) {
+            Some(&queued) if queued >= priority => return false,
+            Some(_) => inner.low.retain(|(i, _)| *i != item),
+            None => {}
+        }
+        inner.pending.insert(item.clone(), priority);
+        let lane = match priority {
+            Priority::High => &mut inner.high,
+            Priority::Low => &mut inner.low,
+        };
+        lane.push_back((item, Instant::now()));
+        true
+    }
+
+    pub fn pop(&self, max_low_wait: Duration) -> Option<T> {
+        let mut inner = self.inner.lock().unwrap();
+        let low_overdue = inner
+            .low
+            .front()
+            .is_some_and(|(_, since)| since.elapsed() > max_low_wait);
+        let (item, _) = if low_overdue {
+            inner.low.pop_front()?
+        } else {
+            inner.high.pop_front().or_else(|| inner.low.pop_front())?
+        };
+        inner.pending.remove(&item);
+        Some(item)
+    }
+
+    /// Lists all waiting items in the order they would be popped if nothing else was pushed.
+    pub fn snapshot(&self, max_low_wait: Duration) -> Vec<QueueEntry<T>> {
+        let inner = self.inner.lock().unwrap();
+        let (overdue, low): (Vec<_>, Vec<_>) = inner
+            .low
+            .iter()
+            .partition(|(_, since)| since.elapsed() > max_low_wait);
+
+        overdue
+            .into_iter()
+            .map(|(i, _)| (i, Priority::Low))
+            .chain(inner.high.iter().map(|(i, _)| (i, Priority::High)))
+            .chain(low.into_iter().map(|(i, _)| (i, Priority::Low)))
+            .enumerate()
+            .map(|(position, (item, priority))| QueueEntry {
+                item: item.clone(),
+                priority,
+                position,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WAIT: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn queued_items_are_ignored() {
+        let queue = UniqueQueue::new();
+        assert!(queue.push("a", Priority::Low));
+        assert!(!queue.push("a", Priority::Low));
+        assert!(queue.push("b", Priority::High));
+        assert!(!queue.push("b", Priority::Low));
+        assert!(!queue.push("b", Priority::High));
+
+        assert_eq!(queue.pop(WAIT), Some("b"));
+        assert_eq!(queue.pop(WAIT), Some("a"));
+        assert_eq!(queue.pop(WAIT), None);
+        // Popped items can be queued again
+        assert!(queue.push("a", Priority::Low));
+    }
+
+    #[test]
+    fn raised_items_move_to_the_high_lane_once() {
+        let queue = UniqueQueue::new();
+        queue.push("a", Priority::Low);
+        queue.push("b", Priority::Low);
+        assert!(queue.push("b", Priority::High));
+
+        let snapshot = queue.snapshot(WAIT);
+        assert_eq!(snapshot.len(), 2);
+        assert_eq!(
+            (snapshot[0].item, snapshot[0].priority),
+            ("b", Priority::High)
+        );
+        assert_eq!(queue.pop(WAIT), Some("b"));
+        assert_eq!(queue.pop(WAIT), Some("a"));
+        assert_eq!(queue.pop(WAIT), None);
+    }
+
+    #[test]
+    fn overdue_low_items_are_served_first() {
+        let queue = UniqueQueue::new();
+        queue.push("low", Priority::Low);
+        std::thread::sleep(Duration::from_millis(5));
+        queue.push("high", Priority::High);
+
+        assert_eq!(queue.pop(Duration::from_millis(1)), Some("low"));
+        assert_eq!(queue.pop(Duration::from_millis(1)), Some("high"));
+    }
+}
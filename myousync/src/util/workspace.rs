@@ -0,0 +1,126 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use log::{error, warn};
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+/// Extensions yt-dlp uses for files which are still being written.
+const PARTIAL_EXTENSIONS: [&str; 3] = ["part", "ytdl", "temp"];
+/// Marks files ffmpeg is still converting into, like `id.converting.opus`.
+pub const CONVERTING_INFIX: &str = ".converting.";
+
+/// A fixed set of temp directories, one per concurrency slot, so parallel jobs never see each
+/// others temp files.
+///
+/// Each slot lives in its own `worker-N` subdirectory of the temp folder. When a job finishes its
+/// finished downloads are parked in the temp folder itself, everything else is deleted.
+#[derive(Debug)]
+pub struct WorkspacePool {
+    root: PathBuf,
+    free: Mutex<Vec<PathBuf>>,
+    available: Semaphore,
+}
+
+/// A temp directory exclusively owned by one job until dropped.
+pub struct Workspace<'a> {
+    pool: &'a WorkspacePool,
+    dir: PathBuf,
+    job: String,
+    _permit: SemaphorePermit<'a>,
+}
+
+impl WorkspacePool {
+    pub fn new(root: &Path, slots: usize) -> Self {
+        let dirs: Vec<_> = (0..slots.max(1))
+            .map(|i| root.join(format!("worker-{i}")))
+            .collect();
+
+        // Leftovers of an interrupted run
+        for dir in &dirs {
+            park_files(dir, root, |_| true);
+        }
+
+        WorkspacePool {
+            root: root.to_path_buf(),
+            available: Semaphore::new(dirs.len()),
+            free: Mutex::new(dirs),
+        }
+    }
+
+    /// Waits for a free slot and hands out its directory for processing `job`.
+    pub async fn acquire(&self, job: &str) -> Workspace<'_> {
+        let permit = self
+            .available
+            .acquire()
+            .await
+            .expect("workspace semaphore is never closed");
+        let dir = self
+            .free
+            .lock()
+            .unwrap()
+            .pop()
+            .expect("a permit guarantees a free workspace");
+
+        if let Err(err) = std::fs::create_dir_all(&dir) {
+            error!("Failed to create workspace {}: {}", dir.display(), err);
+        }
+
+        Workspace {
+            pool: self,
+            dir,
+            job: job.to_owned(),
+            _permit: permit,
+        }
+    }
+}
+
+impl Workspace<'_> {
+    pub fn path(&self) -> &Path {
+        &self.dir
+    }
+}
+
+impl Drop for Workspace<'_> {
+    fn drop(&mut self) {
+        let prefix = format!("{}.", self.job);
+        park_files(&self.dir, &self.pool.root, |name| name.starts_with(&prefix));
+        self.pool
+            .free
+            .lock()
+            .unwrap()
+            .push(std::mem::take(&mut self.dir));
+    }
+}
+
+/// Moves the finished files in `dir` accepted by `keep` to `root` and deletes `dir`.
+fn park_files(dir: &Path, root: &Path, keep: impl Fn(&str) -> bool) {
+    let Ok(entries) = dir.read_dir() else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        let is_partial = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| PARTIAL_EXTENSIONS.contains(&e))
+            || name.contains(CONVERTING_INFIX);
+        if !path.is_file() || is_partial || !keep(name) {
+            continue;
+        }
+        if let Err(err) = std::fs::rename(&path, root.join(name)) {
+            warn!("Failed to park {}: {}", path.display(), err);
+        }
+    }
+
+    if let Err(err) = std::fs::remove_dir_all(dir)
+        && err.kind() != std::io::ErrorKind::NotFound
+    {
+        warn!("Failed to clean workspace {}: {}", dir.display(), err);
+    }
+}
@@ -0,0 +1,105 @@
+//! Keeps the file cache current while files are moved, renamed or deleted by hand, so lookups
+//! after a manual edit do not have to fall back to a full rescan of the library.
+//!
+//! Only the music and migrate folders are watched. Changes of the file contents are ignored,
+//! only files which appear at a new path have their `youtube_id` read.
+
+use std::path::Path;
+
+use log::{debug, info, warn};
+use notify::{
+    Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher,
+    event::{AccessKind, AccessMode, ModifyKind, RenameMode},
+};
+use tokio::sync::mpsc;
+use walkdir::WalkDir;
+
+use crate::MsState;
+
+/// Watches the library until the process ends. Never returns, also when watching fails.
+pub async fn run(s: &MsState) {
+    if !s.config.paths.watch {
+        return std::future::pending().await;
+    }
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+        let _ = tx.send(event);
+    })
+    .and_then(|mut watcher: RecommendedWatcher| {
+        watcher.watch(&s.config.paths.music, RecursiveMode::Recursive)?;
+        if let Some(migrate) = &s.config.paths.migrate {
+            watcher.watch(migrate, RecursiveMode::Recursive)?;
+        }
+        Ok(watcher)
+    });
+    let _watcher = match watcher {
+        Ok(watcher) => watcher,
+        Err(err) => {
+            warn!("Not watching the library, files moved by hand are found by rescans: {err}");
+            return std::future::pending().await;
+        }
+    };
+    info!("Watching the library for changes");
+
+    while let Some(event) = rx.recv().await {
+        match event {
+            Ok(event) => {
+                let s = s.clone();
+                if let Err(err) = tokio::task::spawn_blocking(move || handle(&s, event)).await {
+                    warn!("Failed to handle a library change: {err}");
+                }
+            }
+            Err(err) => warn!("Error watching the library: {err}"),
+        }
+    }
+    std::future::pending().await
+}
+
+fn handle(s: &MsState, event: Event) {
+    match event.kind {
+        EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(RenameMode::From)) => {
+            event.paths.iter().for_each(|path| forget(s, path));
+        }
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => {
+            if let [from, to] = event.paths.as_slice() {
+                forget(s, from);
+                read(s, to);
+            }
+        }
+        EventKind::Create(_)
+        | EventKind::Modify(ModifyKind::Name(_))
+        | EventKind::Access(AccessKind::Close(AccessMode::Write)) => {
+            event.paths.iter().for_each(|path| read(s, path));
+        }
+        _ => {}
+    }
+}
+
+fn forget(s: &MsState, path: &Path) {
+    for video_id in s.file_cache.remove_path(path) {
+        debug!("File of {} left {}", video_id, path.display());
+    }
+}
+
+/// Adds the files at or below `path` to the cache by their youtube id. Files still being written
+/// may not be readable yet, they are read again once they are closed.
+fn read(s: &MsState, path: &Path) {
+    for path in WalkDir::new(path)
+        .into_iter()
+        .filter_map(|p| p.ok())
+        .filter(|p| p.file_type().is_file())
+        .map(|f| f.into_path())
+    {
+        let Some(video_id) = multitag::Tag::read_from_path_lenient(&path)
+            .ok()
+            .and_then(|(t, _)| t.get_comment("youtube_id"))
+        else {
+            continue;
+        };
+        if s.file_cache.get(&video_id).as_deref() != Some(path.as_path()) {
+            debug!("File of {} is now at {}", video_id, path.display());
+            s.file_cache.insert(video_id, path);
+        }
+    }
+}
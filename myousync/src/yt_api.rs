@@ -1,15 +1,35 @@
-use std::{io, mem};
+use std::{collections::HashMap, io, mem, sync::LazyLock};
 
-use crate::{net::CLIENT, MsConfig};
+use crate::{MsConfig, net::CLIENT, trace};
 use chrono::TimeDelta;
-use log::{debug, info};
-use serde::Deserialize;
+use log::{debug, info, warn};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-use crate::dbdata::{self, AuthData, Playlist, PlaylistItem};
+use crate::dbdata::{self, AuthData, Playlist, PlaylistInfo, PlaylistItem};
 
 const PLAYLISTS_QUICK_CACHE_TIME: TimeDelta = chrono::Duration::minutes(1);
 
+static VIDEO_ID_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^[A-Za-z0-9_-]{11}$").unwrap());
+static VIDEO_URL_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?:[?&]v=|youtu\.be/|/shorts/|/embed/|/live/)([A-Za-z0-9_-]{11})(?:[^A-Za-z0-9_-]|$)",
+    )
+    .unwrap()
+});
+
+/// Extracts the video id from a youtube url, like `https://www.youtube.com/watch?v=...` or
+/// `https://youtu.be/...`, or from a bare video id.
+pub fn parse_video_id(input: &str) -> Option<String> {
+    let input = input.trim();
+    if VIDEO_ID_REGEX.is_match(input) {
+        return Some(input.to_owned());
+    }
+    VIDEO_URL_REGEX.captures(input).map(|c| c[1].to_owned())
+}
+
 #[derive(Error, Debug)]
 pub enum YTError {
     #[error("")]
@@ -20,6 +40,8 @@ pub enum YTError {
     AuthRejected,
     #[error("Missing refresh token")]
     MissingRefreshToken,
+    #[error("No YouTube client credentials configured")]
+    MissingCredentials,
     #[error("")]
     IOError(#[from] io::Error),
     #[error("")]
@@ -28,10 +50,31 @@ pub enum YTError {
     JsonDeserializationErr(#[from] serde_json::Error),
     #[error("unknown data store error")]
     Unknown,
+    #[error(transparent)]
+    Db(#[from] dbdata::DbError),
+}
+
+/// Key-value entries of the client credentials entered in the setup.
+pub const CLIENT_ID_KEY: &str = "youtube_client_id";
+pub const CLIENT_SECRET_KEY: &str = "youtube_client_secret";
+
+/// The OAuth client of the config, or the one entered in the setup when the config has none.
+pub fn client_credentials(config: &MsConfig) -> Result<(String, String), YTError> {
+    if !config.youtube.client_id.is_empty() {
+        return Ok((
+            config.youtube.client_id.clone(),
+            config.youtube.client_secret.clone(),
+        ));
+    }
+    dbdata::DB
+        .get_key(CLIENT_ID_KEY)?
+        .zip(dbdata::DB.get_key(CLIENT_SECRET_KEY)?)
+        .ok_or(YTError::MissingCredentials)
 }
 
 pub async fn get_auth(config: &MsConfig) -> Result<AuthData, YTError> {
-    if let Some(data) = dbdata::DB.try_get_auth() {
+    let (client_id, client_secret) = client_credentials(config)?;
+    if let Some(data) = dbdata::DB.try_get_auth()? {
         debug!("Found YT Auth");
 
         if chrono::Utc::now().timestamp() < data.expires_at {
@@ -43,9 +86,9 @@ pub async fn get_auth(config: &MsConfig) -> Result<AuthData, YTError> {
 
         let mut form_data = String::new();
         form_data.push_str("client_id=");
-        form_data.push_str(&urlencoding::encode(&config.youtube.client_id));
+        form_data.push_str(&urlencoding::encode(&client_id));
         form_data.push_str("&client_secret=");
-        form_data.push_str(&urlencoding::encode(&config.youtube.client_secret));
+        form_data.push_str(&urlencoding::encode(&client_secret));
         form_data.push_str("&refresh_token=");
         form_data.push_str(&urlencoding::encode(&data.refresh_token));
         form_data.push_str("&grant_type=refresh_token");
@@ -67,7 +110,7 @@ pub async fn get_auth(config: &MsConfig) -> Result<AuthData, YTError> {
                     refresh_token: data.refresh_token,
                 };
 
-                dbdata::DB.set_auth(&new_data);
+                dbdata::DB.set_auth(&new_data)?;
 
                 return Ok(new_data);
             }
@@ -81,7 +124,7 @@ pub async fn get_auth(config: &MsConfig) -> Result<AuthData, YTError> {
 
     let mut form_data = String::new();
     form_data.push_str("client_id=");
-    form_data.push_str(&urlencoding::encode(&config.youtube.client_id));
+    form_data.push_str(&urlencoding::encode(&client_id));
     form_data.push_str("&scope=");
     form_data.push_str(&urlencoding::encode(
         "https://www.googleapis.com/auth/youtube",
@@ -103,9 +146,9 @@ pub async fn get_auth(config: &MsConfig) -> Result<AuthData, YTError> {
 
     let mut form_data = String::new();
     form_data.push_str("client_id=");
-    form_data.push_str(&urlencoding::encode(&config.youtube.client_id));
+    form_data.push_str(&urlencoding::encode(&client_id));
     form_data.push_str("&client_secret=");
-    form_data.push_str(&urlencoding::encode(&config.youtube.client_secret));
+    form_data.push_str(&urlencoding::encode(&client_secret));
     form_data.push_str("&device_code=");
     form_data.push_str(&urlencoding::encode(&code_response.device_code));
     form_data.push_str("&grant_type=urn:ietf:params:oauth:grant-type:device_code");
@@ -152,7 +195,7 @@ pub async fn get_auth(config: &MsConfig) -> Result<AuthData, YTError> {
                         .ok_or(YTError::MissingRefreshToken)?,
                 };
 
-                dbdata::DB.set_auth(&new_data);
+                dbdata::DB.set_auth(&new_data)?;
 
                 return Ok(new_data);
             }
@@ -163,7 +206,7 @@ pub async fn get_auth(config: &MsConfig) -> Result<AuthData, YTError> {
 }
 
 pub async fn get_playlist(config: &MsConfig, playlist_id: &str) -> Result<Playlist, YTError> {
-    let maybe_cached_playlist = dbdata::DB.try_get_playlist(playlist_id);
+    let maybe_cached_playlist = dbdata::DB.try_get_playlist(playlist_id)?;
 
     if maybe_cached_playlist
         .as_ref()
@@ -182,24 +225,45 @@ pub async fn get_playlist(config: &MsConfig, playlist_id: &str) -> Result<Playli
 
     debug!("Got page info: {:?}", page_info);
 
+    let mut known_durations = HashMap::new();
     if let Some(cached_playlist) = maybe_cached_playlist {
         if cached_playlist.etag == response.etag
             && cached_playlist.total_results == page_info.total_results
             && cached_playlist.items.len() == page_info.total_results as usize
         {
             debug!("Found cached playlist by etag");
-            dbdata::DB.update_playlist_fetch_time(playlist_id, chrono::Utc::now());
+            dbdata::DB.update_playlist_fetch_time(playlist_id, chrono::Utc::now())?;
             return Ok(cached_playlist);
         }
+
+        known_durations.extend(
+            cached_playlist
+                .items
+                .into_iter()
+                .filter_map(|i| Some((i.video_id, i.duration?))),
+        );
     }
 
     debug!("Creating new playlist");
 
+    let info = match get_playlist_info(&auth, playlist_id).await {
+        Ok(info) => info,
+        Err(err) => {
+            warn!(
+                "Failed to get playlist details{}: {:?}",
+                trace::request_tag(),
+                err
+            );
+            PlaylistInfo::default()
+        }
+    };
+
     let mut playlist = Playlist {
         playlist_id: playlist_id.to_owned(),
         fetch_time: chrono::Utc::now(),
         etag: mem::take(&mut response.etag),
         total_results: page_info.total_results,
+        info,
         items: Vec::with_capacity(page_info.total_results as usize),
     };
 
@@ -214,13 +278,43 @@ pub async fn get_playlist(config: &MsConfig, playlist_id: &str) -> Result<Playli
         drain_to(&mut playlist.items, response);
     }
 
+    if let Err(err) = fill_durations(&auth, &mut playlist.items, &known_durations).await {
+        warn!(
+            "Failed to get video durations{}: {:?}",
+            trace::request_tag(),
+            err
+        );
+    }
+
     debug!("Saving playlist to db cache");
 
-    dbdata::DB.set_playlist(&playlist);
+    dbdata::DB.set_playlist(&playlist)?;
 
     Ok(playlist)
 }
 
+async fn get_playlist_info(auth: &AuthData, playlist_id: &str) -> Result<PlaylistInfo, YTError> {
+    let response = CLIENT
+        .get("https://www.googleapis.com/youtube/v3/playlists")
+        .query(&[("part", "snippet"), ("id", playlist_id)])
+        .header("Authorization", format!("Bearer {}", auth.access_token))
+        .send()
+        .await?
+        .text()
+        .await?;
+    let response: YtPlaylistsResponse = serde_json::from_str(&response)?;
+
+    let Some(mut playlist) = response.items.into_iter().next() else {
+        return Ok(PlaylistInfo::default());
+    };
+    Ok(PlaylistInfo {
+        title: Some(playlist.snippet.title),
+        description: Some(playlist.snippet.description).filter(|d| !d.is_empty()),
+        owner: Some(playlist.snippet.channel_title),
+        thumbnail: pick_thumbnail(&mut playlist.snippet.thumbnails),
+    })
+}
+
 async fn get_playlist_reponse(
     auth: &AuthData,
     playlist_id: &str,
@@ -246,6 +340,112 @@ async fn get_playlist_reponse(
     Ok(serde_json::from_str(&response)?)
 }
 
+async fn fill_durations(
+    auth: &AuthData,
+    items: &mut [PlaylistItem],
+    known_durations: &HashMap<String, u32>,
+) -> Result<(), YTError> {
+    for item in items.iter_mut() {
+        item.duration = known_durations.get(&item.video_id).copied();
+    }
+
+    let missing: Vec<&str> = items
+        .iter()
+        .filter(|i| i.duration.is_none())
+        .map(|i| i.video_id.as_str())
+        .collect();
+
+    let mut durations = HashMap::new();
+    for chunk in missing.chunks(50) {
+        debug!("Getting durations for {} videos", chunk.len());
+        let response = CLIENT
+            .get("https://www.googleapis.com/youtube/v3/videos")
+            .query(&[("part", "contentDetails"), ("id", &chunk.join(","))])
+            .header("Authorization", format!("Bearer {}", auth.access_token))
+            .send()
+            .await?
+            .text()
+            .await?;
+        let response: YtVideosResponse = serde_json::from_str(&response)?;
+
+        durations.extend(
+            response
+                .items
+                .into_iter()
+                .filter_map(|v| Some((v.id, parse_iso8601_duration(&v.content_details.duration)?))),
+        );
+    }
+
+    for item in items.iter_mut().filter(|i| i.duration.is_none()) {
+        item.duration = durations.get(&item.video_id).copied();
+    }
+
+    Ok(())
+}
+
+/// Searches youtube for videos matching `query`.
+/// Note that each search costs 100 units of the api quota.
+pub async fn search_videos(
+    config: &MsConfig,
+    query: &str,
+    max_results: u32,
+) -> Result<Vec<YtSearchResult>, YTError> {
+    let auth = get_auth(config).await?;
+
+    debug!("Searching videos: {}", query);
+    let response = CLIENT
+        .get("https://www.googleapis.com/youtube/v3/search")
+        .query(&[
+            ("part", "snippet"),
+            ("type", "video"),
+            ("q", query),
+            ("maxResults", &max_results.to_string()),
+        ])
+        .header("Authorization", format!("Bearer {}", auth.access_token))
+        .send()
+        .await?
+        .text()
+        .await?;
+    let response: YtSearchResponse = serde_json::from_str(&response)?;
+
+    Ok(response
+        .items
+        .into_iter()
+        .map(|item| YtSearchResult {
+            video_id: item.id.video_id,
+            title: item.snippet.title,
+            channel: item.snippet.channel_title,
+        })
+        .collect())
+}
+
+/// Parses durations as returned by the YouTube API, e.g. `PT1H2M3S` or `P1DT4M`, into seconds.
+fn parse_iso8601_duration(text: &str) -> Option<u32> {
+    let mut seconds = 0u32;
+    let mut num = String::new();
+    let mut in_time = false;
+    for c in text.strip_prefix('P')?.chars() {
+        match c {
+            '0'..='9' => num.push(c),
+            'T' => in_time = true,
+            _ => {
+                let value: u32 = num.parse().ok()?;
+                num.clear();
+                let factor = match (in_time, c) {
+                    (false, 'W') => 7 * 24 * 60 * 60,
+                    (false, 'D') => 24 * 60 * 60,
+                    (true, 'H') => 60 * 60,
+                    (true, 'M') => 60,
+                    (true, 'S') => 1,
+                    _ => return None,
+                };
+                seconds += value * factor;
+            }
+        }
+    }
+    num.is_empty().then_some(seconds)
+}
+
 fn drain_to(items: &mut Vec<PlaylistItem>, response: YtPlaylistItemsResponse) {
     for mut item in response.items.into_iter() {
         let artist = if let Some(mut artist) = item.snippet.video_owner_channel_title.take() {
@@ -265,10 +465,20 @@ fn drain_to(items: &mut Vec<PlaylistItem>, response: YtPlaylistItemsResponse) {
             video_id: mem::take(&mut item.snippet.resource_id.video_id),
             title: mem::take(&mut item.snippet.title),
             artist,
+            duration: None,
+            thumbnail: pick_thumbnail(&mut item.snippet.thumbnails),
         });
     }
 }
 
+fn pick_thumbnail(thumbnails: &mut HashMap<String, YtThumbnail>) -> Option<String> {
+    ["medium", "default", "high"]
+        .iter()
+        .find_map(|size| thumbnails.remove(*size))
+        .or_else(|| thumbnails.drain().next().map(|(_, t)| t))
+        .map(|t| t.url)
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(rename_all(deserialize = "camelCase"))]
 pub struct YtPlaylistItemsResponse {
@@ -291,6 +501,38 @@ pub struct YtSnippet {
     pub channel_title: String,
     pub video_owner_channel_title: Option<String>,
     pub resource_id: YtResourceId,
+    /// Available sizes by name, e.g. `default`, `medium` or `high`
+    #[serde(default)]
+    pub thumbnails: HashMap<String, YtThumbnail>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all(deserialize = "camelCase"))]
+struct YtPlaylistsResponse {
+    pub items: Vec<YtPlaylist>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all(deserialize = "camelCase"))]
+struct YtPlaylist {
+    pub snippet: YtPlaylistSnippet,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all(deserialize = "camelCase"))]
+struct YtPlaylistSnippet {
+    pub title: String,
+    #[serde(default)]
+    pub description: String,
+    pub channel_title: String,
+    #[serde(default)]
+    pub thumbnails: HashMap<String, YtThumbnail>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all(deserialize = "camelCase"))]
+pub struct YtThumbnail {
+    pub url: String,
 }
 
 #[derive(Debug, Deserialize)]
@@ -307,6 +549,52 @@ pub struct PageInfo {
     pub results_per_page: u32,
 }
 
+#[derive(Debug, Deserialize)]
+#[serde(rename_all(deserialize = "camelCase"))]
+struct YtVideosResponse {
+    pub items: Vec<YtVideo>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all(deserialize = "camelCase"))]
+struct YtVideo {
+    pub id: String,
+    pub content_details: YtContentDetails,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all(deserialize = "camelCase"))]
+struct YtContentDetails {
+    pub duration: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct YtSearchResult {
+    pub video_id: String,
+    pub title: String,
+    pub channel: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all(deserialize = "camelCase"))]
+struct YtSearchResponse {
+    pub items: Vec<YtSearchItem>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all(deserialize = "camelCase"))]
+struct YtSearchItem {
+    pub id: YtResourceId,
+    pub snippet: YtSearchSnippet,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all(deserialize = "camelCase"))]
+struct YtSearchSnippet {
+    pub title: String,
+    pub channel_title: String,
+}
+
 // Auth Stuff
 
 #[derive(Deserialize, Debug)]
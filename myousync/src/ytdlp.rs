@@ -1,17 +1,38 @@
-use std::path::PathBuf;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::{LazyLock, Mutex},
+    time::Duration,
+};
 
-use log::{error, info};
-use serde::Deserialize;
+use chrono::{NaiveDate, TimeDelta, Utc};
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use tokio::process::Command;
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    process::Command,
+};
 
 use crate::{
-    dbdata::{self},
+    MsScrape, MsState,
+    brainz::BrainzMetadata,
+    dbdata::{self, VideoStatus},
+    net, sources,
     util::limiter::Limiter,
-    MsState,
 };
 
-static LIMITER: Limiter = Limiter::new(std::time::Duration::from_secs(10));
+/// Configured from `scrape.yt_dlp_rate` on startup
+pub static LIMITER: Limiter = Limiter::new("yt_dlp", std::time::Duration::from_secs(10));
+
+/// Progress of the downloads which are currently running, by video id
+static PROGRESS: LazyLock<Mutex<HashMap<String, DownloadProgress>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Marks progress lines, so they can be told apart from the json dump on stdout
+const PROGRESS_PREFIX: &str = "[myousync-progress] ";
+const PROGRESS_TEMPLATE: &str = "download:[myousync-progress] %(progress.downloaded_bytes)s/%(progress.total_bytes,progress.total_bytes_estimate)s";
 
 #[derive(thiserror::Error, Debug)]
 pub enum YtDlpError {
@@ -23,37 +44,83 @@ pub enum YtDlpError {
     JsonDeserializationErr(#[from] serde_json::Error),
     #[error("YT-dlp returned an error: {0}")]
     CommandError(String),
+    #[error(transparent)]
+    Db(#[from] dbdata::DbError),
 }
 
-pub async fn get(s: &MsState, video_id: &str) -> Result<YtDlpResponse, YtDlpError> {
-    if let Some(file) = try_get_metadata(video_id) {
+/// Downloads the video into `workspace` and returns its metadata.
+#[tracing::instrument(name = "download", skip(s, workspace))]
+pub async fn get(
+    s: &MsState,
+    workspace: &Path,
+    video_id: &str,
+) -> Result<YtDlpResponse, YtDlpError> {
+    if let Some(file) = try_get_metadata(video_id)? {
         return Ok(file);
     }
 
     info!("Getting yt-dlp for: {}", video_id);
-    LIMITER
-        .wait_for_next_fetch_of_time(s.config.scrape.yt_dlp_rate)
-        .await;
+    let target = sources::download_target(video_id);
+    let _slot = LIMITER.acquire().await;
 
-    let dlp_output = Command::new(&s.config.scrape.yt_dlp)
-        .current_dir(s.config.paths.temp.as_path())
+    let args = s.config.yt_dlp_args(video_id);
+    let mut command = yt_dlp_command(&s.config.scrape);
+    command
+        .current_dir(workspace)
         .arg("--quiet")
         .arg("--dump-json")
         .arg("--no-simulate")
         .arg("--extract-audio")
-        .arg("--embed-thumbnail")
-        .args(["--format", "ba"])
-        .args(["--sponsorblock-remove", "music_offtopic"])
-        .args(["--use-extractors", "youtube"])
-        .args(["--output", "%(id)s.%(ext)s"])
-        .arg(format!("https://www.youtube.com/watch?v={video_id}"))
-        .output()
-        .await?;
+        .args(["--progress", "--newline"])
+        .args(["--progress-template", PROGRESS_TEMPLATE])
+        .args(["--format", &args.format])
+        .args(["--use-extractors", target.extractor])
+        .args(["--output", &args.output.replace("{video_id}", video_id)]);
+    if args.embed_thumbnail {
+        command.arg("--embed-thumbnail");
+    }
+    if !args.sponsorblock_remove.is_empty() {
+        command.args(["--sponsorblock-remove", &args.sponsorblock_remove.join(",")]);
+    }
+    let mut child = command
+        .args(&args.extra_args)
+        .arg(&target.url)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // Depending on the version, progress lines end up on stdout next to the json dump or on stderr
+    let mut stdout = BufReader::new(child.stdout.take().expect("stdout is piped")).lines();
+    let mut stderr = BufReader::new(child.stderr.take().expect("stderr is piped")).lines();
+    let (mut stdout_open, mut stderr_open) = (true, true);
+    let mut dump = String::new();
+    let mut errors = Vec::new();
+    let mut reporter = ProgressReporter::new(video_id);
+    while stdout_open || stderr_open {
+        tokio::select! {
+            line = stdout.next_line(), if stdout_open => match line? {
+                Some(line) => match line.strip_prefix(PROGRESS_PREFIX) {
+                    Some(progress) => reporter.report(progress),
+                    None => dump.push_str(&line),
+                },
+                None => stdout_open = false,
+            },
+            line = stderr.next_line(), if stderr_open => match line? {
+                Some(line) => match line.strip_prefix(PROGRESS_PREFIX) {
+                    Some(progress) => reporter.report(progress),
+                    None => errors.push(line),
+                },
+                None => stderr_open = false,
+            },
+        }
+    }
+    drop(reporter);
+    child.wait().await?;
 
-    let mut json = match serde_json::from_slice::<Value>(&dlp_output.stdout) {
+    let mut json = match serde_json::from_str::<Value>(&dump) {
         Ok(json) => json,
         Err(json_err) => {
-            let dlp_stderr = String::from_utf8(dlp_output.stderr)?.trim().to_string();
+            let dlp_stderr = redact(&s.config.scrape, errors.join("\n").trim());
             error!("Got ERROR yt-dlp: {} | {}", json_err, dlp_stderr);
             return Err(YtDlpError::CommandError(dlp_stderr));
         }
@@ -67,24 +134,194 @@ pub async fn get(s: &MsState, video_id: &str) -> Result<YtDlpResponse, YtDlpErro
     }
     let dlp_res = serde_json::to_string(&json)?;
 
-    dbdata::DB.set_yt_dlp(video_id, &dlp_res);
+    dbdata::DB.set_yt_dlp(video_id, &dlp_res)?;
 
     let dlp_res: YtDlpResponse = serde_json::from_str(&dlp_res)?;
 
     Ok(dlp_res)
 }
 
-pub fn try_get_metadata(video_id: &str) -> Option<YtDlpResponse> {
-    if let Some(dlp_res) = dbdata::DB.try_get_yt_dlp(video_id) {
-        let ytdlp_data = serde_json::from_str(&dlp_res).unwrap();
-        return Some(ytdlp_data);
+/// A yt-dlp invocation with the cookies, proxy and extractor arguments of `config`.
+fn yt_dlp_command(config: &MsScrape) -> Command {
+    let mut command = Command::new(&config.yt_dlp);
+    // Stopped jobs must not leave downloads running, e.g. on shutdown
+    command.kill_on_drop(true);
+    if let Some(cookies) = &config.cookies_file {
+        command.arg("--cookies").arg(cookies);
+    }
+    if let Some(proxy) = &config.proxy {
+        command.args(["--proxy", proxy]);
+    }
+    for args in &config.extractor_args {
+        command.args(["--extractor-args", args]);
+    }
+    command
+}
+
+/// Hides the credentials of the proxy in output of yt-dlp, which ends up in logs and the
+/// `last_error` of videos.
+fn redact(config: &MsScrape, output: &str) -> String {
+    let Some(proxy) = &config.proxy else {
+        return output.to_string();
+    };
+    let mut output = output.to_string();
+    if let Ok(mut url) = reqwest::Url::parse(proxy)
+        && (url.password().is_some() || !url.username().is_empty())
+    {
+        let password = url.password().map(str::to_owned);
+        let _ = url.set_username("***");
+        let _ = url.set_password(None);
+        output = output.replace(proxy.as_str(), url.as_str());
+        if let Some(password) = password {
+            output = output.replace(&password, "***");
+        }
+    }
+    output
+}
+
+/// Lists the entries of a playlist, or the results of a search like `ytsearch1:...`, without
+/// downloading anything.
+pub async fn get_flat_playlist(s: &MsState, url: &str) -> Result<FlatPlaylist, YtDlpError> {
+    debug!("Listing yt-dlp playlist: {}", url);
+    let _slot = LIMITER.acquire().await;
+
+    let output = yt_dlp_command(&s.config.scrape)
+        .arg("--quiet")
+        .arg("--flat-playlist")
+        .arg("--dump-single-json")
+        .arg(url)
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        let dlp_stderr = redact(&s.config.scrape, String::from_utf8(output.stderr)?.trim());
+        error!("Got ERROR yt-dlp: {}", dlp_stderr);
+        return Err(YtDlpError::CommandError(dlp_stderr));
+    }
+    Ok(serde_json::from_slice(&output.stdout)?)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FlatPlaylist {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub uploader: Option<String>,
+    #[serde(default)]
+    pub entries: Vec<FlatEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FlatEntry {
+    /// Some extractors give numeric ids
+    #[serde(deserialize_with = "deserialize_id")]
+    pub id: String,
+    pub title: Option<String>,
+    pub uploader: Option<String>,
+    /// Length in seconds
+    pub duration: Option<f64>,
+}
+
+fn deserialize_id<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    Ok(match Value::deserialize(deserializer)? {
+        Value::String(id) => id,
+        other => other.to_string(),
+    })
+}
+
+/// Progress of a running download.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DownloadProgress {
+    pub downloaded_bytes: u64,
+    /// Exact or estimated size, `None` while yt-dlp does not know it yet
+    pub total_bytes: Option<u64>,
+    pub percent: Option<f32>,
+}
+
+impl DownloadProgress {
+    /// Parses a line printed with [`PROGRESS_TEMPLATE`], after its prefix.
+    fn parse(line: &str) -> Option<Self> {
+        let (downloaded, total) = line.trim().split_once('/')?;
+        let downloaded_bytes = downloaded.parse::<f64>().ok()? as u64;
+        let total_bytes = total
+            .parse::<f64>()
+            .ok()
+            .map(|t| t as u64)
+            .filter(|t| *t > 0);
+        Some(DownloadProgress {
+            downloaded_bytes,
+            total_bytes,
+            percent: total_bytes.map(|t| (downloaded_bytes as f32 / t as f32 * 100.0).min(100.0)),
+        })
     }
-    None
 }
 
-pub fn find_local_file(s: &MsState, video_id: &str) -> Option<PathBuf> {
-    let mut path = s.config.paths.temp.clone();
-    path.push(format!("{}.*", video_id));
+/// Returns the progress of the video if it is being downloaded right now.
+pub fn get_progress(video_id: &str) -> Option<DownloadProgress> {
+    PROGRESS.lock().unwrap().get(video_id).cloned()
+}
+
+/// Keeps [`PROGRESS`] up to date for one download and notifies clients about changes.
+/// The progress is removed again when dropped.
+struct ProgressReporter<'a> {
+    video_id: &'a str,
+    status: Option<VideoStatus>,
+    last_percent: Option<u32>,
+}
+
+impl<'a> ProgressReporter<'a> {
+    fn new(video_id: &'a str) -> Self {
+        ProgressReporter {
+            video_id,
+            // Without the status the download only goes without progress updates
+            status: dbdata::DB.get_video(video_id).unwrap_or_else(|err| {
+                warn!("Failed to get video {}: {}", video_id, err);
+                None
+            }),
+            last_percent: None,
+        }
+    }
+
+    fn report(&mut self, line: &str) {
+        let Some(progress) = DownloadProgress::parse(line) else {
+            debug!("Unexpected yt-dlp progress: {}", line);
+            return;
+        };
+        PROGRESS
+            .lock()
+            .unwrap()
+            .insert(self.video_id.to_owned(), progress.clone());
+
+        // Only whole percent steps are sent, so clients are not flooded
+        let percent = progress.percent.map(|p| p as u32);
+        if percent == self.last_percent {
+            return;
+        }
+        self.last_percent = percent;
+        if let Some(status) = &mut self.status {
+            status.download_progress = Some(progress);
+            MsState::push_update_notification(status);
+        }
+    }
+}
+
+impl Drop for ProgressReporter<'_> {
+    fn drop(&mut self) {
+        PROGRESS.lock().unwrap().remove(self.video_id);
+    }
+}
+
+pub fn try_get_metadata(video_id: &str) -> Result<Option<YtDlpResponse>, YtDlpError> {
+    if let Some(dlp_res) = dbdata::DB.try_get_yt_dlp(video_id)? {
+        let ytdlp_data = serde_json::from_str(&dlp_res)?;
+        return Ok(Some(ytdlp_data));
+    }
+    Ok(None)
+}
+
+/// Finds a file downloaded by [`get`] in `dir`, which is either a job workspace or the temp folder
+/// finished downloads are parked in.
+pub fn find_local_file(dir: &Path, video_id: &str) -> Option<PathBuf> {
+    let path = dir.join(format!("{}.*", video_id));
     glob::glob(path.to_str().unwrap())
         .unwrap()
         .next()
@@ -97,12 +334,151 @@ pub struct YtDlpResponse {
     pub id: String,
 
     pub title: String,
-    #[expect(dead_code)]
+    /// Only set for youtube videos
+    #[serde(default)]
     pub channel: String,
-    #[expect(dead_code)]
-    pub duration: u32,
+    pub duration: Option<f64>,
 
     pub album: Option<String>,
     pub artist: Option<String>,
     pub track: Option<String>,
+    /// Url of the best thumbnail
+    pub thumbnail: Option<String>,
+}
+
+impl YtDlpResponse {
+    /// The artist of an auto-generated "Artist - Topic" channel, whose uploads carry the
+    /// metadata provided by the label.
+    pub fn topic_artist(&self) -> Option<&str> {
+        self.channel
+            .strip_suffix(" - Topic")
+            .map(str::trim)
+            .filter(|a| !a.is_empty())
+    }
+
+    /// The metadata of the video as is, for playlists which are not looked up on MusicBrainz.
+    pub fn raw_metadata(&self) -> BrainzMetadata {
+        let artist = match &self.artist {
+            Some(artist) => artist.split(", ").map(str::to_owned).collect(),
+            None => vec![self.topic_artist().unwrap_or(&self.channel).to_owned()],
+        };
+        BrainzMetadata {
+            brainz_recording_id: None,
+            brainz_release_id: None,
+            brainz_artist_ids: Vec::new(),
+            title: self.track.clone().unwrap_or_else(|| self.title.clone()),
+            artist: artist.into_iter().filter(|a| !a.is_empty()).collect(),
+            album: self.album.clone(),
+            album_artist: None,
+            release_form: None,
+            date: None,
+            genres: Vec::new(),
+        }
+    }
+}
+
+/// Installs the pinned yt-dlp release and checks the age of the yt-dlp used, updating it if
+/// enabled. Problems are only logged, downloads fail on their own if yt-dlp is unusable.
+pub async fn prepare_binary(config: &mut MsScrape) {
+    if let Some(pin) = &config.yt_dlp_pin {
+        match install_release(pin).await {
+            Ok(path) => config.yt_dlp = path.to_string_lossy().into_owned(),
+            Err(err) => error!("Failed to install yt-dlp {}: {:#}", pin, err),
+        }
+    }
+
+    let version = match binary_version(&config.yt_dlp).await {
+        Ok(version) => version,
+        Err(err) => {
+            error!("yt-dlp at '{}' cannot be run: {:?}", config.yt_dlp, err);
+            return;
+        }
+    };
+    info!("Using yt-dlp {}", version);
+    if config.yt_dlp_pin.is_some() || !is_stale(&version, config.yt_dlp_max_age) {
+        return;
+    }
+    if !config.yt_dlp_self_update {
+        warn!(
+            "yt-dlp {} is older than {} days, downloads may fail until it is updated or \
+             scrape.yt_dlp_self_update is enabled",
+            version,
+            config.yt_dlp_max_age.as_secs() / (24 * 60 * 60)
+        );
+        return;
+    }
+
+    info!("Updating yt-dlp {}", version);
+    match Command::new(&config.yt_dlp).arg("-U").output().await {
+        Ok(output) if output.status.success() => match binary_version(&config.yt_dlp).await {
+            Ok(updated) => info!("Updated yt-dlp to {}", updated),
+            Err(err) => error!("yt-dlp cannot be run after the update: {:?}", err),
+        },
+        Ok(output) => error!(
+            "Failed to update yt-dlp: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+        Err(err) => error!("Failed to update yt-dlp: {}", err),
+    }
+}
+
+async fn binary_version(yt_dlp: &str) -> Result<String, YtDlpError> {
+    let output = Command::new(yt_dlp).arg("--version").output().await?;
+    if !output.status.success() {
+        return Err(YtDlpError::CommandError(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    Ok(String::from_utf8(output.stdout)?.trim().to_string())
+}
+
+/// Versions are release dates like `2024.12.23`, nightly builds append the time.
+fn is_stale(version: &str, max_age: Duration) -> bool {
+    let mut parts = version.split('.').map(str::parse::<u32>);
+    let (Some(Ok(year)), Some(Ok(month)), Some(Ok(day))) =
+        (parts.next(), parts.next(), parts.next())
+    else {
+        warn!("Unknown yt-dlp version format '{}'", version);
+        return false;
+    };
+    NaiveDate::from_ymd_opt(year as i32, month, day).is_some_and(|released| {
+        Utc::now().date_naive() - released > TimeDelta::from_std(max_age).unwrap_or(TimeDelta::MAX)
+    })
+}
+
+/// Downloads the release `version` of yt-dlp into the data folder, unless it is there already.
+async fn install_release(version: &str) -> anyhow::Result<PathBuf> {
+    let asset = match (std::env::consts::OS, std::env::consts::ARCH) {
+        ("linux", "x86_64") => "yt-dlp_linux",
+        ("linux", "aarch64") => "yt-dlp_linux_aarch64",
+        ("macos", _) => "yt-dlp_macos",
+        ("windows", _) => "yt-dlp.exe",
+        // Needs a python interpreter
+        _ => "yt-dlp",
+    };
+    let dir = dbdata::data_dir().join("bin");
+    let path = dir.join(format!("yt-dlp-{version}{}", std::env::consts::EXE_SUFFIX));
+    if path.exists() {
+        return Ok(path);
+    }
+
+    let url = format!("https://github.com/yt-dlp/yt-dlp/releases/download/{version}/{asset}");
+    info!("Downloading yt-dlp from {}", url);
+    let binary = net::CLIENT
+        .get(&url)
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+    std::fs::create_dir_all(&dir)?;
+    let partial = path.with_extension("part");
+    std::fs::write(&partial, &binary)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&partial, std::fs::Permissions::from_mode(0o755))?;
+    }
+    std::fs::rename(&partial, &path)?;
+    Ok(path)
 }
@@ -0,0 +1,74 @@
+//! Drives the web app through its router, without binding a port.
+
+use std::sync::LazyLock;
+
+use axum::{
+    Router,
+    body::Body,
+    http::{Request, StatusCode, header},
+};
+use http_body_util::BodyExt;
+use myousync::{MsState, build_router};
+use serde_json::{Value, json};
+use tower::ServiceExt;
+
+/// The database is global, so all tests share one state.
+static STATE: LazyLock<MsState> = LazyLock::new(MsState::new_for_tests);
+
+fn router() -> Router {
+    build_router(&STATE)
+}
+
+async fn send(request: Request<Body>) -> (StatusCode, Value) {
+    let response = router().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+}
+
+fn post_json(uri: &str, body: Value) -> Request<Body> {
+    Request::post(uri)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn requests_without_token_are_rejected() {
+    let (status, _) = send(Request::post("/login/check").body(Body::empty()).unwrap()).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn setup_creates_a_user_that_can_sign_in() {
+    let (status, setup) = send(Request::get("/setup").body(Body::empty()).unwrap()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(setup["required"], true);
+
+    let short = json!({ "username": "admin", "password": "short" });
+    let (status, _) = send(post_json("/setup", short)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let credentials = json!({ "username": "admin", "password": "correct horse" });
+    let (status, _) = send(post_json("/setup", credentials.clone())).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send(post_json("/setup", credentials.clone())).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    let wrong = json!({ "username": "admin", "password": "wrong" });
+    let (status, _) = send(post_json("/login", wrong)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, tokens) = send(post_json("/login", credentials)).await;
+    assert_eq!(status, StatusCode::OK);
+    let access_token = tokens["access_token"].as_str().unwrap();
+
+    let (status, _) = send(
+        Request::post("/login/check")
+            .header(header::AUTHORIZATION, format!("Bearer {access_token}"))
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+}
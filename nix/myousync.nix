@@ -104,10 +104,23 @@ in {
     };
 
     playlists = mkOption {
-      type = types.listOf types.str;
+      type = types.listOf (types.either types.str settingsFormat.type);
       default = [];
+      example = literalExpression ''
+        [
+          "LM"
+          {
+            id = "LL";
+            exclude_title = "(?i)podcast|interview";
+            max_duration = "10m";
+          }
+        ]
+      '';
       description = ''
         The youtube playlists to scrape. Add "LM" for the 'liked music' list.
+        Instead of a plain id an attribute set with an `id` can be given to filter the synced items by
+        `include_title`, `exclude_title`, `include_channel`, `exclude_channel` (regexes),
+        `min_duration` and `max_duration`.
       '';
     };
 
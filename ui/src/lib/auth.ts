@@ -1,9 +1,13 @@
 import { derived, Readable, Writable, writable } from 'svelte/store';
-import { API_URL } from './defs';
+import { API_URL, Tokens } from './defs';
+
+/** Refreshes the access token this many seconds before it expires */
+const REFRESH_MARGIN = 60;
 
 class Auth {
 	private _jwt: Writable<string | null> = writable(null);
 	private _loggedIn: Readable<boolean> = derived(this._jwt, jwt => jwt !== null);
+	private refreshTimer: ReturnType<typeof setTimeout> | undefined;
 
 	public get loggedIn(): Readable<boolean> {
 		return this._loggedIn;
@@ -14,27 +18,15 @@ class Auth {
 	}
 
 	public async init() {
-		let jwt = localStorage.getItem("jwt");
-		if (!jwt) {
+		localStorage.removeItem("jwt");
+		if (!localStorage.getItem("refresh_token")) {
 			this._jwt.set(null);
 			return;
 		}
 
-		let res = await fetch(`${API_URL}/login/check`, {
-			method: "POST",
-			headers: {
-				Authorization: `Bearer ${jwt}`,
-			},
-		});
-
-		if (!res.ok) {
-			localStorage.removeItem("jwt");
-			this._jwt.set(null);
+		if (!await this.refresh()) {
 			console.error("auth expired");
-			return;
 		}
-
-		this._jwt.set(jwt);
 	}
 
 	async login(username: string, password: string) {
@@ -53,13 +45,63 @@ class Auth {
 		}
 
 		console.log("logged in");
-		let jwt = await res.json();
-		localStorage.setItem("jwt", jwt);
+		this.setTokens(await res.json());
+		return true;
+	}
+
+	async logout() {
+		const jwt = localStorage.getItem("access_token");
+		if (jwt) {
+			await fetch(`${API_URL}/logout`, {
+				method: "POST",
+				headers: {
+					Authorization: `Bearer ${jwt}`,
+				},
+			});
+		}
+		this.clear();
+	}
+
+	/** Exchanges the refresh token for new tokens, returns whether the session is still valid */
+	private async refresh() {
+		const refresh_token = localStorage.getItem("refresh_token");
+		if (!refresh_token) {
+			return false;
+		}
+
+		const res = await fetch(`${API_URL}/login/refresh`, {
+			method: "POST",
+			headers: {
+				"Content-Type": "application/json",
+			},
+			body: JSON.stringify({ refresh_token }),
+		});
+
+		if (!res.ok) {
+			this.clear();
+			return false;
+		}
 
-		this._jwt.set(jwt);
+		this.setTokens(await res.json());
 		return true;
 	}
+
+	private setTokens(tokens: Tokens) {
+		localStorage.setItem("access_token", tokens.access_token);
+		localStorage.setItem("refresh_token", tokens.refresh_token);
+		this._jwt.set(tokens.access_token);
+
+		clearTimeout(this.refreshTimer);
+		const delay = Math.max(tokens.expires_in - REFRESH_MARGIN, REFRESH_MARGIN);
+		this.refreshTimer = setTimeout(() => this.refresh(), delay * 1000);
+	}
+
+	private clear() {
+		clearTimeout(this.refreshTimer);
+		localStorage.removeItem("access_token");
+		localStorage.removeItem("refresh_token");
+		this._jwt.set(null);
+	}
 }
 
 export const AUTH = new Auth();
-
@@ -2,6 +2,21 @@ export const API_URL = process.env.NODE_ENV === "development"
 	? 'http://localhost:3001'
 	: `${import.meta.env.ASSET_PREFIX}`.replace(/\/*$/, '');
 
+/** Payloads of another version are not compatible, see `api.rs` for the policy */
+export const API_VERSION = 3;
+
+export interface WsMessage {
+	api_version: number;
+	kind: "init" | "update";
+	videos: VideoData[];
+}
+
+/** Sent on `/ws` as `{ token?, subscribe? }`, a `subscribe` is answered with a new init message */
+export interface WsSubscription {
+	playlist?: string;
+	errors_only?: boolean;
+}
+
 export interface VideoData {
 	video_id: string;
 	last_update: number;
@@ -10,8 +25,35 @@ export interface VideoData {
 	last_query?: BrainzMultiSearch;
 	last_result?: BrainzMetadata;
 	last_error?: string;
+	error_code?: ErrorCode;
 	override_query?: BrainzMultiSearch;
 	override_result?: BrainzMetadata;
+	removal?: Removal;
+	candidates: BrainzCandidate[];
+	confidence?: number;
+	/** The last tagging found the file already carrying the desired metadata */
+	tags_unchanged?: boolean;
+	download_progress?: DownloadProgress;
+	retry?: RetryInfo;
+}
+
+export interface Removal {
+	playlist_id: string;
+	action: "keep" | "mark" | "archive" | "delete";
+	time: number;
+}
+
+export interface RetryInfo {
+	/** Number of failed runs */
+	attempts: number;
+	/** Unix timestamp of the next run, not set once no more runs follow */
+	next_attempt?: number;
+}
+
+export interface DownloadProgress {
+	downloaded_bytes: number;
+	total_bytes?: number;
+	percent?: number;
 }
 
 export interface BrainzMultiSearch {
@@ -23,9 +65,81 @@ export interface BrainzMultiSearch {
 
 export interface BrainzMetadata {
 	brainz_recording_id?: string;
+	brainz_release_id?: string;
+	brainz_artist_ids?: string[];
 	title: string;
 	artist: string[];
 	album?: string;
+	album_artist?: string;
+	/** The form of the release the names were taken from, when picked by the locale preference */
+	release_form?: ReleaseForm;
+	/** Release date as YYYY[-MM[-DD]] */
+	date?: string;
+	genres?: string[];
+}
+
+export interface ReleaseForm {
+	release_id: string;
+	language?: string;
+	script?: string;
+}
+
+export interface BrainzCandidate extends BrainzMetadata {
+	/** Search score of MusicBrainz from 0 to 100 */
+	score: number;
+	/** Length of the recording in milliseconds */
+	length?: number;
+}
+
+export interface BrainzSearchLog {
+	query: string;
+	candidates: BrainzCandidate[];
+	error?: string;
+}
+
+export interface MatchAttempt {
+	attempt_id: number;
+	video_id: string;
+	created: number;
+	query: BrainzMultiSearch;
+	searches: BrainzSearchLog[];
+	result?: BrainzMetadata;
+}
+
+export interface MatchHistory {
+	api_version: number;
+	video_id: string;
+	attempts: MatchAttempt[];
+}
+
+/** A state of a video, recorded whenever its status, error, query or result changed */
+export interface StatusChange {
+	history_id: number;
+	video_id: string;
+	time: number;
+	fetch_status: FetchStatus;
+	error_code?: ErrorCode;
+	last_error?: string;
+	query?: BrainzMultiSearch;
+	result?: BrainzMetadata;
+	tags_unchanged: boolean;
+}
+
+export interface StatusHistory {
+	api_version: number;
+	video_id: string;
+	changes: StatusChange[];
+}
+
+/** What a delete would remove, confirmed by passing the token back */
+export interface DeleteConfirmation {
+	api_version: number;
+	video_id: string;
+	token: string;
+	path?: string;
+	size?: number;
+	/** Unix timestamp */
+	expires: number;
 }
 
 export const enum FetchStatus {
@@ -35,8 +149,32 @@ export const enum FetchStatus {
 	BRAINZ_ERROR = "BrainzError",
 	CATEGORIZED = "Categorized",
 	DISABLED = "Disabled",
+	FILE_MISSING = "FileMissing",
+	NEEDS_REVIEW = "NeedsReview",
+	DUPLICATE = "Duplicate",
 }
 
+/** Why a video failed, see `errors.rs` */
+export const ERROR_CODES = [
+	"YT_UNAVAILABLE",
+	"YT_PRIVATE",
+	"YT_GEO_BLOCKED",
+	"YT_AGE_RESTRICTED",
+	"YT_RATE_LIMIT",
+	"YT_NETWORK",
+	"YT_FAILED",
+	"BRAINZ_EMPTY",
+	"BRAINZ_FAILED",
+	"TAG_WRITE_FAILED",
+	"UNTAGGABLE",
+	"FS_PERMISSION",
+	"FS_NO_SPACE",
+	"FILE_MISSING",
+	"DUPLICATE",
+	"UNKNOWN",
+] as const;
+export type ErrorCode = (typeof ERROR_CODES)[number];
+
 export function BrainzMetadata_contains(data: BrainzMetadata, text: string) {
 	if (data.title.toLowerCase().includes(text)) return true;
 	if (data.artist.some(a => a.toLowerCase().includes(text))) return true;
@@ -50,3 +188,76 @@ export function BrainzMultiSearch_contains(data: BrainzMultiSearch, text: string
 	if (data.album && data.album.toLowerCase().includes(text)) return true;
 	return false;
 }
+
+export interface SetupStatus {
+	required: boolean;
+	paths?: SetupPathCheck[];
+	youtube_configured?: boolean;
+	plex?: { error?: string };
+}
+
+export interface SetupPathCheck {
+	name: string;
+	path: string;
+	error?: string;
+}
+
+/** Returned by `/login` and `/login/refresh` */
+export interface Tokens {
+	access_token: string;
+	/** Valid for one `/login/refresh` */
+	refresh_token: string;
+	/** Seconds until the access token expires */
+	expires_in: number;
+}
+
+export interface Session {
+	session_id: string;
+	username: string;
+	created: number;
+	last_used: number;
+	expires: number;
+	client_ip?: string;
+	user_agent?: string;
+	/** The session of the request */
+	current: boolean;
+}
+
+export type ApiScope = "read" | "write";
+
+/** Sent as `X-Api-Key`, the key itself is only returned when it is created */
+export interface ApiKey {
+	key_id: string;
+	name: string;
+	username: string;
+	scopes: ApiScope[];
+	created: number;
+	last_used?: number;
+	key?: string;
+}
+
+/** Listed at `/users`, only admins can add and remove users */
+export interface User {
+	username: string;
+	admin: boolean;
+}
+
+/** Listed newest first at `/admin/audit`, only for admins */
+export interface AuditEntry {
+	id: number;
+	time: number;
+	username: string;
+	api_key?: string;
+	action: string;
+	target?: string;
+	details?: unknown;
+	request_id?: string;
+	client_ip?: string;
+}
+
+/** Written by `POST /admin/backup`, only for admins */
+export interface Backup {
+	path: string;
+	size: number;
+	created: number;
+}
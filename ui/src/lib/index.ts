@@ -9,6 +9,9 @@ import {
 	mdiTimerSandEmpty,
 	mdiClose,
 	mdiDownloadOff,
+	mdiFileQuestionOutline,
+	mdiEyeOutline,
+	mdiContentDuplicate,
 } from "@mdi/js";
 
 export enum ConState {
@@ -41,6 +44,12 @@ export function state_to_icon(state: FetchStatus) {
 			return mdiCheckCircleOutline;
 		case FetchStatus.DISABLED:
 			return mdiDownloadOff;
+		case FetchStatus.FILE_MISSING:
+			return mdiFileQuestionOutline;
+		case FetchStatus.NEEDS_REVIEW:
+			return mdiEyeOutline;
+		case FetchStatus.DUPLICATE:
+			return mdiContentDuplicate;
 		default:
 			return mdiAlertOutline;
 	}
@@ -60,6 +69,12 @@ export function state_to_color(state: FetchStatus) {
 			return "green";
 		case FetchStatus.DISABLED:
 			return "grey";
+		case FetchStatus.FILE_MISSING:
+			return "red";
+		case FetchStatus.NEEDS_REVIEW:
+			return "orange";
+		case FetchStatus.DUPLICATE:
+			return "orange";
 		default:
 			return "yellow";
 	}